@@ -0,0 +1,260 @@
+//! Compile-time check that the public API matches on every platform
+//!
+//! fruitbasket provides a do-nothing implementation of its API on platforms
+//! other than macOS, so cross-platform apps can use it without `cfg` blocks.
+//! This example does nothing when run, but fails to build if any function is
+//! missing, or has a different signature, on the platform being built.
+//!
+//! When adding to the public API, add the new function here too.
+extern crate fruitbasket;
+use fruitbasket::*;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Signature shared by the Trampoline builder's single-string setters
+type Setter = for<'r> fn(&'r mut Trampoline, &str) -> &'r mut Trampoline;
+
+#[allow(clippy::type_complexity)]
+fn main() {
+    // FruitApp
+    let _: fn() -> Result<FruitApp<'static>, FruitError> = FruitApp::new;
+    let _: fn(&str) -> Result<AgentApp<'static>, FruitError> = FruitApp::new_agent;
+    let _: fn(&mut FruitApp<'static>, FruitCallbackKey, FruitObjcCallback<'static>) = FruitApp::register_callback;
+    let _: fn(&mut FruitApp<'static>, FruitCallbackKey, FruitObjcCallback<'static>) = FruitApp::register_callback_once;
+    let _: fn(&mut FruitApp<'static>, FruitCallbackKey, Duration) -> Option<EventPayload> = FruitApp::wait_for;
+    let _: fn(&FruitApp<'static>) -> std::os::unix::io::RawFd = FruitApp::wake_fd;
+    let _: fn(&mut FruitApp<'static>) -> Result<(), ()> = FruitApp::pump_pending;
+    let _: fn(&mut FruitApp<'static>, &'static str, MethodSignature, FruitObjcCallback<'static>) -> Result<(), FruitError> = FruitApp::add_delegate_method;
+    let _: fn(&mut FruitApp<'static>, u32, u32) = FruitApp::register_apple_event;
+    let _: fn(&mut FruitApp<'static>, u32, u32) = FruitApp::unregister_apple_event;
+    let _: fn(&mut FruitApp<'static>, CoreEventCallback<'static>) = FruitApp::register_core_events;
+    let _: fn(&mut FruitApp<'static>, OpenFileCallback<'static>, bool) = FruitApp::register_open_file_handler;
+    let _: fn(&FruitApp<'static>) -> Vec<OpenRequest> = FruitApp::startup_open_requests;
+    let _: fn(&FruitApp<'static>, &str, &[&str]) -> Result<std::process::Child, FruitError> = FruitApp::launch_helper;
+    let _: fn(&FruitApp<'static>) -> Result<(), FruitError> = FruitApp::relaunch;
+    let _: fn(&FruitApp<'static>) -> bool = FruitApp::first_run;
+    let _: fn(&FruitApp<'static>) -> Option<String> = FruitApp::previous_version;
+    let _: fn(&FruitApp<'static>, fn(&str, &str)) = FruitApp::on_upgrade::<fn(&str, &str)>;
+    let _: fn(&FruitApp<'static>, ActivationPolicy) = FruitApp::set_activation_policy;
+    let _: fn(&FruitApp<'static>) -> ActivationPolicy = FruitApp::activation_policy;
+    let _: fn(&FruitApp<'static>) -> LaunchPhase = FruitApp::launch_phase;
+    let _: fn(&FruitApp<'static>) -> LaunchContext = FruitApp::launch_context;
+    let _: fn(&FruitApp<'static>, bool, bool) -> Result<(), FruitError> = FruitApp::set_shows_in_dock;
+    let _: fn(&FruitApp<'static>, ActivationPolicy, bool) -> Result<(), FruitError> = FruitApp::change_activation_policy;
+    let _: fn(&FruitApp<'static>) = FruitApp::activate;
+    let _: fn(&FruitApp<'static>, bool) = FruitApp::set_services_provider;
+    let _: fn(&FruitApp<'static>, Option<&str>) = FruitApp::set_dock_badge;
+    let _: fn(&FruitApp<'static>, bool) = FruitApp::request_attention;
+    let _: fn(&FruitApp<'static>) -> bool = FruitApp::is_visible;
+    let _: fn(i32) = FruitApp::terminate;
+    let _: fn(&FruitStopper) = FruitApp::stop;
+    let _: fn(&mut FruitApp<'static>, RunPeriod) -> Result<(),()> = FruitApp::run;
+    let _: fn(&FruitApp<'static>, u64) = FruitApp::set_pool_drain_interval;
+    let _: fn(&FruitApp<'static>, Duration) = FruitApp::set_idle_interval;
+    let _: fn(&FruitApp<'static>, bool) = FruitApp::watch_clipboard;
+    let _: fn(&FruitApp<'static>) -> RunLoopStats = FruitApp::stats;
+    let _: fn(&FruitApp<'static>) -> FruitStopper = FruitApp::stopper;
+    let _: fn(&FruitApp<'static>) -> FruitHandle = FruitApp::handle;
+    let _: fn(&str, &str) -> Result<Option<String>, FruitError> = FruitApp::bundled_resource_path;
+    // Raw ObjC pointers have a platform-specific type
+    let _ = FruitApp::nsapp;
+    let _ = FruitApp::delegate;
+
+    // FruitAppBuilder
+    let _: fn() -> FruitAppBuilder = FruitAppBuilder::new;
+    let _: for<'r> fn(&'r mut FruitAppBuilder, ActivationPolicy) -> &'r mut FruitAppBuilder = FruitAppBuilder::activation_policy;
+    let _: for<'r> fn(&'r mut FruitAppBuilder, &str) -> &'r mut FruitAppBuilder = FruitAppBuilder::dock_icon;
+    let _: for<'r> fn(&'r mut FruitAppBuilder, &[&'static str]) -> &'r mut FruitAppBuilder = FruitAppBuilder::delegate_methods;
+    let _: for<'r> fn(&'r mut FruitAppBuilder, bool) -> &'r mut FruitAppBuilder = FruitAppBuilder::terminate_after_last_window_closed;
+    let _: for<'r> fn(&'r mut FruitAppBuilder, &str) -> &'r mut FruitAppBuilder = FruitAppBuilder::class_prefix;
+    let _: fn(&FruitAppBuilder) -> Result<FruitApp<'static>, FruitError> = FruitAppBuilder::build;
+
+    // Trampoline
+    let _: fn(&str, &str, &str) -> Trampoline = Trampoline::new;
+    let _: Setter = Trampoline::name;
+    let _: Setter = Trampoline::exe;
+    let _: Setter = Trampoline::ident;
+    let _: Setter = Trampoline::icon;
+    let _: Setter = Trampoline::version;
+    let _: for<'r> fn(&'r mut Trampoline, &str, &str) -> &'r mut Trampoline = Trampoline::plist_key;
+    let _: for<'r> fn(&'r mut Trampoline, &Vec<(&str,&str)>) -> &'r mut Trampoline = Trampoline::plist_keys;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::retina;
+    let _: for<'r> fn(&'r mut Trampoline, String) -> &'r mut Trampoline = Trampoline::plist_raw_string;
+    let _: Setter = Trampoline::resource;
+    let _: Setter = Trampoline::scripting_definition;
+    let _: Setter = Trampoline::capture_output;
+    let _: for<'r> fn(&'r mut Trampoline, BundleLayout) -> &'r mut Trampoline = Trampoline::layout;
+    let _: for<'r> fn(&'r mut Trampoline, PlistFormat) -> &'r mut Trampoline = Trampoline::plist_format;
+    let _: Setter = Trampoline::executable;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::single_instance;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::terminal;
+    let _: for<'r> fn(&'r mut Trampoline, LaunchOptions) -> &'r mut Trampoline = Trampoline::launch_options;
+    let _: for<'r> fn(&'r mut Trampoline, &str, &str) -> &'r mut Trampoline = Trampoline::env;
+    let _: for<'r> fn(&'r mut Trampoline, &[Arch]) -> &'r mut Trampoline = Trampoline::architectures;
+    let _: fn(&Arch) -> &'static str = Arch::name;
+    let _: for<'r> fn(&'r mut Trampoline, &[(&str, &str)]) -> &'r mut Trampoline = Trampoline::acknowledgements;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::resource_manifest;
+    type Hook = fn(&Path) -> Result<(), FruitError>;
+    let _: for<'r> fn(&'r mut Trampoline, Hook) -> &'r mut Trampoline = Trampoline::post_build::<Hook>;
+    type Progress = fn(&Path, usize, usize);
+    let _: for<'r> fn(&'r mut Trampoline, Progress) -> &'r mut Trampoline = Trampoline::copy_progress::<Progress>;
+    let _: for<'r> fn(&'r mut Trampoline, &Vec<&str>) -> &'r mut Trampoline = Trampoline::resources;
+    let _: Setter = Trampoline::spotlight_importer;
+    let _: Setter = Trampoline::quicklook_generator;
+    let _: Setter = Trampoline::app_extension;
+    let _: Setter = Trampoline::xpc_service;
+    let _: for<'r> fn(&'r mut Trampoline) -> &'r mut Trampoline = Trampoline::agent;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::ui_element;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::background_only;
+    let _: for<'r> fn(&'r mut Trampoline, bool) -> &'r mut Trampoline = Trampoline::multiple_instances_prohibited;
+    let _: for<'r> fn(&'r mut Trampoline, &str, &[&str]) -> &'r mut Trampoline = Trampoline::url_scheme;
+    let _: for<'r> fn(&'r mut Trampoline, &str, &str, &[&str]) -> &'r mut Trampoline = Trampoline::service;
+    let _: fn(&mut Trampoline, InstallDir) -> Result<FruitApp<'static>, FruitError> = Trampoline::build;
+    let _: fn(&Trampoline, InstallDir) -> Result<(), FruitError> = Trampoline::self_bundle;
+    let _: fn(&Trampoline, InstallDir) -> Result<PathBuf, FruitError> = Trampoline::create_bundle;
+    let _: fn() -> bool = Trampoline::is_bundled;
+    let _: fn() -> bool = Trampoline::has_embedded_plist;
+
+    // Thread-safe handles
+    let _: fn(&FruitStopper) = FruitStopper::stop;
+    let _: fn(&FruitHandle) = FruitHandle::stop;
+    let _: fn(&FruitHandle, ActivationPolicy) = FruitHandle::set_activation_policy;
+    let _: fn(&FruitHandle, ActivationPolicy, bool) = FruitHandle::change_activation_policy;
+    let _: fn(&FruitHandle, Option<&str>) = FruitHandle::set_dock_badge;
+    let _: fn(&FruitHandle, bool) = FruitHandle::request_attention;
+    let _: fn(&mut FruitApp<'static>) -> UrlHandler = UrlHandler::new;
+    let _: fn(&UrlHandler) -> Option<String> = UrlHandler::try_recv;
+    let _: fn(&UrlHandler) -> Vec<String> = UrlHandler::drain;
+    let _: fn(UrlHandler) -> std::sync::mpsc::Receiver<String> = UrlHandler::into_receiver;
+
+    // Free functions.  Those taking ObjC objects use a placeholder pointer
+    // type on other platforms, so only their presence is checked.
+    let _ = parse_url_event;
+    let _ = parse_url_list;
+    let _ = parse_universal_link;
+    let _ = parse_apple_event;
+    let _ = nsstring_to_string;
+    let _ = script_command_name;
+    let _ = foundation::nsstring_to_string;
+    let _ = foundation::string_to_nsstring;
+    let _ = foundation::nsarray_to_vec;
+    let _ = foundation::nsarray_to_strings;
+    let _ = foundation::nsdict_to_hashmap;
+    let _ = foundation::nsurl_to_path;
+    let _ = foundation::nsurl_to_string;
+    let _ = foundation::path_to_nsurl;
+    let _: fn() -> Vec<String> = preferred_languages;
+    let _: fn() -> String = current_locale;
+    let _: fn() -> Vec<Screen> = screens;
+    let _: fn() -> Option<Screen> = main_screen;
+    let _: fn() -> f64 = menu_bar_height;
+    let _: fn() -> Option<Dock> = dock;
+    let _: fn(&Screen) -> (u32, u32) = Screen::pixel_size;
+    let _: fn(&str, LogDir, u32, u32) -> Result<String, FruitError> = create_logger;
+    let _: fn(bool) = install_panic_handler;
+    let _: fn(&str) -> FourCC = fourcc_from_str;
+    let _: fn(&Path, &LaunchOptions) -> Result<(), FruitError> = launch_bundle;
+    let _: fn(&RunLoopStats) -> Duration = RunLoopStats::avg_dispatch_latency;
+
+    // Modules
+    let _: fn() -> dialog::OpenPanel = dialog::OpenPanel::new;
+    let _: for<'r> fn(&'r mut dialog::OpenPanel, &[&str]) -> &'r mut dialog::OpenPanel = dialog::OpenPanel::types;
+    let _: for<'r> fn(&'r mut dialog::OpenPanel, bool) -> &'r mut dialog::OpenPanel = dialog::OpenPanel::choose_directories;
+    let _: for<'r> fn(&'r mut dialog::OpenPanel, bool) -> &'r mut dialog::OpenPanel = dialog::OpenPanel::can_create_directories;
+    let _: for<'r> fn(&'r mut dialog::OpenPanel, bool) -> &'r mut dialog::OpenPanel = dialog::OpenPanel::bookmarks;
+    let _: fn(&dialog::OpenPanel) -> Result<Vec<dialog::Selection>, FruitError> = dialog::OpenPanel::run;
+    let _: fn() -> entitlements::Entitlements = entitlements::Entitlements::new;
+    let _: for<'r> fn(&'r mut entitlements::Entitlements, bool) -> &'r mut entitlements::Entitlements = entitlements::Entitlements::allow_jit;
+    let _: for<'r> fn(&'r mut entitlements::Entitlements, &[&str]) -> &'r mut entitlements::Entitlements = entitlements::Entitlements::icloud_containers;
+    let _: for<'r> fn(&'r mut entitlements::Entitlements, &[&str]) -> &'r mut entitlements::Entitlements = entitlements::Entitlements::associated_domains;
+    let _: fn(&entitlements::Entitlements) -> String = entitlements::Entitlements::to_plist;
+    let _: fn() -> bool = icloud::is_available;
+    let _: fn(Option<&str>) -> Option<PathBuf> = icloud::ubiquity_container_url;
+    let _: fn() -> Vec<workspace::RunningApp> = workspace::running_applications;
+    let _: fn(&str) -> bool = workspace::activate_app;
+    let _: fn(&str) -> bool = workspace::hide_app;
+    let _: fn(&str) -> bool = workspace::terminate_app;
+    let _: fn(&[workspace::ShareItem], workspace::ShareService) -> Result<(), FruitError> = workspace::share;
+    let _: fn(&Path) -> Result<PathBuf, FruitError> = workspace::move_to_trash;
+    let _: fn(&Path) -> Result<PathBuf, FruitError> = workspace::duplicate;
+    let _: fn(workspace::VolumeCallback) -> Result<workspace::VolumeWatcher, FruitError> = workspace::watch_volumes;
+    let _: fn(&Path, watch::WatchCallback) -> Result<watch::Watcher, FruitError> = watch::watch;
+    let _: fn(&str, &[u8]) -> Result<(), FruitError> = keychain::set_password;
+    let _: fn(&str) -> Result<Option<Vec<u8>>, FruitError> = keychain::get_password;
+    let _: fn(&str) -> Result<bool, FruitError> = keychain::delete_password;
+    let _: fn(bool) -> bool = permissions::ensure_accessibility;
+    let _: fn(&str, &str) -> spotlight::SearchableItem = spotlight::SearchableItem::new;
+    let _: fn(&[spotlight::SearchableItem]) -> Result<(), FruitError> = spotlight::index_items;
+    let _: fn(&[&str]) -> Result<(), FruitError> = spotlight::delete_items;
+    let _: fn(&[&str]) -> Result<(), FruitError> = spotlight::delete_domains;
+    let _: fn() -> Result<(), FruitError> = spotlight::delete_all;
+    let _ = spotlight::parse_activity;
+    let _: fn(&spotlight::SearchableItem) -> Result<spotlight::UserActivity, FruitError> = spotlight::UserActivity::new;
+    let _: fn() -> Result<(), FruitError> = manifest::verify_resources;
+    let _: fn(&Path) -> Result<(), FruitError> = manifest::verify_dir;
+    let _: &str = manifest::MANIFEST_FILE;
+    let _: fn(&[u8]) -> Result<receipt::Receipt, FruitError> = receipt::parse;
+    let _: fn() -> Option<PathBuf> = receipt::receipt_path;
+    let _: fn() -> Result<receipt::Receipt, FruitError> = receipt::load;
+    let _: fn() -> Result<receipt::Receipt, FruitError> = receipt::validate;
+    let _: fn() -> ! = receipt::request_new_receipt;
+    let _: fn() -> receipt::Receipt = receipt::require_valid;
+    let _: fn(&str) -> Result<(), FruitError> = crash::install;
+    let _: fn(&str) = crash::breadcrumb;
+    let _: fn(&str) -> Result<Vec<u8>, FruitError> = bookmark::create_bookmark;
+    let _: fn(&[u8]) -> Result<bookmark::ScopedAccess, FruitError> = bookmark::resolve_bookmark;
+    let _: fn(&str, &str) -> Result<(), FruitError> = bookmark::save_bookmark;
+    let _: fn(&str) -> Result<Option<bookmark::ScopedAccess>, FruitError> = bookmark::load_bookmark;
+    let _: fn(&bookmark::ScopedAccess) -> bool = bookmark::ScopedAccess::is_stale;
+    let _: fn(&str) -> PathBuf = crash::reports_dir;
+    let _: fn(&str) -> Vec<PathBuf> = crash::pending_reports;
+    let _: fn() -> bool = sandbox::is_sandboxed;
+    let _: fn() -> Option<PathBuf> = sandbox::container_dir;
+    let _: fn() -> Option<PathBuf> = sandbox::home_dir;
+    let _: fn() -> Preflight = preflight;
+    let _: fn(&str) -> Result<status::StatusItem, FruitError> = status::StatusItem::new;
+    let _: fn(&status::StatusItem, &str) = status::StatusItem::set_title;
+    let _: fn(&status::StatusItem, &str) -> Result<(), FruitError> = status::StatusItem::add_quit_item;
+    let _: fn(&status::StatusItem, &str) -> Result<(), FruitError> = status::StatusItem::set_template_image;
+    let _: fn(&status::StatusItem, &str, bool) -> Result<(), FruitError> = status::StatusItem::set_image_file;
+    let _: fn(&status::StatusItem) = status::StatusItem::clear_image;
+    let _: unsafe fn(&mut status::StatusItem, *mut std::os::raw::c_void, f64, f64) -> Result<(), FruitError> = status::StatusItem::set_popover;
+    let _: fn(&mut status::StatusItem) = status::StatusItem::remove_popover;
+    let _: fn(&status::StatusItem) = status::StatusItem::show_popover;
+    let _: fn(&status::StatusItem) = status::StatusItem::hide_popover;
+    let _: fn(&status::StatusItem) -> bool = status::StatusItem::is_popover_shown;
+    let _: fn(&status::StatusItem, status::PopoverBehavior) = status::StatusItem::set_popover_behavior;
+    let _: fn() -> bool = relocate::needs_move;
+    let _: fn() -> Result<(), FruitError> = relocate::move_to_applications;
+    let _: fn(&Path, Option<&str>) -> Result<bundle::Signature, FruitError> = bundle::verify;
+    let _: fn() -> Result<Vec<signing::Identity>, FruitError> = signing::identities;
+    let _: fn(&str) -> Result<signing::Identity, FruitError> = signing::find_identity;
+    let _: fn(&Path, &notarize::Credentials) -> Result<(), FruitError> = notarize::notarize;
+    let _: fn(&signing::Identity) -> bool = signing::Identity::is_developer_id;
+    let _: fn(&Path) -> Result<(), FruitError> = update::verify_update;
+    let _: fn(&Path) -> Result<(), FruitError> = update::install_update;
+    let _: fn(&str, &Path, bool, update::DownloadCallback) -> Result<update::Download, FruitError> = update::download;
+    let _: fn(&[u8], &Path, bool, update::DownloadCallback) -> Result<update::Download, FruitError> = update::resume_download;
+    let _: fn(&update::Download) = update::Download::suspend;
+    let _: fn(&update::Download) = update::Download::resume;
+    let _: fn(&update::Download) = update::Download::cancel;
+    let _: fn(&str) -> Option<i32> = instance::running_instance;
+    let _: fn(&str) -> Result<(), FruitError> = instance::ensure_single_instance;
+    let _: fn(&str, instance::MessageCallback) -> Result<instance::Listener, FruitError> = instance::listen;
+    let _: fn(&str, &[u8]) -> Result<(), FruitError> = instance::send;
+    #[cfg(feature = "window")]
+    {
+        let _: fn(&str, u32, u32) -> Result<window::Window, FruitError> = window::Window::new;
+        let _: fn(&window::Window, &str) = window::Window::set_title;
+        let _: fn(&window::Window, &[u32], usize, usize) = window::Window::present;
+        let _: fn(&window::Window) -> bool = window::Window::is_visible;
+    }
+    #[cfg(feature = "xpc")]
+    {
+        let _: fn(&str, xpc::XpcCallback) -> Result<xpc::Connection, FruitError> = xpc::Connection::new;
+        let _: fn(&xpc::Connection, &[u8]) = xpc::Connection::send;
+        let _: fn(&xpc::Connection, &[u8]) -> Result<Vec<u8>, FruitError> = xpc::Connection::send_with_reply;
+        let _: fn(xpc::XpcHandler) -> ! = xpc::serve;
+    }
+}
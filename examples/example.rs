@@ -24,23 +24,17 @@ fn main() {
         .version("2.1.3")
         .icon("fruitbasket.icns")
         .plist_key("CFBundleSpokenName","\"fruit basket\"")
-        .plist_keys(&vec![
-            ("LSMinimumSystemVersion", "10.12.0"),
-            ("LSBackgroundOnly", "1"),
-        ])
+        .plist_key("LSMinimumSystemVersion", "10.12.0")
+        .background_only(true)
         .resource(icon.to_str().unwrap())
         .build(InstallDir::Temp) {
-            Err(FruitError::UnsupportedPlatform(_)) => {
+            Err(FruitError::UnsupportedPlatform) => {
                 info!("This is not a Mac.  App bundling is not supported.");
                 info!("It is still safe to use FruitApp::new(), though the dummy app will do nothing.");
-                FruitApp::new()
+                FruitApp::new().unwrap()
             },
-            Err(FruitError::IOError(e)) => {
-                info!("IO error! {}", e);
-                std::process::exit(1);
-            },
-            Err(FruitError::GeneralError(e)) => {
-                info!("General error! {}", e);
+            Err(e) => {
+                info!("Failed to build app bundle: {}", e);
                 std::process::exit(1);
             },
             Ok(app) => app,
@@ -48,13 +42,8 @@ fn main() {
 
     // App is guaranteed to be running in a bundle now!
 
-    // Make it a regular app in the dock.
-    // Note: Because 'LSBackgroundOnly' is set to true in the Info.plist, the
-    // app will launch backgrounded and will not take focus.  If we only did
-    // that, the app would stay in 'Prohibited' mode and would not create a dock
-    // icon.  By overriding the activation policy now, it will stay background
-    // but create the Dock and menu bar entries.  This basically implements a
-    // "pop-under" behavior.
+    // Make it a regular app in the dock.  It was built with background_only(),
+    // so this "pops under" the frontmost app instead of taking focus.
     app.set_activation_policy(ActivationPolicy::Regular);
 
     // Give it a bit of time for the launching process to quit, to prove that
@@ -77,7 +66,7 @@ fn main() {
 
     // Find the icon we stored in the bundle
     let icon = fruitbasket::FruitApp::bundled_resource_path("icon", "png");
-    info!("Bundled icon: {}", icon.unwrap_or("MISSING!".to_string()));
+    info!("Bundled icon: {}", icon.ok().flatten().unwrap_or("MISSING!".to_string()));
 
     // Cleanly terminate
     fruitbasket::FruitApp::terminate(0);
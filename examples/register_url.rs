@@ -19,6 +19,7 @@ use fruitbasket::InstallDir;
 use fruitbasket::RunPeriod;
 use fruitbasket::FruitError;
 use fruitbasket::FruitCallbackKey;
+use fruitbasket::UrlHandler;
 use std::path::PathBuf;
 
 #[macro_use]
@@ -37,30 +38,19 @@ fn main() {
         .version("2.1.3")
         .icon("fruitbasket.icns")
         .plist_key("CFBundleSpokenName","\"fruit basket\"")
-        .plist_keys(&vec![
-            ("LSMinimumSystemVersion", "10.12.0"),
-            ("LSBackgroundOnly", "1"),
-        ])
+        .plist_key("LSMinimumSystemVersion", "10.12.0")
+        .background_only(true)
         // Register "fruitbasket://" and "fbasket://" URL schemes in Info.plist
-        .plist_raw_string("
-CFBundleURLTypes = ( {
-  CFBundleTypeRole = \"Viewer\";
-  CFBundleURLName = \"Fruitbasket Example URL\";
-  CFBundleURLSchemes = (\"fruitbasket\", \"fbasket\");
-} );\n".into())
+        .url_scheme("Fruitbasket Example URL", &["fruitbasket", "fbasket"])
         .resource(icon.to_str().unwrap())
         .build(InstallDir::Temp) {
-            Err(FruitError::UnsupportedPlatform(_)) => {
+            Err(FruitError::UnsupportedPlatform) => {
                 info!("This is not a Mac.  App bundling is not supported.");
                 info!("It is still safe to use FruitApp::new(), though the dummy app will do nothing.");
-                FruitApp::new()
+                FruitApp::new().unwrap()
             },
-            Err(FruitError::IOError(e)) => {
-                info!("IO error! {}", e);
-                std::process::exit(1);
-            },
-            Err(FruitError::GeneralError(e)) => {
-                info!("General error! {}", e);
+            Err(e) => {
+                info!("Failed to build app bundle: {}", e);
                 std::process::exit(1);
             },
             Ok(app) => app,
@@ -68,39 +58,27 @@ CFBundleURLTypes = ( {
 
     // App is guaranteed to be running in a bundle now!
 
-    // Make it a regular app in the dock.
-    // Note: Because 'LSBackgroundOnly' is set to true in the Info.plist, the
-    // app will launch backgrounded and will not take focus.  If we only did
-    // that, the app would stay in 'Prohibited' mode and would not create a dock
-    // icon.  By overriding the activation policy now, it will stay background
-    // but create the Dock and menu bar entries.  This basically implements a
-    // "pop-under" behavior.
+    // Make it a regular app in the dock.  It was built with background_only(),
+    // so this "pops under" the frontmost app instead of taking focus.
     app.set_activation_policy(ActivationPolicy::Regular);
 
     // Register a callback for when the ObjC application finishes launching
     let stopper = app.stopper();
-    app.register_callback(FruitCallbackKey::Method("applicationWillFinishLaunching:"),
-                          Box::new(move |_event| {
-                              info!("applicationDidFinishLaunching.");
-                              stopper.stop();
-                          }));
+    app.register_callback_once(FruitCallbackKey::Method("applicationDidFinishLaunching:"),
+                               Box::new(move |_event| {
+                                   info!("applicationDidFinishLaunching.");
+                                   stopper.stop();
+                               }));
 
     // Run until callback is called
     info!("Spawned process started.  Run until applicationDidFinishLaunching.");
     let _ = app.run(RunPeriod::Forever);
+    info!("Launch phase: {:?}", app.launch_phase());
 
-    info!("Application launched.  Registering URL callbacks.");
-    // Register a callback to get receive custom URL schemes from any Mac program
-    app.register_apple_event(fruitbasket::kInternetEventClass, fruitbasket::kAEGetURL);
-    let stopper = app.stopper();
-    app.register_callback(FruitCallbackKey::Method("handleEvent:withReplyEvent:"),
-                          Box::new(move |event| {
-                              // Event is a raw NSAppleEventDescriptor.
-                              // Fruitbasket has a parser for URLs.  Call that to get the URL:
-                              let url: String = fruitbasket::parse_url_event(event);
-                              info!("Received URL: {}", url);
-                              stopper.stop();
-                          }));
+    info!("Application launched.  Registering URL handler.");
+    // Receive custom URL schemes from any Mac program.  URLs that arrived
+    // before this, such as one that launched the app, are delivered too.
+    let urls = UrlHandler::new(&mut app);
 
     let stopper = app.stopper();
     app.register_callback(
@@ -114,10 +92,18 @@ CFBundleURLTypes = ( {
         }),
     );
 
-    // Run 'forever', until one of the URL or file callbacks fire
+    // Run until a URL arrives or the file callback fires
     info!("Spawned process running!");
-    let _ = app.run(RunPeriod::Forever);
-    info!("Run loop stopped after URL callback.");
+    loop {
+        if app.run(RunPeriod::Time(std::time::Duration::from_millis(100))).is_err() {
+            info!("Run loop stopped after file callback.");
+            break;
+        }
+        if let Some(url) = urls.try_recv() {
+            info!("Received URL: {}", url);
+            break;
+        }
+    }
 
     // Cleanly terminate
     fruitbasket::FruitApp::terminate(0);
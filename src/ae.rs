@@ -0,0 +1,69 @@
+//! Apple event classes, IDs, and keywords
+//!
+//! Four-character codes for the Apple events most apps handle, with the same
+//! names as in Apple's headers.  Pass the class and ID constants to
+//! [FruitApp::register_apple_event](../struct.FruitApp.html#method.register_apple_event),
+//! and compare them against the result of
+//! [parse_apple_event](../fn.parse_apple_event.html).  Use the
+//! [fourcc!](../macro.fourcc.html) macro for codes not listed here.
+#![allow(non_upper_case_globals)]
+
+use FourCC;
+
+/// Core suite: events every app is sent by the system
+pub const kCoreEventClass: FourCC = fourcc!("aevt");
+/// Core suite: the app was launched without any documents
+pub const kAEOpenApplication: FourCC = fourcc!("oapp");
+/// Core suite: the already running app was launched again (ex: Dock click)
+pub const kAEReopenApplication: FourCC = fourcc!("rapp");
+/// Core suite: open the documents in the direct object
+pub const kAEOpenDocuments: FourCC = fourcc!("odoc");
+/// Core suite: print the documents in the direct object
+pub const kAEPrintDocuments: FourCC = fourcc!("pdoc");
+/// Core suite: open content, such as dragged text, in the direct object
+pub const kAEOpenContents: FourCC = fourcc!("ocon");
+/// Core suite: quit the app
+pub const kAEQuitApplication: FourCC = fourcc!("quit");
+/// Core suite: show the app's preferences
+pub const kAEShowPreferences: FourCC = fourcc!("pref");
+
+/// Internet suite: events for URL handlers
+pub const kInternetEventClass: FourCC = fourcc!("GURL");
+/// Internet suite: open the URL in the direct object
+pub const kAEGetURL: FourCC = fourcc!("GURL");
+
+/// Keyword of an event's main parameter
+pub const keyDirectObject: FourCC = fourcc!("----");
+/// Keyword of the reason given in a quit event
+pub const kAEQuitReason: FourCC = fourcc!("why?");
+/// Keyword of the properties parameter of an event
+pub const keyAEPropData: FourCC = fourcc!("prdt");
+/// Value of `keyAEPropData` in the open-application event of a login item
+pub const keyAELaunchedAsLogInItem: FourCC = fourcc!("lgit");
+/// Attribute holding the process ID of an event's sender
+pub const keySenderPIDAttr: FourCC = fourcc!("spid");
+/// Keyword of the error number in a reply event
+pub const keyErrorNumber: FourCC = fourcc!("errn");
+/// Keyword of the error message in a reply event
+pub const keyErrorString: FourCC = fourcc!("errs");
+
+/// Descriptor type: list of values
+pub const typeAEList: FourCC = fourcc!("list");
+/// Descriptor type: record of values by keyword
+pub const typeAERecord: FourCC = fourcc!("reco");
+/// Descriptor type: 32-bit signed integer
+pub const typeSInt32: FourCC = fourcc!("long");
+/// Descriptor type: 16-bit signed integer
+pub const typeSInt16: FourCC = fourcc!("shor");
+/// Descriptor type: 64-bit floating point number
+pub const typeIEEE64BitFloatingPoint: FourCC = fourcc!("doub");
+/// Descriptor type: 32-bit floating point number
+pub const typeIEEE32BitFloatingPoint: FourCC = fourcc!("sing");
+/// Descriptor type: boolean
+pub const typeBoolean: FourCC = fourcc!("bool");
+/// Descriptor type: boolean true
+pub const typeTrue: FourCC = fourcc!("true");
+/// Descriptor type: boolean false
+pub const typeFalse: FourCC = fourcc!("fals");
+/// Descriptor type: empty value
+pub const typeNull: FourCC = fourcc!("null");
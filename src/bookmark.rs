@@ -0,0 +1,236 @@
+//! Security-scoped bookmarks for keeping file access across launches
+//!
+//! A sandboxed app can only access files the user chose for it, such as with
+//! an open panel or by dropping a document on the app's icon, and only until
+//! the app quits.  To keep access to such a file, the app creates a
+//! security-scoped bookmark while it has access, saves the bookmark, and
+//! resolves it on the next launch to regain access.
+//!
+//! Access to a resolved bookmark lasts as long as the returned
+//! [ScopedAccess](ScopedAccess) guard is alive.
+//!
+//! These also work outside of the sandbox, where the security scope is simply
+//! ignored, so apps don't need separate code paths.
+
+use std::path::{Path, PathBuf};
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, BOOL, NO};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, nsstring_to_string, str_to_nsstring};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// NSURLBookmarkCreationWithSecurityScope
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const CREATION_WITH_SECURITY_SCOPE: u64 = 1 << 11;
+/// NSURLBookmarkResolutionWithSecurityScope
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const RESOLUTION_WITH_SECURITY_SCOPE: u64 = 1 << 10;
+
+/// Access to the file referenced by a resolved bookmark
+///
+/// The app can access the file while this is alive.  Access is given up when
+/// it is dropped.
+pub struct ScopedAccess {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    url: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    accessing: bool,
+    path: PathBuf,
+    stale: bool,
+}
+
+impl ScopedAccess {
+    /// Current path of the bookmarked file, which may have moved or been
+    /// renamed since the bookmark was created
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Whether the bookmark is out of date and should be created again
+    ///
+    /// The file is still accessible, but the bookmark should be replaced with
+    /// a new one from [create_bookmark](create_bookmark).
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for ScopedAccess {
+    fn drop(&mut self) {
+        unsafe {
+            if self.accessing {
+                let _:() = msg_send![self.url, stopAccessingSecurityScopedResource];
+            }
+            let _:() = msg_send![self.url, release];
+        }
+    }
+}
+
+/// Create a security-scoped bookmark for a file the app has access to
+///
+/// # Arguments
+///
+/// `path` - Full path to the file or directory
+///
+/// # Returns
+///
+/// Opaque bookmark data, which can be stored anywhere and later passed to
+/// [resolve_bookmark](resolve_bookmark)
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn create_bookmark(path: &str) -> Result<Vec<u8>, FruitError> {
+    unsafe {
+        let cls = class("NSURL")?;
+        let url: *mut Object = msg_send![cls, fileURLWithPath: str_to_nsstring(path)];
+        let mut error: *mut Object = nil;
+        let data: *mut Object = msg_send![url,
+                                          bookmarkDataWithOptions: CREATION_WITH_SECURITY_SCOPE
+                                          includingResourceValuesForKeys: nil
+                                          relativeToURL: nil
+                                          error: &mut error];
+        if data == nil {
+            return Err(bookmark_error(error));
+        }
+        let len: usize = msg_send![data, length];
+        let bytes: *const u8 = msg_send![data, bytes];
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(std::slice::from_raw_parts(bytes, len).to_vec())
+    }
+}
+
+/// Resolve a bookmark and start accessing the file it refers to
+///
+/// # Arguments
+///
+/// `bookmark` - Bookmark data from [create_bookmark](create_bookmark)
+///
+/// # Returns
+///
+/// A guard that keeps the file accessible until it is dropped
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn resolve_bookmark(bookmark: &[u8]) -> Result<ScopedAccess, FruitError> {
+    unsafe {
+        let cls = class("NSData")?;
+        let data: *mut Object = msg_send![cls, dataWithBytes: bookmark.as_ptr()
+                                          length: bookmark.len()];
+        let cls = class("NSURL")?;
+        let mut stale: BOOL = NO;
+        let mut error: *mut Object = nil;
+        let url: *mut Object = msg_send![cls,
+                                         URLByResolvingBookmarkData: data
+                                         options: RESOLUTION_WITH_SECURITY_SCOPE
+                                         relativeToURL: nil
+                                         bookmarkDataIsStale: &mut stale
+                                         error: &mut error];
+        if url == nil {
+            return Err(bookmark_error(error));
+        }
+        let url: *mut Object = msg_send![url, retain];
+        let accessing: BOOL = msg_send![url, startAccessingSecurityScopedResource];
+        let path: *mut Object = msg_send![url, path];
+        Ok(ScopedAccess {
+            url,
+            accessing: accessing != NO,
+            path: PathBuf::from(nsstring_to_string(path)),
+            stale: stale != NO,
+        })
+    }
+}
+
+/// Create a bookmark for a file and save it in the app's user defaults
+///
+/// # Arguments
+///
+/// `key` - Name to save the bookmark under
+///
+/// `path` - Full path to the file or directory
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn save_bookmark(key: &str, path: &str) -> Result<(), FruitError> {
+    let bookmark = create_bookmark(path)?;
+    unsafe {
+        let cls = class("NSData")?;
+        let data: *mut Object = msg_send![cls, dataWithBytes: bookmark.as_ptr()
+                                          length: bookmark.len()];
+        let cls = class("NSUserDefaults")?;
+        let defaults: *mut Object = msg_send![cls, standardUserDefaults];
+        let _:() = msg_send![defaults, setObject: data forKey: str_to_nsstring(key)];
+    }
+    Ok(())
+}
+
+/// Resolve a bookmark saved with [save_bookmark](save_bookmark)
+///
+/// Stale bookmarks are replaced with fresh ones automatically.
+///
+/// # Arguments
+///
+/// `key` - Name the bookmark was saved under
+///
+/// # Returns
+///
+/// A guard that keeps the file accessible until it is dropped, or `None` if
+/// no bookmark was saved under the key
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn load_bookmark(key: &str) -> Result<Option<ScopedAccess>, FruitError> {
+    let bookmark = unsafe {
+        let cls = class("NSUserDefaults")?;
+        let defaults: *mut Object = msg_send![cls, standardUserDefaults];
+        let data: *mut Object = msg_send![defaults, dataForKey: str_to_nsstring(key)];
+        if data == nil {
+            return Ok(None);
+        }
+        let len: usize = msg_send![data, length];
+        let bytes: *const u8 = msg_send![data, bytes];
+        if len == 0 {
+            return Ok(None);
+        }
+        std::slice::from_raw_parts(bytes, len).to_vec()
+    };
+    let access = resolve_bookmark(&bookmark)?;
+    if access.is_stale() {
+        if let Some(path) = access.path().to_str() {
+            save_bookmark(key, path)?;
+        }
+    }
+    Ok(Some(access))
+}
+
+/// Convert a failed bookmark operation's NSError into a FruitError
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn bookmark_error(error: *mut Object) -> FruitError {
+    if error == nil {
+        return FruitError::BookmarkError("unknown error".to_string());
+    }
+    let description: *mut Object = unsafe { msg_send![error, localizedDescription] };
+    FruitError::BookmarkError(nsstring_to_string(description))
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn create_bookmark(_path: &str) -> Result<Vec<u8>, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn resolve_bookmark(_bookmark: &[u8]) -> Result<ScopedAccess, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn save_bookmark(_key: &str, _path: &str) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn load_bookmark(_key: &str) -> Result<Option<ScopedAccess>, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
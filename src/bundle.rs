@@ -0,0 +1,219 @@
+//! Checking the code signature of other bundles
+//!
+//! [verify](verify) checks that a bundle, such as a downloaded update or a
+//! helper app about to be launched, is validly signed and hasn't been
+//! modified since, and reports who signed it.  Pass a code requirement to
+//! also check that it was signed by a particular developer:
+//!
+//! ```no_run
+//! # use fruitbasket::bundle;
+//! # use std::path::Path;
+//! let sig = bundle::verify(Path::new("/Applications/Helper.app"),
+//!                          Some("anchor apple generic and certificate leaf[subject.OU] = \"ABCDE12345\""))
+//!     .unwrap();
+//! if sig.valid && sig.satisfies_requirement == Some(true) {
+//!     // Safe to launch
+//! }
+//! ```
+
+use std::path::Path;
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{nsstring_to_string, str_to_nsstring};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::c_void;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// kSecCSCheckAllArchitectures | kSecCSCheckNestedCode | kSecCSStrictValidate
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const VALIDITY_FLAGS: u32 = 0x1 | 0x8 | 0x10;
+/// kSecCSSigningInformation
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const SIGNING_INFORMATION: u32 = 0x2;
+/// errSecCSUnsigned
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ERR_UNSIGNED: i32 = -67062;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "Security", kind = "framework")]
+extern "C" {
+    static kSecCodeInfoIdentifier: *mut Object;
+    static kSecCodeInfoTeamIdentifier: *mut Object;
+    fn SecStaticCodeCreateWithPath(path: *mut Object, flags: u32,
+                                   code: *mut *mut c_void) -> i32;
+    fn SecStaticCodeCheckValidity(code: *mut c_void, flags: u32,
+                                  requirement: *mut c_void) -> i32;
+    fn SecRequirementCreateWithString(text: *mut Object, flags: u32,
+                                      requirement: *mut *mut c_void) -> i32;
+    fn SecCodeCopySigningInformation(code: *mut c_void, flags: u32,
+                                     information: *mut *mut Object) -> i32;
+    fn SecCodeCopyDesignatedRequirement(code: *mut c_void, flags: u32,
+                                        requirement: *mut *mut c_void) -> i32;
+    fn SecRequirementCopyString(requirement: *mut c_void, flags: u32,
+                                text: *mut *mut Object) -> i32;
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFRelease(cf: *mut Object);
+}
+
+/// Code signature of a bundle, as reported by [verify](verify)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    /// Whether the bundle is signed at all
+    pub signed: bool,
+    /// Whether the bundle is signed, and every file in it, including nested
+    /// code, still matches its signature
+    pub valid: bool,
+    /// Signing identifier, usually the bundle identifier
+    pub identifier: Option<String>,
+    /// Team identifier of the developer certificate that signed the bundle.
+    /// None for ad-hoc signatures and Apple's own code.
+    pub team_identifier: Option<String>,
+    /// Whether the bundle satisfies the requirement passed to
+    /// [verify](verify), or None if no requirement was given.  Always false
+    /// for bundles that aren't valid.
+    pub satisfies_requirement: Option<bool>,
+}
+
+/// Check the code signature of a bundle
+///
+/// Validates the bundle's signature with Code Signing Services, the same
+/// way Gatekeeper and `codesign --verify --deep --strict` do: the code for
+/// every architecture, any nested code such as frameworks and helpers, and
+/// every resource must match the signature.
+///
+/// # Arguments
+///
+/// `path` - Path to a bundle, or to a single executable
+///
+/// `requirement` - Optional code requirement in the language used by
+/// `codesign -r` (ex: `identifier "com.example.helper" and anchor apple
+/// generic`), checked against the bundle
+///
+/// # Returns
+///
+/// The signature details.  Unsigned or modified bundles are reported
+/// through the `Signature` fields, not as errors.
+/// `FruitError::IOError` if `path` doesn't exist, or
+/// `FruitError::SignError` with the OSStatus if the requirement doesn't
+/// compile or the bundle can't be examined.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn verify(path: &Path, requirement: Option<&str>) -> Result<Signature, FruitError> {
+    std::fs::metadata(path)?;
+    unsafe {
+        let mut code: *mut c_void = std::ptr::null_mut();
+        let status = SecStaticCodeCreateWithPath(::foundation::path_to_nsurl(path), 0, &mut code);
+        if status != 0 {
+            return Err(code_sign_error(status));
+        }
+        let result = check_code(code, requirement);
+        CFRelease(code as *mut Object);
+        result
+    }
+}
+
+/// Check a static code object's validity and read its signing information
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn check_code(code: *mut c_void, requirement: Option<&str>) -> Result<Signature, FruitError> {
+    let mut req: *mut c_void = std::ptr::null_mut();
+    if let Some(text) = requirement {
+        let status = SecRequirementCreateWithString(str_to_nsstring(text), 0, &mut req);
+        if status != 0 {
+            return Err(code_sign_error(status));
+        }
+    }
+    let status = SecStaticCodeCheckValidity(code, VALIDITY_FLAGS, std::ptr::null_mut());
+    let valid = status == 0;
+    if !valid {
+        info!("Code signature check failed (OSStatus {})", status);
+    }
+    let satisfies_requirement = if req.is_null() {
+        None
+    } else {
+        let req_status = if valid {
+            SecStaticCodeCheckValidity(code, VALIDITY_FLAGS, req)
+        } else {
+            status
+        };
+        CFRelease(req as *mut Object);
+        if valid && req_status != 0 {
+            info!("Code does not satisfy requirement (OSStatus {})", req_status);
+        }
+        Some(req_status == 0)
+    };
+    let mut sig = Signature {
+        signed: status != ERR_UNSIGNED,
+        valid: valid,
+        identifier: None,
+        team_identifier: None,
+        satisfies_requirement: satisfies_requirement,
+    };
+    let mut info: *mut Object = nil;
+    if sig.signed && SecCodeCopySigningInformation(code, SIGNING_INFORMATION, &mut info) == 0
+        && info != nil {
+        sig.identifier = dictionary_string(info, kSecCodeInfoIdentifier);
+        sig.team_identifier = dictionary_string(info, kSecCodeInfoTeamIdentifier);
+        let _:() = msg_send![info, release];
+    }
+    Ok(sig)
+}
+
+/// Error for an OSStatus returned by Code Signing Services
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn code_sign_error(os_status: i32) -> FruitError {
+    FruitError::SignError(format!("Code Signing Services error (OSStatus {})", os_status))
+}
+
+/// Designated requirement of a signed bundle, in the requirement language,
+/// or None if it isn't signed
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn designated_requirement(path: &Path) -> Option<String> {
+    unsafe {
+        let mut code: *mut c_void = std::ptr::null_mut();
+        if SecStaticCodeCreateWithPath(::foundation::path_to_nsurl(path), 0, &mut code) != 0 {
+            return None;
+        }
+        let mut req: *mut c_void = std::ptr::null_mut();
+        let status = SecCodeCopyDesignatedRequirement(code, 0, &mut req);
+        CFRelease(code as *mut Object);
+        if status != 0 || req.is_null() {
+            return None;
+        }
+        let mut text: *mut Object = nil;
+        let status = SecRequirementCopyString(req, 0, &mut text);
+        CFRelease(req as *mut Object);
+        if status != 0 || text == nil {
+            return None;
+        }
+        let requirement = nsstring_to_string(text);
+        CFRelease(text);
+        Some(requirement)
+    }
+}
+
+/// String value for a key in a dictionary, if present
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn dictionary_string(dict: *mut Object, key: *mut Object) -> Option<String> {
+    let value: *mut Object = msg_send![dict, objectForKey: key];
+    if value == nil {
+        None
+    } else {
+        Some(nsstring_to_string(value))
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn verify(_path: &Path, _requirement: Option<&str>) -> Result<Signature, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
@@ -0,0 +1,299 @@
+//! Crash reports for bundled apps
+//!
+//! Once [install](install) is called, fatal signals (segmentation faults, bus
+//! errors, illegal instructions, aborts, etc) write a plain-text crash report
+//! with a backtrace into `~/Library/Logs/<ident>/Crashes/` before the process
+//! dies.  Handlers run on an alternate signal stack, so stack overflows are
+//! reported as well.
+//!
+//! No Mach exception handler is installed.  On macOS, hardware exceptions
+//! that no Mach exception handler claims are delivered as the signals above,
+//! so they are still reported, but crashes that a debugger or another Mach
+//! handler intercepts first are not, and reports don't include the Mach
+//! exception type and code.
+//!
+//! The next time the app starts, [pending_reports](pending_reports) lists the
+//! reports left behind, so the app can offer to send them.  Delete a report
+//! once it has been dealt with.
+//!
+//! Call [breadcrumb](breadcrumb) to record what the app was doing.  The most
+//! recent breadcrumbs are included in crash reports, to help make sense of
+//! crashes that can't be reproduced.
+//!
+//! Rust panics are not crashes in this sense; see
+//! [install_panic_handler](../fn.install_panic_handler.html) for those.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+#[cfg(unix)]
+use std::ffi::CString;
+#[cfg(unix)]
+use std::sync::OnceLock;
+#[cfg(unix)]
+use std::os::raw::{c_int, c_void};
+
+use FruitError;
+
+/// File extension of crash reports
+pub const REPORT_EXTENSION: &str = "crash";
+
+/// Directory that crash reports for the given app are written to
+///
+/// # Arguments
+///
+/// `ident` - Bundle identifier of the app (ex: `com.company.AppName`)
+pub fn reports_dir(ident: &str) -> PathBuf {
+    ::sandbox::home_dir().unwrap_or_default()
+        .join("Library/Logs")
+        .join(ident)
+        .join("Crashes")
+}
+
+/// List crash reports written by previous runs of the app, oldest first
+///
+/// # Arguments
+///
+/// `ident` - Bundle identifier of the app, as given to `install()`
+pub fn pending_reports(ident: &str) -> Vec<PathBuf> {
+    let mut reports: Vec<PathBuf> = match std::fs::read_dir(reports_dir(ident)) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == REPORT_EXTENSION))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    reports.sort();
+    reports
+}
+
+/// Number of breadcrumbs kept for crash reports
+pub const MAX_BREADCRUMBS: usize = 32;
+
+/// Maximum length of a breadcrumb, in bytes.  Longer messages are truncated.
+pub const MAX_BREADCRUMB_LEN: usize = 120;
+
+/// One entry in the breadcrumb ring buffer
+///
+/// Made entirely of atomics so the crash handler can read it without locks.
+struct Breadcrumb {
+    len: AtomicUsize,
+    bytes: [AtomicU8; MAX_BREADCRUMB_LEN],
+}
+
+static BREADCRUMBS: [Breadcrumb; MAX_BREADCRUMBS] = [const {
+    Breadcrumb {
+        len: AtomicUsize::new(0),
+        bytes: [const { AtomicU8::new(0) }; MAX_BREADCRUMB_LEN],
+    }
+}; MAX_BREADCRUMBS];
+
+/// Total number of breadcrumbs ever recorded
+static BREADCRUMB_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Record a short message describing what the app is doing
+///
+/// Breadcrumbs are kept in a small ring buffer in memory, and the most recent
+/// [MAX_BREADCRUMBS](MAX_BREADCRUMBS) of them are written into the crash
+/// report if the app crashes.  They are cheap enough to record frequently,
+/// such as on every user action or network request.
+///
+/// Can be called from any thread.  If many threads record breadcrumbs at the
+/// same moment, an entry may occasionally be garbled.
+///
+/// # Arguments
+///
+/// `msg` - Message to record, truncated to [MAX_BREADCRUMB_LEN](MAX_BREADCRUMB_LEN) bytes
+pub fn breadcrumb(msg: &str) {
+    let idx = BREADCRUMB_COUNT.fetch_add(1, Ordering::Relaxed) % MAX_BREADCRUMBS;
+    let slot = &BREADCRUMBS[idx];
+    let mut len = std::cmp::min(msg.len(), MAX_BREADCRUMB_LEN);
+    while !msg.is_char_boundary(len) {
+        len -= 1;
+    }
+    slot.len.store(0, Ordering::Release);
+    for (dst, &src) in slot.bytes.iter().zip(&msg.as_bytes()[..len]) {
+        dst.store(src, Ordering::Relaxed);
+    }
+    slot.len.store(len, Ordering::Release);
+}
+
+/// Signals that are reported as crashes
+#[cfg(unix)]
+const CRASH_SIGNALS: &[c_int] = &[
+    libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE, libc::SIGABRT, libc::SIGTRAP,
+];
+
+/// Maximum number of stack frames recorded in a report
+#[cfg(unix)]
+const MAX_FRAMES: usize = 128;
+
+/// Size of the alternate stack the crash handler runs on
+#[cfg(unix)]
+const ALT_STACK_SIZE: usize = 64 * 1024;
+
+/// Path and pre-formatted header of this run's crash report
+///
+/// Prepared ahead of time, since the signal handler must not allocate.
+#[cfg(unix)]
+struct Report {
+    path: CString,
+    header: Vec<u8>,
+}
+
+#[cfg(unix)]
+static REPORT: OnceLock<Report> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" {
+    fn backtrace(buffer: *mut *mut c_void, size: c_int) -> c_int;
+    fn backtrace_symbols_fd(buffer: *const *mut c_void, size: c_int, fd: c_int);
+}
+
+/// Install handlers that write a crash report when the app crashes
+///
+/// Only the first call has any effect.
+///
+/// Gives the calling thread an alternate signal stack, if it doesn't have
+/// one, so a stack overflow on it can still be reported.  Threads started
+/// by `std::thread` already have one.
+///
+/// # Arguments
+///
+/// `ident` - Bundle identifier of the app (ex: `com.company.AppName`), used
+/// to name the directory reports are written to
+///
+/// # Returns
+///
+/// Error if the reports directory could not be created
+#[cfg(unix)]
+pub fn install(ident: &str) -> Result<(), FruitError> {
+    let dir = reports_dir(ident);
+    std::fs::create_dir_all(&dir)?;
+    let pid = std::process::id();
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = dir.join(format!("{}-{}.{}", timestamp, pid, REPORT_EXTENSION));
+    let exe = std::env::current_exe().unwrap_or_default();
+    let header = format!("Identifier: {}\nProcess: {} [{}]\nTimestamp: {}\n",
+                         ident, exe.display(), pid, timestamp);
+    let path = CString::new(path.to_string_lossy().into_owned())
+        .map_err(|e| FruitError::IOError(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)))?;
+    if REPORT.set(Report { path, header: header.into_bytes() }).is_err() {
+        return Ok(());
+    }
+    unsafe {
+        install_alt_stack();
+        for &sig in CRASH_SIGNALS {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_crash as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_ONSTACK | libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(sig, &action, std::ptr::null_mut());
+        }
+    }
+    Ok(())
+}
+
+/// Give the calling thread an alternate signal stack, unless it has one
+///
+/// The stack is never freed, since a signal can arrive at any time.
+#[cfg(unix)]
+unsafe fn install_alt_stack() {
+    let mut current: libc::stack_t = std::mem::zeroed();
+    if libc::sigaltstack(std::ptr::null(), &mut current) == 0
+        && current.ss_flags & libc::SS_DISABLE == 0 {
+        return;
+    }
+    let size = std::cmp::max(ALT_STACK_SIZE, libc::SIGSTKSZ);
+    let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+    let alt = libc::stack_t {
+        ss_sp: stack.as_mut_ptr() as *mut c_void,
+        ss_flags: 0,
+        ss_size: size,
+    };
+    libc::sigaltstack(&alt, std::ptr::null_mut());
+}
+
+/// Write the crash report, then let the signal kill the process as usual
+///
+/// Runs in a signal handler, so only async-signal-safe calls are allowed.
+/// The handler is reset to the default on entry (`SA_RESETHAND`), so the
+/// re-raised signal is delivered with its default action once this returns.
+#[cfg(unix)]
+extern "C" fn handle_crash(sig: c_int) {
+    if let Some(report) = REPORT.get() {
+        unsafe {
+            let fd = libc::open(report.path.as_ptr(),
+                                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+                                0o644 as libc::c_uint);
+            if fd >= 0 {
+                write_all(fd, &report.header);
+                write_all(fd, b"Signal: ");
+                write_all(fd, signal_name(sig).as_bytes());
+                write_all(fd, b"\n\nBreadcrumbs:\n");
+                write_breadcrumbs(fd);
+                write_all(fd, b"\nBacktrace:\n");
+                let mut frames = [std::ptr::null_mut::<c_void>(); MAX_FRAMES];
+                let count = backtrace(frames.as_mut_ptr(), MAX_FRAMES as c_int);
+                backtrace_symbols_fd(frames.as_ptr(), count, fd);
+                libc::close(fd);
+            }
+        }
+    }
+    unsafe {
+        libc::raise(sig);
+    }
+}
+
+/// Write a whole buffer to a file descriptor, from a signal handler
+#[cfg(unix)]
+unsafe fn write_all(fd: c_int, mut buf: &[u8]) {
+    while !buf.is_empty() {
+        let written = libc::write(fd, buf.as_ptr() as *const c_void, buf.len());
+        if written <= 0 {
+            return;
+        }
+        buf = &buf[written as usize..];
+    }
+}
+
+/// Write the recorded breadcrumbs, oldest first, from a signal handler
+#[cfg(unix)]
+unsafe fn write_breadcrumbs(fd: c_int) {
+    let count = BREADCRUMB_COUNT.load(Ordering::Acquire);
+    let first = count.saturating_sub(MAX_BREADCRUMBS);
+    for idx in first..count {
+        let slot = &BREADCRUMBS[idx % MAX_BREADCRUMBS];
+        let len = std::cmp::min(slot.len.load(Ordering::Acquire), MAX_BREADCRUMB_LEN);
+        let mut buf = [0u8; MAX_BREADCRUMB_LEN];
+        for (dst, src) in buf.iter_mut().zip(&slot.bytes[..len]) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+        write_all(fd, b"  ");
+        write_all(fd, &buf[..len]);
+        write_all(fd, b"\n");
+    }
+}
+
+/// Name of a crash signal, without allocating
+#[cfg(unix)]
+fn signal_name(sig: c_int) -> &'static str {
+    match sig {
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGILL => "SIGILL",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGTRAP => "SIGTRAP",
+        _ => "unknown",
+    }
+}
+
+#[cfg(not(unix))]
+/// Docs in OS X build.
+pub fn install(_ident: &str) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
@@ -0,0 +1,277 @@
+//! Standard dialogs for choosing files
+//!
+//! [OpenPanel](OpenPanel) shows the system's open panel, for picking files
+//! or directories to open, limited to the types the app can handle:
+//!
+//! ```no_run
+//! # use fruitbasket::dialog::OpenPanel;
+//! let chosen = OpenPanel::new()
+//!     .message("Choose images to import")
+//!     .types(&["public.image", "pdf"])
+//!     .allow_multiple(true)
+//!     .run();
+//! ```
+//!
+//! A sandboxed app can access the files the user chooses until it quits.
+//! Ask for [bookmarks](OpenPanel::bookmarks) to keep access across launches,
+//! and restore it later with
+//! [resolve_bookmark](../bookmark/fn.resolve_bookmark.html).
+
+use std::path::{Path, PathBuf};
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, BOOL, NO, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, is_main_thread, str_to_nsstring};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// NSModalResponseOK
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const MODAL_RESPONSE_OK: i64 = 1;
+
+/// A file or directory chosen in an [OpenPanel](OpenPanel)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// Absolute path of the file or directory
+    pub path: PathBuf,
+    /// Security-scoped bookmark for the path, if requested with
+    /// [bookmarks](OpenPanel::bookmarks)
+    pub bookmark: Option<Vec<u8>>,
+}
+
+/// Builder for a dialog that asks the user to choose files to open
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenPanel {
+    title: Option<String>,
+    message: Option<String>,
+    prompt: Option<String>,
+    types: Vec<String>,
+    directory: Option<PathBuf>,
+    files: bool,
+    directories: bool,
+    multiple: bool,
+    create_directories: bool,
+    bookmarks: bool,
+}
+
+impl Default for OpenPanel {
+    fn default() -> OpenPanel {
+        OpenPanel {
+            title: None,
+            message: None,
+            prompt: None,
+            types: Vec::new(),
+            directory: None,
+            files: true,
+            directories: false,
+            multiple: false,
+            create_directories: false,
+            bookmarks: false,
+        }
+    }
+}
+
+impl OpenPanel {
+    /// Creates a panel that chooses a single file of any type
+    pub fn new() -> OpenPanel {
+        Default::default()
+    }
+
+    /// Sets the panel's title
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    /// Sets the text shown at the top of the panel, explaining what to choose
+    pub fn message(&mut self, message: &str) -> &mut Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Sets the label of the button that accepts the selection (default:
+    /// "Open")
+    pub fn prompt(&mut self, prompt: &str) -> &mut Self {
+        self.prompt = Some(prompt.to_string());
+        self
+    }
+
+    /// Limits the files that can be chosen to the given types
+    ///
+    /// Each type is either a uniform type identifier (ex: `public.image`,
+    /// `com.adobe.pdf`), which also allows every type that conforms to it,
+    /// or a filename extension without the dot (ex: `png`).  Files matching
+    /// any of them can be chosen, and other files are shown dimmed.  Any file
+    /// can be chosen if no types are given.
+    pub fn types(&mut self, types: &[&str]) -> &mut Self {
+        self.types = types.iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    /// Sets the directory the panel starts in
+    ///
+    /// Defaults to the last directory the user chose files from.
+    pub fn directory(&mut self, dir: &Path) -> &mut Self {
+        self.directory = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Sets whether files can be chosen (default: true)
+    pub fn choose_files(&mut self, enable: bool) -> &mut Self {
+        self.files = enable;
+        self
+    }
+
+    /// Sets whether directories can be chosen (default: false)
+    ///
+    /// Directories can always be opened to browse them.  This allows
+    /// choosing the directory itself, such as for an app that works on a
+    /// project folder.
+    pub fn choose_directories(&mut self, enable: bool) -> &mut Self {
+        self.directories = enable;
+        self
+    }
+
+    /// Sets whether more than one item can be chosen (default: false)
+    pub fn allow_multiple(&mut self, enable: bool) -> &mut Self {
+        self.multiple = enable;
+        self
+    }
+
+    /// Sets whether the panel has a button to create a new directory
+    /// (default: false)
+    pub fn can_create_directories(&mut self, enable: bool) -> &mut Self {
+        self.create_directories = enable;
+        self
+    }
+
+    /// Sets whether to create a security-scoped bookmark for each chosen item
+    /// (default: false)
+    ///
+    /// The bookmarks are returned in each [Selection](Selection), and can be
+    /// saved and passed to
+    /// [resolve_bookmark](../bookmark/fn.resolve_bookmark.html) on a later
+    /// launch to access the items again without asking the user.  Only
+    /// needed by sandboxed apps.
+    pub fn bookmarks(&mut self, enable: bool) -> &mut Self {
+        self.bookmarks = enable;
+        self
+    }
+
+    /// Show the panel and wait for the user to choose
+    ///
+    /// Runs the panel as a modal window, which processes events until the
+    /// user chooses or cancels.  The app is activated first, so the panel
+    /// appears in front even for agent apps.  Must be called on the main
+    /// thread, after the `FruitApp` is created.
+    ///
+    /// # Returns
+    ///
+    /// The chosen items, or an empty list if the user cancelled.
+    /// `FruitError::MainThreadViolation` if not called on the main thread,
+    /// or `FruitError::BookmarkError` if a requested bookmark could not be
+    /// created.
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    pub fn run(&self) -> Result<Vec<Selection>, FruitError> {
+        if !is_main_thread() {
+            return Err(FruitError::MainThreadViolation);
+        }
+        let panel_cls = class("NSOpenPanel")?;
+        let app_cls = class("NSApplication")?;
+        let urls = unsafe {
+            let panel: *mut Object = msg_send![panel_cls, openPanel];
+            if let Some(ref title) = self.title {
+                let _:() = msg_send![panel, setTitle: str_to_nsstring(title)];
+            }
+            if let Some(ref message) = self.message {
+                let _:() = msg_send![panel, setMessage: str_to_nsstring(message)];
+            }
+            if let Some(ref prompt) = self.prompt {
+                let _:() = msg_send![panel, setPrompt: str_to_nsstring(prompt)];
+            }
+            if let Some(ref dir) = self.directory {
+                let _:() = msg_send![panel, setDirectoryURL: ::foundation::path_to_nsurl(dir)];
+            }
+            let _:() = msg_send![panel, setCanChooseFiles: to_bool(self.files)];
+            let _:() = msg_send![panel, setCanChooseDirectories: to_bool(self.directories)];
+            let _:() = msg_send![panel, setAllowsMultipleSelection: to_bool(self.multiple)];
+            let _:() = msg_send![panel, setCanCreateDirectories: to_bool(self.create_directories)];
+            if !self.types.is_empty() {
+                set_allowed_types(panel, &self.types)?;
+            }
+
+            let app: *mut Object = msg_send![app_cls, sharedApplication];
+            let _:() = msg_send![app, activateIgnoringOtherApps: YES];
+            let response: i64 = msg_send![panel, runModal];
+            if response != MODAL_RESPONSE_OK {
+                return Ok(Vec::new());
+            }
+            let urls: *mut Object = msg_send![panel, URLs];
+            ::foundation::nsarray_to_vec(urls)
+        };
+        urls.into_iter()
+            .filter_map(::foundation::nsurl_to_path)
+            .map(|path| {
+                let bookmark = if self.bookmarks {
+                    Some(::bookmark::create_bookmark(&path.to_string_lossy())?)
+                } else {
+                    None
+                };
+                Ok(Selection { path, bookmark })
+            })
+            .collect()
+    }
+
+    /// Docs in OS X build.
+    #[cfg(any(not(target_os = "macos"), feature="dummy"))]
+    pub fn run(&self) -> Result<Vec<Selection>, FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+}
+
+/// Convert to an ObjC BOOL
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn to_bool(value: bool) -> BOOL {
+    if value { YES } else { NO }
+}
+
+/// Limit an open panel to the given identifiers and extensions
+///
+/// Uses UTType objects on macOS 11 and later, and falls back to the older
+/// list of type strings, which accepts both kinds as well.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn set_allowed_types(panel: *mut Object, types: &[String]) -> Result<(), FruitError> {
+    let array_cls = class("NSMutableArray")?;
+    let array: *mut Object = msg_send![array_cls, array];
+    let ut_type = class("UTType").ok();
+    let responds: BOOL = msg_send![panel, respondsToSelector: sel!(setAllowedContentTypes:)];
+    match ut_type {
+        Some(ut_type) if responds == YES => {
+            for ty in types {
+                let name = str_to_nsstring(ty);
+                // Identifiers are reverse-DNS, extensions have no dots
+                let obj: *mut Object = if ty.contains('.') {
+                    msg_send![ut_type, typeWithIdentifier: name]
+                } else {
+                    msg_send![ut_type, typeWithFilenameExtension: name]
+                };
+                if obj != nil {
+                    let _:() = msg_send![array, addObject: obj];
+                }
+            }
+            let _:() = msg_send![panel, setAllowedContentTypes: array];
+        },
+        _ => {
+            for ty in types {
+                let _:() = msg_send![array, addObject: str_to_nsstring(ty)];
+            }
+            let _:() = msg_send![panel, setAllowedFileTypes: array];
+        },
+    }
+    Ok(())
+}
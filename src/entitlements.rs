@@ -0,0 +1,177 @@
+//! Entitlements for code signing an app bundle
+//!
+//! Entitlements grant a signed app capabilities, or exceptions to the
+//! restrictions of the hardened runtime that notarized apps must enable.
+//! They are embedded in the app's code signature, so they take effect only
+//! when the bundle is signed with them.
+//!
+//! fruitbasket doesn't sign bundles itself.  Build an [Entitlements](Entitlements)
+//! list, write it next to the bundle, and pass it to `codesign` from a
+//! [post_build](../struct.Trampoline.html#method.post_build) hook:
+//!
+//! ```no_run
+//! # use fruitbasket::*;
+//! # use fruitbasket::entitlements::Entitlements;
+//! let mut entitlements = Entitlements::new();
+//! entitlements.allow_jit(true);
+//! let _ = Trampoline::new("myapp", "myapp", "com.example.myapp")
+//!     .post_build(move |bundle| {
+//!         let path = bundle.with_extension("entitlements");
+//!         entitlements.write(&path)?;
+//!         let status = std::process::Command::new("/usr/bin/codesign")
+//!             .args(&["--force", "--options", "runtime", "--sign", "-", "--entitlements"])
+//!             .arg(&path).arg(bundle)
+//!             .status()?;
+//!         if !status.success() {
+//!             return Err(FruitError::SignError(format!("codesign exited with {}", status)));
+//!         }
+//!         Ok(())
+//!     })
+//!     .build(InstallDir::Temp);
+//! ```
+
+use std::io::Write;
+use std::path::Path;
+
+use FruitError;
+
+/// Value of a single entitlement
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bool(bool),
+    Strings(Vec<String>),
+}
+
+/// A list of entitlements, written as an XML property list
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entitlements {
+    entries: Vec<(String, Value)>,
+}
+
+impl Entitlements {
+    /// Creates an empty entitlements list
+    pub fn new() -> Entitlements {
+        Default::default()
+    }
+
+    /// Allow loading frameworks and plugins signed by other developers
+    ///
+    /// Sets `com.apple.security.cs.disable-library-validation`.  Required by
+    /// hardened apps that load third-party plugins or unsigned libraries.
+    pub fn disable_library_validation(&mut self, enable: bool) -> &mut Self {
+        self.set_bool("com.apple.security.cs.disable-library-validation", enable)
+    }
+
+    /// Allow creating writable and executable memory for JIT compilation
+    ///
+    /// Sets `com.apple.security.cs.allow-jit`.  Required by hardened apps
+    /// embedding a JIT runtime, such as wasmtime or a JavaScript engine, that
+    /// maps memory with `MAP_JIT`.
+    pub fn allow_jit(&mut self, enable: bool) -> &mut Self {
+        self.set_bool("com.apple.security.cs.allow-jit", enable)
+    }
+
+    /// Allow writable and executable memory without `MAP_JIT`
+    ///
+    /// Sets `com.apple.security.cs.allow-unsigned-executable-memory`, a
+    /// broader exception than [allow_jit](Entitlements::allow_jit) for
+    /// runtimes that can't use `MAP_JIT`.  Prefer `allow_jit` when possible.
+    pub fn allow_unsigned_executable_memory(&mut self, enable: bool) -> &mut Self {
+        self.set_bool("com.apple.security.cs.allow-unsigned-executable-memory", enable)
+    }
+
+    /// Allow syncing documents through the given iCloud containers
+    ///
+    /// Sets `com.apple.developer.icloud-container-identifiers` and
+    /// `com.apple.developer.ubiquity-container-identifiers` to the containers,
+    /// and enables the `CloudDocuments` service in
+    /// `com.apple.developer.icloud-services`.  Container identifiers usually
+    /// take the form `iCloud.<bundle identifier>`, and must be registered
+    /// for the app's team in the Apple Developer portal.  The first one is
+    /// the app's default container, as found by
+    /// [ubiquity_container_url](../icloud/fn.ubiquity_container_url.html).
+    ///
+    /// iCloud entitlements are restricted, so the bundle must be signed with
+    /// a provisioning profile that grants them.
+    pub fn icloud_containers(&mut self, containers: &[&str]) -> &mut Self {
+        self.set_strings("com.apple.developer.icloud-container-identifiers", containers);
+        self.set_strings("com.apple.developer.ubiquity-container-identifiers", containers);
+        self.set_strings("com.apple.developer.icloud-services", &["CloudDocuments"])
+    }
+
+    /// Associate the app with domains, such as for universal links
+    ///
+    /// Sets `com.apple.developer.associated-domains`.  Each entry is a
+    /// service and domain: `applinks:example.com` opens https links to
+    /// `example.com` in the app instead of the browser, and `webcredentials:`
+    /// shares saved passwords with the web site.  The domain must serve an
+    /// `apple-app-site-association` file naming the app.  Receive the links
+    /// with a [UrlHandler](../struct.UrlHandler.html).
+    ///
+    /// This entitlement is restricted, so the bundle must be signed with a
+    /// provisioning profile that grants it.
+    pub fn associated_domains(&mut self, domains: &[&str]) -> &mut Self {
+        self.set_strings("com.apple.developer.associated-domains", domains)
+    }
+
+    /// Set a boolean entitlement by its full key
+    ///
+    /// For entitlements without a typed setter.  Replaces any earlier value
+    /// of the same key.
+    pub fn set_bool(&mut self, key: &str, value: bool) -> &mut Self {
+        self.set(key, Value::Bool(value))
+    }
+
+    /// Set a string array entitlement by its full key
+    ///
+    /// For entitlements without a typed setter.  Replaces any earlier value
+    /// of the same key.
+    pub fn set_strings(&mut self, key: &str, values: &[&str]) -> &mut Self {
+        self.set(key, Value::Strings(values.iter().map(|v| v.to_string()).collect()))
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> &mut Self {
+        match self.entries.iter_mut().find(|entry| entry.0 == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+        self
+    }
+
+    /// The entitlements as an XML property list, as `codesign` expects
+    pub fn to_plist(&self) -> String {
+        let mut plist = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n<dict>\n");
+        for (key, value) in &self.entries {
+            plist.push_str(&format!("  <key>{}</key>\n", escape(key)));
+            match *value {
+                Value::Bool(true) => plist.push_str("  <true/>\n"),
+                Value::Bool(false) => plist.push_str("  <false/>\n"),
+                Value::Strings(ref values) => {
+                    plist.push_str("  <array>\n");
+                    for value in values {
+                        plist.push_str(&format!("    <string>{}</string>\n", escape(value)));
+                    }
+                    plist.push_str("  </array>\n");
+                },
+            }
+        }
+        plist.push_str("</dict>\n</plist>\n");
+        plist
+    }
+
+    /// Write the entitlements property list to a file
+    pub fn write(&self, path: &Path) -> Result<(), FruitError> {
+        let mut f = std::fs::File::create(path)?;
+        f.write_all(self.to_plist().as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Escape text for an XML property list or HTML document
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
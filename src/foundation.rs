@@ -0,0 +1,142 @@
+//! Conversions between Foundation objects and Rust types
+//!
+//! Callbacks receive their payloads as raw ObjC objects: NSNotifications,
+//! NSArrays of NSURLs, Apple event descriptors, and so on.  These functions
+//! convert the common Foundation types to and from their Rust equivalents, so
+//! handlers don't each need their own unsafe message sends.
+//!
+//! All of them accept nil, and return an empty value for it.  Objects
+//! returned to Rust are not retained, and are only valid as long as the
+//! container they came from.  Objects created from Rust are autoreleased.
+//!
+//! On other platforms the conversions are stand-ins that return empty values,
+//! except `nsstring_to_string()`, which reads the payloads of events
+//! simulated by the dummy `FruitApp`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, BOOL, NO};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::str_to_nsstring;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::class;
+
+pub use nsstring_to_string;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// Convert a Rust string into an autoreleased NSString
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn string_to_nsstring(s: &str) -> *mut Object {
+    str_to_nsstring(s)
+}
+
+/// Convert an NSArray into a `Vec` of its elements
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn nsarray_to_vec(array: *mut Object) -> Vec<*mut Object> {
+    if array == nil {
+        return Vec::new();
+    }
+    unsafe {
+        let count: usize = msg_send![array, count];
+        (0..count).map(|idx| msg_send![array, objectAtIndex: idx]).collect()
+    }
+}
+
+/// Convert an NSArray of NSStrings into a `Vec` of `String`s
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn nsarray_to_strings(array: *mut Object) -> Vec<String> {
+    nsarray_to_vec(array).into_iter().map(nsstring_to_string).collect()
+}
+
+/// Convert an NSDictionary into a `HashMap` keyed by string
+///
+/// Keys that aren't NSStrings are converted with their `description`.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn nsdict_to_hashmap(dict: *mut Object) -> HashMap<String, *mut Object> {
+    if dict == nil {
+        return HashMap::new();
+    }
+    unsafe {
+        let keys: *mut Object = msg_send![dict, allKeys];
+        nsarray_to_vec(keys).into_iter().map(|key| {
+            let value: *mut Object = msg_send![dict, objectForKey: key];
+            let key: *mut Object = msg_send![key, description];
+            (nsstring_to_string(key), value)
+        }).collect()
+    }
+}
+
+/// Convert a file NSURL into a path
+///
+/// Returns None for nil, and for URLs that don't refer to a local file.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn nsurl_to_path(url: *mut Object) -> Option<PathBuf> {
+    if url == nil {
+        return None;
+    }
+    unsafe {
+        let is_file: BOOL = msg_send![url, isFileURL];
+        if is_file == NO {
+            return None;
+        }
+        let path: *mut Object = msg_send![url, path];
+        Some(PathBuf::from(nsstring_to_string(path)))
+    }
+}
+
+/// Convert an NSURL into its absolute URL string
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn nsurl_to_string(url: *mut Object) -> String {
+    if url == nil {
+        return "".into();
+    }
+    unsafe {
+        let string: *mut Object = msg_send![url, absoluteString];
+        nsstring_to_string(string)
+    }
+}
+
+/// Convert a path into an autoreleased file NSURL
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn path_to_nsurl(path: &Path) -> *mut Object {
+    let cls = match class("NSURL") {
+        Ok(cls) => cls,
+        Err(_) => return nil,
+    };
+    unsafe {
+        msg_send![cls, fileURLWithPath: str_to_nsstring(&path.to_string_lossy())]
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn string_to_nsstring(_s: &str) -> *mut u64 { std::ptr::null_mut() }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn nsarray_to_vec(_array: *mut u64) -> Vec<*mut u64> { Vec::new() }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn nsarray_to_strings(_array: *mut u64) -> Vec<String> { Vec::new() }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn nsdict_to_hashmap(_dict: *mut u64) -> HashMap<String, *mut u64> { HashMap::new() }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn nsurl_to_path(_url: *mut u64) -> Option<PathBuf> { None }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn nsurl_to_string(_url: *mut u64) -> String { "".into() }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn path_to_nsurl(_path: &Path) -> *mut u64 { std::ptr::null_mut() }
@@ -0,0 +1,67 @@
+//! iCloud Drive document storage
+//!
+//! Apps sync documents through iCloud Drive by storing them in a ubiquity
+//! container, a directory that macOS keeps in sync with the user's other
+//! devices.  The app's bundle must be signed with the containers it uses; see
+//! [Entitlements::icloud_containers](../entitlements/struct.Entitlements.html#method.icloud_containers).
+//!
+//! Files that should be visible to the user in Finder's iCloud Drive go in
+//! the `Documents` subdirectory of the container.
+
+use std::path::PathBuf;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, str_to_nsstring};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// Returns whether the user is signed in to iCloud with iCloud Drive enabled
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn is_available() -> bool {
+    let cls = match class("NSFileManager") {
+        Ok(cls) => cls,
+        Err(_) => return false,
+    };
+    unsafe {
+        let manager: *mut Object = msg_send![cls, defaultManager];
+        let token: *mut Object = msg_send![manager, ubiquityIdentityToken];
+        token != nil
+    }
+}
+
+/// Local directory of an iCloud ubiquity container
+///
+/// Sets up the container on first use, which can take a while, so call this
+/// from a background thread rather than the main thread.
+///
+/// # Arguments
+///
+/// `container` - Container identifier (ex: `iCloud.com.example.myapp`), or
+/// None for the first container in the app's entitlements
+///
+/// # Returns
+///
+/// The container's directory, or None if iCloud is unavailable or the app
+/// isn't entitled to the container
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn ubiquity_container_url(container: Option<&str>) -> Option<PathBuf> {
+    let cls = class("NSFileManager").ok()?;
+    unsafe {
+        let manager: *mut Object = msg_send![cls, defaultManager];
+        let ident = container.map_or(nil, str_to_nsstring);
+        let url: *mut Object = msg_send![manager, URLForUbiquityContainerIdentifier: ident];
+        ::foundation::nsurl_to_path(url)
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn is_available() -> bool { false }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn ubiquity_container_url(_container: Option<&str>) -> Option<PathBuf> { None }
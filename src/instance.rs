@@ -0,0 +1,362 @@
+//! Coordination with other running instances of the app
+//!
+//! macOS normally runs one instance of each app, but an app launched by
+//! running its executable directly, or relaunched by the trampoline, can end
+//! up running twice.  [ensure_single_instance](ensure_single_instance)
+//! detects an instance that is already running, hands it the files and URLs
+//! this process was launched with, brings it to the front, and exits.
+//!
+//! Forwarded files arrive in the running instance as an 'open documents'
+//! Apple event, and URLs as `kAEGetURL` Apple events, exactly as if the user
+//! had opened them from Finder or a browser.
+//!
+//! For anything else, instances can exchange messages directly: the running
+//! app calls [listen](listen), and other processes, including unbundled
+//! command-line tools, call [send](send) with the app's bundle identifier.
+//! Messages are delivered over a `CFMessagePort` named after the bundle
+//! identifier.
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::path::PathBuf;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, BOOL};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, nsstring_to_string, str_to_nsstring};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::is_main_thread;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use ae;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::c_void;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// kAENoReply
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const NO_REPLY: u64 = 0x1;
+/// kAutoGenerateReturnID
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const AUTO_GENERATE_RETURN_ID: i16 = -1;
+/// kAnyTransactionID
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ANY_TRANSACTION_ID: i32 = 0;
+/// Seconds to wait for the running instance to accept an event
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const SEND_TIMEOUT: f64 = 5.0;
+
+/// A boxed Fn type for receiving messages sent with [send](send)
+pub type MessageCallback = Box<dyn Fn(&[u8])>;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+type MessagePortCallout = extern "C" fn(*mut Object, i32, *mut Object, *mut c_void) -> *mut Object;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[repr(C)]
+struct CFMessagePortContext {
+    version: isize,
+    info: *mut c_void,
+    retain: *const c_void,
+    release: *const c_void,
+    copy_description: *const c_void,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    static kCFRunLoopDefaultMode: *mut Object;
+    fn CFMessagePortCreateLocal(allocator: *mut Object, name: *mut Object,
+                                callout: MessagePortCallout,
+                                context: *mut CFMessagePortContext,
+                                should_free: *mut u8) -> *mut Object;
+    fn CFMessagePortCreateRemote(allocator: *mut Object, name: *mut Object) -> *mut Object;
+    fn CFMessagePortSendRequest(remote: *mut Object, msgid: i32, data: *mut Object,
+                                send_timeout: f64, rcv_timeout: f64,
+                                reply_mode: *mut Object, return_data: *mut *mut Object) -> i32;
+    fn CFMessagePortCreateRunLoopSource(allocator: *mut Object, port: *mut Object,
+                                        order: isize) -> *mut Object;
+    fn CFMessagePortInvalidate(port: *mut Object);
+    fn CFRunLoopGetMain() -> *mut Object;
+    fn CFRunLoopAddSource(rl: *mut Object, source: *mut Object, mode: *mut Object);
+    fn CFRunLoopSourceInvalidate(source: *mut Object);
+    fn CFRelease(cf: *mut Object);
+}
+
+/// Registration for messages sent to this app
+///
+/// Returned by [listen](listen).  Messages are received for as long as this
+/// is alive.
+pub struct Listener {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    port: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    source: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    _callback: Box<MessageCallback>,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        unsafe {
+            CFRunLoopSourceInvalidate(self.source);
+            CFMessagePortInvalidate(self.port);
+            CFRelease(self.source);
+            CFRelease(self.port);
+        }
+    }
+}
+
+/// Name of the message port for a bundle identifier
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn port_name(bundle_id: &str) -> *mut Object {
+    str_to_nsstring(&format!("{}.fruitbasket.instance", bundle_id))
+}
+
+/// Receive messages sent to this app by other processes
+///
+/// Opens a message port for the given bundle identifier, normally the app's
+/// own, and calls `cb` on the main thread with each message sent to it with
+/// [send](send).  Messages are delivered while the app's run loop is
+/// running.
+///
+/// Only one process can listen for a bundle identifier at a time.
+///
+/// # Returns
+///
+/// A [Listener](Listener) that must be kept alive to keep receiving
+/// messages.  `FruitError::MainThreadViolation` if not called from the main
+/// thread, or `FruitError::IOError` if another process is already listening.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn listen(bundle_id: &str, cb: MessageCallback) -> Result<Listener, FruitError> {
+    if !is_main_thread() {
+        return Err(FruitError::MainThreadViolation);
+    }
+    let mut callback = Box::new(cb);
+    let mut context = CFMessagePortContext {
+        version: 0,
+        info: &mut *callback as *mut MessageCallback as *mut c_void,
+        retain: std::ptr::null(),
+        release: std::ptr::null(),
+        copy_description: std::ptr::null(),
+    };
+    unsafe {
+        let port = CFMessagePortCreateLocal(nil, port_name(bundle_id), message_callout,
+                                            &mut context, std::ptr::null_mut());
+        if port == nil {
+            return Err(FruitError::IOError(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!("another process is listening for {}", bundle_id))));
+        }
+        let source = CFMessagePortCreateRunLoopSource(nil, port, 0);
+        CFRunLoopAddSource(CFRunLoopGetMain(), source, kCFRunLoopDefaultMode);
+        Ok(Listener {
+            port: port,
+            source: source,
+            _callback: callback,
+        })
+    }
+}
+
+/// Receives messages from CFMessagePort and passes them to the callback
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn message_callout(_port: *mut Object, _msgid: i32,
+                              data: *mut Object, info: *mut c_void) -> *mut Object {
+    let cb = unsafe { &*(info as *const MessageCallback) };
+    if data == nil {
+        cb(&[]);
+        return nil;
+    }
+    unsafe {
+        let bytes: *const u8 = msg_send![data, bytes];
+        let len: usize = msg_send![data, length];
+        if bytes.is_null() {
+            cb(&[]);
+        } else {
+            cb(std::slice::from_raw_parts(bytes, len));
+        }
+    }
+    nil
+}
+
+/// Send a message to the process listening for a bundle identifier
+///
+/// Delivers `msg` to the callback passed to [listen](listen) by the app with
+/// the given bundle identifier.  Can be called from any process, bundled or
+/// not, and from any thread.
+///
+/// # Returns
+///
+/// `FruitError::IOError` with `ErrorKind::NotFound` if nothing is listening,
+/// or another `FruitError::IOError` if the message could not be delivered.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn send(bundle_id: &str, msg: &[u8]) -> Result<(), FruitError> {
+    let cls = class("NSData")?;
+    unsafe {
+        let port = CFMessagePortCreateRemote(nil, port_name(bundle_id));
+        if port == nil {
+            return Err(FruitError::IOError(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("nothing is listening for {}", bundle_id))));
+        }
+        let data: *mut Object = msg_send![cls, dataWithBytes: msg.as_ptr() length: msg.len()];
+        let status = CFMessagePortSendRequest(port, 0, data, SEND_TIMEOUT, 0.0,
+                                              nil, std::ptr::null_mut());
+        CFRelease(port);
+        if status != 0 {
+            return Err(FruitError::IOError(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("failed to send message (CFMessagePort error {})", status))));
+        }
+    }
+    Ok(())
+}
+
+/// Find another running instance of an app
+///
+/// # Returns
+///
+/// The process ID of a running app with the given bundle identifier, other
+/// than the current process, or None if there isn't one.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn running_instance(bundle_id: &str) -> Option<i32> {
+    let this_pid = std::process::id() as i32;
+    let cls = class("NSRunningApplication").ok()?;
+    unsafe {
+        let apps: *mut Object = msg_send![cls,
+                                          runningApplicationsWithBundleIdentifier:
+                                          str_to_nsstring(bundle_id)];
+        let count: usize = msg_send![apps, count];
+        (0..count).map(|idx| -> i32 {
+            let app: *mut Object = msg_send![apps, objectAtIndex: idx];
+            msg_send![app, processIdentifier]
+        }).find(|&pid| pid != this_pid)
+    }
+}
+
+/// Exit if another instance of the app is running, after forwarding to it
+///
+/// If an instance of the app with the given bundle identifier is already
+/// running, the files and URLs named on this process's command line are sent
+/// to it, it is brought to the front, and this process exits.  Otherwise,
+/// nothing happens.
+///
+/// Command-line arguments that name existing files are forwarded as files,
+/// and arguments that look like URLs (`scheme:...`) as URLs.  Everything else
+/// is dropped.
+///
+/// # Returns
+///
+/// `Ok(())` if no other instance is running.  An error if another instance
+/// is running but could not be sent the arguments, in which case this process
+/// keeps running.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn ensure_single_instance(bundle_id: &str) -> Result<(), FruitError> {
+    let pid = match running_instance(bundle_id) {
+        Some(pid) => pid,
+        None => return Ok(()),
+    };
+    info!("{} is already running as pid {}.  Forwarding arguments.", bundle_id, pid);
+    let files: Vec<PathBuf> = ::merge_open_requests(&[]).into_iter()
+        .map(|req| req.path)
+        .collect();
+    let urls: Vec<String> = std::env::args().skip(1)
+        .filter(|arg| is_url(arg) && !PathBuf::from(arg).exists())
+        .collect();
+    if !files.is_empty() {
+        let list = unsafe {
+            let desc_cls = class("NSAppleEventDescriptor")?;
+            let list: *mut Object = msg_send![desc_cls, listDescriptor];
+            let url_cls = class("NSURL")?;
+            for file in &files {
+                let url: *mut Object = msg_send![url_cls, fileURLWithPath:
+                                                 str_to_nsstring(&file.to_string_lossy())];
+                let item: *mut Object = msg_send![desc_cls, descriptorWithFileURL: url];
+                // Index 0 appends to the list
+                let _:() = msg_send![list, insertDescriptor: item atIndex: 0isize];
+            }
+            list
+        };
+        send_event(pid, ae::kCoreEventClass, ae::kAEOpenDocuments, list)?;
+    }
+    for url in &urls {
+        let direct: *mut Object = unsafe {
+            let cls = class("NSAppleEventDescriptor")?;
+            msg_send![cls, descriptorWithString: str_to_nsstring(url)]
+        };
+        send_event(pid, ae::kInternetEventClass, ae::kAEGetURL, direct)?;
+    }
+    unsafe {
+        let cls = class("NSRunningApplication")?;
+        let app: *mut Object = msg_send![cls, runningApplicationWithProcessIdentifier: pid];
+        if app != nil {
+            // NSApplicationActivateIgnoringOtherApps
+            let _: BOOL = msg_send![app, activateWithOptions: 1u64 << 1];
+        }
+    }
+    info!("Forwarded to running instance.  Exiting.");
+    std::process::exit(0);
+}
+
+/// Whether a command-line argument looks like a URL
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn is_url(arg: &str) -> bool {
+    match arg.find(':') {
+        Some(end) if end > 0 => {
+            let scheme = &arg[..end];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+                scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        },
+        _ => false,
+    }
+}
+
+/// Send an Apple event with a direct object to another process
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn send_event(pid: i32, event_class: u32, id: u32, direct: *mut Object) -> Result<(), FruitError> {
+    unsafe {
+        let cls = class("NSAppleEventDescriptor")?;
+        let target: *mut Object = msg_send![cls, descriptorWithProcessIdentifier: pid];
+        let event: *mut Object = msg_send![cls,
+                                           appleEventWithEventClass: event_class
+                                           eventID: id
+                                           targetDescriptor: target
+                                           returnID: AUTO_GENERATE_RETURN_ID
+                                           transactionID: ANY_TRANSACTION_ID];
+        let _:() = msg_send![event, setParamDescriptor: direct forKeyword: ae::keyDirectObject];
+        let mut error: *mut Object = nil;
+        let reply: *mut Object = msg_send![event,
+                                           sendEventWithOptions: NO_REPLY
+                                           timeout: SEND_TIMEOUT
+                                           error: &mut error];
+        if reply == nil && error != nil {
+            let description: *mut Object = msg_send![error, localizedDescription];
+            return Err(FruitError::IOError(std::io::Error::new(
+                std::io::ErrorKind::Other, nsstring_to_string(description))));
+        }
+    }
+    Ok(())
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn running_instance(_bundle_id: &str) -> Option<i32> { None }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn ensure_single_instance(_bundle_id: &str) -> Result<(), FruitError> { Ok(()) }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn listen(_bundle_id: &str, _cb: MessageCallback) -> Result<Listener, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn send(_bundle_id: &str, _msg: &[u8]) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
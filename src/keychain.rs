@@ -0,0 +1,169 @@
+//! Password storage in the user's keychain, scoped to the app bundle
+//!
+//! Stores secrets such as passwords and API tokens as generic password items
+//! in the login keychain.  Items are filed under the app's bundle identifier,
+//! and addressed by an account name chosen by the app (ex: a username, or
+//! `"api-token"`).
+//!
+//! Requires running from an app bundle, since the bundle identifier is used
+//! to scope the items.
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, str_to_nsstring};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// errSecItemNotFound
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ERR_ITEM_NOT_FOUND: i32 = -25300;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "Security", kind = "framework")]
+extern "C" {
+    static kSecClass: *mut Object;
+    static kSecClassGenericPassword: *mut Object;
+    static kSecAttrService: *mut Object;
+    static kSecAttrAccount: *mut Object;
+    static kSecValueData: *mut Object;
+    static kSecReturnData: *mut Object;
+    static kSecMatchLimit: *mut Object;
+    static kSecMatchLimitOne: *mut Object;
+    fn SecItemAdd(attributes: *mut Object, result: *mut *mut Object) -> i32;
+    fn SecItemUpdate(query: *mut Object, attributes: *mut Object) -> i32;
+    fn SecItemCopyMatching(query: *mut Object, result: *mut *mut Object) -> i32;
+    fn SecItemDelete(query: *mut Object) -> i32;
+}
+
+/// Store a password, replacing any existing password for the account
+///
+/// # Arguments
+///
+/// `account` - Name identifying the password within this app
+///
+/// `password` - Secret to store
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn set_password(account: &str, password: &[u8]) -> Result<(), FruitError> {
+    unsafe {
+        let query = item_query(account)?;
+        let cls = class("NSData")?;
+        let data: *mut Object = msg_send![cls, dataWithBytes: password.as_ptr()
+                                          length: password.len()];
+        let cls = class("NSDictionary")?;
+        let update: *mut Object = msg_send![cls, dictionaryWithObject: data forKey: kSecValueData];
+        let status = match SecItemUpdate(query, update) {
+            ERR_ITEM_NOT_FOUND => {
+                let _:() = msg_send![query, setObject: data forKey: kSecValueData];
+                SecItemAdd(query, std::ptr::null_mut())
+            },
+            status => status,
+        };
+        check(status)
+    }
+}
+
+/// Retrieve a stored password
+///
+/// # Arguments
+///
+/// `account` - Name identifying the password within this app
+///
+/// # Returns
+///
+/// The stored secret, or `None` if no password is stored for the account
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn get_password(account: &str) -> Result<Option<Vec<u8>>, FruitError> {
+    unsafe {
+        let query = item_query(account)?;
+        let cls = class("NSNumber")?;
+        let yes: *mut Object = msg_send![cls, numberWithBool: YES];
+        let _:() = msg_send![query, setObject: yes forKey: kSecReturnData];
+        let _:() = msg_send![query, setObject: kSecMatchLimitOne forKey: kSecMatchLimit];
+        let mut result: *mut Object = nil;
+        match SecItemCopyMatching(query, &mut result) {
+            ERR_ITEM_NOT_FOUND => return Ok(None),
+            status => check(status)?,
+        }
+        if result == nil {
+            return Ok(None);
+        }
+        let len: usize = msg_send![result, length];
+        let bytes: *const u8 = msg_send![result, bytes];
+        let password = if len == 0 {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(bytes, len).to_vec()
+        };
+        let _:() = msg_send![result, release];
+        Ok(Some(password))
+    }
+}
+
+/// Delete a stored password
+///
+/// # Arguments
+///
+/// `account` - Name identifying the password within this app
+///
+/// # Returns
+///
+/// true if a password was deleted, false if none was stored
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn delete_password(account: &str) -> Result<bool, FruitError> {
+    unsafe {
+        match SecItemDelete(item_query(account)?) {
+            ERR_ITEM_NOT_FOUND => Ok(false),
+            status => check(status).map(|_| true),
+        }
+    }
+}
+
+/// Build a mutable query dictionary matching this app's item for `account`
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn item_query(account: &str) -> Result<*mut Object, FruitError> {
+    let cls = class("NSBundle")?;
+    let bundle: *mut Object = msg_send![cls, mainBundle];
+    let ident: *mut Object = msg_send![bundle, bundleIdentifier];
+    if ident == nil {
+        return Err(FruitError::NotBundled);
+    }
+    let cls = class("NSMutableDictionary")?;
+    let query: *mut Object = msg_send![cls, dictionary];
+    let _:() = msg_send![query, setObject: kSecClassGenericPassword forKey: kSecClass];
+    let _:() = msg_send![query, setObject: ident forKey: kSecAttrService];
+    let _:() = msg_send![query, setObject: str_to_nsstring(account) forKey: kSecAttrAccount];
+    Ok(query)
+}
+
+/// Convert an OSStatus from Keychain Services into a Result
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn check(status: i32) -> Result<(), FruitError> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(FruitError::KeychainError { os_status: status })
+    }
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn set_password(_account: &str, _password: &[u8]) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn get_password(_account: &str) -> Result<Option<Vec<u8>>, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn delete_password(_account: &str) -> Result<bool, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
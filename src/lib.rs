@@ -36,21 +36,28 @@
 #![deny(missing_docs)]
 
 use std::error::Error;
+use std::path::Path;
+use std::path::PathBuf;
 use std::time::Duration;
 use std::sync::mpsc::Sender;
 
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+use std::time::Instant;
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
 use std::sync::mpsc::Receiver;
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
-use std::thread;
+use std::collections::HashMap;
 
-extern crate time;
 extern crate dirs;
+extern crate libc;
 
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 #[macro_use]
 extern crate objc;
 
+#[cfg(all(target_os = "macos", not(feature="dummy"), feature = "xpc"))]
+extern crate block;
+
 #[cfg(feature = "logging")]
 #[allow(unused_imports)]
 #[macro_use]
@@ -66,12 +73,105 @@ macro_rules! info {
     ($x:expr, $($arg:tt)+) => {println!($x, $($arg)+)};
 }
 
+/// Build a four-character code from a 4-byte string
+///
+/// Apple events identify classes, events, parameters, and types with
+/// four-character codes, which Apple's headers write as multi-character
+/// literals (ex: `'GURL'`).  This packs one into a [FourCC](type.FourCC.html)
+/// and can be used in constants:
+///
+/// ```
+/// # #[macro_use] extern crate fruitbasket;
+/// # fn main() {
+/// const MY_EVENT_CLASS: u32 = fourcc!("MyAp");
+/// assert_eq!(fourcc!("GURL"), 0x4755524c);
+/// # }
+/// ```
+///
+/// Fails to compile, when used in a constant, if the string is not exactly
+/// four bytes long.
+#[macro_export]
+macro_rules! fourcc {
+    ($code:expr) => { $crate::fourcc_from_str($code) };
+}
+
+/// Build a four-character code from a 4-byte string.  See [fourcc!](macro.fourcc.html).
+///
+/// # Panics
+///
+/// Panics if the string is not exactly four bytes long.
+pub const fn fourcc_from_str(code: &str) -> FourCC {
+    let bytes = code.as_bytes();
+    assert!(bytes.len() == 4, "four-character codes must be exactly 4 bytes");
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
+
+/// Embed an Info.plist in the executable, for development without a bundle
+///
+/// Writes a minimal Info.plist, with the given bundle identifier, name, and
+/// optional version, into the `__TEXT,__info_plist` section of the binary.
+/// macOS reads it as the Info.plist of an executable that isn't in a bundle,
+/// so a program started with `cargo run` still has a bundle identifier for
+/// privacy permissions, the Keychain, user defaults, and notifications,
+/// without relaunching through a [Trampoline](struct.Trampoline.html).
+///
+/// Invoke it once, at the top level of the binary crate:
+///
+/// ```
+/// # #[macro_use] extern crate fruitbasket;
+/// embed_info_plist!("com.example.myapp", "My App", "1.2.0");
+/// # fn main() {}
+/// ```
+///
+/// The arguments must be string literals, and can't contain XML special
+/// characters.  A `Trampoline` treats a binary with an embedded Info.plist as
+/// already bundled; see
+/// [Trampoline::has_embedded_plist](struct.Trampoline.html#method.has_embedded_plist).
+/// The section is only written when building for macOS.
+#[macro_export]
+macro_rules! embed_info_plist {
+    ($ident:literal, $name:literal) => {
+        $crate::embed_info_plist!($ident, $name, "1.0.0");
+    };
+    ($ident:literal, $name:literal, $version:literal) => {
+        #[cfg(target_os = "macos")]
+        const _: () = {
+            const PLIST: &str = concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ",
+                "\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+                "<plist version=\"1.0\">\n<dict>\n",
+                "  <key>CFBundleIdentifier</key>\n  <string>", $ident, "</string>\n",
+                "  <key>CFBundleName</key>\n  <string>", $name, "</string>\n",
+                "  <key>CFBundleVersion</key>\n  <string>", $version, "</string>\n",
+                "  <key>CFBundleInfoDictionaryVersion</key>\n  <string>6.0</string>\n",
+                "</dict>\n</plist>\n");
+            #[used]
+            #[link_section = "__TEXT,__info_plist"]
+            static INFO_PLIST: [u8; PLIST.len()] = $crate::embedded_plist_bytes(PLIST);
+        };
+    };
+}
+
+/// Copy a string into a byte array for [embed_info_plist!](macro.embed_info_plist.html)
+#[doc(hidden)]
+pub const fn embedded_plist_bytes<const N: usize>(plist: &str) -> [u8; N] {
+    let bytes = plist.as_bytes();
+    let mut out = [0u8; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
 /// Info.plist entries that have default values, but can be overridden
 ///
 /// These properties are always set in the app bundle's Property List, with the
 /// default values provided here, but can be overridden by your application with
 /// the Trampoline builder's `plist_key*()` functions.
-pub const DEFAULT_PLIST: &'static [(&'static str, &'static str)] = &[
+pub const DEFAULT_PLIST: &[(&str, &str)] = &[
     ("CFBundleInfoDictionaryVersion","6.0"),
     ("CFBundlePackageType","APPL"),
     ("CFBundleSignature","xxxx"),
@@ -83,7 +183,7 @@ pub const DEFAULT_PLIST: &'static [(&'static str, &'static str)] = &[
 /// These properties are always set in the app bundle's Property List, based on
 /// information provided to the Trampoline builder, and cannot be overridden
 /// with the builder's `plist_key*()` functions.
-pub const FORBIDDEN_PLIST: &'static [&'static str] = & [
+pub const FORBIDDEN_PLIST: &[&str] = & [
     "CFBundleName",
     "CFBundleDisplayName",
     "CFBundleIdentifier",
@@ -92,39 +192,111 @@ pub const FORBIDDEN_PLIST: &'static [&'static str] = & [
     "CFBundleVersion",
 ];
 
-/// Apple kInternetEventClass constant
-#[allow(non_upper_case_globals)]
-pub const kInternetEventClass: u32 = 0x4755524c;
-/// Apple kAEGetURL constant
-#[allow(non_upper_case_globals)]
-pub const kAEGetURL: u32 = 0x4755524c;
-/// Apple keyDirectObject constant
-#[allow(non_upper_case_globals)]
-pub const keyDirectObject: u32 = 0x2d2d2d2d;
+pub use ae::kInternetEventClass;
+pub use ae::kAEGetURL;
+pub use ae::keyDirectObject;
+
+pub mod ae;
 
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 mod osx;
 
+pub mod bookmark;
+pub mod bundle;
+pub mod crash;
+pub mod dialog;
+pub mod entitlements;
+pub mod foundation;
+pub mod icloud;
+pub mod instance;
+pub mod keychain;
+pub mod manifest;
+pub mod notarize;
+pub mod permissions;
+mod preflight;
+pub mod receipt;
+pub mod relocate;
+pub mod sandbox;
+pub mod signing;
+pub mod spotlight;
+pub mod status;
+pub mod update;
+mod waker;
+pub mod watch;
+pub mod workspace;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+mod signpost;
+#[cfg(feature = "window")]
+pub mod window;
+#[cfg(feature = "xpc")]
+pub mod xpc;
+
+pub use preflight::preflight;
+pub use preflight::Preflight;
+
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 pub use osx::FruitApp;
 
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 pub use osx::Trampoline;
 
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::FruitAppBuilder;
+
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 pub use osx::FruitObjcCallback;
 
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::StaticFruitApp;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::StaticFruitObjcCallback;
+
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 pub use osx::FruitCallbackKey;
 
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 pub use osx::parse_url_event;
 
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::parse_url_list;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::parse_universal_link;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::parse_apple_event;
+
 #[cfg(all(target_os = "macos", not(feature="dummy")))]
 pub use osx::nsstring_to_string;
 
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::preferred_languages;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::current_locale;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::screens;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::main_screen;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::menu_bar_height;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::dock;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::script_command_name;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub use osx::launch_bundle;
+
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
 /// Docs in OS X build.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub enum FruitCallbackKey {
     /// Docs in OS X build.
     Method(&'static str),
@@ -134,53 +306,333 @@ pub enum FruitCallbackKey {
 
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
 /// Docs in OS X build.
-pub type FruitObjcCallback = Box<dyn Fn(*mut u64)>;
+pub type FruitObjcCallback<'a> = Box<dyn Fn(*mut u64) + 'a>;
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub type StaticFruitApp = FruitApp<'static>;
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub type StaticFruitObjcCallback = FruitObjcCallback<'static>;
 
 /// Main interface for controlling and interacting with the AppKit app
 ///
 /// Dummy implementation for non-OSX platforms.  See OS X build for proper
 /// documentation.
+///
+/// The dummy app never receives events from the OS, but events can be
+/// simulated with [simulate_url](FruitApp::simulate_url) and
+/// [simulate_open_file](FruitApp::simulate_open_file).  Simulated events are
+/// delivered by `run()` to the same callbacks, in the same form, as on macOS,
+/// so URL and document handling can be tested without a Mac.
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
-pub struct FruitApp {
-    tx: Sender<()>,
-    rx: Receiver<()>,
+pub struct FruitApp<'a> {
+    tx: CommandSender,
+    rx: Receiver<FruitCommand>,
+    wake: waker::WakeReceiver,
+    callbacks: HashMap<FruitCallbackKey, FruitObjcCallback<'a>>,
+    once: Vec<FruitCallbackKey>,
+    apple_events: Vec<(u32, u32)>,
+    simulated: Vec<(FruitCallbackKey, String)>,
+    launch_opens: Vec<OpenRequest>,
+    open_file_handler: Option<OpenFileCallback<'a>>,
+    phase: LaunchPhase,
+    idle_interval: std::cell::Cell<Duration>,
+    clipboard: std::cell::Cell<Option<i64>>,
+    _not_send: std::marker::PhantomData<*const ()>,
 }
+
+/// Payloads of the simulated events currently being dispatched
+///
+/// Callbacks receive an opaque handle in place of an ObjC object, which the
+/// dummy `parse_url_event()` and `nsstring_to_string()` look up here.  The
+/// handle is never dereferenced, so a stale or arbitrary pointer reads back
+/// as an empty string.
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
-impl FruitApp {
+#[derive(Default)]
+struct SimulatedObjects {
+    next: usize,
+    text: HashMap<usize, String>,
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+thread_local! {
+    static SIMULATED_OBJECTS: std::cell::RefCell<SimulatedObjects> = Default::default();
+}
+
+/// Call `f` with a handle that reads back as `text` until `f` returns
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+fn with_simulated_object<F: FnOnce(*mut u64)>(text: String, f: F) {
+    let handle = SIMULATED_OBJECTS.with(|objs| {
+        let mut objs = objs.borrow_mut();
+        // Handles start at 1, since null means no payload
+        objs.next += 1;
+        let handle = objs.next;
+        objs.text.insert(handle, text);
+        handle
+    });
+    f(handle as *mut u64);
+    SIMULATED_OBJECTS.with(|objs| objs.borrow_mut().text.remove(&handle));
+}
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+impl<'a> FruitApp<'a> {
+    /// Docs in OS X build.
+    pub fn new() -> Result<FruitApp<'a>, FruitError> {
+        let (tx, rx, wake) = command_channel();
+        Ok(FruitApp{
+            tx,
+            rx,
+            wake,
+            callbacks: HashMap::new(),
+            once: Vec::new(),
+            apple_events: Vec::new(),
+            simulated: Vec::new(),
+            launch_opens: Vec::new(),
+            open_file_handler: None,
+            phase: LaunchPhase::NotLaunched,
+            idle_interval: std::cell::Cell::new(Duration::from_millis(50)),
+            clipboard: std::cell::Cell::new(None),
+            _not_send: std::marker::PhantomData,
+        })
+    }
+    /// Docs in OS X build.
+    pub fn new_agent(title: &str) -> Result<AgentApp<'a>, FruitError> {
+        Ok(AgentApp {
+            app: FruitApp::new()?,
+            status_item: status::StatusItem::new(title)?,
+        })
+    }
+    /// Docs in OS X build.
+    pub fn register_callback(&mut self, key: FruitCallbackKey, cb: FruitObjcCallback<'a>) {
+        self.once.retain(|k| *k != key);
+        let _ = self.callbacks.insert(key, cb);
+    }
+    /// Docs in OS X build.
+    pub fn register_callback_once(&mut self, key: FruitCallbackKey, cb: FruitObjcCallback<'a>) {
+        if !self.once.contains(&key) {
+            self.once.push(key);
+        }
+        let _ = self.callbacks.insert(key, cb);
+    }
+    fn finish_once(&mut self, key: &FruitCallbackKey) {
+        if let Some(idx) = self.once.iter().position(|k| k == key) {
+            self.once.remove(idx);
+            let _ = self.callbacks.remove(key);
+        }
+    }
+    /// Docs in OS X build.
+    pub fn add_delegate_method(&mut self, selector: &'static str, signature: MethodSignature,
+                               cb: FruitObjcCallback<'a>) -> Result<(), FruitError> {
+        let args = match signature {
+            MethodSignature::NoArgs => 0,
+            MethodSignature::OneArg => 1,
+            MethodSignature::TwoArgs => 2,
+            MethodSignature::Service => 3,
+        };
+        if selector.matches(':').count() != args {
+            return Err(FruitError::ObjcError(
+                format!("{} does not take {} arguments", selector, args)));
+        }
+        self.register_callback(FruitCallbackKey::Method(selector), cb);
+        Ok(())
+    }
+    /// Docs in OS X build.
+    pub fn register_apple_event(&mut self, class: u32, id: u32) {
+        self.apple_events.push((class, id));
+    }
+    /// Docs in OS X build.
+    pub fn unregister_apple_event(&mut self, class: u32, id: u32) {
+        self.apple_events.retain(|&event| event != (class, id));
+    }
+    /// Docs in OS X build.
+    pub fn register_core_events(&mut self, _cb: CoreEventCallback<'a>) {}
+    /// Docs in OS X build.
+    ///
+    /// Simulated files are passed to the handler instead of the
+    /// `application:openFile:` callback, and rejections are ignored.
+    pub fn register_open_file_handler(&mut self, cb: OpenFileCallback<'a>, _show_alert: bool) {
+        self.open_file_handler = Some(cb);
+    }
+    /// Docs in OS X build.
+    pub fn startup_open_requests(&self) -> Vec<OpenRequest> {
+        merge_open_requests(&self.launch_opens)
+    }
+    /// Simulate the app being asked to open a URL
+    ///
+    /// Only available in the dummy implementation.  On the next call to
+    /// `run()`, the `handleEvent:withReplyEvent:` callback is called, as it
+    /// would be on macOS, if the app registered for the `kInternetEventClass`
+    /// / `kAEGetURL` Apple event.  Pass the callback's argument to
+    /// [parse_url_event](parse_url_event) to get the URL back.
+    ///
+    /// As on macOS, the URL is held until both the Apple event and the
+    /// callback are registered.  The descriptor returned by
+    /// [wake_fd](FruitApp::wake_fd) becomes readable.
+    pub fn simulate_url(&mut self, url: &str) {
+        self.simulated.push((FruitCallbackKey::Method("handleEvent:withReplyEvent:"),
+                             url.to_string()));
+        self.tx.waker.wake();
+    }
+    /// Simulate the app being asked to open a file
+    ///
+    /// Only available in the dummy implementation.  On the next call to
+    /// `run()`, the `application:openFile:` callback is called, as it would
+    /// be on macOS.  Pass the callback's argument to
+    /// [nsstring_to_string](nsstring_to_string) to get the path back.
+    ///
+    /// Files simulated before the first call to `run()` are also reported by
+    /// [startup_open_requests](FruitApp::startup_open_requests).  The
+    /// descriptor returned by [wake_fd](FruitApp::wake_fd) becomes readable.
+    pub fn simulate_open_file(&mut self, path: &str) {
+        if self.phase < LaunchPhase::DidFinish {
+            self.launch_opens.push(OpenRequest { path: PathBuf::from(path),
+                                                 source: OpenSource::OpenFile });
+        }
+        self.simulated.push((FruitCallbackKey::Method("application:openFile:"),
+                             path.to_string()));
+        self.tx.waker.wake();
+    }
     /// Docs in OS X build.
-    pub fn new() -> FruitApp {
-        use std::sync::mpsc::channel;
-        let (tx,rx) = channel();
-        FruitApp{ tx: tx, rx: rx}
+    pub fn watch_clipboard(&self, enable: bool) {
+        self.clipboard.set(if enable { Some(self.clipboard.get().unwrap_or(0)) } else { None });
+    }
+    /// Simulate the user copying something to the clipboard
+    ///
+    /// Only available in the dummy implementation.  While the clipboard is
+    /// watched with [watch_clipboard](FruitApp::watch_clipboard), the
+    /// `pasteboardDidChange:` callback is called on the next call to
+    /// `run()`, as it would be on macOS.  Does nothing otherwise.
+    ///
+    /// As on macOS, changes made before the callback runs are reported once,
+    /// with the latest change count.
+    pub fn simulate_clipboard_change(&mut self) {
+        let count = match self.clipboard.get() {
+            Some(count) => count + 1,
+            None => return,
+        };
+        self.clipboard.set(Some(count));
+        let key = FruitCallbackKey::Method("pasteboardDidChange:");
+        self.simulated.retain(|&(k, _)| k != key);
+        self.simulated.push((key, count.to_string()));
+        self.tx.waker.wake();
+    }
+    /// Deliver launch notifications and simulated events to their callbacks
+    fn dispatch_simulated(&mut self, limit: usize) {
+        if self.phase == LaunchPhase::NotLaunched {
+            for &(method, phase) in &[("applicationWillFinishLaunching:", LaunchPhase::WillFinish),
+                                      ("applicationDidFinishLaunching:", LaunchPhase::DidFinish)] {
+                self.phase = phase;
+                let key = FruitCallbackKey::Method(method);
+                if let Some(cb) = self.callbacks.get(&key) {
+                    cb(std::ptr::null_mut());
+                }
+                self.finish_once(&key);
+            }
+        }
+        let url_key = FruitCallbackKey::Method("handleEvent:withReplyEvent:");
+        let url_events = self.apple_events.contains(&(kInternetEventClass, kAEGetURL));
+        let mut dispatched = 0;
+        for (key, text) in std::mem::take(&mut self.simulated) {
+            if dispatched >= limit ||
+                (key == url_key && (!url_events || !self.callbacks.contains_key(&key))) {
+                self.simulated.push((key, text));
+                continue;
+            }
+            dispatched += 1;
+            if let (Some(handler), FruitCallbackKey::Method("application:openFile:")) =
+                (self.open_file_handler.as_ref(), key) {
+                let _ = handler(Path::new(&text));
+                continue;
+            }
+            if let Some(cb) = self.callbacks.get(&key) {
+                with_simulated_object(text, cb);
+            }
+            self.finish_once(&key);
+        }
     }
     /// Docs in OS X build.
-    pub fn register_callback(&mut self, _key: FruitCallbackKey, _cb: FruitObjcCallback) {}
+    pub fn launch_phase(&self) -> LaunchPhase { self.phase }
     /// Docs in OS X build.
-    pub fn register_apple_event(&mut self, _class: u32, _id: u32) {}
+    pub fn launch_context(&self) -> LaunchContext { LaunchContext::Unknown }
     /// Docs in OS X build.
     pub fn set_activation_policy(&self, _policy: ActivationPolicy) {}
     /// Docs in OS X build.
+    pub fn activation_policy(&self) -> ActivationPolicy { ActivationPolicy::Regular }
+    /// Docs in OS X build.
+    pub fn change_activation_policy(&self, _policy: ActivationPolicy, _activate: bool)
+                                    -> Result<(), FruitError> {
+        Ok(())
+    }
+    /// Docs in OS X build.
+    pub fn activate(&self) {}
+    /// Docs in OS X build.
+    pub fn set_services_provider(&self, _enabled: bool) {}
+    /// Docs in OS X build.
+    pub fn set_dock_badge(&self, _badge: Option<&str>) {}
+    /// Docs in OS X build.
+    pub fn request_attention(&self, _critical: bool) {}
+    /// Docs in OS X build.
+    pub fn is_visible(&self) -> bool { true }
+    /// Docs in OS X build.
     pub fn terminate(exit_code: i32) {
         std::process::exit(exit_code);
     }
     /// Docs in OS X build.
+    pub fn launch_helper(&self, name: &str, args: &[&str]) -> Result<std::process::Child, FruitError> {
+        let exe = std::env::current_exe()?;
+        let dir = exe.parent().unwrap_or(std::path::Path::new("/"));
+        Ok(std::process::Command::new(dir.join(name)).args(args).current_dir(dir).spawn()?)
+    }
+    /// Docs in OS X build.
+    pub fn relaunch(&self) -> Result<(), FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+    /// Docs in OS X build.
+    pub fn set_shows_in_dock(&self, _show: bool, persist: bool) -> Result<(), FruitError> {
+        if persist {
+            return Err(FruitError::UnsupportedPlatform);
+        }
+        Ok(())
+    }
+    /// Docs in OS X build.
     pub fn stop(stopper: &FruitStopper) {
         stopper.stop();
     }
     /// Docs in OS X build.
+    #[allow(clippy::result_unit_err)]
     pub fn run(&mut self, period: RunPeriod) -> Result<(),()> {
-        let start = time::now_utc().to_timespec();
+        let start = Instant::now();
+        if self.phase == LaunchPhase::DidFinish {
+            self.phase = LaunchPhase::Running;
+        }
         loop {
-            if self.rx.try_recv().is_ok() {
-                return Err(());
+            while let Ok(cmd) = self.rx.try_recv() {
+                if self.handle_command(cmd) {
+                    return Err(());
+                }
             }
-            if period == RunPeriod::Once {
+            let limit = match period {
+                RunPeriod::Events(n) => n,
+                _ => usize::MAX,
+            };
+            self.dispatch_simulated(limit);
+            if let RunPeriod::Once | RunPeriod::Events(_) = period {
                 break;
             }
-            thread::sleep(Duration::from_millis(500));
+            // Wait on the command channel instead of sleeping, so requests
+            // from other threads are handled as soon as they arrive.
+            let mut wait = self.idle_interval.get();
             if let RunPeriod::Time(t) = period {
-                let now = time::now_utc().to_timespec();
-                if now >= start + time::Duration::from_std(t).unwrap() {
+                wait = std::cmp::min(wait, t.saturating_sub(start.elapsed()));
+            }
+            if let Ok(cmd) = self.rx.recv_timeout(wait) {
+                if self.handle_command(cmd) {
+                    return Err(());
+                }
+            }
+            if let RunPeriod::Time(t) = period {
+                if start.elapsed() >= t {
                     break;
                 }
             }
@@ -188,23 +640,192 @@ impl FruitApp {
         Ok(())
     }
     /// Docs in OS X build.
+    pub fn wait_for(&mut self, key: FruitCallbackKey, timeout: Duration) -> Option<EventPayload> {
+        let previous = self.callbacks.remove(&key);
+        let previous_once = self.once.contains(&key);
+        let payload = std::rc::Rc::new(std::cell::RefCell::new(None));
+        let received = payload.clone();
+        self.register_callback_once(key, Box::new(move |obj| {
+            *received.borrow_mut() = Some(simulated_payload(&key, obj));
+        }));
+        let start = Instant::now();
+        while payload.borrow().is_none() {
+            if self.run(RunPeriod::Once).is_err() {
+                break;
+            }
+            let elapsed = start.elapsed();
+            if payload.borrow().is_some() || elapsed >= timeout {
+                break;
+            }
+            let wait = std::cmp::min(self.idle_interval.get(), timeout - elapsed);
+            if let Ok(cmd) = self.rx.recv_timeout(wait) {
+                if self.handle_command(cmd) {
+                    break;
+                }
+            }
+        }
+        self.once.retain(|k| *k != key);
+        let _ = self.callbacks.remove(&key);
+        match previous {
+            Some(cb) if previous_once => self.register_callback_once(key, cb),
+            Some(cb) => self.register_callback(key, cb),
+            None => {},
+        }
+        payload.replace(None)
+    }
+    /// Perform a request from `FruitStopper` or `FruitHandle`.  Returns true
+    /// if a stop was requested.
+    fn handle_command(&self, cmd: FruitCommand) -> bool {
+        match cmd {
+            FruitCommand::Stop => return true,
+            FruitCommand::SetActivationPolicy(policy, activate) => {
+                let _ = self.change_activation_policy(policy, activate);
+            },
+            FruitCommand::SetDockBadge(badge) => self.set_dock_badge(badge.as_deref()),
+            FruitCommand::RequestAttention(critical) => self.request_attention(critical),
+        }
+        false
+    }
+    /// Docs in OS X build.
+    pub fn set_idle_interval(&self, interval: Duration) {
+        self.idle_interval.set(interval);
+    }
+    /// Docs in OS X build.
+    pub fn set_pool_drain_interval(&self, _iterations: u64) {}
+    /// Docs in OS X build.
+    pub fn stats(&self) -> RunLoopStats { RunLoopStats::default() }
+    /// Docs in OS X build.
+    #[cfg(unix)]
+    pub fn wake_fd(&self) -> std::os::unix::io::RawFd {
+        self.wake.fd()
+    }
+    /// Docs in OS X build.
+    #[allow(clippy::result_unit_err)]
+    pub fn pump_pending(&mut self) -> Result<(),()> {
+        self.wake.drain();
+        self.run(RunPeriod::Once)
+    }
+    /// Docs in OS X build.
     pub fn stopper(&self) -> FruitStopper {
         FruitStopper { tx: self.tx.clone() }
     }
     /// Docs in OS X build.
-    pub fn bundled_resource_path(_name: &str, _extension: &str) -> Option<String> { None }
+    pub fn handle(&self) -> FruitHandle {
+        FruitHandle { tx: self.tx.clone() }
+    }
+    /// Docs in OS X build.
+    ///
+    /// # Safety
+    ///
+    /// Always returns null on this platform.
+    pub unsafe fn nsapp(&self) -> *mut u64 { std::ptr::null_mut() }
+    /// Docs in OS X build.
+    ///
+    /// # Safety
+    ///
+    /// Always returns null on this platform.
+    pub unsafe fn delegate(&self) -> *mut u64 { std::ptr::null_mut() }
+    /// Docs in OS X build.
+    pub fn first_run(&self) -> bool { true }
+    /// Docs in OS X build.
+    pub fn previous_version(&self) -> Option<String> { None }
+    /// Docs in OS X build.
+    pub fn on_upgrade<F: FnOnce(&str, &str)>(&self, _cb: F) {}
+    /// Docs in OS X build.
+    pub fn bundled_resource_path(_name: &str, _extension: &str) -> Result<Option<String>, FruitError> {
+        Ok(None)
+    }
 }
 
 #[cfg(any(not(target_os = "macos"), feature="dummy"))]
 /// Docs in OS X build.
-pub fn parse_url_event(_event: *mut u64) -> String { "".into() }
+pub fn parse_url_event(event: *mut u64) -> String { nsstring_to_string(event) }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn parse_url_list(_urls: *mut u64) -> Vec<String> { vec![] }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn parse_universal_link(_activity: *mut u64) -> Option<String> { None }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn parse_apple_event(event: *mut u64) -> AppleEvent {
+    // The only Apple events the dummy app delivers are simulated URLs
+    let mut params = HashMap::new();
+    if !event.is_null() {
+        params.insert(keyDirectObject, AEValue::Text(nsstring_to_string(event)));
+    }
+    AppleEvent { class: kInternetEventClass, id: kAEGetURL, params }
+}
 
 #[cfg(any(not(target_os = "macos"), feature = "dummy"))]
 /// Docs in OS X build.
-pub fn nsstring_to_string(_nsstring: *mut u64) -> String {
-    "".into()
+pub fn nsstring_to_string(nsstring: *mut u64) -> String {
+    // Only simulated events ever hand out handles in the dummy build
+    SIMULATED_OBJECTS.with(|objs| {
+        objs.borrow().text.get(&(nsstring as usize)).cloned().unwrap_or_default()
+    })
 }
 
+/// Convert the payload of a simulated event for `wait_for()`
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+fn simulated_payload(key: &FruitCallbackKey, obj: *mut u64) -> EventPayload {
+    match *key {
+        FruitCallbackKey::Method("handleEvent:withReplyEvent:") => {
+            EventPayload::Url(nsstring_to_string(obj))
+        },
+        FruitCallbackKey::Method("application:openFile:") => {
+            EventPayload::File(PathBuf::from(nsstring_to_string(obj)))
+        },
+        FruitCallbackKey::Method("pasteboardDidChange:") => {
+            EventPayload::ClipboardChanged(nsstring_to_string(obj).parse().unwrap_or(0))
+        },
+        // Launch notifications, named as AppKit names them
+        FruitCallbackKey::Method(method) if obj.is_null() && method.starts_with("application") => {
+            let name = method.trim_start_matches("application").trim_end_matches(':');
+            EventPayload::Notification(format!("NSApplication{}Notification", name))
+        },
+        _ if obj.is_null() => EventPayload::Empty,
+        _ => EventPayload::Other(nsstring_to_string(obj)),
+    }
+}
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn launch_bundle(_path: &std::path::Path, _options: &LaunchOptions) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn script_command_name(_command: *mut u64) -> String { "".into() }
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn preferred_languages() -> Vec<String> { vec![] }
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn current_locale() -> String { "".into() }
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn screens() -> Vec<Screen> { vec![] }
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn main_screen() -> Option<Screen> { None }
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn menu_bar_height() -> f64 { 0. }
+
+#[cfg(any(not(target_os = "macos"), feature = "dummy"))]
+/// Docs in OS X build.
+pub fn dock() -> Option<Dock> { None }
+
 /// API to move the executable into a Mac app bundle and relaunch (if necessary)
 ///
 /// Dummy implementation for non-OSX platforms.  See OS X build for proper
@@ -236,18 +857,323 @@ impl Trampoline {
     /// Docs in OS X build.
     pub fn resource(&mut self, _file: &str) -> &mut Self { self }
     /// Docs in OS X build.
+    pub fn acknowledgements(&mut self, _licenses: &[(&str, &str)]) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn resource_manifest(&mut self, _enable: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn scripting_definition(&mut self, _sdef: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn capture_output(&mut self, _log_path: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn layout(&mut self, _layout: BundleLayout) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn plist_format(&mut self, _format: PlistFormat) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn executable(&mut self, _path: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn agent(&mut self) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn ui_element(&mut self, _enable: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn background_only(&mut self, _enable: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn multiple_instances_prohibited(&mut self, _prohibit: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn service(&mut self, _menu_item: &str, _message: &str, _send_types: &[&str]) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn url_scheme(&mut self, _name: &str, _schemes: &[&str]) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn xpc_service(&mut self, _service: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn single_instance(&mut self, _single: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn terminal(&mut self, _terminal: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn launch_options(&mut self, _options: LaunchOptions) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn architectures(&mut self, _archs: &[Arch]) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn env(&mut self, _key: &str, _value: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn post_build<F>(&mut self, _hook: F) -> &mut Self
+        where F: Fn(&std::path::Path) -> Result<(), FruitError> + 'static { self }
+    /// Docs in OS X build.
+    pub fn copy_progress<F>(&mut self, _hook: F) -> &mut Self
+        where F: Fn(&std::path::Path, usize, usize) + 'static { self }
+    /// Docs in OS X build.
     pub fn resources(&mut self, _files: &Vec<&str>) -> &mut Self{ self }
     /// Docs in OS X build.
-    pub fn build(&mut self, dir: InstallDir) -> Result<FruitApp, FruitError> {
+    pub fn spotlight_importer(&mut self, _importer: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn quicklook_generator(&mut self, _generator: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn app_extension(&mut self, _appex: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn build<'a>(&mut self, dir: InstallDir) -> Result<FruitApp<'a>, FruitError> {
         self.self_bundle(dir)?;
         unreachable!()
     }
     /// Docs in OS X build.
-    pub fn self_bundle(&mut self, _dir: InstallDir) -> Result<(), FruitError> {
-        Err(FruitError::UnsupportedPlatform("fruitbasket disabled or not supported on this platform.".to_string()))
+    pub fn self_bundle(&self, _dir: InstallDir) -> Result<(), FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+    /// Docs in OS X build.
+    pub fn create_bundle(&self, _dir: InstallDir) -> Result<PathBuf, FruitError> {
+        Err(FruitError::UnsupportedPlatform)
     }
     /// Docs in OS X build.
     pub fn is_bundled() -> bool { false }
+    /// Docs in OS X build.
+    pub fn has_embedded_plist() -> bool { false }
+}
+
+/// Builder for a `FruitApp` configured before it finishes launching
+///
+/// Dummy implementation for non-OSX platforms.  See OS X build for proper
+/// documentation.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+#[derive(Default)]
+pub struct FruitAppBuilder {}
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+impl FruitAppBuilder {
+    /// Docs in OS X build.
+    pub fn new() -> FruitAppBuilder { FruitAppBuilder {} }
+    /// Docs in OS X build.
+    pub fn activation_policy(&mut self, _policy: ActivationPolicy) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn dock_icon(&mut self, _icon: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn delegate_methods(&mut self, _methods: &[&'static str]) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn terminate_after_last_window_closed(&mut self, _terminate: bool) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn class_prefix(&mut self, _prefix: &str) -> &mut Self { self }
+    /// Docs in OS X build.
+    pub fn build<'a>(&self) -> Result<FruitApp<'a>, FruitError> {
+        FruitApp::new()
+    }
+}
+
+/// A four-character code, as used for Apple event classes, IDs, and keywords
+///
+/// Packed big-endian into a `u32`, so `'GURL'` is `0x4755524c`.
+pub type FourCC = u32;
+
+/// An Apple event, with its parameters converted to Rust values
+///
+/// Returned by [parse_apple_event](parse_apple_event).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppleEvent {
+    /// Event class (ex: `kInternetEventClass`)
+    pub class: FourCC,
+    /// Event ID within the class (ex: `kAEGetURL`)
+    pub id: FourCC,
+    /// Parameters of the event, by keyword (ex: `keyDirectObject`)
+    pub params: std::collections::HashMap<FourCC, AEValue>,
+}
+
+/// A value in an Apple event
+#[derive(Debug, Clone, PartialEq)]
+pub enum AEValue {
+    /// Text, or any value that can be converted to text (ex: URLs)
+    Text(String),
+    /// Integer
+    Int(i32),
+    /// Floating point number
+    Real(f64),
+    /// Boolean
+    Bool(bool),
+    /// List of values
+    List(Vec<AEValue>),
+    /// Record of values, by keyword
+    Record(std::collections::HashMap<FourCC, AEValue>),
+    /// Explicitly empty value
+    Null,
+    /// Value of a type that can't be converted, with its descriptor type
+    Other(FourCC),
+}
+
+/// One of the core Apple events the system sends to every app
+///
+/// Passed to the callback given to `FruitApp::register_core_events()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreEvent {
+    /// Open the given documents, such as files double-clicked in Finder or
+    /// dropped on the app's icon
+    OpenDocuments(Vec<PathBuf>),
+    /// The app was launched again while already running, such as by
+    /// clicking its Dock icon.  Apps usually show their main window.
+    Reopen,
+    /// The user asked the app to quit
+    Quit,
+}
+
+/// The payload passed to a callback, converted to Rust values
+///
+/// Returned by `FruitApp::wait_for()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventPayload {
+    /// The callback was passed nil
+    Empty,
+    /// An NSNotification, by name (ex: from `applicationDidFinishLaunching:`)
+    Notification(String),
+    /// The URL of a `kAEGetURL` Apple event
+    Url(String),
+    /// Any other Apple event
+    AppleEvent(AppleEvent),
+    /// The path passed to `application:openFile:`
+    File(PathBuf),
+    /// The URLs passed to `application:openURLs:`
+    Urls(Vec<String>),
+    /// The clipboard's new change count, from `pasteboardDidChange:`
+    ClipboardChanged(i64),
+    /// Any other object, as its description
+    Other(String),
+}
+
+/// A boxed Fn type for receiving core Apple events
+pub type CoreEventCallback<'a> = Box<dyn Fn(CoreEvent) + 'a>;
+
+/// A boxed Fn type for opening files, returning why a file was rejected
+pub type OpenFileCallback<'a> = Box<dyn Fn(&Path) -> Result<(), String> + 'a>;
+
+/// How a file to open was passed to the app at launch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenSource {
+    /// A command-line argument
+    Argument,
+    /// An 'open documents' (odoc) Apple event
+    AppleEvent,
+    /// The `application:openFile:` delegate method
+    OpenFile,
+}
+
+/// A file the app was asked to open when it launched
+///
+/// Returned by `FruitApp::startup_open_requests()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenRequest {
+    /// Absolute path of the file
+    pub path: PathBuf,
+    /// Where the request came from.  If the same file arrived by more than
+    /// one path, this is the first one seen.
+    pub source: OpenSource,
+}
+
+/// Merge launch-time open requests with the command-line arguments
+///
+/// Arguments that don't name an existing file are skipped, as are the
+/// flags macOS itself passes (like `-psn_...`).  Paths are made absolute and
+/// requests for the same file are merged.
+pub(crate) fn merge_open_requests(received: &[OpenRequest]) -> Vec<OpenRequest> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let args = std::env::args_os().skip(1)
+        .filter(|arg| !arg.to_string_lossy().starts_with('-'))
+        .map(|arg| OpenRequest { path: PathBuf::from(arg), source: OpenSource::Argument })
+        .filter(|req| req.path.is_file());
+    let mut merged: Vec<OpenRequest> = Vec::new();
+    for mut req in args.chain(received.iter().cloned()) {
+        req.path = cwd.join(&req.path);
+        if let Ok(path) = req.path.canonicalize() {
+            req.path = path;
+        }
+        if !merged.iter().any(|seen| seen.path == req.path) {
+            merged.push(req);
+        }
+    }
+    merged
+}
+
+/// Statistics about the work done by the event loop
+///
+/// Returned by `FruitApp::stats()`.  Counters accumulate over every call to
+/// `run()` for the lifetime of the `FruitApp`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunLoopStats {
+    /// Number of event loop iterations
+    pub iterations: u64,
+    /// Number of events dispatched
+    pub events: u64,
+    /// Total time spent dispatching events, including time spent in callbacks
+    pub dispatch_time: Duration,
+    /// Number of times the autorelease pool was drained
+    pub pool_drains: u64,
+}
+
+impl RunLoopStats {
+    /// Average time taken to dispatch a single event
+    ///
+    /// A high average usually means a callback is doing too much work on the
+    /// main thread, which causes the UI to stutter.
+    pub fn avg_dispatch_latency(&self) -> Duration {
+        match self.events {
+            0 => Duration::from_secs(0),
+            n => Duration::from_nanos((self.dispatch_time.as_nanos() / n as u128) as u64),
+        }
+    }
+}
+
+/// A display connected to the Mac
+///
+/// Returned by [screens](screens).  Positions and sizes are in points, with
+/// the origin at the bottom-left corner of the main screen and y increasing
+/// upwards, as AppKit lays out windows.  Multiply by `scale` for pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+    /// Localized name of the display (ex: `Built-in Retina Display`)
+    pub name: String,
+    /// Left edge of the display, in points
+    pub x: f64,
+    /// Bottom edge of the display, in points
+    pub y: f64,
+    /// Width of the display, in points
+    pub width: f64,
+    /// Height of the display, in points
+    pub height: f64,
+    /// Left edge of the area not covered by the menu bar or Dock
+    pub visible_x: f64,
+    /// Bottom edge of the area not covered by the menu bar or Dock
+    pub visible_y: f64,
+    /// Width of the area not covered by the menu bar or Dock
+    pub visible_width: f64,
+    /// Height of the area not covered by the menu bar or Dock
+    pub visible_height: f64,
+    /// Pixels per point: 2.0 on Retina displays, 1.0 otherwise
+    pub scale: f64,
+    /// Whether this is the screen with the active window and menu bar focus
+    pub main: bool,
+}
+
+impl Screen {
+    /// Size of the display in pixels, for sizing a framebuffer to fill it
+    pub fn pixel_size(&self) -> (u32, u32) {
+        ((self.width * self.scale).round() as u32, (self.height * self.scale).round() as u32)
+    }
+}
+
+/// Edge of the screen the Dock is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DockPosition {
+    /// Bottom edge
+    Bottom,
+    /// Left edge
+    Left,
+    /// Right edge
+    Right,
+}
+
+/// Where the Dock is, and how much of the screen it takes up
+///
+/// Returned by [dock](dock).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dock {
+    /// Edge of the screen the Dock is attached to
+    pub position: DockPosition,
+    /// Space reserved for the Dock, in points: its height at the bottom of
+    /// the screen, or its width at the sides
+    pub size: f64,
+    /// Index of the Dock's screen in [screens](screens)
+    pub screen: usize,
 }
 
 /// Options for how long to run the event loop on each call
@@ -259,9 +1185,36 @@ pub enum RunPeriod {
     Forever,
     /// Run event loop at least the specified length of time
     Time(Duration),
+    /// Dispatch at most the specified number of pending events and return,
+    /// without waiting for more
+    Events(usize),
+}
+
+/// ObjC signature of a method added with
+/// [add_delegate_method](FruitApp::add_delegate_method)
+///
+/// Determines which argument of the ObjC method is passed to the callback.
+/// Added methods return nothing, so delegate methods that expect a return
+/// value can't be added.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MethodSignature {
+    /// `- (void)method;` passes nil to the callback
+    NoArgs,
+    /// `- (void)method:(id)arg;` passes `arg`, such as the NSNotification of
+    /// `applicationDidBecomeActive:`
+    OneArg,
+    /// `- (void)method:(id)first with:(id)second;` passes `second`, such as
+    /// the token of `application:didRegisterForRemoteNotificationsWithDeviceToken:`,
+    /// whose first argument is the NSApplication
+    TwoArgs,
+    /// `- (void)method:(NSPasteboard *)pboard userData:(NSString *)data
+    /// error:(NSString **)error;` passes `pboard`, for services offered
+    /// with `Trampoline::service()`
+    Service,
 }
 
 /// Policies controlling how a Mac application's UI is interacted with
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ActivationPolicy {
     /// Appears in the Dock and menu bar and can have an interactive UI with windows
     Regular,
@@ -271,33 +1224,181 @@ pub enum ActivationPolicy {
     Prohibited,
 }
 
-/// Class for errors generated by fruitbasket.  Dereferences to a String.
+/// Stages of the app's launch, as reported by
+/// [FruitApp::launch_phase](FruitApp::launch_phase)
+///
+/// Phases only move forward, in the order listed, so they can be compared:
+/// `app.launch_phase() >= LaunchPhase::DidFinish` once the app has launched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LaunchPhase {
+    /// `run()` hasn't been called yet
+    NotLaunched,
+    /// The first `run()` call has started launching the app, and the
+    /// `applicationWillFinishLaunching:` callback has been called
+    WillFinish,
+    /// The `applicationDidFinishLaunching:` callback has been called, during
+    /// the current `run()` call
+    DidFinish,
+    /// Launching finished during an earlier `run()` call
+    Running,
+}
+
+/// How the app was started, as reported by
+/// [FruitApp::launch_context](FruitApp::launch_context)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaunchContext {
+    /// Opened as a login item when the user logged in.  Apps usually start
+    /// without showing windows.
+    LoginItem,
+    /// Relaunched from its app bundle by a [Trampoline](Trampoline)
+    Trampoline,
+    /// Run from a shell, such as in Terminal or by `cargo run`
+    Terminal,
+    /// Started by another app, such as Finder, the Dock, or an app that
+    /// opened a document with it
+    App {
+        /// Process ID of the app that started this one
+        pid: i32,
+        /// Bundle identifier of the app that started this one, if it has one
+        bundle_id: Option<String>,
+    },
+    /// Launched by LaunchServices, without saying which app asked it to
+    LaunchServices,
+    /// Not known yet, because the app hasn't started launching
+    Unknown,
+}
+
+/// Class for errors generated by fruitbasket
+///
+/// Each variant describes a distinct cause of failure, so callers can decide
+/// programmatically how to react.  Underlying I/O errors are available from
+/// `source()`.
 #[derive(Debug)]
 pub enum FruitError {
     /// fruitbasket doesn't run on this platform (safe to ignore)
-    UnsupportedPlatform(String),
+    UnsupportedPlatform,
     /// Disk I/O errors: failed to write app bundle to disk
-    IOError(String),
-    /// Any other unclassified error
-    GeneralError(String),
+    IOError(std::io::Error),
+    /// Failed to copy a file into the app bundle
+    ResourceCopyError {
+        /// Path of the file that could not be copied
+        path: PathBuf,
+        /// Underlying I/O error
+        source: std::io::Error,
+    },
+    /// Failed to generate a valid Info.plist
+    PlistError(String),
+    /// Failed to code sign the app bundle, or to check a code signature
+    SignError(String),
+    /// LaunchServices failed to launch the app bundle
+    LaunchError {
+        /// OSStatus error code returned by LaunchServices
+        os_status: i32,
+    },
+    /// LaunchServices accepted the app bundle, but it did not start running
+    LaunchFailed {
+        /// OSStatus error code describing the failure
+        os_status: i32,
+    },
+    /// Operation requires running from a Mac app bundle
+    NotBundled,
+    /// Operation must be performed on the main thread
+    MainThreadViolation,
+    /// Operation requires a cargo feature that was not enabled at compile time
+    FeatureDisabled(&'static str),
+    /// Failed to configure the logging backend
+    LoggerError(String),
+    /// Failed to create or resolve a security-scoped bookmark
+    BookmarkError(String),
+    /// Keychain Services returned an error
+    KeychainError {
+        /// OSStatus error code returned by Keychain Services
+        os_status: i32,
+    },
+    /// An update bundle failed verification, or could not be installed
+    UpdateError(String),
+    /// A required Objective-C class is not available in the runtime
+    ClassNotFound(String),
+    /// The Objective-C runtime rejected a class or method definition
+    ObjcError(String),
+    /// NSApplication is already managed by another FruitApp or framework
+    AlreadyInitialized,
+    /// The App Store receipt is malformed, or wasn't issued for this app
+    ReceiptError(String),
+    /// The executable can't run on this Mac, or lacks code for an
+    /// architecture the bundle declares
+    ArchitectureError(String),
+    /// AppKit doesn't allow the running app to change between these
+    /// activation policies
+    ActivationPolicyError {
+        /// The app's current activation policy
+        from: ActivationPolicy,
+        /// The requested activation policy
+        to: ActivationPolicy,
+    },
+    /// A bundled resource doesn't match the bundle's resource manifest
+    ResourceVerifyError {
+        /// Path of the resource, or of the manifest if it couldn't be read
+        path: PathBuf,
+        /// Why verification failed
+        reason: String,
+    },
+    /// A system sharing service couldn't share the given items
+    ShareError(String),
+    /// The notary service rejected a bundle, or couldn't be reached
+    NotarizeError(String),
 }
 
 impl std::fmt::Display for FruitError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match *self {
+            FruitError::UnsupportedPlatform =>
+                write!(f, "fruitbasket disabled or not supported on this platform"),
+            FruitError::IOError(ref e) => write!(f, "I/O error: {}", e),
+            FruitError::ResourceCopyError { ref path, ref source } =>
+                write!(f, "failed to copy {} into bundle: {}", path.display(), source),
+            FruitError::PlistError(ref s) => write!(f, "invalid Info.plist: {}", s),
+            FruitError::SignError(ref s) => write!(f, "code signing failed: {}", s),
+            FruitError::LaunchError { os_status } =>
+                write!(f, "failed to launch app bundle (OSStatus {})", os_status),
+            FruitError::LaunchFailed { os_status } =>
+                write!(f, "app bundle did not start running (OSStatus {})", os_status),
+            FruitError::NotBundled => write!(f, "not running from an app bundle"),
+            FruitError::MainThreadViolation => write!(f, "must be called from the main thread"),
+            FruitError::FeatureDisabled(feature) =>
+                write!(f, "must recompile with '{}' feature", feature),
+            FruitError::LoggerError(ref s) => write!(f, "failed to configure logger: {}", s),
+            FruitError::BookmarkError(ref s) => write!(f, "bookmark error: {}", s),
+            FruitError::KeychainError { os_status } =>
+                write!(f, "keychain error (OSStatus {})", os_status),
+            FruitError::UpdateError(ref s) => write!(f, "update failed: {}", s),
+            FruitError::ClassNotFound(ref s) => write!(f, "Objective-C class not found: {}", s),
+            FruitError::ObjcError(ref s) => write!(f, "Objective-C runtime error: {}", s),
+            FruitError::AlreadyInitialized =>
+                write!(f, "NSApplication already has a delegate from another FruitApp or framework"),
+            FruitError::ReceiptError(ref s) => write!(f, "invalid App Store receipt: {}", s),
+            FruitError::ArchitectureError(ref s) => write!(f, "unsupported architecture: {}", s),
+            FruitError::ActivationPolicyError { from, to } =>
+                write!(f, "can't change activation policy from {:?} to {:?}", from, to),
+            FruitError::ResourceVerifyError { ref path, ref reason } =>
+                write!(f, "resource {} failed verification: {}", path.display(), reason),
+            FruitError::ShareError(ref s) => write!(f, "sharing failed: {}", s),
+            FruitError::NotarizeError(ref s) => write!(f, "notarization failed: {}", s),
+        }
     }
 }
 impl From<std::io::Error> for FruitError {
     fn from(error: std::io::Error) -> Self {
-        FruitError::IOError(error.to_string())
+        FruitError::IOError(error)
     }
 }
 impl Error for FruitError {
-    fn description(&self) -> &str {
-        "Hmm"
-    }
-    fn cause(&self) -> Option<&dyn Error> {
-        None
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            FruitError::IOError(ref e) => Some(e),
+            FruitError::ResourceCopyError { ref source, .. } => Some(source),
+            _ => None,
+        }
     }
 }
 
@@ -309,7 +1410,7 @@ impl Error for FruitError {
 /// from any thread.
 #[derive(Clone)]
 pub struct FruitStopper {
-    tx: Sender<()>,
+    tx: CommandSender,
 }
 impl FruitStopper {
     /// Stop the run loop on the `FruitApp` instance that created this object
@@ -317,7 +1418,208 @@ impl FruitStopper {
     /// This is equivalent to passing the object to [FruitApp::stop](FruitApp::stop).  See it
     /// for more documentation.
     pub fn stop(&self) {
-        let _ = self.tx.send(());
+        let _ = self.tx.send(FruitCommand::Stop);
+    }
+}
+
+/// An opaque, thread-safe object that can control the app from any thread.
+///
+/// Like [FruitStopper](FruitStopper), but able to request more than just
+/// stopping the run loop.  AppKit may only be used from the main thread, so
+/// each request is queued and performed by the `FruitApp` that created this
+/// object the next time its run loop is pumped.  Requests are silently
+/// dropped if that `FruitApp` no longer exists.
+#[derive(Clone)]
+pub struct FruitHandle {
+    tx: CommandSender,
+}
+impl FruitHandle {
+    /// Stop the run loop, equivalent to [FruitStopper::stop](FruitStopper::stop)
+    pub fn stop(&self) {
+        let _ = self.tx.send(FruitCommand::Stop);
+    }
+    /// Queue a call to [FruitApp::set_activation_policy](FruitApp::set_activation_policy)
+    pub fn set_activation_policy(&self, policy: ActivationPolicy) {
+        let _ = self.tx.send(FruitCommand::SetActivationPolicy(policy, false));
+    }
+    /// Queue a call to [FruitApp::change_activation_policy](FruitApp::change_activation_policy)
+    ///
+    /// Errors are logged, since the change happens on the main thread later.
+    pub fn change_activation_policy(&self, policy: ActivationPolicy, activate: bool) {
+        let _ = self.tx.send(FruitCommand::SetActivationPolicy(policy, activate));
+    }
+    /// Queue a call to [FruitApp::set_dock_badge](FruitApp::set_dock_badge)
+    pub fn set_dock_badge(&self, badge: Option<&str>) {
+        let _ = self.tx.send(FruitCommand::SetDockBadge(badge.map(String::from)));
+    }
+    /// Queue a call to [FruitApp::request_attention](FruitApp::request_attention)
+    pub fn request_attention(&self, critical: bool) {
+        let _ = self.tx.send(FruitCommand::RequestAttention(critical));
+    }
+}
+
+/// A menu bar (agent) app, ready to run
+///
+/// Returned by [FruitApp::new_agent](FruitApp::new_agent).  Keep the status
+/// item alive for as long as it should stay in the menu bar.
+pub struct AgentApp<'a> {
+    /// The app, with the Accessory activation policy already set
+    pub app: FruitApp<'a>,
+    /// The app's item in the menu bar
+    pub status_item: status::StatusItem,
+}
+
+/// Receives the URLs the app is asked to open
+///
+/// The high-level way to handle custom URL schemes.  Declare the schemes with
+/// [Trampoline::url_scheme](Trampoline::url_scheme), then create a
+/// `UrlHandler` from the `FruitApp`, and read URLs from it while pumping the
+/// run loop:
+///
+/// ```no_run
+/// # use fruitbasket::*;
+/// let mut app = Trampoline::new("myapp", "myapp", "com.example.myapp")
+///     .url_scheme("My App URL", &["myapp"])
+///     .build(InstallDir::Temp).unwrap();
+/// let urls = UrlHandler::new(&mut app);
+/// loop {
+///     let _ = app.run(RunPeriod::Once);
+///     for url in urls.drain() {
+///         println!("Opened: {}", url);
+///     }
+/// }
+/// ```
+///
+/// URLs are received from `kAEGetURL` Apple events and from the
+/// `application:openURLs:` delegate method, and URLs that arrived before the
+/// handler was created, such as the one that launched the app, are delivered
+/// too.  Universal links, https URLs for domains declared with
+/// [Entitlements::associated_domains](entitlements/struct.Entitlements.html#method.associated_domains),
+/// are received from the `application:continueUserActivity:restorationHandler:`
+/// delegate method.  This replaces any callbacks registered for those
+/// three methods.
+pub struct UrlHandler {
+    rx: std::sync::mpsc::Receiver<String>,
+}
+impl UrlHandler {
+    /// Start receiving URLs opened with the app
+    pub fn new(app: &mut FruitApp) -> UrlHandler {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let event_tx = tx.clone();
+        let list_tx = tx.clone();
+        app.register_apple_event(kInternetEventClass, kAEGetURL);
+        app.register_callback(FruitCallbackKey::Method("handleEvent:withReplyEvent:"),
+                              Box::new(move |event| {
+                                  let url = parse_url_event(event);
+                                  if !url.is_empty() {
+                                      let _ = event_tx.send(url);
+                                  }
+                              }));
+        app.register_callback(FruitCallbackKey::Method("application:openURLs:"),
+                              Box::new(move |urls| {
+                                  for url in parse_url_list(urls) {
+                                      let _ = list_tx.send(url);
+                                  }
+                              }));
+        app.register_callback(
+            FruitCallbackKey::Method("application:continueUserActivity:restorationHandler:"),
+            Box::new(move |activity| {
+                if let Some(url) = parse_universal_link(activity) {
+                    let _ = tx.send(url);
+                }
+            }));
+        UrlHandler { rx }
+    }
+    /// Next URL received, if any, without waiting
+    pub fn try_recv(&self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+    /// All URLs received since the last call, oldest first
+    pub fn drain(&self) -> Vec<String> {
+        self.rx.try_iter().collect()
+    }
+    /// The underlying channel, for receiving URLs on another thread
+    ///
+    /// URLs are only received while the `FruitApp` run loop is running, so
+    /// don't block the main thread waiting on this.
+    pub fn into_receiver(self) -> std::sync::mpsc::Receiver<String> {
+        self.rx
+    }
+}
+
+/// Requests sent to the run loop from `FruitStopper` and `FruitHandle`
+pub(crate) enum FruitCommand {
+    Stop,
+    SetActivationPolicy(ActivationPolicy, bool),
+    SetDockBadge(Option<String>),
+    RequestAttention(bool),
+}
+
+/// Sends requests to the run loop, waking any external event loop polling it
+#[derive(Clone)]
+pub(crate) struct CommandSender {
+    tx: Sender<FruitCommand>,
+    waker: waker::Waker,
+}
+
+impl CommandSender {
+    pub(crate) fn send(&self, cmd: FruitCommand)
+                       -> Result<(), std::sync::mpsc::SendError<FruitCommand>> {
+        self.tx.send(cmd)?;
+        self.waker.wake();
+        Ok(())
+    }
+}
+
+/// Create the channel for requests to the run loop, and its wake-up pipe
+pub(crate) fn command_channel()
+    -> (CommandSender, std::sync::mpsc::Receiver<FruitCommand>, waker::WakeReceiver) {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (waker, wake) = waker::pair();
+    (CommandSender { tx, waker }, rx, wake)
+}
+
+/// Directory layouts for generated app bundles
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BundleLayout {
+    /// Mac app layout, with everything in `Contents/` and the executable in
+    /// `Contents/MacOS/`.  Also used by Mac Catalyst apps.
+    #[default]
+    MacOS,
+    /// Flat iOS app layout, with the executable, Info.plist, and resources
+    /// all at the root of the bundle.  For the iOS simulator and devices.
+    IOS,
+}
+
+/// File formats for generated Info.plist files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlistFormat {
+    /// Old-style OpenStep text, the format values are given in
+    #[default]
+    OpenStep,
+    /// XML property list, the format Xcode writes
+    Xml,
+    /// Binary property list, which is smaller and faster to parse
+    Binary,
+}
+
+/// CPU architectures a Mac app can contain code for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    /// 64-bit ARM, for Apple silicon
+    Arm64,
+    /// 64-bit Intel
+    X86_64,
+}
+
+impl Arch {
+    /// Name of the architecture as macOS spells it, such as in
+    /// `LSArchitecturePriority` (ex: `arm64`)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Arch::Arm64 => "arm64",
+            Arch::X86_64 => "x86_64",
+        }
     }
 }
 
@@ -329,20 +1631,117 @@ pub enum InstallDir {
     SystemApplications,
     /// Store in the user-specific Application directory (current user)
     UserApplications,
-    /// Store in a custom directory, specified as a String
+    /// Store in a custom directory, specified as a String.  `~` and
+    /// environment variables (`$HOME`, `${VAR}`) are expanded, relative paths
+    /// are resolved against the directory the program was started from, and
+    /// the directory is created if it doesn't exist.
     Custom(String),
+    /// Store in a subdirectory named after the bundle version, for keeping
+    /// several versions side by side (ex: `<dir>/1.2.3/MyApp.app`)
+    Versioned {
+        /// Directory holding one subdirectory per version, expanded and
+        /// created as for `Custom`
+        dir: String,
+        /// Whether to point a `Current` symlink in `dir` at the version just
+        /// built, so `<dir>/Current/MyApp.app` is always the latest bundle
+        current_link: bool,
+    },
+}
+
+/// Options for how to launch an app bundle
+///
+/// Used by [launch_bundle](fn.launch_bundle.html), and by a
+/// [Trampoline](struct.Trampoline.html) when it launches the bundle it
+/// built.  The default launches one instance, in the foreground, with no
+/// arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaunchOptions {
+    /// Command-line arguments to pass to the app
+    pub arguments: Vec<String>,
+    /// Environment variables to set for the app, in addition to the ones
+    /// LaunchServices provides
+    pub environment: Vec<(String, String)>,
+    /// Whether to bring the app to the foreground
+    pub activates: bool,
+    /// Whether to hide the app once it launches
+    pub hides: bool,
+    /// Whether to launch another instance if the app is already running,
+    /// instead of activating the running one
+    pub creates_new_instance: bool,
+}
+
+impl Default for LaunchOptions {
+    fn default() -> LaunchOptions {
+        LaunchOptions {
+            arguments: Vec::new(),
+            environment: Vec::new(),
+            activates: true,
+            hides: false,
+            creates_new_instance: false,
+        }
+    }
 }
 
 /// Options for where to save logging output generated by fruitbasket
 pub enum LogDir {
-    /// User's home directory
+    /// User's home directory, or the sandbox container if sandboxed
     Home,
     /// Temporary directory (as specified by OS)
     Temp,
-    /// Custom location, provided as a String
+    /// Custom location, provided as a String.  Expanded and created as for
+    /// `InstallDir::Custom`, with relative paths resolved against the
+    /// directory the program was started from, even after a `Trampoline`
+    /// relaunches it.
     Custom(String),
 }
 
+/// Environment variable in which a `Trampoline` passes the directory the
+/// program was started from to the relaunched bundle
+#[cfg(any(all(target_os = "macos", not(feature="dummy")), feature = "logging"))]
+pub(crate) const ORIGINAL_DIR_ENV: &str = "FRUITBASKET_ORIGINAL_DIR";
+
+/// Expand `~` and environment variables in a path, and make it absolute
+///
+/// Relative paths are resolved against the directory the program was
+/// originally started from.  Unset variables expand to nothing, as in a shell.
+#[cfg(any(all(target_os = "macos", not(feature="dummy")), feature = "logging"))]
+pub(crate) fn expand_path(path: &str) -> PathBuf {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&sandbox::home_dir().unwrap_or_default().to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            }
+        } else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        match name {
+            "" => expanded.push('$'),
+            name => expanded.push_str(&std::env::var(name).unwrap_or_default()),
+        }
+        rest = after;
+    }
+    expanded.push_str(rest);
+    let path = PathBuf::from(expanded);
+    if path.is_absolute() {
+        return path;
+    }
+    std::env::var_os(ORIGINAL_DIR_ENV).map(PathBuf::from)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default()
+        .join(path)
+}
+
 /// Enable logging to rolling log files with Rust `log` library
 ///
 /// Requires the 'logging' feature to be specified at compile time.
@@ -359,6 +1758,12 @@ pub enum LogDir {
 ///
 /// `(backup_count + 1) * max_size_mb`
 ///
+/// Messages are logged at the `Info` level and above by default.  This can be
+/// changed at runtime with the `RUST_LOG` environment variable, which accepts
+/// a comma-separated list of levels (`RUST_LOG=debug`) and per-module levels
+/// (`RUST_LOG=warn,my_app::net=trace`), as with `env_logger`.  `RUST_LOG` is
+/// forwarded to the app when a `Trampoline` relaunches it from a bundle.
+///
 /// # Arguments
 ///
 /// `filename` - Filename for the log file, *without* path
@@ -380,7 +1785,7 @@ pub enum LogDir {
 pub fn create_logger(filename: &str,
                      dir: LogDir,
                      max_size_mb: u32,
-                     backup_count: u32) -> Result<String, String> {
+                     backup_count: u32) -> Result<String, FruitError> {
     use log::LevelFilter;
     use self::log4rs::append::console::ConsoleAppender;
     use self::log4rs::append::rolling_file::RollingFileAppender;
@@ -391,9 +1796,13 @@ pub fn create_logger(filename: &str,
     use self::log4rs::config::{Appender, Config, Logger, Root};
 
     let log_path = match dir {
-        LogDir::Home => format!("{}/{}", dirs::home_dir().unwrap().display(), filename),
+        LogDir::Home => format!("{}/{}", sandbox::home_dir().unwrap().display(), filename),
         LogDir::Temp => format!("{}/{}", std::env::temp_dir().display(), filename),
-        LogDir::Custom(s) => format!("{}/{}", s, filename),
+        LogDir::Custom(s) => {
+            let dir = expand_path(&s);
+            std::fs::create_dir_all(&dir)?;
+            format!("{}/{}", dir.display(), filename)
+        },
     };
     let stdout = ConsoleAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{m}{n}")))
@@ -406,21 +1815,73 @@ pub fn create_logger(filename: &str,
         .build(&log_path, policy)
         .unwrap();
 
-    let config = Config::builder()
+    let (root_level, mut directives) = match std::env::var("RUST_LOG") {
+        Ok(spec) => parse_log_filter(&spec),
+        Err(_) => (LevelFilter::Info, Vec::new()),
+    };
+    let level_for = |directives: &mut Vec<(String, LevelFilter)>, target: &str| {
+        match directives.iter().position(|d| d.0 == target) {
+            Some(idx) => directives.remove(idx).1,
+            None => LevelFilter::Info,
+        }
+    };
+    let db_level = level_for(&mut directives, "app::backend::db");
+    let requests_level = level_for(&mut directives, "app::requests");
+    let mut config = Config::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
         .appender(Appender::builder().build("requests", Box::new(rolling)))
-        .logger(Logger::builder().build("app::backend::db", LevelFilter::Info))
+        .logger(Logger::builder().build("app::backend::db", db_level))
         .logger(Logger::builder()
                 .appender("requests")
                 .additive(false)
-                .build("app::requests", LevelFilter::Info))
-        .build(Root::builder().appender("stdout").appender("requests").build(LevelFilter::Info))
+                .build("app::requests", requests_level));
+    for (target, level) in directives {
+        config = config.logger(Logger::builder().build(target, level));
+    }
+    let config = config
+        .build(Root::builder().appender("stdout").appender("requests").build(root_level))
         .unwrap();
     match log4rs::init_config(config) {
         Ok(_) => Ok(log_path),
-        Err(e) => Err(e.to_string()),
+        Err(e) => Err(FruitError::LoggerError(e.to_string())),
+    }
+}
+
+/// Parse a `RUST_LOG`-style filter into a root level and per-module levels
+///
+/// Each comma-separated directive is either a level (`debug`), a module path
+/// (`my_app::net`, which enables all levels for it), or both (`my_app=warn`).
+/// Directives that can't be parsed are ignored.  The last root level given
+/// wins, and the root level defaults to `Info`.
+#[cfg(feature = "logging")]
+fn parse_log_filter(spec: &str) -> (log::LevelFilter, Vec<(String, log::LevelFilter)>) {
+    use log::LevelFilter;
+    let mut root = LevelFilter::Info;
+    let mut targets: Vec<(String, LevelFilter)> = Vec::new();
+    for directive in spec.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+        let mut parts = directive.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let (target, level) = match parts.next() {
+            Some(level) => match level.trim().parse() {
+                Ok(level) => (Some(name), level),
+                Err(_) => continue,
+            },
+            None => match name.parse() {
+                Ok(level) => (None, level),
+                Err(_) => (Some(name), LevelFilter::Trace),
+            },
+        };
+        match target {
+            Some(target) => {
+                targets.retain(|t| t.0 != target);
+                targets.push((target.to_string(), level));
+            },
+            None => root = level,
+        }
     }
+    (root, targets)
 }
+
 /// Enable logging to rolling log files with Rust `log` library
 ///
 /// Requires the 'logging' feature to be specified at compile time.
@@ -437,6 +1898,12 @@ pub fn create_logger(filename: &str,
 ///
 /// `(backup_count + 1) * max_size_mb`
 ///
+/// Messages are logged at the `Info` level and above by default.  This can be
+/// changed at runtime with the `RUST_LOG` environment variable, which accepts
+/// a comma-separated list of levels (`RUST_LOG=debug`) and per-module levels
+/// (`RUST_LOG=warn,my_app::net=trace`), as with `env_logger`.  `RUST_LOG` is
+/// forwarded to the app when a `Trampoline` relaunches it from a bundle.
+///
 /// # Arguments
 ///
 /// `filename` - Filename for the log file, *without* path
@@ -459,5 +1926,77 @@ pub fn create_logger(_filename: &str,
                      _dir: LogDir,
                      _max_size_mb: u32,
                      _backup_count: u32) -> Result<String, FruitError> {
-    Err(FruitError::GeneralError("Must recompile with 'logging' feature to use logger.".to_string()))
+    Err(FruitError::FeatureDisabled("logging"))
+}
+
+/// Report panics in a bundled app, which otherwise die silently
+///
+/// Apps launched from a bundle have no terminal attached, so the default
+/// panic message is lost and the app simply vanishes.  This installs a panic
+/// hook that records the panic message and a backtrace, optionally shows the
+/// user an alert, and then terminates the process.
+///
+/// The panic is written to the `log` logger if one was configured (see
+/// [create_logger](create_logger)), and to stderr otherwise.  Combine with
+/// [Trampoline::capture_output](Trampoline::capture_output) to keep stderr.
+///
+/// Since the process is terminated, panics can no longer be caught with
+/// `std::panic::catch_unwind` once this is installed.
+///
+/// # Arguments
+///
+/// `show_alert` - If true, show a modal alert with the panic message before
+/// terminating.  The alert is only shown for panics on the main thread.
+pub fn install_panic_handler(show_alert: bool) {
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        let report = format!("thread '{}' {}\n{}",
+                             thread.name().unwrap_or("<unnamed>"),
+                             info,
+                             std::backtrace::Backtrace::force_capture());
+        #[cfg(feature = "logging")]
+        let logged = log::max_level() != log::LevelFilter::Off;
+        #[cfg(not(feature = "logging"))]
+        let logged = false;
+        if logged {
+            #[cfg(feature = "logging")]
+            error!("{}", report);
+        } else {
+            eprintln!("{}", report);
+        }
+        #[cfg(all(target_os = "macos", not(feature="dummy")))]
+        {
+            if show_alert && osx::is_main_thread() {
+                osx::show_panic_alert(&info.to_string());
+            }
+        }
+        #[cfg(any(not(target_os = "macos"), feature="dummy"))]
+        let _ = show_alert;
+        std::process::exit(101);
+    }));
+}
+
+#[cfg(all(test, any(not(target_os = "macos"), feature="dummy")))]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn launch_callbacks_run_once_in_order() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mut app = FruitApp::new().unwrap();
+        for &method in &["applicationWillFinishLaunching:", "applicationDidFinishLaunching:"] {
+            let calls = calls.clone();
+            app.register_callback(FruitCallbackKey::Method(method),
+                                  Box::new(move |_| calls.borrow_mut().push(method)));
+        }
+        assert_eq!(app.launch_phase(), LaunchPhase::NotLaunched);
+        for _ in 0..5 {
+            let _ = app.run(RunPeriod::Once);
+        }
+        assert_eq!(*calls.borrow(), vec!["applicationWillFinishLaunching:",
+                                         "applicationDidFinishLaunching:"]);
+        assert_eq!(app.launch_phase(), LaunchPhase::Running);
+    }
 }
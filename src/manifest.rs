@@ -0,0 +1,436 @@
+//! Checksums of the resources bundled in an app
+//!
+//! When a [Trampoline](../struct.Trampoline.html) is built with
+//! [resource_manifest](../struct.Trampoline.html#method.resource_manifest)
+//! enabled, it writes `manifest.json` to the bundle's Resources directory,
+//! listing the SHA-256 hash of every bundled resource:
+//!
+//! ```json
+//! {
+//!   "sha256": {
+//!     "icon.png": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//!   }
+//! }
+//! ```
+//!
+//! At runtime, [verify_resources](verify_resources) checks the files against
+//! the manifest, so an app can detect assets that were modified or not
+//! completely copied before it relies on them.
+
+use std::io::Read;
+use std::path::Path;
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::path::PathBuf;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::class;
+
+/// Name of the manifest file in the Resources directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Check the running app's bundled resources against its manifest
+///
+/// # Returns
+///
+/// `FruitError::NotBundled` if the app is not running from a bundle.
+/// `FruitError::ResourceVerifyError` naming the first file that is missing,
+/// unreadable, or doesn't match its checksum, or the manifest itself if it is
+/// missing or malformed.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn verify_resources() -> Result<(), FruitError> {
+    if !::Trampoline::is_bundled() {
+        return Err(FruitError::NotBundled);
+    }
+    let cls = class("NSBundle")?;
+    let dir = unsafe {
+        let bundle: *mut Object = msg_send![cls, mainBundle];
+        let url: *mut Object = msg_send![bundle, resourceURL];
+        ::foundation::nsurl_to_path(url)
+    };
+    verify_dir(&dir.ok_or(FruitError::NotBundled)?)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn verify_resources() -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Check the resources in a directory against the manifest in it
+///
+/// Like [verify_resources](verify_resources), for a bundle other than the
+/// running app, such as a downloaded update.
+///
+/// # Arguments
+///
+/// `resources_dir` - Directory containing `manifest.json`, usually the
+/// `Contents/Resources` directory of an app bundle
+pub fn verify_dir(resources_dir: &Path) -> Result<(), FruitError> {
+    let manifest_path = resources_dir.join(MANIFEST_FILE);
+    let fail = |path: &Path, reason: &str| FruitError::ResourceVerifyError {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+    let manifest = std::fs::read_to_string(&manifest_path)
+        .map_err(|e| fail(&manifest_path, &e.to_string()))?;
+    let entries = parse(&manifest).ok_or_else(|| fail(&manifest_path, "malformed manifest"))?;
+    for (name, expected) in entries {
+        let path = resources_dir.join(&name);
+        if name.split('/').any(|part| part == ".." || part.is_empty()) {
+            return Err(fail(&path, "path outside of the resources directory"));
+        }
+        let actual = hash_file(&path).map_err(|e| fail(&path, &e.to_string()))?;
+        if !actual.eq_ignore_ascii_case(&expected) {
+            return Err(fail(&path, "checksum mismatch"));
+        }
+    }
+    Ok(())
+}
+
+/// Write a manifest of the given files to `resources_dir`
+///
+/// `files` are paths relative to `resources_dir`.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn write(resources_dir: &Path, files: &[PathBuf]) -> Result<(), FruitError> {
+    let mut json = String::from("{\n  \"sha256\": {");
+    for (i, file) in files.iter().enumerate() {
+        let path = resources_dir.join(file);
+        let hash = hash_file(&path).map_err(|e| FruitError::ResourceCopyError {
+            path: path.clone(),
+            source: e,
+        })?;
+        let name = file.to_string_lossy().replace('\\', "/");
+        json.push_str(if i == 0 { "\n" } else { ",\n" });
+        json.push_str(&format!("    {}: \"{}\"", json_quote(&name), hash));
+    }
+    json.push_str("\n  }\n}\n");
+    std::fs::write(resources_dir.join(MANIFEST_FILE), json)?;
+    Ok(())
+}
+
+/// Quote a string as a JSON string literal
+///
+/// Unlike an OpenStep plist string, control characters must be `\u` escaped.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn json_quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Parse the file names and hashes out of a manifest
+///
+/// Only understands the structure written by [write](write): an object
+/// whose `sha256` member is an object of strings.
+fn parse(json: &str) -> Option<Vec<(String, String)>> {
+    let mut parser = Parser { chars: json.chars().peekable() };
+    let mut entries = None;
+    parser.expect('{')?;
+    if parser.peek()? == '}' {
+        return None;
+    }
+    loop {
+        let key = parser.string()?;
+        parser.expect(':')?;
+        if key == "sha256" {
+            entries = Some(parser.string_object()?);
+        } else {
+            parser.skip_value()?;
+        }
+        match parser.next()? {
+            ',' => continue,
+            '}' => break,
+            _ => return None,
+        }
+    }
+    entries
+}
+
+/// Minimal JSON reader for manifests
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    /// Next character that isn't whitespace, without consuming it
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.peek()?.is_whitespace() {
+            self.chars.next();
+        }
+        self.chars.peek().cloned()
+    }
+
+    /// Consume the next character that isn't whitespace
+    fn next(&mut self) -> Option<char> {
+        self.peek()?;
+        self.chars.next()
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        if self.next()? == c { Some(()) } else { None }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(s),
+                '\\' => match self.chars.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        if hex.len() != 4 {
+                            return None;
+                        }
+                        s.push(std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                    },
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    /// An object whose values are all strings
+    fn string_object(&mut self) -> Option<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        self.expect('{')?;
+        if self.peek()? == '}' {
+            self.next();
+            return Some(entries);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            entries.push((key, self.string()?));
+            match self.next()? {
+                ',' => continue,
+                '}' => return Some(entries),
+                _ => return None,
+            }
+        }
+    }
+
+    /// Skip over a value of any type
+    fn skip_value(&mut self) -> Option<()> {
+        match self.peek()? {
+            '"' => { self.string()?; },
+            '{' | '[' => {
+                let mut depth = 0;
+                loop {
+                    match self.peek()? {
+                        '"' => { self.string()?; continue; },
+                        '{' | '[' => depth += 1,
+                        '}' | ']' => depth -= 1,
+                        _ => {},
+                    }
+                    self.chars.next();
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            },
+            _ => {
+                while !matches!(self.peek()?, ',' | '}' | ']') {
+                    self.chars.next();
+                }
+            },
+        }
+        Some(())
+    }
+}
+
+/// SHA-256 of a file's contents, as lowercase hex
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Streaming SHA-256, so manifests don't need a crypto dependency
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    fn new() -> Sha256 {
+        Sha256 {
+            state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                    0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len == 64 {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.block[56..].copy_from_slice(&bit_len.to_be_bytes());
+        self.compress();
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+        for (state, v) in self.state.iter_mut().zip(v.iter()) {
+            *state = state.wrapping_add(*v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finish().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Known answers from FIPS 180-4 and its example vectors
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(sha256_hex(b""),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(sha256_hex(&vec![b'a'; 1_000_000]),
+                   "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn sha256_split_updates() {
+        let data = vec![b'a'; 1_000_000];
+        let mut hasher = Sha256::new();
+        for chunk in data.chunks(4099) {
+            hasher.update(chunk);
+        }
+        let digest: String = hasher.finish().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(digest, sha256_hex(&data));
+    }
+
+    #[test]
+    fn parse_manifest() {
+        let json = r#"{
+          "version": 1,
+          "sha256": { "a.png": "00ff", "dir/b.txt": "abcd" },
+          "extra": [1, {"x": "]}"}, "y"]
+        }"#;
+        assert_eq!(parse(json), Some(vec![("a.png".to_string(), "00ff".to_string()),
+                                          ("dir/b.txt".to_string(), "abcd".to_string())]));
+        assert_eq!(parse(r#"{"sha256": {}}"#), Some(vec![]));
+    }
+
+    #[test]
+    fn parse_escapes() {
+        let json = r#"{"sha256": {"q\"uote\\d\n\u00e9\/": "1"}}"#;
+        assert_eq!(parse(json), Some(vec![("q\"uote\\d\n\u{e9}/".to_string(), "1".to_string())]));
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for json in &["", "{}", "[]", r#"{"sha256": {"a": "1"}"#,
+                      r#"{"sha256": {"a": 1}}"#, r#"{"sha256": {"a" "1"}}"#,
+                      r#"{"sha256": {"a": "1",}}"#, r#"{"sha256": {"a\u12": "1"}}"#,
+                      r#"{"other": {}}"#, r#"{"sha256": {"a": "1"} x"#] {
+            assert_eq!(parse(json), None, "{:?}", json);
+        }
+    }
+
+    #[test]
+    fn verify_dir_checks_hashes() {
+        let dir = std::env::temp_dir().join(format!("fruitbasket-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.txt"), "abc").unwrap();
+        std::fs::write(dir.join(MANIFEST_FILE), format!("{{\"sha256\": {{\"data.txt\": \"{}\"}}}}",
+                                                        sha256_hex(b"abc"))).unwrap();
+        assert!(verify_dir(&dir).is_ok());
+        std::fs::write(dir.join("data.txt"), "abd").unwrap();
+        assert!(verify_dir(&dir).is_err());
+        std::fs::write(dir.join(MANIFEST_FILE), "{\"sha256\": {\"../data.txt\": \"00\"}}").unwrap();
+        assert!(verify_dir(&dir).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
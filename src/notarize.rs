@@ -0,0 +1,136 @@
+//! Notarizing app bundles with Apple's notary service
+//!
+//! Apps distributed outside the Mac App Store must be notarized for
+//! Gatekeeper to open them without a warning.  [notarize](notarize) uploads
+//! a signed bundle with `notarytool`, waits for Apple to accept it, and
+//! staples the ticket to the bundle so it opens offline too.
+//!
+//! The bundle must already be signed with a Developer ID identity and the
+//! hardened runtime (`codesign --options runtime`).  See
+//! [signing](../signing/index.html) for finding the identity.
+//!
+//! Credentials never need a password typed in, so notarizing works from CI:
+//!
+//! ```no_run
+//! # use fruitbasket::notarize::{notarize, Credentials};
+//! # use std::path::Path;
+//! let credentials = Credentials::ApiKey {
+//!     key: "/secrets/AuthKey_ABCDE12345.p8".into(),
+//!     key_id: "ABCDE12345".into(),
+//!     issuer: Some("69a6de70-03db-47e3-e053-5b8c7c11a4d1".into()),
+//! };
+//! notarize(Path::new("target/MyApp.app"), &credentials).unwrap();
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::ffi::OsString;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use relocate::run_tool;
+
+/// How to sign in to the notary service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    /// A profile saved in the keychain with `xcrun notarytool
+    /// store-credentials`
+    Profile {
+        /// Name the profile was saved under
+        name: String,
+        /// Keychain holding the profile, if not the default keychain.  CI
+        /// jobs often use a temporary keychain.
+        keychain: Option<PathBuf>,
+    },
+    /// An App Store Connect API key
+    ApiKey {
+        /// Path to the `AuthKey_<key id>.p8` private key file
+        key: PathBuf,
+        /// Key ID shown in App Store Connect
+        key_id: String,
+        /// Issuer ID of the team's keys, or None for an individual key
+        issuer: Option<String>,
+    },
+}
+
+impl Credentials {
+    /// Arguments that pass these credentials to `notarytool`
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        match *self {
+            Credentials::Profile { ref name, ref keychain } => {
+                args.push("--keychain-profile".into());
+                args.push(name.into());
+                if let Some(ref keychain) = *keychain {
+                    args.push("--keychain".into());
+                    args.push(keychain.into());
+                }
+            },
+            Credentials::ApiKey { ref key, ref key_id, ref issuer } => {
+                args.push("--key".into());
+                args.push(::expand_path(&key.to_string_lossy()).into());
+                args.push("--key-id".into());
+                args.push(key_id.into());
+                if let Some(ref issuer) = *issuer {
+                    args.push("--issuer".into());
+                    args.push(issuer.into());
+                }
+            },
+        }
+        args
+    }
+}
+
+/// Notarize a signed bundle, and staple the ticket to it
+///
+/// Zips the bundle into a temporary file, submits it with `xcrun notarytool
+/// submit --wait`, and runs `xcrun stapler staple` once it is accepted.
+/// Blocks until the notary service finishes, which usually takes a few
+/// minutes.  Requires the Xcode command line tools.
+///
+/// # Arguments
+///
+/// `bundle` - Path to the signed `.app` bundle
+///
+/// `credentials` - How to sign in to the notary service
+///
+/// # Returns
+///
+/// `FruitError::NotarizeError` if the submission fails or is rejected.  Run
+/// `xcrun notarytool log <submission id>` with the same credentials for
+/// details of a rejection.  `FruitError::IOError` if the bundle can't be
+/// zipped or the ticket can't be stapled.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn notarize(bundle: &Path, credentials: &Credentials) -> Result<(), FruitError> {
+    let name = bundle.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let zip = std::env::temp_dir().join(format!("{}.{}.zip", name, std::process::id()));
+    info!("Zipping {:?} to {:?}", bundle, zip);
+    run_tool("/usr/bin/ditto", &["-c".as_ref(), "-k".as_ref(), "--keepParent".as_ref(),
+                                 bundle.as_os_str(), zip.as_os_str()])?;
+    info!("Submitting {:?} for notarization", zip);
+    let output = std::process::Command::new("/usr/bin/xcrun")
+        .args(&["notarytool", "submit"])
+        .arg(&zip)
+        .args(credentials.args())
+        .args(&["--wait", "--output-format", "json"])
+        .output();
+    let _ = std::fs::remove_file(&zip);
+    let output = output?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let compact: String = stdout.chars().filter(|c| !c.is_whitespace()).collect();
+    if !output.status.success() || !compact.contains("\"status\":\"Accepted\"") {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(FruitError::NotarizeError(
+            format!("{} {}", stdout.trim(), stderr.trim()).trim().to_string()));
+    }
+    info!("Notarization accepted.  Stapling {:?}", bundle);
+    run_tool("/usr/bin/xcrun", &["stapler".as_ref(), "staple".as_ref(), bundle.as_os_str()])
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn notarize(_bundle: &Path, _credentials: &Credentials) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
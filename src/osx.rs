@@ -35,32 +35,57 @@
 //! Cocoa apps outside of an app bundle.
 #![deny(missing_docs)]
 
-// Temporarily (mmmmhmm...) disable deprecated function warnings, because objc macros
-// throw tons of them in rustc 1.34-nightly when initializing atomic uints.
-#![allow(deprecated)]
-
 use std;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 use std::path::Path;
 use std::path::PathBuf;
 use std::io::Write;
 use std::cell::Cell;
-use std::sync::mpsc::channel;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::os::raw::c_void;
 use std::sync::mpsc::Receiver;
-use std::sync::mpsc::Sender;
+use std::os::unix::io::RawFd;
 use std::collections::HashMap;
+use std::marker::PhantomData;
 
 use super::FruitError;
 use super::ActivationPolicy;
 use super::RunPeriod;
+use super::RunLoopStats;
+use super::AppleEvent;
+use super::EventPayload;
+use super::AEValue;
+use super::CoreEvent;
+use super::CoreEventCallback;
+use super::OpenFileCallback;
+use super::OpenRequest;
+use super::OpenSource;
+use super::ae;
 use super::InstallDir;
+use super::BundleLayout;
+use super::PlistFormat;
 use super::FruitStopper;
+use super::FruitHandle;
+use super::FruitCommand;
+use super::CommandSender;
+use super::waker::{WakeReceiver, Waker};
+use super::AgentApp;
+use super::MethodSignature;
+use super::LaunchOptions;
+use super::Arch;
+use super::LaunchPhase;
+use super::LaunchContext;
+use super::Screen;
+use super::Dock;
+use super::DockPosition;
+use super::manifest;
 use super::DEFAULT_PLIST;
+use super::signpost;
 use super::FORBIDDEN_PLIST;
 
-extern crate time;
-
 extern crate dirs;
 
 extern crate objc;
@@ -69,14 +94,14 @@ use objc::runtime::Class;
 
 extern crate objc_id;
 use self::objc_id::Id;
-use self::objc_id::WeakId;
 use self::objc_id::Shared;
 
 extern crate objc_foundation;
-use std::sync::{Once, ONCE_INIT};
+use std::sync::{Once, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use objc::Message;
 use objc::declare::ClassDecl;
-use objc::runtime::{Sel};
+use objc::runtime::{Sel, Imp, BOOL, NO, YES, class_addMethod};
 use self::objc_foundation::{INSObject, NSObject};
 
 
@@ -87,7 +112,39 @@ const nil: *mut Object = 0 as *mut Object;
 #[link(name = "CoreFoundation", kind = "framework")]
 #[link(name = "ApplicationServices", kind = "framework")]
 #[link(name = "AppKit", kind = "framework")]
-extern {}
+extern "C" {
+    fn pthread_main_np() -> i32;
+    fn dup2(src: i32, dst: i32) -> i32;
+    fn object_setClass(obj: *mut Object, cls: *const Class) -> *const Class;
+    static _mh_execute_header: u8;
+    fn getsectiondata(mhp: *const u8, segname: *const std::os::raw::c_char,
+                      sectname: *const std::os::raw::c_char, size: *mut usize) -> *mut u8;
+    fn NSUpdateDynamicServices();
+    fn LSRegisterURL(url: *mut Object, update: u8) -> i32;
+    static kCFRunLoopCommonModes: *mut Object;
+    fn CFRunLoopGetMain() -> *mut Object;
+    fn CFRunLoopObserverCreate(allocator: *mut Object, activities: u64, repeats: u8, order: isize,
+                               callout: RunLoopObserverCallback,
+                               context: *mut RunLoopObserverContext) -> *mut Object;
+    fn CFRunLoopAddObserver(run_loop: *mut Object, observer: *mut Object, mode: *mut Object);
+    fn CFRunLoopObserverInvalidate(observer: *mut Object);
+    fn CFRelease(cf: *mut Object);
+}
+
+/// kCFRunLoopAfterWaiting
+const RUN_LOOP_AFTER_WAITING: u64 = 1 << 6;
+
+type RunLoopObserverCallback = extern "C" fn(*mut Object, u64, *mut c_void);
+
+/// CFRunLoopObserverContext
+#[repr(C)]
+struct RunLoopObserverContext {
+    version: isize,
+    info: *mut c_void,
+    retain: Option<extern "C" fn(*const c_void) -> *const c_void>,
+    release: Option<extern "C" fn(*const c_void)>,
+    copy_description: Option<extern "C" fn(*const c_void) -> *mut Object>,
+}
 
 /// Main interface for controlling and interacting with the AppKit app
 ///
@@ -101,6 +158,9 @@ extern {}
 /// You must follow all of the standard requirements for NSApplication.  Most
 /// notably: FruitApp **must** be created on your app's main thread, and **must**
 /// be pumped from the same main thread.  Doing otherwise angers the beast.
+/// fruitbasket panics if `new()` or `run()` are called from any other thread,
+/// and `FruitApp` is neither `Send` nor `Sync`, so it cannot be moved off the
+/// main thread after creation.
 ///
 /// An application does *not* need to be in a Mac app bundle to run, so this can
 /// be created in any application with [FruitApp::new](FruitApp::new).  However, many Apple
@@ -108,19 +168,44 @@ extern {}
 /// may want to consider creating your FruitApp instance from the [Trampoline](Trampoline)
 /// struct's builder instead.
 ///
+/// # Callback lifetimes
+///
+/// The `'a` lifetime allows registered callbacks to borrow from the
+/// surrounding scope.  Applications that want to keep their `FruitApp` in a
+/// struct, a `thread_local!`, or hand it to another framework can instead use
+/// [StaticFruitApp](StaticFruitApp), whose callbacks own all of their state
+/// and place no lifetime restrictions on the app.
+///
 pub struct FruitApp<'a> {
     app: *mut Object,
     pool: Cell<*mut Object>,
+    pool_drain_interval: Cell<u64>,
+    idle_interval: Cell<Duration>,
     run_count: Cell<u64>,
+    stats: Cell<RunLoopStats>,
     run_mode: *mut Object,
-    tx: Sender<()>,
-    rx: Receiver<()>,
-    objc: Box<ObjcWrapper<'a>>,
+    tx: CommandSender,
+    rx: Receiver<FruitCommand>,
+    wake: WakeReceiver,
+    wake_observer: *mut Object,
+    objc: ObjcWrapper<'a>,
+    apple_events: Vec<(u32, u32)>,
+    clipboard: Cell<Option<(Instant, i64)>>,
+    _not_send: PhantomData<*const ()>,
 }
 
 /// A boxed Fn type for receiving Rust callbacks from ObjC events
 pub type FruitObjcCallback<'a> = Box<dyn Fn(*mut Object) + 'a>;
 
+/// A `FruitApp` whose callbacks own all of their captured state
+///
+/// Created the same way as any other `FruitApp`, ex:
+/// `let app: StaticFruitApp = FruitApp::new()?;`
+pub type StaticFruitApp = FruitApp<'static>;
+
+/// A callback that can be registered with a [StaticFruitApp](StaticFruitApp)
+pub type StaticFruitObjcCallback = FruitObjcCallback<'static>;
+
 /// Key into the ObjC callback hash map
 ///
 /// You can register to receive callbacks from ObjectiveC based on these keys.
@@ -133,6 +218,8 @@ pub type FruitObjcCallback<'a> = Box<dyn Fn(*mut Object) + 'a>;
 /// Other pre-defined selectors are:
 ///   FruitCallbackKey::Method("applicationWillFinishlaunching:")
 ///   FruitCallbackKey::Method("applicationDidFinishlaunching:")
+///   FruitCallbackKey::Method("application:openFile:")
+///   FruitCallbackKey::Method("performScriptCommand:")
 ///
 /// The Object variant is currently unused, and reserved for the future.
 /// If the callback will be from a particular object, you use the Object type
@@ -145,7 +232,7 @@ pub type FruitObjcCallback<'a> = Box<dyn Fn(*mut Object) + 'a>;
 ///       println!("got callback from button1, address: {:x}", button1 as u64);
 ///   }));
 ///
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub enum FruitCallbackKey {
     /// A callback tied to a generic selector
     Method(&'static str),
@@ -160,22 +247,146 @@ pub enum FruitCallbackKey {
 /// for the NSApplication delegate.  This is a native Rust class that wraps the
 /// ObjC object.
 ///
-/// There should be exactly one of this object, and it must be stored on the
-/// heap (i.e. in a Box).  This is because the ObjC object calls into this class
-/// via raw function pointers, and its address must not change.
+/// The registered callbacks live in a reference-counted `CallbackState`.  The
+/// wrapper holds one reference, and the ObjC object holds another in its
+/// `_rust_state` ivar.  When the wrapper is dropped, the ivar is cleared and
+/// the ObjC object's reference is released, so a stray message to the ObjC
+/// object after that point is silently ignored instead of reaching freed
+/// memory.
 ///
 struct ObjcWrapper<'a> {
     objc: Id<ObjcSubclass, Shared>,
-    map: HashMap<FruitCallbackKey, FruitObjcCallback<'a>>,
+    state: Rc<CallbackState<'a>>,
+    /// Subclass holding methods added by `add_delegate_method()`, if any
+    subclass: Option<&'static Class>,
+}
+
+/// Rust callbacks reachable from the ObjC side of an `ObjcWrapper`
+struct CallbackState<'a> {
+    /// Registered callbacks.  Shared so one can be called without holding a
+    /// borrow of the map, which the callback may change.
+    map: RefCell<HashMap<FruitCallbackKey, Rc<FruitObjcCallback<'a>>>>,
+    core_events: RefCell<Option<CoreEventCallback<'a>>>,
+    /// Whether the user has registered for kAEGetURL events
+    url_events: Cell<bool>,
+    /// Retained URL events received before a handler was registered
+    pending_urls: RefCell<Vec<*mut Object>>,
+    /// How far the app has got through launching
+    phase: Cell<LaunchPhase>,
+    /// Files the app was asked to open before it finished launching
+    launch_opens: RefCell<Vec<OpenRequest>>,
+    /// Whether the Apple event the app launched with was from a login item,
+    /// and the PID of its sender, once launching has started
+    launch_event: Cell<Option<(bool, i32)>>,
+    /// Handler for application:openFiles:, which replaces openFile:
+    open_files: RefCell<Option<OpenFileCallback<'a>>>,
+    /// Whether to show an alert for files the open-files handler rejects
+    open_file_alerts: Cell<bool>,
+    /// Optional delegate methods to respond to, or None for all of them
+    delegate_methods: RefCell<Option<Vec<&'static str>>>,
+    /// Reply to applicationShouldTerminateAfterLastWindowClosed:
+    terminate_after_last_window_closed: Cell<bool>,
+    /// Selectors added with add_delegate_method()
+    dynamic_methods: RefCell<Vec<&'static str>>,
+    /// Callbacks registered with register_callback_once()
+    once: RefCell<Vec<FruitCallbackKey>>,
+}
+
+impl<'a> CallbackState<'a> {
+    /// Call the callback registered for `key`, if any
+    ///
+    /// A callback registered once is removed before it is called.  No borrow
+    /// is held while it runs, so it can run a nested event loop, such as a
+    /// modal panel, that calls other callbacks or registers new ones.
+    fn call(&self, key: &FruitCallbackKey, obj: *mut Object) {
+        let cb = {
+            let mut once = self.once.borrow_mut();
+            match once.iter().position(|k| k == key) {
+                Some(idx) => {
+                    once.remove(idx);
+                    self.map.borrow_mut().remove(key)
+                },
+                None => self.map.borrow().get(key).cloned(),
+            }
+        };
+        if let Some(cb) = cb {
+            cb(obj);
+        }
+    }
+}
+
+impl<'a> Drop for CallbackState<'a> {
+    fn drop(&mut self) {
+        for event in self.pending_urls.borrow_mut().drain(..) {
+            unsafe { let _:() = msg_send![event, release]; }
+        }
+    }
 }
 
 impl<'a> ObjcWrapper<'a> {
+    fn new(class_prefix: Option<&str>) -> Result<ObjcWrapper<'a>, FruitError> {
+        ObjcSubclass::register(class_prefix)?;
+        let objc = ObjcSubclass::new().share();
+        let state = Rc::new(CallbackState {
+            map: RefCell::new(HashMap::new()),
+            core_events: RefCell::new(None),
+            url_events: Cell::new(false),
+            pending_urls: RefCell::new(Vec::new()),
+            phase: Cell::new(LaunchPhase::NotLaunched),
+            launch_opens: RefCell::new(Vec::new()),
+            launch_event: Cell::new(None),
+            open_files: RefCell::new(None),
+            open_file_alerts: Cell::new(false),
+            delegate_methods: RefCell::new(None),
+            terminate_after_last_window_closed: Cell::new(false),
+            dynamic_methods: RefCell::new(Vec::new()),
+            once: RefCell::new(Vec::new()),
+        });
+        let raw = Rc::into_raw(state.clone()) as *const c_void;
+        unsafe {
+            let obj: *mut Object = &*objc as *const ObjcSubclass as *mut Object;
+            (*obj).set_ivar::<*const c_void>("_rust_state", raw);
+        }
+        Ok(ObjcWrapper {
+            objc: objc,
+            state: state,
+            subclass: None,
+        })
+    }
+
     fn take(&mut self) -> Id<ObjcSubclass, Shared> {
-        let weak = WeakId::new(&self.objc);
-        weak.load().unwrap()
+        self.objc.clone()
+    }
+
+    /// Class of this wrapper's object, which methods can be added to
+    ///
+    /// Methods added to the shared ObjcSubclass would affect every FruitApp,
+    /// so the object is moved to its own subclass the first time.
+    fn subclass(&mut self) -> Result<&'static Class, FruitError> {
+        if let Some(cls) = self.subclass {
+            return Ok(cls);
+        }
+        let superclass = ObjcSubclass::register(None)?;
+        let name = format!("{}_{}", superclass.name(),
+                           DELEGATE_SUBCLASS_COUNT.fetch_add(1, Ordering::SeqCst));
+        let decl = ClassDecl::new(&name, superclass)
+            .ok_or_else(|| FruitError::ObjcError(format!("failed to declare class {}", name)))?;
+        let cls = decl.register();
+        unsafe {
+            let obj: *mut Object = &*self.objc as *const ObjcSubclass as *mut Object;
+            object_setClass(obj, cls);
+        }
+        self.subclass = Some(cls);
+        Ok(cls)
     }
 }
 
+/// Whether a FruitApp currently exists
+static APP_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Number of per-app delegate subclasses created, for unique class names
+static DELEGATE_SUBCLASS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
 /// API to move the executable into a Mac app bundle and relaunch (if necessary)
 ///
 /// `Trampoline` is a builder pattern for creating a `FruitApp` application
@@ -208,7 +419,22 @@ pub struct Trampoline {
     keys: Vec<(String,String)>,
     plist_raw_strings: Vec<String>,
     resources: Vec<String>,
+    plugins: Vec<(String, &'static str)>,
     hidpi: bool,
+    layout: BundleLayout,
+    plist_format: PlistFormat,
+    src_exe: Option<String>,
+    post_build: Option<Box<dyn Fn(&Path) -> Result<(), FruitError>>>,
+    copy_progress: Option<Box<dyn Fn(&Path, usize, usize)>>,
+    single_instance: bool,
+    url_types: Vec<(String, Vec<String>)>,
+    services: Vec<(String, String, Vec<String>)>,
+    terminal: bool,
+    acknowledgements: Vec<(String, String)>,
+    resource_manifest: bool,
+    launch_options: LaunchOptions,
+    env: Vec<(String, String)>,
+    architectures: Vec<Arch>,
 }
 
 impl Trampoline {
@@ -301,7 +527,9 @@ impl Trampoline {
     /// and a few keys are always configured by the `Trampoline` builder and
     /// cannot be overridden with this function.
     ///
-    /// `Trampoline` creates Info.plist files in the "old-style" OpenStep format.
+    /// `Trampoline` creates Info.plist files in the "old-style" OpenStep format,
+    /// and values are given in that format even when
+    /// [plist_format()](Trampoline::plist_format) selects XML output.
     /// Be sure to format your values appropriately for this style.  Read up on
     /// [Old-Style ASCII Property Lists](https://developer.apple.com/library/content/documentation/Cocoa/Conceptual/PropertyLists/OldStylePlists/OldStylePLists.html).  You can also verify your
     /// formatting by creating a simple `test.plist` with your key/value pairs
@@ -311,6 +539,14 @@ impl Trampoline {
     /// See the [Apple documentation](https://developer.apple.com/library/content/documentation/General/Reference/InfoPlistKeyReference/Introduction/Introduction.html#//apple_ref/doc/uid/TP40009247)
     /// on Info.plist keys for options.
     ///
+    /// Values can refer to settings the builder already knows, which are
+    /// filled in when the bundle is created: `${NAME}`, `${EXECUTABLE}`,
+    /// `${IDENT}`, `${VERSION}`, and `${YEAR}` (the current year).  Other
+    /// `${...}` sequences are left as they are.  Settings filled in inside a
+    /// quoted string are escaped, and a placeholder outside of quotes becomes a
+    /// quoted string of its own.  For example:
+    /// `plist_key("NSHumanReadableCopyright", "\"Copyright ${YEAR} Example Inc.\"")`
+    ///
     /// # Arguments
     ///
     /// `key` - Property List key to set (ex: `CFBundleURLTypes`)
@@ -385,6 +621,108 @@ impl Trampoline {
         self.hidpi = doit;
         self
     }
+    /// Build the bundle as an agent (menu bar) app
+    ///
+    /// Sets `LSUIElement` in Info.plist, so the app launches without a Dock
+    /// icon or menu bar menus.  Use with
+    /// [FruitApp::new_agent](FruitApp::new_agent), or create the app's status
+    /// item with the [status](status/index.html) module.  Same as
+    /// `ui_element(true)`.
+    pub fn agent(&mut self) -> &mut Self {
+        self.ui_element(true)
+    }
+    /// Launch the app as a UI element, without a Dock icon or menu bar
+    ///
+    /// Sets `LSUIElement` in Info.plist.  The app starts with
+    /// `ActivationPolicy::Accessory`: it can show windows and status items,
+    /// and is activated when the user clicks its windows, but has no Dock
+    /// icon, app switcher entry, or menu bar menus.  Calling
+    /// [set_activation_policy](FruitApp::set_activation_policy) with
+    /// `ActivationPolicy::Regular` later adds the Dock icon, such as while a
+    /// preferences window is open.
+    ///
+    /// Defaults to false.
+    pub fn ui_element(&mut self, enable: bool) -> &mut Self {
+        self.set_bool_key("LSUIElement", enable)
+    }
+    /// Launch the app in the background, without any UI
+    ///
+    /// Sets `LSBackgroundOnly` in Info.plist.  The app starts with
+    /// `ActivationPolicy::Prohibited`: it has no Dock icon or menu bar, can't
+    /// show windows, and doesn't take focus from the frontmost app when it
+    /// launches.  Takes precedence over `ui_element`.
+    ///
+    /// Calling [set_activation_policy](FruitApp::set_activation_policy) with
+    /// `ActivationPolicy::Regular` after launching adds the Dock icon and menu
+    /// bar while leaving the app behind the frontmost one, which is a way to
+    /// open an app without stealing focus.  Without that call, the app stays
+    /// invisible.
+    ///
+    /// Defaults to false.
+    pub fn background_only(&mut self, enable: bool) -> &mut Self {
+        self.set_bool_key("LSBackgroundOnly", enable)
+    }
+    /// Prevent LaunchServices from starting a second instance of the app
+    ///
+    /// Sets `LSMultipleInstancesProhibited` in Info.plist.  Opening the app
+    /// from Finder, the Dock, or `open` while it is running, even from
+    /// another copy of the bundle, activates the running instance instead.
+    /// Another user logged in to the same Mac can still run their own
+    /// instance.  `LaunchOptions::creates_new_instance` is ignored for the
+    /// bundle.
+    ///
+    /// This only covers launches through LaunchServices.  Running the
+    /// executable directly doesn't check it, which
+    /// [single_instance](Trampoline::single_instance) does.
+    ///
+    /// Defaults to false.
+    pub fn multiple_instances_prohibited(&mut self, prohibit: bool) -> &mut Self {
+        self.set_bool_key("LSMultipleInstancesProhibited", prohibit)
+    }
+    /// Register custom URL schemes for the app
+    ///
+    /// Declares the schemes in the bundle's `CFBundleURLTypes`, so macOS
+    /// sends URLs like `myapp://...` to the app.  Receive them with a
+    /// [UrlHandler](UrlHandler).  Can be called more than once to declare
+    /// several URL types.
+    ///
+    /// # Arguments
+    ///
+    /// `name` - Description of this URL type (`CFBundleURLName`)
+    ///
+    /// `schemes` - Schemes to register, without the `://` (ex: `"myapp"`)
+    pub fn url_scheme(&mut self, name: &str, schemes: &[&str]) -> &mut Self {
+        self.url_types.push((name.to_string(), schemes.iter().map(|s| s.to_string()).collect()));
+        self
+    }
+    /// Offer a service in the Services menu of other apps
+    ///
+    /// Declares the service in the bundle's `NSServices`, so it appears in
+    /// the Services menu when the user selects data of one of `send_types`
+    /// in any app.  The app handles it with a delegate method added by
+    /// [add_delegate_method](FruitApp::add_delegate_method) with
+    /// `MethodSignature::Service`, whose selector is `message` followed by
+    /// `:userData:error:`, once it enables the delegate as its services
+    /// provider with [set_services_provider](FruitApp::set_services_provider).
+    /// Can be called more than once to offer several services.
+    ///
+    /// macOS only notices new services after the user logs in again, or
+    /// after the app calls `set_services_provider()`.
+    ///
+    /// # Arguments
+    ///
+    /// `menu_item` - Title of the item in the Services menu
+    ///
+    /// `message` - Name of the method that performs the service (ex:
+    /// `"lookUp"` for `lookUp:userData:error:`)
+    ///
+    /// `send_types` - Pasteboard types the service accepts (ex:
+    /// `"public.utf8-plain-text"`)
+    pub fn service(&mut self, menu_item: &str, message: &str, send_types: &[&str]) -> &mut Self {
+        self.services.push((menu_item.to_string(), message.to_string(),
+                            send_types.iter().map(|s| s.to_string()).collect()));
+        self
+    }
     /// Add a 'raw', preformatted string to Info.plist
     ///
     /// Pastes a raw, unedited string into the Info.plist file.  This is
@@ -418,6 +756,266 @@ impl Trampoline {
         self
     }
 
+    /// Credit third-party code in the app's About panel
+    ///
+    /// Writes the licenses to `Credits.html` in the Resources directory of
+    /// the bundle, which the standard About panel shows in a scrolling box
+    /// below the app's version.  Most open source licenses require shipping
+    /// their text with binaries built from the licensed code.
+    ///
+    /// The list can be generated at build time by a tool such as
+    /// `cargo-license` or `cargo-about`.  Can be called more than once to
+    /// add more entries.  Replaces any `Credits.html` added as a resource.
+    ///
+    /// # Arguments
+    ///
+    /// `licenses` - Pairs of a crate or component name and its license text
+    pub fn acknowledgements(&mut self, licenses: &[(&str, &str)]) -> &mut Self {
+        self.acknowledgements.extend(
+            licenses.iter().map(|&(name, text)| (name.to_string(), text.to_string())));
+        self
+    }
+
+    /// Write a checksum manifest of the bundled resources
+    ///
+    /// Writes the SHA-256 hash of every resource, including `Credits.html`,
+    /// to `manifest.json` in the Resources directory.  The app can then call
+    /// [manifest::verify_resources](manifest/fn.verify_resources.html) to
+    /// check that its assets weren't modified or left incomplete.  Defaults
+    /// to false.
+    pub fn resource_manifest(&mut self, enable: bool) -> &mut Self {
+        self.resource_manifest = enable;
+        self
+    }
+
+    /// Make the app scriptable with an AppleScript scripting definition
+    ///
+    /// Copies the given `.sdef` scripting definition file into the Resources
+    /// directory of the app bundle, and sets the `NSAppleScriptEnabled` and
+    /// `OSAScriptingDefinition` keys in Info.plist so the OS knows the app
+    /// can be automated from AppleScript and Shortcuts.
+    ///
+    /// To route a scripting command to Rust, give it the Cocoa class
+    /// `FruitScriptCommand` in the sdef:
+    ///
+    ///   `<command name="refresh" code="frutrfsh"><cocoa class="FruitScriptCommand"/></command>`
+    ///
+    /// and register a callback with the key:
+    ///
+    ///   `FruitCallbackKey::Method("performScriptCommand:")`
+    ///
+    /// The callback receives the raw `NSScriptCommand`.  Use
+    /// [script_command_name](script_command_name) to find which command was sent.
+    ///
+    /// # Arguments
+    ///
+    /// `sdef` - Full path to the `.sdef` scripting definition file
+    pub fn scripting_definition(&mut self, sdef: &str) -> &mut Self {
+        let filename = Path::new(sdef).file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.resources.push(sdef.to_string());
+        self.keys.push(("NSAppleScriptEnabled".to_string(), "True".to_string()));
+        self.keys.push(("OSAScriptingDefinition".to_string(), openstep_quote(&filename)));
+        self
+    }
+
+    /// Redirect stdout and stderr of the bundled app to a file
+    ///
+    /// An app launched from Finder, the Dock, or by the trampoline has no
+    /// terminal attached, so anything it prints, including panic messages, is
+    /// silently lost.  This marks the bundle so that, when it starts up, its
+    /// stdout and stderr are appended to the given file instead.
+    ///
+    /// Output is only redirected when running from the bundle.  Running the
+    /// bare binary, before it relaunches itself, still prints to the terminal.
+    ///
+    /// # Arguments
+    ///
+    /// `log_path` - Full path to the file to append output to.  It is created
+    /// if it does not exist.
+    pub fn capture_output(&mut self, log_path: &str) -> &mut Self {
+        self.keys.push((OUTPUT_LOG_KEY.to_string(),
+                        openstep_quote(log_path)));
+        self
+    }
+
+    /// Set the directory layout of the generated bundle
+    ///
+    /// Defaults to [BundleLayout::MacOS](BundleLayout::MacOS).  Use
+    /// [BundleLayout::IOS](BundleLayout::IOS), along with
+    /// [executable()](Trampoline::executable) and
+    /// [create_bundle()](Trampoline::create_bundle), to package a binary
+    /// cross-compiled for the iOS simulator or devices.  Mac Catalyst apps are
+    /// Mac apps, and use the default layout.
+    pub fn layout(&mut self, layout: BundleLayout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Set the file format of the generated Info.plist
+    ///
+    /// Defaults to [PlistFormat::OpenStep](PlistFormat::OpenStep).  Values
+    /// passed to [plist_key()](Trampoline::plist_key) and
+    /// [plist_raw_string()](Trampoline::plist_raw_string) are always written
+    /// in OpenStep syntax.  With [PlistFormat::Xml](PlistFormat::Xml) or
+    /// [PlistFormat::Binary](PlistFormat::Binary), the finished plist is
+    /// parsed and rewritten in that format.  OpenStep has no booleans or
+    /// numbers, so values are written as strings, arrays and dictionaries,
+    /// except that top-level keys given an unquoted `True` or `False` (by
+    /// [plist_key()](Trampoline::plist_key) or the builder itself) become
+    /// booleans.  A quoted `"True"` stays a string, as do values nested
+    /// inside arrays, dictionaries, or
+    /// [plist_raw_string()](Trampoline::plist_raw_string).
+    ///
+    /// Fails with `FruitError::PlistError` when the bundle is created if the
+    /// plist can't be parsed, such as when a value has a syntax error.
+    pub fn plist_format(&mut self, format: PlistFormat) -> &mut Self {
+        self.plist_format = format;
+        self
+    }
+
+    /// Set the binary to copy into the bundle
+    ///
+    /// Defaults to the currently running executable.  Only useful with
+    /// [create_bundle()](Trampoline::create_bundle), since the bundle launched
+    /// by `build()` must contain the running program.
+    ///
+    /// # Arguments
+    ///
+    /// `path` - Full path to the executable to bundle
+    pub fn executable(&mut self, path: &str) -> &mut Self {
+        self.src_exe = Some(path.to_string());
+        self
+    }
+
+    /// Allow only one instance of the app to run
+    ///
+    /// If an instance of the app is already running when `build()` or
+    /// `self_bundle()` is called, the files and URLs passed on the command
+    /// line are forwarded to it and this process exits, instead of bundling
+    /// or launching a second copy.  See
+    /// [instance::ensure_single_instance](instance/fn.ensure_single_instance.html).
+    ///
+    /// Defaults to false.
+    pub fn single_instance(&mut self, single: bool) -> &mut Self {
+        self.single_instance = single;
+        self
+    }
+
+    /// Package a command-line program to run in a Terminal window
+    ///
+    /// A bundle launched from Finder or the Dock has no terminal attached,
+    /// so command-line and text UI programs can't interact with the user.
+    /// With this set, the program is copied into the bundle's Resources
+    /// directory, and the bundle's executable is a generated shell script
+    /// that opens it in a new Terminal.app window.  Double-clicking the app
+    /// then runs the program in Terminal, in the user's login shell.
+    ///
+    /// When `build()` or `self_bundle()` is called by the program running in
+    /// Terminal, it is treated as already bundled, and output goes to the
+    /// terminal.  Arguments are not passed through Terminal, and Apple events
+    /// sent to the app, such as files opened with it, go to the script
+    /// rather than the program.
+    ///
+    /// Not supported with the iOS bundle layout.  Defaults to false.
+    pub fn terminal(&mut self, terminal: bool) -> &mut Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// Set how the bundle is launched by `build()` and `self_bundle()`
+    ///
+    /// Defaults to launching it in the foreground with no arguments.  Pass
+    /// the running program's arguments through, for example, with:
+    ///
+    /// `LaunchOptions { arguments: std::env::args().skip(1).collect(), ..Default::default() }`
+    pub fn launch_options(&mut self, options: LaunchOptions) -> &mut Self {
+        self.launch_options = options;
+        self
+    }
+
+    /// Set an environment variable for every launch of the app
+    ///
+    /// Writes the variable to the `LSEnvironment` dictionary in Info.plist,
+    /// so LaunchServices sets it whether the app is relaunched by `build()`,
+    /// opened from Finder or the Dock, or started by `open`.  Use it for
+    /// library search paths or feature flags the app needs at startup.
+    /// Unlike [launch_options](Trampoline::launch_options), it is not applied
+    /// when the executable is run directly from a shell.
+    ///
+    /// `RUST_LOG` is forwarded automatically if set when the bundle is
+    /// created.  Ignored if `LSEnvironment` is set with
+    /// [plist_key](Trampoline::plist_key).  Can be called more than once.
+    ///
+    /// # Arguments
+    ///
+    /// `key` - Name of the environment variable
+    ///
+    /// `value` - Value of the environment variable
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.env.retain(|var| var.0 != key);
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Declare the architectures the app runs as, in order of preference
+    ///
+    /// Writes `LSArchitecturePriority` to Info.plist, which decides how a
+    /// universal binary launches on Apple silicon: listing `Arch::X86_64`
+    /// first runs it under Rosetta, for example for Intel-only plugins.  If
+    /// `Arch::X86_64` isn't listed, `LSRequiresNativeExecution` is also set,
+    /// so users can't choose to open the app with Rosetta.
+    ///
+    /// `create_bundle()` fails with `FruitError::ArchitectureError` if the
+    /// executable lacks code for any listed architecture.
+    ///
+    /// # Arguments
+    ///
+    /// `archs` - Architectures, most preferred first
+    pub fn architectures(&mut self, archs: &[Arch]) -> &mut Self {
+        self.architectures = archs.to_vec();
+        self
+    }
+
+    /// Run a hook on the finished bundle before it is launched
+    ///
+    /// `hook` is called with the path to the `.app` bundle once it has been
+    /// completely written, and before `build()` relaunches into it.  Use it to
+    /// codesign the bundle, add files the builder doesn't know about, or
+    /// register the bundle with other tools.  If the hook returns an error,
+    /// the bundle is not launched and the error is returned from `build()` or
+    /// `create_bundle()`.
+    ///
+    /// The hook is not called when the process is already running from a
+    /// bundle.
+    ///
+    /// # Arguments
+    ///
+    /// `hook` - Function taking the full path to the bundle
+    pub fn post_build<F>(&mut self, hook: F) -> &mut Self
+        where F: Fn(&Path) -> Result<(), FruitError> + 'static {
+        self.post_build = Some(Box::new(hook));
+        self
+    }
+
+    /// Report progress while copying resources into the bundle
+    ///
+    /// Resources are copied on several threads at once, so large asset sets
+    /// can take a while without blocking on each file in turn.  The hook is
+    /// called on the building thread after each resource finishes copying,
+    /// in the order they finish, for showing a progress indicator.
+    ///
+    /// # Arguments
+    ///
+    /// `hook` - Function taking the path of the copied resource, the number
+    /// of resources copied so far, and the total number of resources
+    pub fn copy_progress<F>(&mut self, hook: F) -> &mut Self
+        where F: Fn(&Path, usize, usize) + 'static {
+        self.copy_progress = Some(Box::new(hook));
+        self
+    }
+
     /// Add multiple files to Resources directory of app bundle
     ///
     /// See documentation of [resource()](Trampoline::resource).  This function does the same, but
@@ -429,6 +1027,72 @@ impl Trampoline {
         self
     }
 
+    /// Add a Spotlight importer to the app bundle
+    ///
+    /// Specify full path to a compiled `.mdimporter` bundle to copy into the
+    /// `Contents/Library/Spotlight` directory of the generated app bundle.
+    /// Spotlight discovers importers shipped inside applications, and uses
+    /// them to index the metadata of your app's document types.
+    ///
+    /// # Arguments
+    ///
+    /// `importer` - Full path to the `.mdimporter` bundle directory
+    pub fn spotlight_importer(&mut self, importer: &str) -> &mut Self {
+        self.plugins.push((importer.to_string(), "Library/Spotlight"));
+        self
+    }
+
+    /// Add a Quick Look generator to the app bundle
+    ///
+    /// Specify full path to a compiled `.qlgenerator` bundle to copy into the
+    /// `Contents/Library/QuickLook` directory of the generated app bundle.
+    /// Quick Look uses generators shipped inside applications to render
+    /// thumbnails and previews of your app's document types.
+    ///
+    /// `.qlgenerator` plugins are deprecated by Apple in favor of app
+    /// extensions.  See [app_extension()](Trampoline::app_extension).
+    ///
+    /// # Arguments
+    ///
+    /// `generator` - Full path to the `.qlgenerator` bundle directory
+    pub fn quicklook_generator(&mut self, generator: &str) -> &mut Self {
+        self.plugins.push((generator.to_string(), "Library/QuickLook"));
+        self
+    }
+
+    /// Add an app extension to the app bundle
+    ///
+    /// Specify full path to a compiled `.appex` bundle to copy into the
+    /// `Contents/PlugIns` directory of the generated app bundle.  This is the
+    /// modern equivalent of Spotlight importers and Quick Look generators,
+    /// and is also used for other extension types (Finder Sync, Share, etc).
+    ///
+    /// Note that app extensions must be code signed to be loaded by the OS.
+    ///
+    /// # Arguments
+    ///
+    /// `appex` - Full path to the `.appex` bundle directory
+    pub fn app_extension(&mut self, appex: &str) -> &mut Self {
+        self.plugins.push((appex.to_string(), "PlugIns"));
+        self
+    }
+
+    /// Add an XPC service to the app bundle
+    ///
+    /// Specify full path to a compiled `.xpc` bundle to copy into the
+    /// `Contents/XPCServices` directory of the generated app bundle, where the
+    /// app can connect to it by its bundle identifier.  With the 'xpc'
+    /// feature, the [xpc](xpc/index.html) module provides both ends of the
+    /// connection.
+    ///
+    /// # Arguments
+    ///
+    /// `service` - Full path to the `.xpc` bundle directory
+    pub fn xpc_service(&mut self, service: &str) -> &mut Self {
+        self.plugins.push((service.to_string(), "XPCServices"));
+        self
+    }
+
     /// Finishes building and launching the app bundle
     ///
     /// This builds and executes the "trampoline", meaning it is a highly
@@ -455,6 +1119,8 @@ impl Trampoline {
     ///
     /// * Result<_, FruitError> if not running in a bundle and a new bundle
     ///   could not be created.
+    /// * Result<_, FruitError> if the new bundle could not be launched, or
+    ///   was launched but did not start running (`FruitError::LaunchFailed`).
     /// * Result<_, FruitError> if running in a bundle but the Mac app
     ///   environment could not be initialized.
     /// * Terminates the process if not running in a Mac app bundle and a new
@@ -465,209 +1131,1656 @@ impl Trampoline {
     pub fn build<'a>(&mut self, dir: InstallDir) -> Result<FruitApp<'a>, FruitError> {
         self.self_bundle(dir)?; // terminates this process if not bundled
         info!("Process is bundled.  Continuing.");
-        Ok(FruitApp::new())
+        FruitApp::new()
     }
     /// Returns whether the current process is running from a Mac app bundle
+    ///
+    /// Also true for a binary with an Info.plist embedded by
+    /// [embed_info_plist!](macro.embed_info_plist.html), which the OS treats
+    /// as its bundle information, so a `Trampoline` doesn't relaunch it.
     pub fn is_bundled() -> bool {
+        if Self::has_embedded_plist() {
+            return true;
+        }
+        let cls = match class("NSBundle") {
+            Ok(cls) => cls,
+            Err(_) => return false,
+        };
         unsafe {
-            let cls = Class::get("NSBundle").unwrap();
             let bundle: *mut Object = msg_send![cls, mainBundle];
             let ident: *mut Object = msg_send![bundle, bundleIdentifier];
             ident != nil
         }
     }
+    /// Returns whether the executable has an embedded Info.plist
+    ///
+    /// True if the binary was built with
+    /// [embed_info_plist!](macro.embed_info_plist.html), whether or not it
+    /// is also running from a bundle.
+    pub fn has_embedded_plist() -> bool {
+        let mut size: usize = 0;
+        let data = unsafe {
+            getsectiondata(&_mh_execute_header,
+                           b"__TEXT\0".as_ptr() as *const std::os::raw::c_char,
+                           b"__info_plist\0".as_ptr() as *const std::os::raw::c_char,
+                           &mut size)
+        };
+        !data.is_null() && size > 0
+    }
     /// Same as `build`, but does not construct a FruitApp if successful.
     ///
     /// Useful if you'd like to use a GUI library, such as libui, and don't
     /// want fruitbasket to try to initialize anything for you. Bundling only.
+    ///
+    /// Returns `FruitError::UnsupportedPlatform` without bundling if the
+    /// layout is set to `BundleLayout::IOS`, since those bundles can't be
+    /// launched on macOS.  Use `create_bundle()` for them instead.
     pub fn self_bundle(&self, dir: InstallDir) -> Result<(), FruitError> {
-        unsafe {
-            if Self::is_bundled() {
-                return Ok(());
+        if self.single_instance {
+            if let Err(e) = ::instance::ensure_single_instance(&self.ident) {
+                info!("Failed to forward to running instance: {}", e);
             }
-            info!("Process not bundled.  Self-bundling and relaunching.");
+        }
+        if Self::is_bundled() {
+            return redirect_output();
+        }
+        if self.terminal && in_terminal_bundle() {
+            info!("Process is running in Terminal from its bundle.");
+            return Ok(());
+        }
+        if let BundleLayout::IOS = self.layout {
+            return Err(FruitError::UnsupportedPlatform);
+        }
+        info!("Process not bundled.  Self-bundling and relaunching.");
+        ::preflight::check_runnable(&self.source_exe()?)?;
+        let bundle_dir = self.create_bundle(dir)?;
 
-            let install_dir: PathBuf = match dir {
-                InstallDir::Temp => std::env::temp_dir(),
-                InstallDir::SystemApplications => PathBuf::from("/Applications/"),
-                InstallDir::UserApplications => dirs::home_dir().unwrap().join("Applications/"),
-                InstallDir::Custom(dir) => std::fs::canonicalize(PathBuf::from(dir))?,
-            };
-            info!("Install dir: {:?}", install_dir);
-            let bundle_dir = Path::new(&install_dir).join(&format!("{}.app", self.name));
-            info!("Bundle dir: {:?}", bundle_dir);
-            let contents_dir = Path::new(&bundle_dir).join("Contents");
-            let macos_dir = contents_dir.clone().join("MacOS");
-            let resources_dir = contents_dir.clone().join("Resources");
-            let plist = contents_dir.clone().join("Info.plist");
-            let src_exe = std::env::current_exe()?;
-            info!("Current exe: {:?}", src_exe);
-            let dst_exe = macos_dir.clone().join(&self.exe);
+        // Launch newly created bundle, and make sure it started before exiting.
+        // Terminal bundles only run a script that exits immediately.
+        let mut options = self.launch_options.clone();
+        if let Ok(cwd) = std::env::current_dir() {
+            options.environment.push((::ORIGINAL_DIR_ENV.to_string(),
+                                      cwd.to_string_lossy().into_owned()));
+        }
+        launch_bundle(&bundle_dir, &options)?;
+        if !self.terminal {
+            wait_for_launch(&self.ident, LAUNCH_TIMEOUT)?;
+        }
 
-            let _ = std::fs::remove_dir_all(&bundle_dir); // ignore errors
-            std::fs::create_dir_all(&macos_dir)?;
-            std::fs::create_dir_all(&resources_dir)?;
-            info!("Copy {:?} to {:?}", src_exe, dst_exe);
-            std::fs::copy(src_exe, dst_exe)?;
+        info!("Parent process exited.");
+        std::process::exit(0);
+    }
 
-            for file in &self.resources {
-                let file = Path::new(file);
-                if let Some(filename) = file.file_name() {
-                    let dst = resources_dir.clone().join(filename);
-                    info!("Copy {:?} to {:?}", file, dst);
-                    std::fs::copy(file, dst)?;
-                }
+    /// Create the app bundle without launching it
+    ///
+    /// Writes the bundle just as `self_bundle()` does, but returns its path
+    /// instead of relaunching into it.  Useful for packaging apps as part of a
+    /// build, and required for bundles with the iOS layout, which can't be
+    /// launched on macOS.
+    ///
+    /// # Arguments
+    ///
+    /// `dir` - Directory to create app bundle in
+    ///
+    /// # Returns
+    ///
+    /// Full path to the created `.app` bundle
+    pub fn create_bundle(&self, dir: InstallDir) -> Result<PathBuf, FruitError> {
+        let _signpost = signpost::begin(b"Create bundle\0");
+        let current_link = match dir {
+            InstallDir::Versioned { ref dir, current_link: true } => Some(::expand_path(dir)),
+            _ => None,
+        };
+        if self.terminal && self.layout == BundleLayout::IOS {
+            return Err(FruitError::UnsupportedPlatform);
+        }
+        let install_dir: PathBuf = match dir {
+            InstallDir::Temp => std::env::temp_dir(),
+            InstallDir::SystemApplications => PathBuf::from("/Applications/"),
+            InstallDir::UserApplications => dirs::home_dir()
+                .ok_or_else(|| FruitError::IOError(std::io::Error::new(
+                    std::io::ErrorKind::NotFound, "home directory not found")))?
+                .join("Applications/"),
+            InstallDir::Custom(dir) => {
+                let dir = ::expand_path(&dir);
+                std::fs::create_dir_all(&dir)?;
+                std::fs::canonicalize(dir)?
+            },
+            InstallDir::Versioned { ref dir, .. } => {
+                let dir = ::expand_path(dir).join(&self.version);
+                std::fs::create_dir_all(&dir)?;
+                std::fs::canonicalize(dir)?
+            },
+        };
+        info!("Install dir: {:?}", install_dir);
+        let bundle_dir = Path::new(&install_dir).join(&format!("{}.app", self.name));
+        info!("Bundle dir: {:?}", bundle_dir);
+        // iOS bundles are flat, with everything at the root of the bundle
+        let (contents_dir, macos_dir, resources_dir) = match self.layout {
+            BundleLayout::MacOS => {
+                let contents_dir = Path::new(&bundle_dir).join("Contents");
+                (contents_dir.clone(), contents_dir.join("MacOS"), contents_dir.join("Resources"))
+            },
+            BundleLayout::IOS => (bundle_dir.clone(), bundle_dir.clone(), bundle_dir.clone()),
+        };
+        let plist = contents_dir.clone().join("Info.plist");
+        let src_exe = self.source_exe()?;
+        info!("Current exe: {:?}", src_exe);
+        if self.layout == BundleLayout::MacOS {
+            let archs = ::preflight::executable_archs(&src_exe);
+            let missing = self.architectures.iter().find(|arch| !archs.contains(arch));
+            if let (Some(arch), false) = (missing, archs.is_empty()) {
+                return Err(FruitError::ArchitectureError(
+                    format!("{:?} has no {} code, but it is listed in architectures()",
+                            src_exe, arch.name())));
+            }
+        }
+        // Terminal bundles run a shim that opens the real binary in Terminal
+        let dst_exe = if self.terminal {
+            resources_dir.clone().join(&self.exe)
+        } else {
+            macos_dir.clone().join(&self.exe)
+        };
+
+        let _ = std::fs::remove_dir_all(&bundle_dir); // ignore errors
+        std::fs::create_dir_all(&macos_dir)?;
+        std::fs::create_dir_all(&resources_dir)?;
+        info!("Copy {:?} to {:?}", src_exe, dst_exe);
+        let copy_signpost = signpost::begin(b"Copy executable\0");
+        std::fs::copy(src_exe, dst_exe)?;
+        if self.terminal {
+            write_terminal_shim(&macos_dir.join(&self.exe), &self.exe)?;
+        }
+        drop(copy_signpost);
+
+        let copy_signpost = signpost::begin(b"Copy resources\0");
+        // A later resource with the same name replaces an earlier one
+        let mut copies: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for file in &self.resources {
+            let file = Path::new(file);
+            if let Some(filename) = file.file_name() {
+                let dst = resources_dir.clone().join(filename);
+                copies.retain(|copy| copy.1 != dst);
+                copies.push((file.to_path_buf(), dst));
             }
+        }
+        copy_files(&copies, self.copy_progress.as_ref().map(|hook| &**hook))?;
 
-            // Write Info.plist
-            let mut f = std::fs::File::create(&plist)?;
+        for &(ref plugin, subdir) in &self.plugins {
+            let plugin = Path::new(plugin);
+            if let Some(filename) = plugin.file_name() {
+                let dst = contents_dir.clone().join(subdir).join(filename);
+                info!("Copy {:?} to {:?}", plugin, dst);
+                copy_dir(plugin, &dst).map_err(|e| FruitError::ResourceCopyError {
+                    path: plugin.to_path_buf(),
+                    source: e,
+                })?;
+            }
+        }
 
-            // Mandatory fields
-            write!(&mut f, "{{\n")?;
-            write!(&mut f, "  CFBundleName = \"{}\";\n", self.name)?;
-            write!(&mut f, "  CFBundleDisplayName = \"{}\";\n", self.name)?;
-            write!(&mut f, "  CFBundleIdentifier = \"{}\";\n", self.ident)?;
-            write!(&mut f, "  CFBundleExecutable = \"{}\";\n", self.exe)?;
-            write!(&mut f, "  CFBundleIconFile = \"{}\";\n", self.icon)?;
-            write!(&mut f, "  CFBundleVersion = \"{}\";\n", self.version)?;
+        if !self.acknowledgements.is_empty() {
+            let credits = resources_dir.join("Credits.html");
+            info!("Write {:?}", credits);
+            std::fs::write(&credits, credits_html(&self.acknowledgements))?;
+        }
 
-            // HiDPI fields
-            if self.hidpi {
-                write!(&mut f, "  NSPrincipalClass = \"NSApplication\";\n")?;
-                write!(&mut f, "  NSHighResolutionCapable = True;\n")?;
+        if self.resource_manifest {
+            let mut files: Vec<PathBuf> = copies.iter()
+                .filter_map(|copy| copy.1.file_name().map(PathBuf::from))
+                .filter(|file| self.acknowledgements.is_empty() || file != Path::new("Credits.html"))
+                .collect();
+            if !self.acknowledgements.is_empty() {
+                files.push(PathBuf::from("Credits.html"));
             }
+            info!("Write {:?}", resources_dir.join(manifest::MANIFEST_FILE));
+            manifest::write(&resources_dir, &files)?;
+        }
 
-            // User-supplied fields
-            for &(ref key, ref val) in &self.keys {
-                if !FORBIDDEN_PLIST.contains(&key.as_str()) {
-                    write!(&mut f, "  {} = {};\n", key, val)?;
-                }
+        drop(copy_signpost);
+
+        // Write Info.plist
+        let plist_signpost = signpost::begin(b"Write Info.plist\0");
+        let mut f: Vec<u8> = Vec::new();
+        // Top-level keys written as unquoted True or False, which become
+        // booleans when the plist is rewritten as XML or binary
+        let mut bool_keys: Vec<String> = Vec::new();
+
+        // Mandatory fields
+        write!(&mut f, "{{\n")?;
+        write!(&mut f, "  CFBundleName = {};\n", openstep_quote(&self.name))?;
+        write!(&mut f, "  CFBundleDisplayName = {};\n", openstep_quote(&self.name))?;
+        write!(&mut f, "  CFBundleIdentifier = {};\n", openstep_quote(&self.ident))?;
+        write!(&mut f, "  CFBundleExecutable = {};\n", openstep_quote(&self.exe))?;
+        write!(&mut f, "  CFBundleIconFile = {};\n", openstep_quote(&self.icon))?;
+        write!(&mut f, "  CFBundleVersion = {};\n", openstep_quote(&self.version))?;
+
+        // HiDPI fields
+        if self.hidpi && self.layout == BundleLayout::MacOS {
+            write!(&mut f, "  NSPrincipalClass = \"NSApplication\";\n")?;
+            write!(&mut f, "  NSHighResolutionCapable = True;\n")?;
+            bool_keys.push("NSHighResolutionCapable".to_string());
+        }
+
+        if !self.architectures.is_empty() && self.layout == BundleLayout::MacOS {
+            let archs: Vec<String> = self.architectures.iter()
+                .map(|arch| openstep_quote(arch.name())).collect();
+            write!(&mut f, "  LSArchitecturePriority = ({});\n", archs.join(", "))?;
+            if !self.architectures.contains(&Arch::X86_64) {
+                write!(&mut f, "  LSRequiresNativeExecution = True;\n")?;
+                bool_keys.push("LSRequiresNativeExecution".to_string());
             }
+        }
 
-            // Default fields (if user didn't override)
-            let keys: Vec<&str> = self.keys.iter().map(|x| {x.0.as_ref()}).collect();
-            for &(ref key, ref val) in DEFAULT_PLIST {
-                if !keys.contains(key) {
-                    write!(&mut f, "  {} = {};\n", key, val)?;
+        // User-supplied fields
+        for &(ref key, ref val) in &self.keys {
+            if !FORBIDDEN_PLIST.contains(&key.as_str()) {
+                write!(&mut f, "  {} = {};\n", key, self.expand_template(val))?;
+                if val.trim() == "True" || val.trim() == "False" {
+                    bool_keys.push(key.clone());
                 }
             }
+        }
 
-            // Write raw plist fields
-            for raw in &self.plist_raw_strings {
-                write!(&mut f, "{}\n", raw)?;
+        // Default fields (if user didn't override)
+        let keys: Vec<&str> = self.keys.iter().map(|x| {x.0.as_ref()}).collect();
+        for &(ref key, ref val) in DEFAULT_PLIST {
+            if !keys.contains(key) && !(self.layout == BundleLayout::IOS && *key == "LSMinimumSystemVersion") {
+                write!(&mut f, "  {} = {};\n", key, val)?;
             }
+        }
 
-            write!(&mut f, "}}\n")?;
+        // Write raw plist fields
+        for raw in &self.plist_raw_strings {
+            write!(&mut f, "{}\n", raw)?;
+        }
 
-            // Launch newly created bundle
-            let cls = Class::get("NSWorkspace").unwrap();
-            let wspace: *mut Object = msg_send![cls, sharedWorkspace];
-            let cls = Class::get("NSString").unwrap();
-            let app = bundle_dir.to_str().unwrap();
-            info!("Launching: {}", app);
-            let s: *mut Object = msg_send![cls, alloc];
-            let s: *mut Object = msg_send![s,
-                                           initWithBytes:app.as_ptr()
-                                           length:app.len()
-                                           encoding: 4]; // UTF8_ENCODING
-            let _:() = msg_send![wspace, launchApplication: s];
+        if !self.url_types.is_empty() {
+            let types: Vec<String> = self.url_types.iter().map(|&(ref name, ref schemes)| {
+                let schemes: Vec<String> = schemes.iter().map(|s| openstep_quote(s)).collect();
+                format!("    {{ CFBundleTypeRole = \"Viewer\"; CFBundleURLName = {}; \
+                         CFBundleURLSchemes = ({}); }}",
+                        openstep_quote(name), schemes.join(", "))
+            }).collect();
+            write!(&mut f, "  CFBundleURLTypes = (\n{}\n  );\n", types.join(",\n"))?;
+        }
 
-            // Note: launchedApplication doesn't return until the child process
-            // calls [NSApplication sharedApplication].
-            info!("Parent process exited.");
-            std::process::exit(0);
+        if !self.services.is_empty() {
+            let services: Vec<String> = self.services.iter().map(|&(ref item, ref message, ref types)| {
+                let types: Vec<String> = types.iter().map(|s| openstep_quote(s)).collect();
+                format!("    {{ NSMenuItem = {{ default = {}; }}; NSMessage = {}; \
+                         NSPortName = {}; NSSendTypes = ({}); }}",
+                        openstep_quote(item), openstep_quote(message), openstep_quote(&self.name),
+                        types.join(", "))
+            }).collect();
+            write!(&mut f, "  NSServices = (\n{}\n  );\n", services.join(",\n"))?;
         }
-    }
-}
 
-impl<'a> FruitApp<'a> {
-    /// Initialize the Apple app environment
-    ///
-    /// Initializes the NSApplication singleton that initializes the Mac app
-    /// environment and  creates a memory pool for Objective-C allocations on
-    /// the main thread.
-    ///
-    /// # Returns
-    ///
-    /// A newly allocated FruitApp for managing the app
-    pub fn new() -> FruitApp<'a> {
-        let (tx,rx) = channel::<()>();
+        // LaunchServices doesn't pass our environment to the relaunched
+        // app, so forward the log filter through the bundle instead.
+        let mut env = self.env.clone();
+        if let Ok(filter) = std::env::var("RUST_LOG") {
+            if !env.iter().any(|var| var.0 == "RUST_LOG") {
+                env.push(("RUST_LOG".to_string(), filter));
+            }
+        }
+        if !env.is_empty() && !keys.contains(&"LSEnvironment") {
+            let vars: Vec<String> = env.iter()
+                .map(|&(ref key, ref value)| format!("{} = {};", openstep_quote(key), openstep_quote(value)))
+                .collect();
+            write!(&mut f, "  LSEnvironment = {{ {} }};\n", vars.join(" "))?;
+        }
+
+        write!(&mut f, "}}\n")?;
+        match self.plist_format {
+            PlistFormat::OpenStep => std::fs::write(&plist, &f)?,
+            PlistFormat::Xml => write_serialized_plist(&plist, &f, &bool_keys, PLIST_XML_FORMAT)?,
+            PlistFormat::Binary => write_serialized_plist(&plist, &f, &bool_keys, PLIST_BINARY_FORMAT)?,
+        }
+        drop(plist_signpost);
+
+        if let Some(ref hook) = self.post_build {
+            info!("Running post-build hook");
+            let _signpost = signpost::begin(b"Post-build hook\0");
+            hook(&bundle_dir)?;
+        }
+
+        if let Some(versions_dir) = current_link {
+            update_current_link(&versions_dir, &self.version)?;
+        }
+
+        Ok(bundle_dir)
+    }
+
+    /// Executable to copy into the bundle
+    fn source_exe(&self) -> Result<PathBuf, FruitError> {
+        match self.src_exe {
+            Some(ref exe) => Ok(PathBuf::from(exe)),
+            None => Ok(std::env::current_exe()?),
+        }
+    }
+
+    /// Set a boolean Info.plist key, replacing any earlier value, or remove
+    /// it when false
+    fn set_bool_key(&mut self, key: &str, enable: bool) -> &mut Self {
+        self.keys.retain(|&(ref k, _)| k != key);
+        if enable {
+            self.keys.push((key.to_string(), "True".to_string()));
+        }
+        self
+    }
+
+    /// Replace `${VAR}` placeholders in a plist value with builder settings
+    ///
+    /// Settings are escaped if the placeholder is inside a quoted string, and
+    /// quoted if it isn't, so the value stays valid OpenStep.
+    fn expand_template(&self, value: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = value;
+        let mut quoted = false;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            quoted = openstep_in_string(&rest[..start], quoted);
+            rest = &rest[start..];
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => break,
+            };
+            let setting = match &rest[2..end] {
+                "NAME" => Some(self.name.clone()),
+                "EXECUTABLE" => Some(self.exe.clone()),
+                "IDENT" => Some(self.ident.clone()),
+                "VERSION" => Some(self.version.clone()),
+                "YEAR" => Some(current_year().to_string()),
+                _ => None,
+            };
+            match setting {
+                Some(ref setting) if quoted => expanded.push_str(&openstep_escape(setting)),
+                Some(ref setting) => expanded.push_str(&openstep_quote(setting)),
+                None => {
+                    expanded.push_str(&rest[..end + 1]);
+                    quoted = openstep_in_string(&rest[..end + 1], quoted);
+                },
+            }
+            rest = &rest[end + 1..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+/// Optional NSApplication delegate methods that `FruitAppBuilder` can disable
+const OPTIONAL_DELEGATE_METHODS: &[&str] = &[
+    "applicationWillFinishLaunching:",
+    "applicationDidChangeOcclusionState:",
+    "applicationDidChangeScreenParameters:",
+    "application:openFile:",
+    "application:openURLs:",
+    "application:continueUserActivity:restorationHandler:",
+];
+
+/// Builder for a `FruitApp` configured before it finishes launching
+///
+/// AppKit reads some settings only once, while the app launches: the app
+/// delegate is asked which methods it implements when it is installed, and
+/// changing the activation policy of an app that is already running can
+/// briefly flash a Dock icon.  `FruitApp` finishes launching on its first
+/// call to [run](FruitApp::run), so settings applied by `build()` take effect
+/// cleanly, as if they were in the app's Info.plist.
+///
+/// ```no_run
+/// # use fruitbasket::*;
+/// let mut app = FruitAppBuilder::new()
+///     .activation_policy(ActivationPolicy::Accessory)
+///     .delegate_methods(&["application:openURLs:"])
+///     .build().unwrap();
+/// let _ = app.run(RunPeriod::Once);
+/// ```
+#[derive(Default)]
+pub struct FruitAppBuilder {
+    activation_policy: Option<ActivationPolicy>,
+    dock_icon: Option<String>,
+    delegate_methods: Option<Vec<&'static str>>,
+    terminate_after_last_window_closed: bool,
+    class_prefix: Option<String>,
+}
+
+impl FruitAppBuilder {
+    /// Creates a builder with AppKit's default settings
+    pub fn new() -> FruitAppBuilder {
+        Default::default()
+    }
+    /// Sets the activation policy the app launches with
+    pub fn activation_policy(&mut self, policy: ActivationPolicy) -> &mut Self {
+        self.activation_policy = Some(policy);
+        self
+    }
+    /// Sets the image shown as the app's Dock icon
+    ///
+    /// Overrides the bundle's icon while the app runs.  Any image format
+    /// supported by NSImage can be used.
+    ///
+    /// # Arguments
+    ///
+    /// `icon` - Path to the image file
+    pub fn dock_icon(&mut self, icon: &str) -> &mut Self {
+        self.dock_icon = Some(icon.to_string());
+        self
+    }
+    /// Limits which optional delegate methods the app responds to
+    ///
+    /// By default the app delegate implements every delegate method that can
+    /// be registered as a callback, and AppKit changes its behavior for some
+    /// of them: implementing `application:openFile:`, for instance, means
+    /// open-document Apple events are never delivered as such.  Only the
+    /// methods listed here are reported to AppKit.  The optional methods are
+    /// `applicationWillFinishLaunching:`, `applicationDidChangeOcclusionState:`,
+    /// `applicationDidChangeScreenParameters:`, `application:openFile:`, and
+    /// `application:openURLs:`.  Other methods
+    /// are always installed.
+    ///
+    /// # Arguments
+    ///
+    /// `methods` - Selector names of the optional methods to install
+    pub fn delegate_methods(&mut self, methods: &[&'static str]) -> &mut Self {
+        self.delegate_methods = Some(methods.to_vec());
+        self
+    }
+    /// Sets whether the app quits when its last window is closed
+    ///
+    /// Answers the `applicationShouldTerminateAfterLastWindowClosed:`
+    /// delegate method.  Defaults to false, the standard Mac behavior.
+    pub fn terminate_after_last_window_closed(&mut self, terminate: bool) -> &mut Self {
+        self.terminate_after_last_window_closed = terminate;
+        self
+    }
+    /// Sets the prefix of the app delegate's ObjC class name
+    ///
+    /// fruitbasket registers its app delegate class under a name unique to
+    /// the copy of fruitbasket in the process, beginning with "Fruit" by
+    /// default.  A custom prefix makes the class easy to recognize in crash
+    /// reports and debuggers.  The class is registered by the first `FruitApp`
+    /// created in the process, so the prefix has no effect on later ones.
+    ///
+    /// # Arguments
+    ///
+    /// `prefix` - Letters, digits, and underscores to begin the class name
+    pub fn class_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.class_prefix = Some(prefix.to_string());
+        self
+    }
+    /// Initializes the app environment with the configured settings
+    ///
+    /// # Returns
+    ///
+    /// The new FruitApp, or the errors returned by
+    /// [FruitApp::new](FruitApp::new).  `FruitError::IOError` if the Dock
+    /// icon can't be loaded, and `FruitError::ObjcError` if the class prefix
+    /// isn't a valid class name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from the main thread.
+    pub fn build<'a>(&self) -> Result<FruitApp<'a>, FruitError> {
+        if let Some(ref prefix) = self.class_prefix {
+            let valid = prefix.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) &&
+                prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(FruitError::ObjcError(format!("invalid class prefix: {:?}", prefix)));
+            }
+        }
+        let app = FruitApp::init(self.class_prefix.as_deref())?;
+        let state = &app.objc.state;
+        *state.delegate_methods.borrow_mut() = self.delegate_methods.clone();
+        state.terminate_after_last_window_closed.set(self.terminate_after_last_window_closed);
+        if let Some(policy) = self.activation_policy {
+            app.set_activation_policy(policy);
+        }
+        if let Some(ref icon) = self.dock_icon {
+            let cls = class("NSImage")?;
+            unsafe {
+                let image: *mut Object = msg_send![cls, alloc];
+                let image: *mut Object = msg_send![image, initWithContentsOfFile: str_to_nsstring(icon)];
+                if image == nil {
+                    return Err(FruitError::IOError(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("failed to load dock icon {}", icon))));
+                }
+                let _:() = msg_send![app.app, setApplicationIconImage: image];
+                let _:() = msg_send![image, release];
+            }
+        }
+        Ok(app)
+    }
+}
+
+/// Show a modal warning alert with an OK button
+fn show_alert(message: &str, details: &str) {
+    let alert_cls = match class("NSAlert") {
+        Ok(cls) => cls,
+        Err(_) => return,
+    };
+    unsafe {
+        let alert: *mut Object = msg_send![alert_cls, new];
+        let _:() = msg_send![alert, setAlertStyle: 0u64]; // NSAlertStyleWarning
+        let _:() = msg_send![alert, setMessageText: str_to_nsstring(message)];
+        let _:() = msg_send![alert, setInformativeText: str_to_nsstring(details)];
+        let _: i64 = msg_send![alert, runModal];
+        let _:() = msg_send![alert, release];
+    }
+}
+
+/// Show a modal alert telling the user the app crashed
+///
+/// Used by `install_panic_handler()`.  Must be called from the main thread.
+pub(crate) fn show_panic_alert(message: &str) {
+    let (app_cls, alert_cls) = match (class("NSApplication"), class("NSAlert")) {
+        (Ok(app_cls), Ok(alert_cls)) => (app_cls, alert_cls),
+        _ => return,
+    };
+    unsafe {
+        let app: *mut Object = msg_send![app_cls, sharedApplication];
+        let _:() = msg_send![app, activateIgnoringOtherApps: YES];
+        let alert: *mut Object = msg_send![alert_cls, new];
+        let _:() = msg_send![alert, setAlertStyle: 2u64]; // NSAlertStyleCritical
+        let _:() = msg_send![alert, setMessageText: str_to_nsstring("The application quit unexpectedly.")];
+        let _:() = msg_send![alert, setInformativeText: str_to_nsstring(message)];
+        let _: i64 = msg_send![alert, runModal];
+        let _:() = msg_send![alert, release];
+    }
+}
+
+/// Info.plist key holding the file that bundled output is redirected to
+const OUTPUT_LOG_KEY: &str = "FruitOutputLog";
+
+/// NSWorkspaceLaunchAndHide
+const LAUNCH_AND_HIDE: u64 = 0x00100000;
+/// NSWorkspaceLaunchWithoutActivation
+const LAUNCH_WITHOUT_ACTIVATION: u64 = 0x00000200;
+/// NSWorkspaceLaunchNewInstance
+const LAUNCH_NEW_INSTANCE: u64 = 0x00080000;
+
+/// Launch an app bundle
+///
+/// Launches the bundle through LaunchServices, as if it were opened from
+/// Finder, with the given arguments, environment, and activation options.
+/// Returns once the launch has been requested, without waiting for the app
+/// to finish starting up.
+///
+/// Uses NSWorkspaceOpenConfiguration on macOS 10.15 and later, which
+/// launches the app asynchronously, and falls back to the older synchronous
+/// launch call on earlier versions.
+///
+/// # Arguments
+///
+/// `path` - Full path to the `.app` bundle
+///
+/// `options` - How to launch the app
+///
+/// # Returns
+///
+/// `FruitError::LaunchError` with the error code if the app couldn't be
+/// launched.  Only reported before macOS 10.15, since later versions report
+/// failures asynchronously.
+pub fn launch_bundle(path: &Path, options: &LaunchOptions) -> Result<(), FruitError> {
+    let url_cls = class("NSURL")?;
+    let workspace_cls = class("NSWorkspace")?;
+    let array_cls = class("NSMutableArray")?;
+    let dict_cls = class("NSMutableDictionary")?;
+    info!("Launching: {}", path.display());
+    unsafe {
+        let url: *mut Object = msg_send![url_cls,
+                                         fileURLWithPath: str_to_nsstring(&path.to_string_lossy())];
+        let args: *mut Object = msg_send![array_cls, array];
+        for arg in &options.arguments {
+            let _:() = msg_send![args, addObject: str_to_nsstring(arg)];
+        }
+        let env: *mut Object = msg_send![dict_cls, dictionary];
+        for &(ref key, ref value) in &options.environment {
+            let _:() = msg_send![env, setObject: str_to_nsstring(value)
+                                 forKey: str_to_nsstring(key)];
+        }
+        let workspace: *mut Object = msg_send![workspace_cls, sharedWorkspace];
+        let open_config_cls = class("NSWorkspaceOpenConfiguration").ok();
+        let responds: BOOL = msg_send![workspace,
+                                       respondsToSelector: sel!(openApplicationAtURL:configuration:completionHandler:)];
+        match open_config_cls {
+            Some(open_config_cls) if responds == YES => {
+                let config: *mut Object = msg_send![open_config_cls, configuration];
+                let _:() = msg_send![config, setArguments: args];
+                if !options.environment.is_empty() {
+                    let _:() = msg_send![config, setEnvironment: env];
+                }
+                let activates: BOOL = if options.activates { YES } else { NO };
+                let hides: BOOL = if options.hides { YES } else { NO };
+                let new_instance: BOOL = if options.creates_new_instance { YES } else { NO };
+                let _:() = msg_send![config, setActivates: activates];
+                let _:() = msg_send![config, setHides: hides];
+                let _:() = msg_send![config, setCreatesNewApplicationInstance: new_instance];
+                let _:() = msg_send![workspace, openApplicationAtURL: url
+                                     configuration: config
+                                     completionHandler: nil];
+            },
+            _ => {
+                let config: *mut Object = msg_send![dict_cls, dictionary];
+                if !options.arguments.is_empty() {
+                    let _:() = msg_send![config, setObject: args
+                                         forKey: str_to_nsstring("NSWorkspaceLaunchConfigurationArguments")];
+                }
+                if !options.environment.is_empty() {
+                    let _:() = msg_send![config, setObject: env
+                                         forKey: str_to_nsstring("NSWorkspaceLaunchConfigurationEnvironment")];
+                }
+                let mut launch_options: u64 = 0;
+                if !options.activates {
+                    launch_options |= LAUNCH_WITHOUT_ACTIVATION;
+                }
+                if options.hides {
+                    launch_options |= LAUNCH_AND_HIDE;
+                }
+                if options.creates_new_instance {
+                    launch_options |= LAUNCH_NEW_INSTANCE;
+                }
+                let mut error: *mut Object = nil;
+                let app: *mut Object = msg_send![workspace,
+                                                 launchApplicationAtURL: url
+                                                 options: launch_options
+                                                 configuration: config
+                                                 error: &mut error];
+                if app == nil {
+                    let code: isize = if error == nil { -1 } else { msg_send![error, code] };
+                    return Err(FruitError::LaunchError { os_status: code as i32 });
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Observe the main run loop, waking `waker` each time it wakes from waiting
+///
+/// Returns the observer, or null if it couldn't be created.  The observer
+/// owns `waker`, which is dropped when the observer is released.
+fn add_wake_observer(waker: Waker) -> *mut Object {
+    extern "C" fn observer_callback(_observer: *mut Object, _activity: u64, info: *mut c_void) {
+        let waker = unsafe { &*(info as *const Waker) };
+        waker.wake();
+    }
+    extern "C" fn release_waker(info: *const c_void) {
+        drop(unsafe { Box::from_raw(info as *mut Waker) });
+    }
+    let info = Box::into_raw(Box::new(waker)) as *mut c_void;
+    let mut context = RunLoopObserverContext {
+        version: 0,
+        info: info,
+        retain: None,
+        release: Some(release_waker),
+        copy_description: None,
+    };
+    unsafe {
+        let observer = CFRunLoopObserverCreate(nil, RUN_LOOP_AFTER_WAITING, 1, 0,
+                                               observer_callback, &mut context);
+        if observer == nil {
+            release_waker(info);
+            return observer;
+        }
+        CFRunLoopAddObserver(CFRunLoopGetMain(), observer, kCFRunLoopCommonModes);
+        observer
+    }
+}
+
+/// Point the `Current` symlink in `versions_dir` at the `version` subdirectory
+///
+/// The link is replaced atomically, so it always refers to a complete bundle.
+fn update_current_link(versions_dir: &Path, version: &str) -> Result<(), FruitError> {
+    let link = versions_dir.join("Current");
+    let tmp = versions_dir.join(".Current.tmp");
+    let _ = std::fs::remove_file(&tmp); // ignore errors
+    std::os::unix::fs::symlink(version, &tmp)?;
+    std::fs::rename(&tmp, &link)?;
+    info!("Linked {:?} to {}", link, version);
+    Ok(())
+}
+
+/// How long to wait for a relaunched bundle to appear
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `FruitApp` checks the clipboard for changes while watching it
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Callback key for clipboard changes
+const CLIPBOARD_KEY: FruitCallbackKey = FruitCallbackKey::Method("pasteboardDidChange:");
+
+/// procNotFound: no eligible process with the specified descriptor
+const PROC_NOT_FOUND: i32 = -600;
+
+/// Wait until an app with the given bundle identifier is running
+///
+/// LaunchServices reports success once it has accepted a launch request, even
+/// if the app then fails to start, such as when it is killed for an invalid
+/// code signature.  Returns `FruitError::LaunchFailed` if the app isn't
+/// running before `timeout`.
+fn wait_for_launch(ident: &str, timeout: Duration) -> Result<(), FruitError> {
+    let cls = class("NSRunningApplication")?;
+    let start = Instant::now();
+    loop {
+        let running = unsafe {
+            let apps: *mut Object = msg_send![cls,
+                                              runningApplicationsWithBundleIdentifier: str_to_nsstring(ident)];
+            ::foundation::nsarray_to_vec(apps).into_iter().any(|app| {
+                let terminated: BOOL = msg_send![app, isTerminated];
+                terminated == NO
+            })
+        };
+        if running {
+            info!("Launched app is running.");
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            info!("Launched app did not start within {:?}", timeout);
+            return Err(FruitError::LaunchFailed { os_status: PROC_NOT_FOUND });
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Format license texts as the HTML credits shown in the About panel
+fn credits_html(licenses: &[(String, String)]) -> String {
+    use entitlements::escape;
+    let mut html = String::from("<html><body style=\"font-family: -apple-system; font-size: 11px\">\n");
+    for &(ref name, ref text) in licenses {
+        html.push_str(&format!("<h3>{}</h3>\n<pre style=\"white-space: pre-wrap\">{}</pre>\n",
+                               escape(name), escape(text.trim_end())));
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// Quote a string for an OpenStep property list, escaping backslashes and
+/// double quotes
+fn openstep_quote(s: &str) -> String {
+    format!("\"{}\"", openstep_escape(s))
+}
+
+/// Escape backslashes and double quotes for use inside an OpenStep string
+fn openstep_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Whether OpenStep text that started `quoted` ends inside a quoted string
+fn openstep_in_string(text: &str, mut quoted: bool) -> bool {
+    let mut escaped = false;
+    for c in text.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && quoted {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        }
+    }
+    quoted
+}
+
+/// Write the executable of a terminal bundle, which opens the real binary
+/// in Resources in a new Terminal window
+fn write_terminal_shim(path: &Path, exe: &str) -> Result<(), FruitError> {
+    use std::os::unix::fs::PermissionsExt;
+    let quoted = format!("'{}'", exe.replace('\'', "'\\''"));
+    let mut f = std::fs::File::create(path)?;
+    write!(&mut f, "#!/bin/sh\n")?;
+    write!(&mut f, "# Generated by fruitbasket: run {} in a Terminal window\n", quoted)?;
+    write!(&mut f, "exec /usr/bin/open -a Terminal \"$(dirname \"$0\")/../Resources/\"{}\n", quoted)?;
+    drop(f);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/// Whether this is the binary of a terminal bundle, run from its Resources
+fn in_terminal_bundle() -> bool {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(_) => return false,
+    };
+    let resources = match exe.parent() {
+        Some(dir) if dir.ends_with("Contents/Resources") => dir,
+        _ => return false,
+    };
+    resources.parent().and_then(|contents| contents.parent())
+        .is_some_and(|bundle| bundle.extension().is_some_and(|ext| ext == "app"))
+}
+
+/// Redirect stdout and stderr to the file requested by `capture_output()`
+///
+/// Does nothing if the running bundle was not built with `capture_output()`.
+fn redirect_output() -> Result<(), FruitError> {
+    use std::os::unix::io::AsRawFd;
+    let cls = class("NSBundle")?;
+    let path = unsafe {
+        let bundle: *mut Object = msg_send![cls, mainBundle];
+        let path: *mut Object = msg_send![bundle,
+                                          objectForInfoDictionaryKey: str_to_nsstring(OUTPUT_LOG_KEY)];
+        if path == nil {
+            return Ok(());
+        }
+        nsstring_to_string(path)
+    };
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    for fd in &[1, 2] {
+        if unsafe { dup2(file.as_raw_fd(), *fd) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Look up an ObjC class by name
+///
+/// Returns `FruitError::ClassNotFound` instead of panicking if the class
+/// isn't registered, such as when its framework failed to load.
+pub(crate) fn class(name: &str) -> Result<&'static Class, FruitError> {
+    Class::get(name).ok_or_else(|| FruitError::ClassNotFound(name.to_string()))
+}
+
+/// Tag whose address makes `declare_class()` names unique to this copy of
+/// fruitbasket
+static DECLARED_CLASS_TAG: u8 = 0;
+
+/// Declare an NSObject subclass, or return it if already declared
+///
+/// The class is named `prefix` followed by the crate version and an address
+/// in this copy of fruitbasket, like the app delegate class, so copies of
+/// different versions linked into one app don't collide.  `declare` adds
+/// the class's ivars and methods before it is registered.
+pub(crate) fn declare_class<F>(prefix: &str, declare: F) -> Result<&'static Class, FruitError>
+    where F: FnOnce(&mut ClassDecl) {
+    let name = format!("{}_{}_{:x}", prefix, env!("CARGO_PKG_VERSION").replace('.', "_"),
+                       &DECLARED_CLASS_TAG as *const u8 as usize);
+    if let Some(cls) = Class::get(&name) {
+        return Ok(cls);
+    }
+    let superclass = class("NSObject")?;
+    match ClassDecl::new(&name, superclass) {
+        Some(mut decl) => {
+            declare(&mut decl);
+            Ok(decl.register())
+        },
+        // Declared by another thread in the meantime
+        None => Class::get(&name)
+            .ok_or_else(|| FruitError::ObjcError(format!("failed to declare class {}", name))),
+    }
+}
+
+/// User defaults key holding the version of the app's last launch
+const LAST_VERSION_KEY: &str = "FruitLastVersion";
+
+/// Version recorded by the previous launch, and the current version
+static LAUNCH_VERSIONS: OnceLock<(Option<String>, String)> = OnceLock::new();
+
+/// Read the version recorded by the previous launch, and record this one
+///
+/// Only reads and writes user defaults once per process, so every `FruitApp`
+/// in the process sees the same previous version.  Unbundled apps have an
+/// empty version.
+fn launch_versions() -> &'static (Option<String>, String) {
+    LAUNCH_VERSIONS.get_or_init(|| {
+        let (bundle_cls, defaults_cls) = match (class("NSBundle"), class("NSUserDefaults")) {
+            (Ok(bundle_cls), Ok(defaults_cls)) => (bundle_cls, defaults_cls),
+            _ => return (None, "".into()),
+        };
+        unsafe {
+            let bundle: *mut Object = msg_send![bundle_cls, mainBundle];
+            let version: *mut Object = msg_send![bundle,
+                                                 objectForInfoDictionaryKey: str_to_nsstring("CFBundleVersion")];
+            let current = if version == nil { "".to_string() } else { nsstring_to_string(version) };
+            let defaults: *mut Object = msg_send![defaults_cls, standardUserDefaults];
+            let key = str_to_nsstring(LAST_VERSION_KEY);
+            let previous: *mut Object = msg_send![defaults, stringForKey: key];
+            let previous = if previous == nil { None } else { Some(nsstring_to_string(previous)) };
+            let _:() = msg_send![defaults, setObject: str_to_nsstring(&current) forKey: key];
+            (previous, current)
+        }
+    })
+}
+
+/// Allocate a new NSAutoreleasePool on the current thread
+fn new_autorelease_pool() -> Result<*mut Object, FruitError> {
+    let cls = class("NSAutoreleasePool")?;
+    unsafe {
+        let pool: *mut Object = msg_send![cls, alloc];
+        Ok(msg_send![pool, init])
+    }
+}
+
+/// Returns whether the calling thread is the process's main thread
+pub(crate) fn is_main_thread() -> bool {
+    unsafe { pthread_main_np() != 0 }
+}
+
+/// Panic with a clear message if not called from the main thread
+///
+/// AppKit is not thread-safe, and using NSApplication from any other thread
+/// fails with crashes deep inside AppKit that are very hard to trace back to
+/// the offending call.
+fn assert_main_thread(func: &str) {
+    if !is_main_thread() {
+        panic!("{}: {}", func, FruitError::MainThreadViolation);
+    }
+}
+
+/// Current year in the local time zone
+fn current_year() -> i32 {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm.tm_year + 1900
+    }
+}
+
+/// Maximum number of threads copying resources into a bundle
+const COPY_THREADS: usize = 8;
+
+/// Copy files in parallel, calling `progress` on this thread after each one
+///
+/// Stops starting new copies after the first failure, and returns the error
+/// of the earliest failed file in the list.
+fn copy_files(copies: &[(PathBuf, PathBuf)],
+              progress: Option<&dyn Fn(&Path, usize, usize)>) -> Result<(), FruitError> {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get())
+        .min(COPY_THREADS).min(copies.len());
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut error: Option<(usize, FruitError)> = None;
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (next, failed) = (&next, &failed);
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let (src, dst) = match copies.get(idx) {
+                        Some(copy) => copy,
+                        None => break,
+                    };
+                    info!("Copy {:?} to {:?}", src, dst);
+                    let result = std::fs::copy(src, dst);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    let _ = tx.send((idx, result));
+                }
+            });
+        }
+        drop(tx);
+        let mut copied = 0;
+        for (idx, result) in rx {
+            match result {
+                Ok(_) => {
+                    copied += 1;
+                    if let Some(progress) = progress {
+                        progress(&copies[idx].0, copied, copies.len());
+                    }
+                },
+                Err(e) => {
+                    if error.as_ref().map_or(true, |&(first, _)| idx < first) {
+                        error = Some((idx, FruitError::ResourceCopyError {
+                            path: copies[idx].0.clone(),
+                            source: e,
+                        }));
+                    }
+                },
+            }
+        }
+    });
+    match error {
+        Some((_, e)) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Recursively copy a directory, such as a nested bundle, preserving symlinks
+fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dst = dst.join(entry.file_name());
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, dst)?;
+        } else if file_type.is_dir() {
+            copy_dir(&entry.path(), &dst)?;
+        } else {
+            std::fs::copy(entry.path(), dst)?;
+        }
+    }
+    Ok(())
+}
+
+impl<'a> FruitApp<'a> {
+    /// Initialize the Apple app environment
+    ///
+    /// Initializes the NSApplication singleton that initializes the Mac app
+    /// environment and  creates a memory pool for Objective-C allocations on
+    /// the main thread.  Use [FruitAppBuilder](FruitAppBuilder) instead to
+    /// apply settings before the app finishes launching.
+    ///
+    /// # Returns
+    ///
+    /// A newly allocated FruitApp for managing the app, or
+    /// `FruitError::ClassNotFound` if AppKit or Foundation classes are missing
+    /// from the ObjC runtime.
+    ///
+    /// `FruitError::AlreadyInitialized` if another `FruitApp` exists, or if
+    /// another framework has already given NSApplication a delegate.  Only one
+    /// app delegate can be installed at a time, so continuing would silently
+    /// disconnect one of them.  A new `FruitApp` can be created once the
+    /// previous one is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from the main thread.
+    pub fn new() -> Result<FruitApp<'a>, FruitError> {
+        FruitApp::init(None)
+    }
+
+    /// Initialize the app, registering the delegate class with `class_prefix`
+    fn init(class_prefix: Option<&str>) -> Result<FruitApp<'a>, FruitError> {
+        assert_main_thread("FruitApp::new()");
+        if APP_ACTIVE.load(Ordering::SeqCst) {
+            return Err(FruitError::AlreadyInitialized);
+        }
+        let (tx, rx, wake) = ::command_channel();
+        let app_cls = class("NSApplication")?;
+        let string_cls = class("NSString")?;
+        let app: *mut Object = unsafe { msg_send![app_cls, sharedApplication] };
+        let delegate: *mut Object = unsafe { msg_send![app, delegate] };
+        if delegate != nil {
+            return Err(FruitError::AlreadyInitialized);
+        }
+        register_script_command_class()?;
+        let objc = ObjcWrapper::new(class_prefix)?;
         unsafe {
-            let cls = Class::get("NSApplication").unwrap();
-            let app: *mut Object = msg_send![cls, sharedApplication];
-            let cls = Class::get("NSAutoreleasePool").unwrap();
-            let pool: *mut Object = msg_send![cls, alloc];
-            let pool: *mut Object = msg_send![pool, init];
-            let cls = Class::get("NSString").unwrap();
+            let pool = new_autorelease_pool()?;
+            let cls = string_cls;
             let rust_runmode = "kCFRunLoopDefaultMode";
             let run_mode: *mut Object = msg_send![cls, alloc];
             let run_mode: *mut Object = msg_send![run_mode,
                                                   initWithBytes:rust_runmode.as_ptr()
                                                   length:rust_runmode.len()
                                                   encoding: 4]; // UTF8_ENCODING
-            let objc = ObjcSubclass::new().share();
-            let rustobjc = Box::new(ObjcWrapper {
-                objc: objc,
-                map: HashMap::new(),
-            });
-            let ptr: u64 = &*rustobjc as *const ObjcWrapper as u64;
-            let _:() = msg_send![rustobjc.objc, setRustWrapper: ptr];
-            FruitApp {
+            let wake_observer = add_wake_observer(tx.waker.clone());
+            let mut fruitapp = FruitApp {
                 app: app,
                 pool: Cell::new(pool),
+                pool_drain_interval: Cell::new(1),
+                idle_interval: Cell::new(Duration::from_millis(50)),
                 run_count: Cell::new(0),
+                stats: Cell::new(RunLoopStats::default()),
                 run_mode: run_mode,
                 tx: tx,
                 rx: rx,
-                objc: rustobjc,
+                wake_observer: wake_observer,
+                wake: wake,
+                objc: objc,
+                apple_events: Vec::new(),
+                clipboard: Cell::new(None),
+                _not_send: PhantomData,
+            };
+            // Listen for URL events right away, so a URL that launched the
+            // app is held until the caller registers a handler for it.
+            fruitapp.install_apple_event_handler(ae::kInternetEventClass, ae::kAEGetURL);
+            APP_ACTIVE.store(true, Ordering::SeqCst);
+            Ok(fruitapp)
+        }
+    }
+
+    /// A newly allocated FruitApp set up as a menu bar (agent) app
+    ///
+    /// Sets the Accessory activation policy, so the app has no Dock icon or
+    /// menu bar menus, and adds a status item showing `title` to the menu bar,
+    /// with a menu containing a Quit item.  Pair with
+    /// [Trampoline::agent](Trampoline::agent) so the Dock icon doesn't appear
+    /// briefly while the app launches.
+    ///
+    /// Returns the same errors as [FruitApp::new](FruitApp::new).
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from the main thread.
+    pub fn new_agent(title: &str) -> Result<AgentApp<'a>, FruitError> {
+        let app = FruitApp::new()?;
+        app.set_activation_policy(ActivationPolicy::Accessory);
+        let status_item = ::status::StatusItem::new(title)?;
+        status_item.add_quit_item(&format!("Quit {}", title))?;
+        Ok(AgentApp {
+            app: app,
+            status_item: status_item,
+        })
+    }
+
+    /// Register to receive a callback when the ObjC runtime raises one
+    ///
+    /// ObjCCallbackKey is used to specify the source of the callback, which
+    /// must be something registered with the ObjC runtime.
+    ///
+    /// URL events received before the `handleEvent:withReplyEvent:` callback
+    /// is registered are delivered to it immediately upon registration.
+    ///
+    pub fn register_callback(&mut self, key: FruitCallbackKey, cb: FruitObjcCallback<'a>) {
+        self.objc.state.once.borrow_mut().retain(|k| *k != key);
+        self.insert_callback(key, Rc::new(cb));
+    }
+
+    /// Register a callback that is removed after it is first called
+    ///
+    /// Works like [register_callback](FruitApp::register_callback), but the
+    /// callback only receives the first event for `key`.  Useful for
+    /// one-time setup, such as waiting for `applicationDidFinishLaunching:`,
+    /// without a flag to ignore later calls.
+    ///
+    /// Registering another callback for the same key replaces this one.
+    pub fn register_callback_once(&mut self, key: FruitCallbackKey, cb: FruitObjcCallback<'a>) {
+        {
+            let mut once = self.objc.state.once.borrow_mut();
+            if !once.contains(&key) {
+                once.push(key);
+            }
+        }
+        self.insert_callback(key, Rc::new(cb));
+    }
+
+    fn insert_callback(&mut self, key: FruitCallbackKey, cb: Rc<FruitObjcCallback<'a>>) {
+        let url_key = key == FruitCallbackKey::Method("handleEvent:withReplyEvent:");
+        let _ = self.objc.state.map.borrow_mut().insert(key, cb);
+        if url_key {
+            self.replay_pending_urls();
+        }
+    }
+
+    /// Add a method to the app delegate, and receive its calls in a callback
+    ///
+    /// The app delegate only implements a fixed set of NSApplication delegate
+    /// methods.  This adds another one, or any other method the delegate
+    /// should respond to, at runtime.  Calls to it are delivered to `cb`, the
+    /// same as a callback registered with
+    /// [register_callback](FruitApp::register_callback) for
+    /// `FruitCallbackKey::Method(selector)`.
+    ///
+    /// Methods are added to a subclass of the delegate's class unique to this
+    /// `FruitApp`.  AppKit checks which methods the delegate implements when
+    /// it is installed, so add methods before the first call to
+    /// [run](FruitApp::run) if possible; if the delegate is already installed,
+    /// it is re-installed so AppKit notices the new method.
+    ///
+    /// # Arguments
+    ///
+    /// `selector` - Full selector name, ex: `"applicationDidBecomeActive:"`
+    ///
+    /// `signature` - Arguments the method takes, which must match the number
+    /// of colons in `selector`
+    ///
+    /// `cb` - Called each time the method is called
+    ///
+    /// # Returns
+    ///
+    /// `FruitError::ObjcError` if the signature doesn't match the selector,
+    /// or the method was already added.
+    pub fn add_delegate_method(&mut self, selector: &'static str, signature: MethodSignature,
+                               cb: FruitObjcCallback<'a>) -> Result<(), FruitError> {
+        let (args, types, imp): (usize, &[u8], Imp) = unsafe {
+            match signature {
+                MethodSignature::NoArgs =>
+                    (0, b"v@:\0", std::mem::transmute::<extern "C" fn(&Object, Sel), Imp>(
+                        ObjcSubclass::dynamic_method_0)),
+                MethodSignature::OneArg =>
+                    (1, b"v@:@\0", std::mem::transmute::<extern "C" fn(&Object, Sel, *mut Object), Imp>(
+                        ObjcSubclass::dynamic_method_1)),
+                MethodSignature::TwoArgs =>
+                    (2, b"v@:@@\0", std::mem::transmute::<extern "C" fn(&Object, Sel, *mut Object, *mut Object), Imp>(
+                        ObjcSubclass::dynamic_method_2)),
+                MethodSignature::Service =>
+                    (3, b"v@:@@^@\0", std::mem::transmute::<extern "C" fn(&Object, Sel, *mut Object, *mut Object, *mut *mut Object), Imp>(
+                        ObjcSubclass::dynamic_method_service)),
+            }
+        };
+        if selector.matches(':').count() != args {
+            return Err(FruitError::ObjcError(
+                format!("{} does not take {} arguments", selector, args)));
+        }
+        let cls = self.objc.subclass()?;
+        let added = unsafe {
+            class_addMethod(cls as *const Class as *mut Class, Sel::register(selector),
+                            imp, types.as_ptr() as *const std::os::raw::c_char)
+        };
+        if added == NO {
+            return Err(FruitError::ObjcError(format!("method {} already added", selector)));
+        }
+        self.objc.state.dynamic_methods.borrow_mut().push(selector);
+        self.register_callback(FruitCallbackKey::Method(selector), cb);
+        unsafe {
+            let objc: *mut Object = &*self.objc.objc as *const ObjcSubclass as *mut Object;
+            let delegate: *mut Object = msg_send![self.app, delegate];
+            if delegate == objc {
+                let _:() = msg_send![self.app, setDelegate: nil];
+                let _:() = msg_send![self.app, setDelegate: objc];
+            }
+        }
+        Ok(())
+    }
+
+    /// Register application to receive Apple events of the given type
+    ///
+    /// Register with the underlying NSAppleEventManager so this application gets
+    /// events matching the given Class/ID tuple.  This causes the internal ObjC
+    /// delegate to receive `handleEvent:withReplyEvent:` messages when the
+    /// specified event is sent to your application.
+    ///
+    /// This registers the event to be received internally.  To receive it in
+    /// your code, you must use [register_callback](FruitApp::register_callback) to listen for the
+    /// selector by specifying key:
+    ///
+    ///   `FruitCallbackKey::Method("handleEvent:withReplyEvent:")`
+    ///
+    /// URL events (`kInternetEventClass` / `kAEGetURL`) are special: FruitApp
+    /// listens for them from creation, and holds any that arrive before both
+    /// this and the callback are registered.  This catches the URL that
+    /// launched the app, which is often delivered before the caller gets a
+    /// chance to register.  Held events are replayed, in order, as soon as
+    /// both registrations are in place.
+    ///
+    pub fn register_apple_event(&mut self, class: u32, id: u32) {
+        if (class, id) == (ae::kInternetEventClass, ae::kAEGetURL) {
+            self.objc.state.url_events.set(true);
+            self.replay_pending_urls();
+            return;
+        }
+        self.install_apple_event_handler(class, id);
+    }
+
+    fn install_apple_event_handler(&mut self, class: u32, id: u32) {
+        let cls = match self::class("NSAppleEventManager") {
+            Ok(cls) => cls,
+            Err(e) => {
+                info!("Failed to register Apple event: {}", e);
+                return;
+            }
+        };
+        unsafe {
+            let manager: *mut Object = msg_send![cls, sharedAppleEventManager];
+            let objc = self.objc.take();
+            let _:() = msg_send![manager,
+                              setEventHandler: objc
+                              andSelector: sel!(handleEvent:withReplyEvent:)
+                              forEventClass: class
+                              andEventID: id];
+        }
+        self.apple_events.push((class, id));
+    }
+
+    /// Files the app was asked to open when it launched
+    ///
+    /// A file double-clicked in Finder or dropped on the app's icon can reach
+    /// the app as a command-line argument, an 'open documents' Apple event,
+    /// or an `application:openFile:` delegate call, depending on how the app
+    /// was launched and which of those it registered for.  This gathers the
+    /// ones received before `applicationDidFinishLaunching:`, along with any
+    /// command-line arguments naming existing files, into one deduplicated
+    /// list.
+    ///
+    /// Call it once the app has finished launching, such as from the
+    /// `applicationDidFinishLaunching:` callback or after the first call to
+    /// [run](FruitApp::run).  Earlier, the list may be incomplete.
+    pub fn startup_open_requests(&self) -> Vec<OpenRequest> {
+        ::merge_open_requests(&self.objc.state.launch_opens.borrow())
+    }
+
+    /// Deliver held URL events, if the caller is now ready for them
+    fn replay_pending_urls(&self) {
+        let state = &self.objc.state;
+        if !state.url_events.get() {
+            return;
+        }
+        let key = FruitCallbackKey::Method("handleEvent:withReplyEvent:");
+        loop {
+            if state.pending_urls.borrow().is_empty() || !state.map.borrow().contains_key(&key) {
+                return;
+            }
+            let event = state.pending_urls.borrow_mut().remove(0);
+            state.call(&key, event);
+            unsafe { let _:() = msg_send![event, release]; }
+        }
+    }
+
+    /// Handle the core Apple events sent by the system with one callback
+    ///
+    /// Registers for the events sent when the user opens documents with the
+    /// app, clicks the app's Dock icon while it is running, or quits the app
+    /// from the Dock or menu bar, and calls `cb` with each one as a parsed
+    /// [CoreEvent](CoreEvent).  These events do not reach the
+    /// `handleEvent:withReplyEvent:` callback.
+    ///
+    /// Registering for these events replaces AppKit's default handling of
+    /// them.  Notably, the app no longer quits by itself on a quit event, so
+    /// `cb` must call [terminate](FruitApp::terminate) or stop the run loop
+    /// when it receives `CoreEvent::Quit`.
+    pub fn register_core_events(&mut self, cb: CoreEventCallback<'a>) {
+        *self.objc.state.core_events.borrow_mut() = Some(cb);
+        self.register_apple_event(ae::kCoreEventClass, ae::kAEOpenDocuments);
+        self.register_apple_event(ae::kCoreEventClass, ae::kAEReopenApplication);
+        self.register_apple_event(ae::kCoreEventClass, ae::kAEQuitApplication);
+    }
+
+    /// Open files with a handler that can reject them
+    ///
+    /// Replaces the `application:openFile:` callback for files the user
+    /// opens with the app, such as by double-clicking them in Finder or
+    /// dropping them on the app's Dock icon.  `cb` is called with each file,
+    /// and returns an error message for files it can't open, such as ones
+    /// that are corrupt or from a newer version of the app.
+    ///
+    /// If `show_alert` is true, rejected files are listed with their error
+    /// messages in a standard alert, and the open request is reported to
+    /// LaunchServices as cancelled, since the user has already been told
+    /// why.  Otherwise the request is reported as failed, and the app
+    /// should explain the failure itself.  Either way, Finder doesn't claim
+    /// that the app can't open files of that type.
+    ///
+    /// Files opened at launch are also reported by
+    /// [startup_open_requests](FruitApp::startup_open_requests).  Open
+    /// documents events handled by
+    /// [register_core_events](FruitApp::register_core_events) don't reach
+    /// this handler.
+    pub fn register_open_file_handler(&mut self, cb: OpenFileCallback<'a>, show_alert: bool) {
+        let first = self.objc.state.open_files.borrow().is_none();
+        *self.objc.state.open_files.borrow_mut() = Some(cb);
+        self.objc.state.open_file_alerts.set(show_alert);
+        // AppKit checks which methods the delegate implements when it is set
+        if first {
+            unsafe {
+                let objc: *mut Object = &*self.objc.objc as *const ObjcSubclass as *mut Object;
+                let delegate: *mut Object = msg_send![self.app, delegate];
+                if delegate == objc {
+                    let _:() = msg_send![self.app, setDelegate: nil];
+                    let _:() = msg_send![self.app, setDelegate: objc];
+                }
+            }
+        }
+    }
+
+    /// Stop receiving Apple events of the given type
+    ///
+    /// Removes a handler previously installed with
+    /// [register_apple_event](FruitApp::register_apple_event), so handlers
+    /// can be installed temporarily (for instance, only while waiting for an
+    /// OAuth redirect URL) and removed afterwards.  Callbacks registered with
+    /// [register_callback](FruitApp::register_callback) are left in place.
+    ///
+    /// Unregistered URL events are held again, as before registration.
+    pub fn unregister_apple_event(&mut self, class: u32, id: u32) {
+        if (class, id) == (ae::kInternetEventClass, ae::kAEGetURL) {
+            self.objc.state.url_events.set(false);
+            return;
+        }
+        if let Ok(cls) = self::class("NSAppleEventManager") {
+            unsafe {
+                let manager: *mut Object = msg_send![cls, sharedAppleEventManager];
+                let _:() = msg_send![manager,
+                                     removeEventHandlerForEventClass: class
+                                     andEventID: id];
+            }
+        }
+        self.apple_events.retain(|&event| event != (class, id));
+    }
+
+    /// How far the app has got through launching
+    ///
+    /// The app launches during the first call to [run](FruitApp::run).  That
+    /// call delivers `applicationWillFinishLaunching:` first, before any
+    /// event is dispatched, and `applicationDidFinishLaunching:` once AppKit
+    /// has handled the events it launched with, which is usually within the
+    /// same call but can take a few calls with `RunPeriod::Once`.
+    /// `run(RunPeriod::Forever)` until `launch_phase()` reaches `DidFinish`,
+    /// or until the did-finish callback stops the run loop, to wait for it.
+    ///
+    /// Callbacks for both methods that are registered before the first
+    /// `run()` are each called exactly once, in that order.  Callbacks
+    /// registered later are never called for a phase the app has already
+    /// passed, so check the phase first when registering late.
+    pub fn launch_phase(&self) -> LaunchPhase {
+        self.objc.state.phase.get()
+    }
+
+    /// Find out how the app was started
+    ///
+    /// Lets apps adapt their startup, such as staying hidden in the menu bar
+    /// when opened as a login item instead of showing a window.  Known once
+    /// the app starts launching, in the first call to [run](FruitApp::run),
+    /// so check it from the `applicationDidFinishLaunching:` callback or
+    /// later; it is `LaunchContext::Unknown` before then.
+    ///
+    /// Login items are identified by the Apple event the app launched with.
+    /// Otherwise, the context is worked out from the process that started
+    /// the app: a relaunch by the `Trampoline` is recognized by the
+    /// environment it passes on, an app launched through LaunchServices by
+    /// the sender of its launch event, and one started directly by its
+    /// parent process.  A parent that isn't an app, such as a shell or
+    /// `cargo`, is reported as `Terminal`.
+    pub fn launch_context(&self) -> LaunchContext {
+        let (login_item, sender) = match self.objc.state.launch_event.get() {
+            Some(event) => event,
+            None => return LaunchContext::Unknown,
+        };
+        if login_item {
+            return LaunchContext::LoginItem;
+        }
+        if std::env::var_os(::ORIGINAL_DIR_ENV).is_some() {
+            return LaunchContext::Trampoline;
+        }
+        let parent = unsafe { libc::getppid() };
+        if parent > 1 {
+            return match running_app_bundle_id(parent) {
+                Some(bundle_id) => LaunchContext::App { pid: parent, bundle_id: Some(bundle_id) },
+                None => LaunchContext::Terminal,
+            };
+        }
+        if sender > 0 && sender != std::process::id() as i32 {
+            return LaunchContext::App { pid: sender, bundle_id: running_app_bundle_id(sender) };
+        }
+        LaunchContext::LaunchServices
+    }
+
+    /// Set the app "activation policy" controlling what UI it does/can present.
+    ///
+    /// Same as [change_activation_policy](FruitApp::change_activation_policy)
+    /// without activating the app, ignoring transitions AppKit doesn't allow.
+    pub fn set_activation_policy(&self, policy: ActivationPolicy) {
+        if let Err(e) = self.change_activation_policy(policy, false) {
+            info!("{}", e);
+        }
+    }
+
+    /// The app's current activation policy
+    pub fn activation_policy(&self) -> ActivationPolicy {
+        let policy: i64 = unsafe { msg_send![self.app, activationPolicy] };
+        match policy {
+            0 => ActivationPolicy::Regular,
+            1 => ActivationPolicy::Accessory,
+            _ => ActivationPolicy::Prohibited,
+        }
+    }
+
+    /// Change the activation policy of the running app, and optionally
+    /// bring it to the front
+    ///
+    /// AppKit supports only some changes once the app has launched: between
+    /// `Accessory` and `Regular` in either direction, and from `Prohibited`
+    /// to either.  Changing to `Prohibited` is refused after launch.
+    ///
+    /// The usual use is an agent app that shows a Dock icon and menu bar only
+    /// while one of its windows is open: change to `Regular` with `activate`
+    /// set when opening the window, and back to `Accessory` without it when
+    /// closing the window, so the previously active app comes back to the
+    /// front.  When an active app changes to `Regular`, AppKit doesn't
+    /// replace the menu bar until the app is activated again, so it is
+    /// deactivated and reactivated to show its menus straight away.
+    ///
+    /// # Arguments
+    ///
+    /// `policy` - New activation policy
+    ///
+    /// `activate` - Whether to make the app the active app afterwards
+    ///
+    /// # Returns
+    ///
+    /// `FruitError::ActivationPolicyError` if AppKit doesn't allow the change.
+    pub fn change_activation_policy(&self, policy: ActivationPolicy, activate: bool)
+                                    -> Result<(), FruitError> {
+        let current = self.activation_policy();
+        if policy != current {
+            let policy_int: i64 = match policy {
+                ActivationPolicy::Regular => 0,
+                ActivationPolicy::Accessory => 1,
+                ActivationPolicy::Prohibited => 2,
+            };
+            unsafe {
+                let was_active: BOOL = msg_send![self.app, isActive];
+                let accepted: BOOL = msg_send![self.app, setActivationPolicy: policy_int];
+                if accepted == NO {
+                    return Err(FruitError::ActivationPolicyError { from: current, to: policy });
+                }
+                if policy == ActivationPolicy::Regular && was_active == YES && activate {
+                    let _:() = msg_send![self.app, deactivate];
+                }
             }
         }
+        if activate {
+            self.activate();
+        }
+        Ok(())
     }
 
-    /// Register to receive a callback when the ObjC runtime raises one
+    /// Show or hide the app's Dock icon, optionally for future launches
     ///
-    /// ObjCCallbackKey is used to specify the source of the callback, which
-    /// must be something registered with the ObjC runtime.
+    /// The whole workflow behind a "Show icon in Dock" preference.  The
+    /// change is applied right away by switching between the `Regular` and
+    /// `Accessory` activation policies; the app is activated when its icon
+    /// is shown, so its menu bar appears too.
     ///
-    pub fn register_callback(&mut self, key: FruitCallbackKey, cb: FruitObjcCallback<'a>) {
-        let _ = self.objc.map.insert(key, cb);
+    /// With `persist` set, the running bundle's Info.plist is also changed
+    /// to set `LSUIElement` when hiding the icon, or remove it when showing
+    /// it, so the app launches the same way next time.  LaunchServices is
+    /// told about the change, and the app is relaunched with
+    /// [relaunch](FruitApp::relaunch) so every part of the system, like the
+    /// Dock and app switcher, sees the app's new role.
+    ///
+    /// Editing Info.plist invalidates the bundle's code signature.  Signed
+    /// apps should instead save the preference themselves, and apply it with
+    /// `persist` unset early in each launch.
+    ///
+    /// # Returns
+    ///
+    /// Does not return if `persist` is set and the relaunch succeeds.
+    /// `FruitError::ActivationPolicyError` if AppKit refuses the change,
+    /// `FruitError::NotBundled` if persisting while not running from a
+    /// bundle, or `FruitError::PlistError` if Info.plist can't be updated.
+    pub fn set_shows_in_dock(&self, show: bool, persist: bool) -> Result<(), FruitError> {
+        let policy = if show { ActivationPolicy::Regular } else { ActivationPolicy::Accessory };
+        self.change_activation_policy(policy, show)?;
+        if !persist {
+            return Ok(());
+        }
+        let bundle = ::relocate::bundle_path().ok_or(FruitError::NotBundled)?;
+        set_info_plist_bool(&bundle, "LSUIElement", !show)?;
+        self.relaunch()
     }
 
-    /// Register application to receive Apple events of the given type
+    /// Make the app the active app, bringing its windows to the front
     ///
-    /// Register with the underlying NSAppleEventManager so this application gets
-    /// events matching the given Class/ID tuple.  This causes the internal ObjC
-    /// delegate to receive `handleEvent:withReplyEvent:` messages when the
-    /// specified event is sent to your application.
+    /// Activates the app even if the user is working in another app, so
+    /// call this in response to something the user did, such as clicking a
+    /// status item.
+    pub fn activate(&self) {
+        unsafe {
+            let _:() = msg_send![self.app, activateIgnoringOtherApps: YES];
+        }
+    }
+
+    /// Enable or disable the app's services in other apps' Services menus
     ///
-    /// This registers the event to be received internally.  To receive it in
-    /// your code, you must use [register_callback](FruitApp::register_callback) to listen for the
-    /// selector by specifying key:
+    /// Enabling makes the app delegate the services provider, which receives
+    /// the services declared with [Trampoline::service](Trampoline::service)
+    /// in methods added with
+    /// [add_delegate_method](FruitApp::add_delegate_method).  Disabling
+    /// removes the provider, so choosing one of the services does nothing.
+    /// Either way, the system is told to rescan the app's services, so
+    /// changes to the bundle's `NSServices` take effect without logging out.
+    pub fn set_services_provider(&self, enabled: bool) {
+        unsafe {
+            let provider: *mut Object = if enabled {
+                &*self.objc.objc as *const ObjcSubclass as *mut Object
+            } else {
+                nil
+            };
+            let _:() = msg_send![self.app, setServicesProvider: provider];
+            NSUpdateDynamicServices();
+        }
+    }
+
+    /// Set or clear the badge label shown on the app's Dock icon
     ///
-    ///   `FruitCallbackKey::Method("handleEvent:withReplyEvent:")`
+    /// # Arguments
     ///
-    pub fn register_apple_event(&mut self, class: u32, id: u32) {
+    /// `badge` - Short text to show in the badge (ex: an unread count), or
+    /// `None` to remove it
+    pub fn set_dock_badge(&self, badge: Option<&str>) {
         unsafe {
-            let cls = Class::get("NSAppleEventManager").unwrap();
-            let manager: *mut Object = msg_send![cls, sharedAppleEventManager];
-            let objc = (*self.objc).take();
-            let _:() = msg_send![manager,
-                              setEventHandler: objc
-                              andSelector: sel!(handleEvent:withReplyEvent:)
-                              forEventClass: class
-                              andEventID: id];
+            let tile: *mut Object = msg_send![self.app, dockTile];
+            let label = match badge {
+                Some(text) => str_to_nsstring(text),
+                None => nil,
+            };
+            let _:() = msg_send![tile, setBadgeLabel: label];
         }
     }
 
-    /// Set the app "activation policy" controlling what UI it does/can present.
-    pub fn set_activation_policy(&self, policy: ActivationPolicy) {
-        let policy_int = match policy {
-            ActivationPolicy::Regular => 0,
-            ActivationPolicy::Accessory => 1,
-            ActivationPolicy::Prohibited => 2,
-        };
+    /// Returns whether any part of the app is visible on screen
+    ///
+    /// False when all of the app's windows are hidden, minimized, covered by
+    /// other windows, or on another Space.  Apps can stop rendering while not
+    /// visible to save power.  Register a callback for the
+    /// `applicationDidChangeOcclusionState:` method to be told when this
+    /// changes:
+    ///
+    /// ```no_run
+    /// # use fruitbasket::{FruitApp, FruitCallbackKey};
+    /// # let mut app = FruitApp::new().unwrap();
+    /// app.register_callback(FruitCallbackKey::Method("applicationDidChangeOcclusionState:"),
+    ///     Box::new(|_notification| println!("visibility changed")));
+    /// ```
+    pub fn is_visible(&self) -> bool {
+        unsafe {
+            let state: u64 = msg_send![self.app, occlusionState];
+            state & (1 << 1) != 0 // NSApplicationOcclusionStateVisible
+        }
+    }
+
+    /// Bounce the app's Dock icon to get the user's attention
+    ///
+    /// Has no effect if the app is already active.
+    ///
+    /// # Arguments
+    ///
+    /// `critical` - If true, bounce until the user activates the app.  If
+    /// false, bounce only once.
+    pub fn request_attention(&self, critical: bool) {
+        // NSCriticalRequest = 0, NSInformationalRequest = 10
+        let request_type: i64 = if critical { 0 } else { 10 };
         unsafe {
-            let _:() = msg_send![self.app, setActivationPolicy: policy_int];
+            let _: i64 = msg_send![self.app, requestUserAttention: request_type];
         }
     }
 
@@ -687,13 +2800,120 @@ impl<'a> FruitApp<'a> {
     ///
     /// `exit_code` - Application exit code. '0' is success.
     pub fn terminate(exit_code: i32) {
+        let cls = match class("NSApplication") {
+            Ok(cls) => cls,
+            Err(_) => std::process::exit(exit_code),
+        };
         unsafe {
-            let cls = objc::runtime::Class::get("NSApplication").unwrap();
             let app: *mut objc::runtime::Object = msg_send![cls, sharedApplication];
             let _:() = msg_send![app, terminate: exit_code];
         }
     }
 
+    /// Start a helper executable shipped inside the app bundle
+    ///
+    /// Looks for `name` in the bundle's `Contents/MacOS`, `Contents/Helpers`,
+    /// and `Contents/Resources` directories, in that order, or next to the
+    /// running executable if the app isn't bundled.  The helper is started
+    /// with its own directory as the working directory, and with this
+    /// process's environment, minus the variables LaunchServices sets to
+    /// identify the app, which would otherwise make the helper believe it is
+    /// the app.
+    ///
+    /// # Returns
+    ///
+    /// The running helper process, or `FruitError::IOError` if the helper
+    /// wasn't found or couldn't be started.
+    pub fn launch_helper(&self, name: &str, args: &[&str]) -> Result<std::process::Child, FruitError> {
+        let dirs: Vec<PathBuf> = match ::relocate::bundle_path() {
+            Some(bundle) => {
+                let contents = bundle.join("Contents");
+                vec![contents.join("MacOS"), contents.join("Helpers"), contents.join("Resources")]
+            },
+            None => std::env::current_exe()?.parent().map(Path::to_path_buf).into_iter().collect(),
+        };
+        let helper = dirs.iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound,
+                                               format!("helper not found in bundle: {}", name)))?;
+        info!("Launching helper: {:?}", helper);
+        let child = std::process::Command::new(&helper)
+            .args(args)
+            .current_dir(helper.parent().unwrap_or(Path::new("/")))
+            .env_remove("__CFBundleIdentifier")
+            .env_remove("XPC_SERVICE_NAME")
+            .spawn()?;
+        Ok(child)
+    }
+
+    /// Restart the application
+    ///
+    /// Terminates the app as [terminate](FruitApp::terminate) does, and
+    /// launches it again once this process has exited, with the same
+    /// command-line arguments.  Useful for "restart to apply" settings or
+    /// updates.  A bundled app is relaunched through LaunchServices, and an
+    /// unbundled one by running its executable directly.
+    ///
+    /// This function does not return unless the relaunch could not be
+    /// arranged, in which case the app keeps running and an error is
+    /// returned.
+    pub fn relaunch(&self) -> Result<(), FruitError> {
+        let args: Vec<std::ffi::OsString> = std::env::args_os().skip(1).collect();
+        let mut command: Vec<std::ffi::OsString> = Vec::new();
+        match ::relocate::bundle_path() {
+            Some(bundle) => {
+                command.push("/usr/bin/open".into());
+                command.push(bundle.into_os_string());
+                if !args.is_empty() {
+                    command.push("--args".into());
+                }
+            },
+            None => command.push(std::env::current_exe()?.into_os_string()),
+        }
+        command.extend(args);
+        let command: Vec<&std::ffi::OsStr> = command.iter().map(|arg| arg.as_os_str()).collect();
+        ::relocate::run_after_exit(&command)?;
+        info!("Relaunching.");
+        FruitApp::terminate(0);
+        std::process::exit(0);
+    }
+
+    /// Returns whether this is the first time the app has run
+    ///
+    /// The version of each launch is recorded in the app's user defaults the
+    /// first time this, [previous_version](FruitApp::previous_version), or
+    /// [on_upgrade](FruitApp::on_upgrade) is called in a process, so call one
+    /// of them on every launch.  Deleting the app's preferences resets it.
+    pub fn first_run(&self) -> bool {
+        launch_versions().0.is_none()
+    }
+
+    /// The bundle version (`CFBundleVersion`) the app last ran as
+    ///
+    /// Returns None on the first run.  See [first_run](FruitApp::first_run)
+    /// for how launches are recorded.
+    pub fn previous_version(&self) -> Option<String> {
+        launch_versions().0.clone()
+    }
+
+    /// Run `cb` if the app's version changed since it last ran
+    ///
+    /// Called immediately, with the previous and current `CFBundleVersion`,
+    /// when the version differs from the one recorded by the last launch,
+    /// which is usually because the app was updated.  Use it to migrate data
+    /// or show release notes.  Not called on the first run.  See
+    /// [first_run](FruitApp::first_run) for how launches are recorded.
+    pub fn on_upgrade<F: FnOnce(&str, &str)>(&self, cb: F) {
+        let (ref previous, ref current) = *launch_versions();
+        if let Some(ref previous) = *previous {
+            if previous != current {
+                info!("App version changed from {} to {}", previous, current);
+                cb(previous, current);
+            }
+        }
+    }
+
     /// Stop the running app run loop
     ///
     /// If the run loop is running (`run()`), this stops it after the next event
@@ -719,6 +2939,11 @@ impl<'a> FruitApp<'a> {
     /// delays will cause the UI to hang and cause latency on other internal
     /// operations.
     ///
+    /// Each iteration of the loop dispatches every event that is pending in
+    /// the queue, and only sleeps once the queue is empty.  Apps that pump
+    /// events from their own frame loop can limit how many events are
+    /// dispatched per call with `RunPeriod::Events`.
+    ///
     /// # Arguments
     ///
     /// `period` - How long to run the event loop before returning
@@ -726,55 +2951,302 @@ impl<'a> FruitApp<'a> {
     /// # Returns
     ///
     /// Ok on natural end, Err if stopped by a Stopper.
+    ///
+    /// # Panics
+    ///
+    /// Panics if not called from the main thread.
     pub fn run(&mut self, period: RunPeriod) -> Result<(),()>{
-        let start = time::now_utc().to_timespec();
+        assert_main_thread("FruitApp::run()");
+        let start = Instant::now();
+        if self.objc.state.phase.get() == LaunchPhase::DidFinish {
+            self.objc.state.phase.set(LaunchPhase::Running);
+        }
         loop {
-            if self.rx.try_recv().is_ok() {
+            if self.process_commands() {
                 return Err(());
             }
+            let iteration_signpost = signpost::begin(b"Run loop iteration\0");
             unsafe {
                 let run_count = self.run_count.get();
                 if run_count == 0 {
-                    let cls = objc::runtime::Class::get("NSApplication").unwrap();
-                    let app: *mut objc::runtime::Object = msg_send![cls, sharedApplication];
-                    let objc = (*self.objc).take();
-                    let _:() = msg_send![app, setDelegate: objc];
+                    let objc = self.objc.take();
+                    let _:() = msg_send![self.app, setDelegate: objc];
                     let _:() = msg_send![self.app, finishLaunching];
-                }
-                // Create a new release pool every once in a while, draining the old one
-                if run_count % 100 == 0 {
-                    let old_pool = self.pool.get();
-                    if run_count != 0 {
-                        let _:() = msg_send![old_pool, drain];
+                    // Without the optional delegate method, nothing else
+                    // notes that launching has started
+                    if self.objc.state.phase.get() == LaunchPhase::NotLaunched {
+                        self.objc.state.phase.set(LaunchPhase::WillFinish);
                     }
-                    let cls = Class::get("NSAutoreleasePool").unwrap();
-                    let pool: *mut Object = msg_send![cls, alloc];
-                    let pool: *mut Object = msg_send![pool, init];
-                    self.pool.set(pool);
                 }
+                // Dispatch every pending event, so bursts don't back up
+                // behind the sleep below.  A stop request interrupts the burst.
                 let mode = self.run_mode;
-                let event: *mut Object = msg_send![self.app,
-                                                   nextEventMatchingMask: 0xffffffffffffffffu64
-                                                   untilDate: nil
-                                                   inMode: mode
-                                                   dequeue: 1];
-                let _:() = msg_send![self.app, sendEvent: event];
+                let mut stopped = false;
+                let mut stats = self.stats.get();
+                let max_events = match period {
+                    RunPeriod::Events(n) => n,
+                    _ => usize::MAX,
+                };
+                let mut dispatched = 0;
+                while dispatched < max_events {
+                    let event: *mut Object = msg_send![self.app,
+                                                       nextEventMatchingMask: 0xffffffffffffffffu64
+                                                       untilDate: nil
+                                                       inMode: mode
+                                                       dequeue: 1];
+                    if event == nil {
+                        break;
+                    }
+                    let dispatch_start = Instant::now();
+                    let _:() = msg_send![self.app, sendEvent: event];
+                    stats.dispatch_time += dispatch_start.elapsed();
+                    stats.events += 1;
+                    dispatched += 1;
+                    if self.process_commands() {
+                        stopped = true;
+                        break;
+                    }
+                    // Don't let a long burst run past the requested time
+                    if let RunPeriod::Time(t) = period {
+                        if start.elapsed() >= t {
+                            break;
+                        }
+                    }
+                }
                 let _:() = msg_send![self.app, updateWindows];
+                self.poll_clipboard();
                 self.run_count.set(run_count + 1);
+                stats.iterations += 1;
+                // Replace the release pool with a fresh one, draining the old one
+                if (run_count + 1) % self.pool_drain_interval.get() == 0 {
+                    let _:() = msg_send![self.pool.get(), drain];
+                    self.pool.set(new_autorelease_pool().unwrap_or(nil));
+                    stats.pool_drains += 1;
+                }
+                self.stats.set(stats);
+                if stopped {
+                    return Err(());
+                }
             }
-            if period == RunPeriod::Once {
+            drop(iteration_signpost);
+            if let RunPeriod::Once | RunPeriod::Events(_) = period {
                 break;
             }
-            thread::sleep(Duration::from_millis(50));
+            let mut wait = self.idle_interval.get();
+            if let RunPeriod::Time(t) = period {
+                wait = std::cmp::min(wait, t.saturating_sub(start.elapsed()));
+            }
+            thread::sleep(wait);
             if let RunPeriod::Time(t) = period {
-                let now = time::now_utc().to_timespec();
-                if now >= start + time::Duration::from_std(t).unwrap() {
+                if start.elapsed() >= t {
                     break;
                 }
             }
         }
         return Ok(());
     }
+
+    /// Run the event loop until a callback fires, and return its payload
+    ///
+    /// Pumps the event loop, like [run](FruitApp::run), until an event for
+    /// `key` arrives, and returns the object passed with it converted to an
+    /// [EventPayload](EventPayload).  This lets startup code wait for events
+    /// in sequence, such as waiting for the app to finish launching and then
+    /// for the URL it was launched to open, without stoppers and flags.
+    ///
+    /// Any callback already registered for `key` doesn't receive the event,
+    /// and is restored before returning.  Apple events, such as URLs, must
+    /// still be registered for with `register_apple_event()`.
+    ///
+    /// Panics if not called from the main thread.
+    ///
+    /// # Arguments
+    ///
+    /// `key` - Callback to wait for
+    ///
+    /// `timeout` - How long to wait for it
+    ///
+    /// # Returns
+    ///
+    /// The payload of the event, or None if the timeout expired or the run
+    /// loop was stopped first.
+    pub fn wait_for(&mut self, key: FruitCallbackKey, timeout: Duration) -> Option<EventPayload> {
+        let previous = self.objc.state.map.borrow_mut().remove(&key);
+        let previous_once = self.objc.state.once.borrow().contains(&key);
+        let payload: Rc<RefCell<Option<EventPayload>>> = Rc::new(RefCell::new(None));
+        let received = payload.clone();
+        self.register_callback_once(key, Box::new(move |obj| {
+            *received.borrow_mut() = Some(parse_payload(&key, obj));
+        }));
+        let start = Instant::now();
+        while payload.borrow().is_none() {
+            if self.run(RunPeriod::Once).is_err() {
+                break;
+            }
+            let elapsed = start.elapsed();
+            if payload.borrow().is_some() || elapsed >= timeout {
+                break;
+            }
+            thread::sleep(std::cmp::min(self.idle_interval.get(), timeout - elapsed));
+        }
+        self.objc.state.once.borrow_mut().retain(|k| *k != key);
+        let _ = self.objc.state.map.borrow_mut().remove(&key);
+        if let Some(cb) = previous {
+            if previous_once {
+                self.objc.state.once.borrow_mut().push(key);
+            }
+            self.insert_callback(key, cb);
+        }
+        payload.replace(None)
+    }
+
+    /// A file descriptor that becomes readable when the app has work to do
+    ///
+    /// For apps whose main loop isn't Cocoa's, such as ones built on calloop,
+    /// mio, or a custom reactor.  Register the descriptor for readability
+    /// with the reactor, and call [pump_pending](FruitApp::pump_pending) when
+    /// it is readable.  It becomes readable whenever a request is queued by a
+    /// [FruitStopper](FruitStopper) or [FruitHandle](FruitHandle), so other
+    /// threads can wake the main thread, and, through an observer on the
+    /// main run loop, whenever that run loop wakes from waiting to handle an
+    /// event, timer, or other source.
+    ///
+    /// The run loop only waits while AppKit runs it, such as during a modal
+    /// session, menu tracking, or [run](FruitApp::run).  Events that arrive
+    /// while the reactor itself is blocked are picked up by the next
+    /// `pump_pending()`, so pump once after handling any other descriptor.
+    ///
+    /// The descriptor is owned by the `FruitApp`, is non-blocking, and must
+    /// not be read from or closed.  It is -1 if it could not be created.
+    pub fn wake_fd(&self) -> RawFd {
+        self.wake.fd()
+    }
+
+    /// Dispatch pending events and requests without waiting for more
+    ///
+    /// The counterpart of [wake_fd](FruitApp::wake_fd) for external event
+    /// loops.  Clears the descriptor's readability, then dispatches every
+    /// pending event and request, like `run(RunPeriod::Once)`.
+    ///
+    /// Panics if not called from the main thread.
+    ///
+    /// # Returns
+    ///
+    /// Ok, or Err if a stop was requested.
+    pub fn pump_pending(&mut self) -> Result<(),()> {
+        self.wake.drain();
+        self.run(RunPeriod::Once)
+    }
+
+    /// Perform requests queued by `FruitStopper` and `FruitHandle`
+    ///
+    /// Returns true if a stop was requested.  Any requests queued after the
+    /// stop are left for the next call to `run()`.
+    fn process_commands(&self) -> bool {
+        while let Ok(cmd) = self.rx.try_recv() {
+            match cmd {
+                FruitCommand::Stop => return true,
+                FruitCommand::SetActivationPolicy(policy, false) => self.set_activation_policy(policy),
+                FruitCommand::SetActivationPolicy(policy, true) => {
+                    if let Err(e) = self.change_activation_policy(policy, true) {
+                        info!("{}", e);
+                    }
+                },
+                FruitCommand::SetDockBadge(badge) => self.set_dock_badge(badge.as_deref()),
+                FruitCommand::RequestAttention(critical) => self.request_attention(critical),
+            }
+        }
+        false
+    }
+
+    /// Set how often the run loop drains its autorelease pool
+    ///
+    /// Objective-C objects autoreleased by AppKit and by your callbacks are
+    /// only freed when the run loop drains its autorelease pool.  By default,
+    /// the pool is drained after every iteration of the event loop, which
+    /// keeps memory usage flat even when handling large bursts of events.
+    ///
+    /// Apps that process a very high rate of trivial events can drain less
+    /// often to shave off a small amount of overhead, at the cost of holding
+    /// on to autoreleased objects longer.
+    ///
+    /// # Arguments
+    ///
+    /// `iterations` - Number of event loop iterations between drains.  Values
+    /// less than 1 are treated as 1.
+    pub fn set_pool_drain_interval(&self, iterations: u64) {
+        self.pool_drain_interval.set(std::cmp::max(iterations, 1));
+    }
+
+    /// Set how long the run loop sleeps once the event queue is empty
+    ///
+    /// When running for longer than a single iteration, the run loop sleeps
+    /// between iterations to avoid spinning the CPU while idle.  Shorter
+    /// intervals reduce the latency of handling new events and requests from
+    /// other threads, at the cost of more CPU use while idle.
+    ///
+    /// # Arguments
+    ///
+    /// `interval` - Time to sleep when idle.  Defaults to 50ms.
+    pub fn set_idle_interval(&self, interval: Duration) {
+        self.idle_interval.set(interval);
+    }
+
+    /// Report changes to the clipboard
+    ///
+    /// macOS doesn't notify apps when the clipboard changes, so while
+    /// enabled, the run loop checks the general pasteboard's change count
+    /// every 250ms.  Each time it changes, the `pasteboardDidChange:`
+    /// callback is called with the `NSPasteboard`, or
+    /// [wait_for](FruitApp::wait_for) returns it as
+    /// `EventPayload::ClipboardChanged` with the new change count:
+    ///
+    /// ```no_run
+    /// # use fruitbasket::*;
+    /// # let mut app = FruitApp::new().unwrap();
+    /// app.watch_clipboard(true);
+    /// app.register_callback(FruitCallbackKey::Method("pasteboardDidChange:"),
+    ///     Box::new(|_pasteboard| println!("clipboard changed")));
+    /// ```
+    ///
+    /// Changes made before watching started are not reported.  Disabled by
+    /// default.
+    pub fn watch_clipboard(&self, enable: bool) {
+        if enable {
+            self.clipboard.set(Some((Instant::now(), clipboard_change_count())));
+        } else {
+            self.clipboard.set(None);
+        }
+    }
+
+    /// Dispatch the clipboard callback if the clipboard changed since the
+    /// last check
+    fn poll_clipboard(&self) {
+        let (checked, count) = match self.clipboard.get() {
+            Some(watching) => watching,
+            None => return,
+        };
+        if checked.elapsed() < CLIPBOARD_POLL_INTERVAL {
+            return;
+        }
+        let current = clipboard_change_count();
+        self.clipboard.set(Some((Instant::now(), current)));
+        if current == count {
+            return;
+        }
+        self.objc.state.call(&CLIPBOARD_KEY, general_pasteboard());
+    }
+
+    /// Get statistics about the work done by the event loop
+    ///
+    /// Reports how many iterations of the event loop have run, how many
+    /// events were dispatched, how long dispatching took, and how many times
+    /// the autorelease pool was drained.  Useful for diagnosing an unresponsive
+    /// UI, and for tuning the `RunPeriod` and pool drain interval.
+    pub fn stats(&self) -> RunLoopStats {
+        self.stats.get()
+    }
+
     /// Create a thread-safe object that can interrupt the run loop
     ///
     /// Returns an object that is safe to pass across thread boundaries (i.e.
@@ -796,6 +3268,55 @@ impl<'a> FruitApp<'a> {
         }
     }
 
+    /// Create a thread-safe object that can control the app from any thread
+    ///
+    /// The returned [FruitHandle](FruitHandle) can stop the run loop, like a
+    /// `FruitStopper`, and can also request changes that must be made on the
+    /// main thread, such as setting the activation policy or Dock badge.
+    /// Requests are performed the next time `run()` pumps the event loop.
+    ///
+    /// # Returns
+    ///
+    /// A newly allocated object that can be passed across thread boundaries and
+    /// cloned infinite times.
+    pub fn handle(&self) -> FruitHandle {
+        FruitHandle {
+            tx: self.tx.clone()
+        }
+    }
+
+    /// The shared NSApplication instance
+    ///
+    /// An escape hatch for apps that mix fruitbasket with other Cocoa crates,
+    /// such as cacao or cocoa-rs, so the app can be handed to them directly
+    /// instead of fetching `[NSApplication sharedApplication]` again.
+    ///
+    /// # Safety
+    ///
+    /// The pointer is not retained and is only valid while this `FruitApp`
+    /// exists.  Messages must be sent from the main thread, and must not
+    /// replace the app's delegate, which fruitbasket relies on to deliver
+    /// callbacks.
+    pub unsafe fn nsapp(&self) -> *mut Object {
+        self.app
+    }
+
+    /// The NSApplication delegate object installed by fruitbasket
+    ///
+    /// An escape hatch for apps that mix fruitbasket with other Cocoa crates,
+    /// for APIs that take the app delegate, or for forwarding delegate calls
+    /// to it from another delegate.  It is installed as the app's delegate on
+    /// the first call to [run](FruitApp::run).
+    ///
+    /// # Safety
+    ///
+    /// The pointer is not retained and is only valid while this `FruitApp`
+    /// exists.  Messages must be sent from the main thread, and it must not
+    /// be released.
+    pub unsafe fn delegate(&self) -> *mut Object {
+        &*self.objc.objc as *const ObjcSubclass as *mut Object
+    }
+
     /// Locate a resource in the executing Mac App bundle
     ///
     /// Looks for a resource by name and extension in the bundled Resources
@@ -811,11 +3332,11 @@ impl<'a> FruitApp<'a> {
     /// # Returns
     ///
     /// The full, absolute path to the resource, or None if not found.
-    pub fn bundled_resource_path(name: &str, extension: &str) -> Option<String> {
+    pub fn bundled_resource_path(name: &str, extension: &str) -> Result<Option<String>, FruitError> {
+        let bundle_cls = class("NSBundle")?;
+        let cls = class("NSString")?;
         unsafe {
-            let cls = Class::get("NSBundle").unwrap();
-            let bundle: *mut Object = msg_send![cls, mainBundle];
-            let cls = Class::get("NSString").unwrap();
+            let bundle: *mut Object = msg_send![bundle_cls, mainBundle];
             let objc_str: *mut Object = msg_send![cls, alloc];
             let objc_name: *mut Object = msg_send![objc_str,
                                                   initWithBytes:name.as_ptr()
@@ -834,10 +3355,43 @@ impl<'a> FruitApp<'a> {
             let cstr: *const i8 = msg_send![ini, UTF8String];
             if cstr != std::ptr::null() {
                 let rstr = std::ffi::CStr::from_ptr(cstr).to_string_lossy().into_owned();
-                return Some(rstr);
+                return Ok(Some(rstr));
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Releases the ObjC resources owned by the app
+///
+/// Detaches the internal delegate from NSApplication and from any Apple
+/// events it was registered for, since neither retains it, and releases the
+/// current autorelease pool and the run loop mode string.  A new `FruitApp`
+/// can safely be created after the old one is dropped.
+impl<'a> Drop for FruitApp<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            let objc: *mut Object = &*self.objc.objc as *const ObjcSubclass as *mut Object;
+            let delegate: *mut Object = msg_send![self.app, delegate];
+            if delegate == objc {
+                let _:() = msg_send![self.app, setDelegate: nil];
+            }
+            if let Ok(cls) = class("NSAppleEventManager") {
+                let manager: *mut Object = msg_send![cls, sharedAppleEventManager];
+                for &(class, id) in &self.apple_events {
+                    let _:() = msg_send![manager,
+                                         removeEventHandlerForEventClass: class
+                                         andEventID: id];
+                }
+            }
+            if self.wake_observer != nil {
+                CFRunLoopObserverInvalidate(self.wake_observer);
+                CFRelease(self.wake_observer);
             }
-            None
+            let _:() = msg_send![self.pool.get(), drain];
+            let _:() = msg_send![self.run_mode, release];
         }
+        APP_ACTIVE.store(false, Ordering::SeqCst);
     }
 }
 
@@ -852,12 +3406,165 @@ pub fn parse_url_event(event: *mut Object) -> String {
     unsafe {
         let class: u32 = msg_send![event, eventClass];
         let id: u32 = msg_send![event, eventID];
-        if class != ::kInternetEventClass || id != ::kAEGetURL {
-            return "".into();
+        if class != ::kInternetEventClass || id != ::kAEGetURL {
+            return "".into();
+        }
+        let subevent: *mut Object = msg_send![event, paramDescriptorForKeyword: ::keyDirectObject];
+        let nsstring: *mut Object = msg_send![subevent, stringValue];
+        nsstring_to_string(nsstring)
+    }
+}
+
+/// Parse the URLs passed to the `application:openURLs:` callback
+///
+/// Takes the NSArray of NSURLs that AppKit passes to the delegate, and
+/// returns each URL as a String.
+pub fn parse_url_list(urls: *mut Object) -> Vec<String> {
+    if urls == nil {
+        return Vec::new();
+    }
+    unsafe {
+        let count: usize = msg_send![urls, count];
+        (0..count).map(|idx| {
+            let url: *mut Object = msg_send![urls, objectAtIndex: idx];
+            let string: *mut Object = msg_send![url, absoluteString];
+            nsstring_to_string(string)
+        }).collect()
+    }
+}
+
+/// Convert the object passed to the callback for `key` into an EventPayload
+fn parse_payload(key: &FruitCallbackKey, obj: *mut Object) -> EventPayload {
+    if obj == nil {
+        return EventPayload::Empty;
+    }
+    match *key {
+        FruitCallbackKey::Method("handleEvent:withReplyEvent:") => {
+            if ObjcSubclass::is_event(obj, ::kInternetEventClass, ::kAEGetURL) {
+                EventPayload::Url(parse_url_event(obj))
+            } else {
+                EventPayload::AppleEvent(parse_apple_event(obj))
+            }
+        },
+        FruitCallbackKey::Method("application:openFile:") => {
+            EventPayload::File(PathBuf::from(nsstring_to_string(obj)))
+        },
+        FruitCallbackKey::Method("application:openURLs:") => {
+            EventPayload::Urls(parse_url_list(obj))
+        },
+        CLIPBOARD_KEY => unsafe {
+            EventPayload::ClipboardChanged(msg_send![obj, changeCount])
+        },
+        _ => unsafe {
+            if let Ok(cls) = class("NSNotification") {
+                let is_notification: BOOL = msg_send![obj, isKindOfClass: cls];
+                if is_notification == YES {
+                    let name: *mut Object = msg_send![obj, name];
+                    return EventPayload::Notification(nsstring_to_string(name));
+                }
+            }
+            let description: *mut Object = msg_send![obj, description];
+            EventPayload::Other(nsstring_to_string(description))
+        },
+    }
+}
+
+/// Parse the URL of a universal link from a continued user activity
+///
+/// Takes the NSUserActivity passed to the
+/// `application:continueUserActivity:restorationHandler:` callback, and
+/// returns its web page URL if it is a universal link (an activity of type
+/// `NSUserActivityTypeBrowsingWeb`).  Returns None for other Handoff
+/// activities.
+pub fn parse_universal_link(activity: *mut Object) -> Option<String> {
+    if activity == nil {
+        return None;
+    }
+    unsafe {
+        let kind: *mut Object = msg_send![activity, activityType];
+        if nsstring_to_string(kind) != "NSUserActivityTypeBrowsingWeb" {
+            return None;
+        }
+        let url: *mut Object = msg_send![activity, webpageURL];
+        if url == nil {
+            return None;
+        }
+        let string: *mut Object = msg_send![url, absoluteString];
+        Some(nsstring_to_string(string))
+    }
+}
+
+/// Parse any Apple event into its class, ID, and parameters
+///
+/// Takes the NSAppleEventDescriptor passed to the `handleEvent:withReplyEvent:`
+/// callback, and converts each of its parameters into an [AEValue](AEValue),
+/// recursing into lists and records.  This is the general form of
+/// `parse_url_event()`, for apps that handle other events, such as opening
+/// documents or custom events sent by other apps.
+pub fn parse_apple_event(event: *mut Object) -> AppleEvent {
+    let mut params = HashMap::new();
+    if event == nil {
+        return AppleEvent { class: 0, id: 0, params };
+    }
+    unsafe {
+        let class: u32 = msg_send![event, eventClass];
+        let id: u32 = msg_send![event, eventID];
+        let count: isize = msg_send![event, numberOfItems];
+        for idx in 1..=count {
+            let keyword: u32 = msg_send![event, keywordForDescriptorAtIndex: idx];
+            let param: *mut Object = msg_send![event, paramDescriptorForKeyword: keyword];
+            params.insert(keyword, parse_descriptor(param));
+        }
+        AppleEvent { class, id, params }
+    }
+}
+
+/// Convert one NSAppleEventDescriptor into a Rust value
+fn parse_descriptor(desc: *mut Object) -> AEValue {
+    if desc == nil {
+        return AEValue::Null;
+    }
+    unsafe {
+        let desc_type: u32 = msg_send![desc, descriptorType];
+        match desc_type {
+            ae::typeAEList => {
+                let count: isize = msg_send![desc, numberOfItems];
+                AEValue::List((1..=count).map(|idx| {
+                    let item: *mut Object = msg_send![desc, descriptorAtIndex: idx];
+                    parse_descriptor(item)
+                }).collect())
+            },
+            ae::typeAERecord => {
+                let count: isize = msg_send![desc, numberOfItems];
+                AEValue::Record((1..=count).map(|idx| {
+                    let keyword: u32 = msg_send![desc, keywordForDescriptorAtIndex: idx];
+                    let item: *mut Object = msg_send![desc, descriptorForKeyword: keyword];
+                    (keyword, parse_descriptor(item))
+                }).collect())
+            },
+            ae::typeSInt32 | ae::typeSInt16 => {
+                let value: i32 = msg_send![desc, int32Value];
+                AEValue::Int(value)
+            },
+            ae::typeIEEE64BitFloatingPoint | ae::typeIEEE32BitFloatingPoint => {
+                let value: f64 = msg_send![desc, doubleValue];
+                AEValue::Real(value)
+            },
+            ae::typeBoolean | ae::typeTrue | ae::typeFalse => {
+                let value: BOOL = msg_send![desc, booleanValue];
+                AEValue::Bool(value != NO)
+            },
+            ae::typeNull => AEValue::Null,
+            _ => {
+                // Anything that coerces to text, such as strings and URLs
+                let text: *mut Object = msg_send![desc, stringValue];
+                if text == nil {
+                    AEValue::Other(desc_type)
+                } else {
+                    AEValue::Text(nsstring_to_string(text))
+                }
+            },
         }
-        let subevent: *mut Object = msg_send![event, paramDescriptorForKeyword: ::keyDirectObject];
-        let nsstring: *mut Object = msg_send![subevent, stringValue];
-        nsstring_to_string(nsstring)
     }
 }
 
@@ -875,6 +3582,420 @@ pub fn nsstring_to_string(nsstring: *mut Object) -> String {
     }
 }
 
+/// Convert a Rust string into an autoreleased NSString
+///
+/// Returns nil if NSString is unavailable.
+pub(crate) fn str_to_nsstring(s: &str) -> *mut Object {
+    let cls = match class("NSString") {
+        Ok(cls) => cls,
+        Err(_) => return nil,
+    };
+    unsafe {
+        let nsstring: *mut Object = msg_send![cls, alloc];
+        let nsstring: *mut Object = msg_send![nsstring,
+                                              initWithBytes:s.as_ptr()
+                                              length:s.len()
+                                              encoding: 4]; // UTF8_ENCODING
+        msg_send![nsstring, autorelease]
+    }
+}
+
+/// Get the user's preferred languages, most preferred first
+///
+/// Returns the language identifiers from `[NSLocale preferredLanguages]`,
+/// in the order configured by the user in System Preferences.  Identifiers
+/// are in IETF BCP 47 format (ex: `en-US`, `sv`, `zh-Hant-TW`).
+///
+/// Use this to decide which localization of your bundled resources to load.
+pub fn preferred_languages() -> Vec<String> {
+    let cls = match class("NSLocale") {
+        Ok(cls) => cls,
+        Err(_) => return Vec::new(),
+    };
+    unsafe {
+        let langs: *mut Object = msg_send![cls, preferredLanguages];
+        let count: usize = msg_send![langs, count];
+        (0..count).map(|idx| {
+            let lang: *mut Object = msg_send![langs, objectAtIndex: idx];
+            nsstring_to_string(lang)
+        }).collect()
+    }
+}
+
+/// Get the identifier of the user's current locale
+///
+/// Returns the identifier of `[NSLocale currentLocale]`, which combines the
+/// language and region configured by the user (ex: `en_US`, `sv_SE`).
+pub fn current_locale() -> String {
+    let cls = match class("NSLocale") {
+        Ok(cls) => cls,
+        Err(_) => return "".into(),
+    };
+    unsafe {
+        let locale: *mut Object = msg_send![cls, currentLocale];
+        let ident: *mut Object = msg_send![locale, localeIdentifier];
+        nsstring_to_string(ident)
+    }
+}
+
+/// NSPropertyListImmutable
+const PLIST_IMMUTABLE: u64 = 0;
+/// NSPropertyListXMLFormat_v1_0
+const PLIST_XML_FORMAT: u64 = 100;
+/// NSPropertyListBinaryFormat_v1_0
+const PLIST_BINARY_FORMAT: u64 = 200;
+
+/// Parse an OpenStep plist and write it to `path` in another
+/// NSPropertyListFormat, with the top-level `bool_keys` converted from the
+/// OpenStep `True` and `False` strings to booleans
+fn write_serialized_plist(path: &Path, openstep: &[u8], bool_keys: &[String],
+                          format: u64) -> Result<(), FruitError> {
+    let data_cls = class("NSData")?;
+    let serialization_cls = class("NSPropertyListSerialization")?;
+    unsafe {
+        let data: *mut Object = msg_send![data_cls, dataWithBytes: openstep.as_ptr()
+                                          length: openstep.len()];
+        let mut error: *mut Object = nil;
+        let plist: *mut Object = msg_send![serialization_cls,
+                                           propertyListWithData: data
+                                           options: PLIST_IMMUTABLE
+                                           format: std::ptr::null_mut::<u64>()
+                                           error: &mut error];
+        if plist == nil {
+            return Err(FruitError::PlistError(
+                format!("invalid Info.plist: {}", plist_error_description(error))));
+        }
+        let plist = openstep_booleans(plist, bool_keys)?;
+        let output: *mut Object = msg_send![serialization_cls,
+                                            dataWithPropertyList: plist
+                                            format: format
+                                            options: 0u64
+                                            error: &mut error];
+        if output == nil {
+            return Err(FruitError::PlistError(
+                format!("failed to serialize Info.plist: {}", plist_error_description(error))));
+        }
+        let len: usize = msg_send![output, length];
+        let bytes: *const u8 = msg_send![output, bytes];
+        std::fs::write(path, std::slice::from_raw_parts(bytes, len))?;
+    }
+    Ok(())
+}
+
+/// Copy of a parsed OpenStep plist with the `True` and `False` strings of
+/// `bool_keys`, which OpenStep uses for booleans, replaced with real
+/// booleans
+///
+/// Other strings are left alone, even when they read `True` or `False`,
+/// since OpenStep can't tell them apart from a quoted string.
+unsafe fn openstep_booleans(plist: *mut Object, bool_keys: &[String]) -> Result<*mut Object, FruitError> {
+    let number_cls = class("NSNumber")?;
+    let string_cls = class("NSString")?;
+    let cls = class("NSMutableDictionary")?;
+    let copy: *mut Object = msg_send![cls, dictionaryWithDictionary: plist];
+    for key in bool_keys {
+        let key = str_to_nsstring(key);
+        let value: *mut Object = msg_send![copy, objectForKey: key];
+        if value == nil {
+            continue;
+        }
+        let is_string: BOOL = msg_send![value, isKindOfClass: string_cls];
+        if is_string != YES {
+            continue;
+        }
+        let boolean: *mut Object = match nsstring_to_string(value).as_str() {
+            "True" => msg_send![number_cls, numberWithBool: YES],
+            "False" => msg_send![number_cls, numberWithBool: NO],
+            _ => continue,
+        };
+        let _:() = msg_send![copy, setObject: boolean forKey: key];
+    }
+    Ok(copy)
+}
+
+/// Description of an NSError from NSPropertyListSerialization
+fn plist_error_description(error: *mut Object) -> String {
+    if error == nil {
+        return "unknown error".to_string();
+    }
+    unsafe {
+        let desc: *mut Object = msg_send![error, localizedDescription];
+        nsstring_to_string(desc)
+    }
+}
+
+/// Set or remove a boolean key in an installed bundle's Info.plist, and
+/// have LaunchServices read the new value
+fn set_info_plist_bool(bundle: &Path, key: &str, value: bool) -> Result<(), FruitError> {
+    let mut plist = bundle.join("Contents").join("Info.plist");
+    if !plist.exists() {
+        plist = bundle.join("Info.plist");
+    }
+    let dict_cls = class("NSMutableDictionary")?;
+    let number_cls = class("NSNumber")?;
+    unsafe {
+        let path = str_to_nsstring(&plist.to_string_lossy());
+        let dict: *mut Object = msg_send![dict_cls, dictionaryWithContentsOfFile: path];
+        if dict == nil {
+            return Err(FruitError::PlistError(format!("failed to read {}", plist.display())));
+        }
+        if value {
+            let yes: *mut Object = msg_send![number_cls, numberWithBool: YES];
+            let _:() = msg_send![dict, setObject: yes forKey: str_to_nsstring(key)];
+        } else {
+            let _:() = msg_send![dict, removeObjectForKey: str_to_nsstring(key)];
+        }
+        let written: BOOL = msg_send![dict, writeToFile: path atomically: YES];
+        if written == NO {
+            return Err(FruitError::PlistError(format!("failed to write {}", plist.display())));
+        }
+        let status = LSRegisterURL(::foundation::path_to_nsurl(bundle), 1);
+        if status != 0 {
+            info!("LaunchServices failed to register {} (OSStatus {})", bundle.display(), status);
+        }
+    }
+    Ok(())
+}
+
+/// Bundle identifier of the running app with the given process ID
+fn running_app_bundle_id(pid: i32) -> Option<String> {
+    let cls = class("NSRunningApplication").ok()?;
+    unsafe {
+        let app: *mut Object = msg_send![cls, runningApplicationWithProcessIdentifier: pid];
+        if app == nil {
+            return None;
+        }
+        let ident: *mut Object = msg_send![app, bundleIdentifier];
+        if ident == nil {
+            return None;
+        }
+        Some(nsstring_to_string(ident))
+    }
+}
+
+/// The general NSPasteboard, used for copy and paste
+fn general_pasteboard() -> *mut Object {
+    match class("NSPasteboard") {
+        Ok(cls) => unsafe { msg_send![cls, generalPasteboard] },
+        Err(_) => nil,
+    }
+}
+
+/// Change count of the general pasteboard, which increases with each copy
+fn clipboard_change_count() -> i64 {
+    let pasteboard = general_pasteboard();
+    if pasteboard == nil {
+        return 0;
+    }
+    unsafe { msg_send![pasteboard, changeCount] }
+}
+
+/// NSRect, as passed to and returned by AppKit
+#[repr(C)]
+pub(crate) struct NSRect {
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+}
+
+/// Get the displays connected to the Mac
+///
+/// Returns every `NSScreen`, starting with the primary display, which holds
+/// the menu bar and defines the origin of the coordinate system.  Requires a
+/// `FruitApp` to exist.
+///
+/// Displays are added, removed, rearranged, and changed between Retina and
+/// non-Retina resolutions while the app runs.  Register a callback for the
+/// `applicationDidChangeScreenParameters:` method to be told when to call
+/// this again:
+///
+/// ```no_run
+/// # use fruitbasket::{FruitApp, FruitCallbackKey};
+/// # let mut app = FruitApp::new().unwrap();
+/// app.register_callback(FruitCallbackKey::Method("applicationDidChangeScreenParameters:"),
+///     Box::new(|_notification| {
+///         for screen in fruitbasket::screens() {
+///             println!("{}: {:?} pixels", screen.name, screen.pixel_size());
+///         }
+///     }));
+/// ```
+pub fn screens() -> Vec<Screen> {
+    let cls = match class("NSScreen") {
+        Ok(cls) => cls,
+        Err(_) => return Vec::new(),
+    };
+    unsafe {
+        let main: *mut Object = msg_send![cls, mainScreen];
+        let screens: *mut Object = msg_send![cls, screens];
+        ::foundation::nsarray_to_vec(screens).into_iter()
+            .map(|screen| nsscreen_to_screen(screen, screen == main))
+            .collect()
+    }
+}
+
+/// Get the screen containing the window that has keyboard focus
+///
+/// This is the screen AppKit calls the main screen, which is not
+/// necessarily the primary display.  Returns None if no displays are
+/// connected.
+pub fn main_screen() -> Option<Screen> {
+    let cls = class("NSScreen").ok()?;
+    unsafe {
+        let main: *mut Object = msg_send![cls, mainScreen];
+        if main.is_null() {
+            return None;
+        }
+        Some(nsscreen_to_screen(main, true))
+    }
+}
+
+/// Get the height of the menu bar on the main screen, in points
+///
+/// Status bar utilities can position panels just below it.  Taller on Macs
+/// with a camera housing in the display.  Returns 0 while the menu bar is
+/// hidden, such as when it is set to hide automatically.
+pub fn menu_bar_height() -> f64 {
+    match main_screen() {
+        Some(screen) => (screen.y + screen.height) - (screen.visible_y + screen.visible_height),
+        None => 0.,
+    }
+}
+
+/// Get the position and size of the Dock
+///
+/// Found from the space each screen reserves for the Dock.  When the Dock
+/// hides automatically it reserves only a few points at the edge of the
+/// screen, which is the size reported.  Returns None if no screen reserves
+/// space for it.
+pub fn dock() -> Option<Dock> {
+    screens().iter().enumerate().filter_map(|(idx, screen)| {
+        let left = screen.visible_x - screen.x;
+        let right = (screen.x + screen.width) - (screen.visible_x + screen.visible_width);
+        let bottom = screen.visible_y - screen.y;
+        let (position, size) = if bottom > 0. {
+            (DockPosition::Bottom, bottom)
+        } else if left > 0. {
+            (DockPosition::Left, left)
+        } else if right > 0. {
+            (DockPosition::Right, right)
+        } else {
+            return None;
+        };
+        Some(Dock { position, size, screen: idx })
+    }).next()
+}
+
+/// Convert an NSScreen to a Screen
+fn nsscreen_to_screen(screen: *mut Object, main: bool) -> Screen {
+    unsafe {
+        let frame: NSRect = msg_send![screen, frame];
+        let visible: NSRect = msg_send![screen, visibleFrame];
+        let scale: f64 = msg_send![screen, backingScaleFactor];
+        // localizedName is only available since 10.15
+        let responds: BOOL = msg_send![screen, respondsToSelector: sel!(localizedName)];
+        let name = if responds == YES {
+            let name: *mut Object = msg_send![screen, localizedName];
+            nsstring_to_string(name)
+        } else {
+            String::new()
+        };
+        Screen {
+            name,
+            x: frame.x,
+            y: frame.y,
+            width: frame.width,
+            height: frame.height,
+            visible_x: visible.x,
+            visible_y: visible.y,
+            visible_width: visible.width,
+            visible_height: visible.height,
+            scale,
+            main,
+        }
+    }
+}
+
+/// Get the name of an AppleScript command received by the app
+///
+/// Takes the raw `NSScriptCommand` passed to a
+/// `FruitCallbackKey::Method("performScriptCommand:")` callback and returns
+/// the command's name, as declared in the app's scripting definition.
+pub fn script_command_name(command: *mut Object) -> String {
+    if command.is_null() {
+        return "".into();
+    }
+    unsafe {
+        let desc: *mut Object = msg_send![command, commandDescription];
+        let name: *mut Object = msg_send![desc, commandName];
+        nsstring_to_string(name)
+    }
+}
+
+static FRUIT_SCRIPT_COMMAND_REGISTER_CLASS: Once = Once::new();
+
+/// Register the `FruitScriptCommand` class with the ObjC runtime
+///
+/// Cocoa scripting instantiates the class named in the app's scripting
+/// definition for each incoming command.  This NSScriptCommand subclass
+/// forwards every command it receives to the app delegate, which routes it
+/// into the Rust callback map.
+fn register_script_command_class() -> Result<&'static Class, FruitError> {
+    FRUIT_SCRIPT_COMMAND_REGISTER_CLASS.call_once(|| {
+        let mut decl = match class("NSScriptCommand").ok()
+            .and_then(|superclass| ClassDecl::new("FruitScriptCommand", superclass)) {
+            Some(decl) => decl,
+            None => return,
+        };
+
+        /// Forward the script command to the NSApplication delegate
+        extern "C" fn objc_perform_default(this: &Object, _cmd: Sel) -> *mut Object {
+            let cls = match class("NSApplication") {
+                Ok(cls) => cls,
+                Err(_) => return nil,
+            };
+            unsafe {
+                let app: *mut Object = msg_send![cls, sharedApplication];
+                let delegate: *mut Object = msg_send![app, delegate];
+                if delegate == nil {
+                    return nil;
+                }
+                let responds: BOOL = msg_send![delegate,
+                                               respondsToSelector: sel!(performScriptCommand:)];
+                if responds != NO {
+                    let _:() = msg_send![delegate, performScriptCommand: this];
+                }
+            }
+            nil
+        }
+        unsafe {
+            let f: extern "C" fn(&Object, Sel) -> *mut Object = objc_perform_default;
+            decl.add_method(sel!(performDefaultImplementation), f);
+        }
+
+        decl.register();
+    });
+    class("FruitScriptCommand")
+}
+
+/// Detaches the callback state from the ObjC object
+///
+/// The ObjC object may outlive its wrapper, since the runtime or AppKit can
+/// hold their own references to it.  Clearing the ivar here ensures it can no
+/// longer reach the Rust callbacks, which may borrow from the dropped app.
+impl<'a> Drop for ObjcWrapper<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            let obj: *mut Object = &*self.objc as *const ObjcSubclass as *mut Object;
+            let raw: *const c_void = *(*obj).get_ivar("_rust_state");
+            (*obj).set_ivar::<*const c_void>("_rust_state", std::ptr::null());
+            if !raw.is_null() {
+                drop(Rc::from_raw(raw as *const CallbackState<'a>));
+            }
+        }
+    }
+}
+
 /// ObjcSubclass is a subclass of the objective-c NSObject base class.
 /// This is registered with the objc runtime, so instances of this class
 /// are "owned" by objc, and have no associated Rust data.
@@ -882,103 +4003,553 @@ pub fn nsstring_to_string(nsstring: *mut Object) -> String {
 /// This can be wrapped with a ObjcWrapper, which is a proper Rust struct
 /// with its own storage, and holds an instance of ObjcSubclass.
 ///
-/// An ObjcSubclass can "talk" to its Rust wrapper class through function
-/// pointers, as long as the storage is on the heap with a Box and the underlying
-/// memory address doesn't change.
+/// An ObjcSubclass "talks" to its Rust wrapper through the shared
+/// `CallbackState` stored in its `_rust_state` ivar, which is null once the
+/// wrapper has been dropped.
 ///
 enum ObjcSubclass {}
 
 unsafe impl Message for ObjcSubclass { }
 
-static OBJC_SUBCLASS_REGISTER_CLASS: Once = ONCE_INIT;
+/// Name the ObjcSubclass class was registered under, or None if it failed
+static OBJC_SUBCLASS_NAME: OnceLock<Option<String>> = OnceLock::new();
+
+/// Prefix of the delegate class name when none is configured
+const DEFAULT_CLASS_PREFIX: &str = "Fruit";
 
 impl ObjcSubclass {
     /// Call a registered Rust callback
-    fn dispatch_cb(wrap_ptr: u64, key: FruitCallbackKey, obj: *mut Object) {
-        if wrap_ptr == 0 {
+    fn dispatch_cb(this: &Object, key: FruitCallbackKey, obj: *mut Object) {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
             return;
         }
-        let objcwrap: &mut ObjcWrapper = unsafe { &mut *(wrap_ptr as *mut ObjcWrapper) };
-        if let Some(ref cb) = objcwrap.map.get(&key) {
-            cb(obj);
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        state.call(&key, obj);
+    }
+
+    /// Whether an Apple event has the given class and ID
+    fn is_event(event: *mut Object, class: u32, id: u32) -> bool {
+        if event == nil {
+            return false;
+        }
+        let (event_class, event_id): (u32, u32) = unsafe {
+            (msg_send![event, eventClass], msg_send![event, eventID])
+        };
+        (event_class, event_id) == (class, id)
+    }
+
+    /// Route a call to a method added by add_delegate_method() to its callback
+    fn dispatch_dynamic(this: &Object, cmd: Sel, obj: *mut Object) {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        let selector = state.dynamic_methods.borrow().iter()
+            .find(|&&method| method == cmd.name())
+            .cloned();
+        if let Some(selector) = selector {
+            ObjcSubclass::dispatch_cb(this, FruitCallbackKey::Method(selector), obj);
+        }
+    }
+
+    /// Implementation of methods added with MethodSignature::NoArgs
+    extern "C" fn dynamic_method_0(this: &Object, cmd: Sel) {
+        ObjcSubclass::dispatch_dynamic(this, cmd, nil);
+    }
+
+    /// Implementation of methods added with MethodSignature::OneArg
+    extern "C" fn dynamic_method_1(this: &Object, cmd: Sel, arg: *mut Object) {
+        ObjcSubclass::dispatch_dynamic(this, cmd, arg);
+    }
+
+    /// Implementation of methods added with MethodSignature::TwoArgs
+    extern "C" fn dynamic_method_2(this: &Object, cmd: Sel, _first: *mut Object, arg: *mut Object) {
+        ObjcSubclass::dispatch_dynamic(this, cmd, arg);
+    }
+
+    /// Implementation of methods added with MethodSignature::Service
+    extern "C" fn dynamic_method_service(this: &Object, cmd: Sel, pboard: *mut Object,
+                                         _user_data: *mut Object, _error: *mut *mut Object) {
+        ObjcSubclass::dispatch_dynamic(this, cmd, pboard);
+    }
+
+    /// Whether a Rust callback is registered for `key`
+    fn has_callback(this: &Object, key: &FruitCallbackKey) -> bool {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return false;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        state.map.borrow().contains_key(key)
+    }
+
+    /// Whether the delegate should report that it implements `method`
+    fn delegate_method_enabled(this: &Object, method: &str) -> bool {
+        if !OPTIONAL_DELEGATE_METHODS.contains(&method) {
+            return true;
+        }
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return true;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        match *state.delegate_methods.borrow() {
+            Some(ref methods) => methods.contains(&method),
+            None => true,
+        }
+    }
+
+    /// Whether an open-file handler is registered
+    fn has_open_files(this: &Object) -> bool {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return false;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        state.open_files.borrow().is_some()
+    }
+
+    /// Pass files to the open-file handler, and alert the user about any it
+    /// rejects
+    ///
+    /// Returns the NSApplicationDelegateReply for `replyToOpenOrPrint:`.
+    fn open_files(this: &Object, paths: &[PathBuf]) -> u64 {
+        const REPLY_SUCCESS: u64 = 0;
+        const REPLY_CANCEL: u64 = 1;
+        const REPLY_FAILURE: u64 = 2;
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return REPLY_FAILURE;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        let failures: Vec<(&PathBuf, String)> = match *state.open_files.borrow() {
+            Some(ref cb) => paths.iter()
+                .filter_map(|path| cb(path).err().map(|reason| (path, reason)))
+                .collect(),
+            None => return REPLY_FAILURE,
+        };
+        if failures.is_empty() {
+            return REPLY_SUCCESS;
+        }
+        if !state.open_file_alerts.get() {
+            return REPLY_FAILURE;
+        }
+        let name = |path: &PathBuf| path.file_name()
+            .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy()).into_owned();
+        let message = match failures.len() {
+            1 => format!("The document \u{201c}{}\u{201d} could not be opened.", name(failures[0].0)),
+            n => format!("{} documents could not be opened.", n),
+        };
+        let details: Vec<String> = match failures.len() {
+            1 => vec![failures[0].1.clone()],
+            _ => failures.iter().map(|&(path, ref reason)| format!("{}: {}", name(path), reason)).collect(),
+        };
+        show_alert(&message, &details.join("\n"));
+        REPLY_CANCEL
+    }
+
+    /// Advance to a launch phase, if the app hasn't reached it yet
+    ///
+    /// Returns false if it already had, so the launch notification isn't
+    /// delivered to callbacks a second time.
+    fn advance_phase(this: &Object, phase: LaunchPhase) -> bool {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return true;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        if state.phase.get() >= phase {
+            return false;
+        }
+        state.phase.set(phase);
+        true
+    }
+
+    /// Remember how the Apple event the app is launching with describes the
+    /// launch
+    ///
+    /// Only meaningful while AppKit delivers the will/did-finish-launching
+    /// notifications, when the launch event is the current Apple event.
+    fn record_launch_event(this: &Object) {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        if state.launch_event.get().is_some() {
+            return;
+        }
+        let cls = match class("NSAppleEventManager") {
+            Ok(cls) => cls,
+            Err(_) => return,
+        };
+        unsafe {
+            let manager: *mut Object = msg_send![cls, sharedAppleEventManager];
+            let event: *mut Object = msg_send![manager, currentAppleEvent];
+            if event == nil {
+                state.launch_event.set(Some((false, 0)));
+                return;
+            }
+            let prop: *mut Object = msg_send![event, paramDescriptorForKeyword: ae::keyAEPropData];
+            let login_item = prop != nil && {
+                let code: u32 = msg_send![prop, enumCodeValue];
+                code == ae::keyAELaunchedAsLogInItem
+            };
+            let sender: *mut Object = msg_send![event, attributeDescriptorForKeyword: ae::keySenderPIDAttr];
+            let pid: i32 = if sender == nil { 0 } else { msg_send![sender, int32Value] };
+            state.launch_event.set(Some((login_item, pid)));
+        }
+    }
+
+    /// Remember files the app is asked to open while it is still launching
+    fn record_launch_opens(this: &Object, paths: Vec<PathBuf>, source: OpenSource) {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() {
+            return;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        if state.phase.get() >= LaunchPhase::DidFinish {
+            return;
         }
+        state.launch_opens.borrow_mut().extend(paths.into_iter().map(|path| {
+            OpenRequest { path: path, source: source }
+        }));
+    }
+
+    /// Hold on to a URL event that arrived before anyone was ready for it
+    ///
+    /// Returns true if the event was retained and queued, to be replayed by
+    /// `FruitApp::replay_pending_urls()`.
+    fn hold_early_url(this: &Object, event: *mut Object) -> bool {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() || event == nil {
+            return false;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        if !ObjcSubclass::is_event(event, ae::kInternetEventClass, ae::kAEGetURL) {
+            return false;
+        }
+        let key = FruitCallbackKey::Method("handleEvent:withReplyEvent:");
+        if state.url_events.get() && state.map.borrow().contains_key(&key) {
+            return false;
+        }
+        unsafe { let _:() = msg_send![event, retain]; }
+        state.pending_urls.borrow_mut().push(event);
+        true
+    }
+
+    /// Call the core event callback, if one is registered for this event
+    ///
+    /// Returns false if the event should go to the generic Apple event
+    /// callback instead.
+    fn dispatch_core_event(this: &Object, event: *mut Object) -> bool {
+        let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+        if raw.is_null() || event == nil {
+            return false;
+        }
+        let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+        let core_events = state.core_events.borrow();
+        let cb = match *core_events {
+            Some(ref cb) => cb,
+            None => return false,
+        };
+        let (class, id): (u32, u32) = unsafe {
+            (msg_send![event, eventClass], msg_send![event, eventID])
+        };
+        if class != ae::kCoreEventClass {
+            return false;
+        }
+        match id {
+            ae::kAEOpenDocuments => cb(CoreEvent::OpenDocuments(document_paths(event))),
+            ae::kAEReopenApplication => cb(CoreEvent::Reopen),
+            ae::kAEQuitApplication => cb(CoreEvent::Quit),
+            _ => return false,
+        }
+        true
     }
 }
 
-/// Define an ObjC class and register it with the ObjC runtime
-impl INSObject for ObjcSubclass {
-    fn class() -> &'static Class {
-        OBJC_SUBCLASS_REGISTER_CLASS.call_once(|| {
+/// Get the paths of the files in an odoc event's direct object
+fn document_paths(event: *mut Object) -> Vec<PathBuf> {
+    unsafe {
+        let list: *mut Object = msg_send![event, paramDescriptorForKeyword: ae::keyDirectObject];
+        if list == nil {
+            return Vec::new();
+        }
+        let count: isize = msg_send![list, numberOfItems];
+        (1..=count).filter_map(|idx| {
+            let item: *mut Object = msg_send![list, descriptorAtIndex: idx];
+            let item: *mut Object = msg_send![item, coerceToDescriptorType: fourcc!("furl")];
+            if item == nil {
+                return None;
+            }
+            let url: *mut Object = msg_send![item, fileURLValue];
+            if url == nil {
+                return None;
+            }
+            let path: *mut Object = msg_send![url, path];
+            Some(PathBuf::from(nsstring_to_string(path)))
+        }).collect()
+    }
+}
+
+impl ObjcSubclass {
+    /// Define the ObjC class and register it with the ObjC runtime
+    ///
+    /// The class is registered once per process, under a name made from
+    /// `prefix` and an ID unique to this copy of fruitbasket, so it can't
+    /// collide with a class registered by another crate, or by another
+    /// version of fruitbasket in the same dependency tree.  `prefix` is
+    /// ignored once the class is registered.
+    fn register(prefix: Option<&str>) -> Result<&'static Class, FruitError> {
+        let name = OBJC_SUBCLASS_NAME.get_or_init(|| {
+            let name = format!("{}Delegate_{}_{:x}",
+                               prefix.unwrap_or(DEFAULT_CLASS_PREFIX),
+                               env!("CARGO_PKG_VERSION").replace('.', "_"),
+                               &OBJC_SUBCLASS_NAME as *const _ as usize);
             let superclass = NSObject::class();
-            let mut decl = ClassDecl::new("ObjcSubclass", superclass).unwrap();
-            decl.add_ivar::<u64>("_rustwrapper");
+            let mut decl = ClassDecl::new(&name, superclass)?;
+            decl.add_ivar::<*const c_void>("_rust_state");
 
             /// Callback for events from Apple's NSAppleEventManager
-            extern fn objc_apple_event(this: &Object, _cmd: Sel, event: u64, _reply: u64) {
-                let ptr: u64 = unsafe { *this.get_ivar("_rustwrapper") };
-                ObjcSubclass::dispatch_cb(ptr,
+            extern "C" fn objc_apple_event(this: &Object, _cmd: Sel,
+                                           event: *mut Object, _reply: *mut Object) {
+                if ObjcSubclass::is_event(event, ae::kCoreEventClass, ae::kAEOpenDocuments) {
+                    ObjcSubclass::record_launch_opens(this, document_paths(event),
+                                                      OpenSource::AppleEvent);
+                }
+                if ObjcSubclass::dispatch_core_event(this, event) {
+                    return;
+                }
+                if ObjcSubclass::hold_early_url(this, event) {
+                    return;
+                }
+                ObjcSubclass::dispatch_cb(this,
                                           FruitCallbackKey::Method("handleEvent:withReplyEvent:"),
-                                          event as *mut Object);
+                                          event);
             }
             /// NSApplication delegate callback
-            extern fn objc_did_finish(this: &Object, _cmd: Sel, event: u64) {
-                let ptr: u64 = unsafe { *this.get_ivar("_rustwrapper") };
-                ObjcSubclass::dispatch_cb(ptr,
+            extern "C" fn objc_did_finish(this: &Object, _cmd: Sel, event: *mut Object) {
+                ObjcSubclass::record_launch_event(this);
+                if !ObjcSubclass::advance_phase(this, LaunchPhase::DidFinish) {
+                    return;
+                }
+                ObjcSubclass::dispatch_cb(this,
                                           FruitCallbackKey::Method("applicationDidFinishLaunching:"),
-                                          event as *mut Object);
+                                          event);
             }
             /// NSApplication delegate callback
-            extern fn objc_will_finish(this: &Object, _cmd: Sel, event: u64) {
-                let ptr: u64 = unsafe { *this.get_ivar("_rustwrapper") };
-                ObjcSubclass::dispatch_cb(ptr,
+            extern "C" fn objc_will_finish(this: &Object, _cmd: Sel, event: *mut Object) {
+                ObjcSubclass::record_launch_event(this);
+                if !ObjcSubclass::advance_phase(this, LaunchPhase::WillFinish) {
+                    return;
+                }
+                ObjcSubclass::dispatch_cb(this,
                                           FruitCallbackKey::Method("applicationWillFinishLaunching:"),
-                                          event as *mut Object);
+                                          event);
+            }
+            /// NSApplication delegate callback
+            extern "C" fn objc_occlusion_changed(this: &Object, _cmd: Sel, event: *mut Object) {
+                ObjcSubclass::dispatch_cb(this,
+                                          FruitCallbackKey::Method("applicationDidChangeOcclusionState:"),
+                                          event);
+            }
+            /// NSApplication delegate callback
+            extern "C" fn objc_screens_changed(this: &Object, _cmd: Sel, event: *mut Object) {
+                ObjcSubclass::dispatch_cb(this,
+                                          FruitCallbackKey::Method("applicationDidChangeScreenParameters:"),
+                                          event);
             }
             /// NSApplication delegate callback
             extern "C" fn objc_open_file(
                 this: &Object,
                 _cmd: Sel,
-                _application: u64,
-                file: u64,
-            ) -> bool {
-                let ptr: u64 = unsafe { *this.get_ivar("_rustwrapper") };
+                _application: *mut Object,
+                file: *mut Object,
+            ) -> BOOL {
+                ObjcSubclass::record_launch_opens(this,
+                                                  vec![PathBuf::from(nsstring_to_string(file))],
+                                                  OpenSource::OpenFile);
                 ObjcSubclass::dispatch_cb(
-                    ptr,
+                    this,
                     FruitCallbackKey::Method("application:openFile:"),
-                    file as *mut Object,
+                    file,
                 );
 
-                true
+                YES
+            }
+            /// NSApplication delegate callback, only reported while an
+            /// open-file handler is registered
+            extern "C" fn objc_open_files(this: &Object, _cmd: Sel,
+                                          application: *mut Object, files: *mut Object) {
+                let paths: Vec<PathBuf> = ::foundation::nsarray_to_strings(files)
+                    .into_iter().map(PathBuf::from).collect();
+                ObjcSubclass::record_launch_opens(this, paths.clone(), OpenSource::OpenFile);
+                let reply = ObjcSubclass::open_files(this, &paths);
+                unsafe {
+                    let _:() = msg_send![application, replyToOpenOrPrint: reply];
+                }
+            }
+            /// NSApplication delegate callback
+            extern "C" fn objc_open_urls(this: &Object, _cmd: Sel,
+                                         _application: *mut Object, urls: *mut Object) {
+                ObjcSubclass::dispatch_cb(this,
+                                          FruitCallbackKey::Method("application:openURLs:"),
+                                          urls);
+            }
+            /// NSApplication delegate callback for Handoff and universal links
+            extern "C" fn objc_continue_activity(this: &Object, _cmd: Sel,
+                                                 _application: *mut Object, activity: *mut Object,
+                                                 _handler: *mut c_void) -> BOOL {
+                let key = FruitCallbackKey::Method(
+                    "application:continueUserActivity:restorationHandler:");
+                if !ObjcSubclass::has_callback(this, &key) {
+                    return NO;
+                }
+                ObjcSubclass::dispatch_cb(this, key, activity);
+                YES
             }
-            /// Register the Rust ObjcWrapper instance that wraps this object
-            ///
-            /// In order for an instance of this ObjC owned object to reach back
-            /// into "pure Rust", it needs to know the location of Rust
-            /// functions.  This is accomplished by wrapping it in a Rust struct,
-            /// which is itself in a Box on the heap to ensure a fixed location
-            /// in memory.  The address of this wrapping struct is given to this
-            /// object by casting the Box into a raw pointer, and then casting
-            /// that into a u64, which is stored here.
-            extern fn objc_set_rust_wrapper(this: &mut Object, _cmd: Sel, ptr: u64) {
-                unsafe {this.set_ivar("_rustwrapper", ptr);}
+            /// Hide optional delegate methods disabled by FruitAppBuilder
+            extern "C" fn objc_responds_to_selector(this: &Object, _cmd: Sel, selector: Sel) -> BOOL {
+                if !ObjcSubclass::delegate_method_enabled(this, selector.name()) {
+                    return NO;
+                }
+                if selector == sel!(application:openFiles:) && !ObjcSubclass::has_open_files(this) {
+                    return NO;
+                }
+                unsafe { msg_send![super(this, NSObject::class()), respondsToSelector: selector] }
+            }
+            /// NSApplication delegate callback
+            extern "C" fn objc_terminate_after_last_window(this: &Object, _cmd: Sel,
+                                                           _application: *mut Object) -> BOOL {
+                let raw: *const c_void = unsafe { *this.get_ivar("_rust_state") };
+                if raw.is_null() {
+                    return NO;
+                }
+                let state: &CallbackState = unsafe { &*(raw as *const CallbackState) };
+                if state.terminate_after_last_window_closed.get() { YES } else { NO }
+            }
+            /// Callback for AppleScript commands from FruitScriptCommand
+            extern "C" fn objc_script_command(this: &Object, _cmd: Sel, command: *mut Object) {
+                ObjcSubclass::dispatch_cb(this,
+                                          FruitCallbackKey::Method("performScriptCommand:"),
+                                          command);
             }
             unsafe {
                 // Register all of the above handlers as true ObjC selectors:
-                let f: extern fn(&mut Object, Sel, u64) = objc_set_rust_wrapper;
-                decl.add_method(sel!(setRustWrapper:), f);
-                let f: extern fn(&Object, Sel, u64, u64) = objc_apple_event;
+                let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object) = objc_apple_event;
                 decl.add_method(sel!(handleEvent:withReplyEvent:), f);
-                let f: extern fn(&Object, Sel, u64) = objc_did_finish;
+                let f: extern "C" fn(&Object, Sel, *mut Object) = objc_did_finish;
                 decl.add_method(sel!(applicationDidFinishLaunching:), f);
-                let f: extern fn(&Object, Sel, u64) = objc_will_finish;
+                let f: extern "C" fn(&Object, Sel, *mut Object) = objc_will_finish;
                 decl.add_method(sel!(applicationWillFinishLaunching:), f);
-                let f: extern "C" fn(&Object, Sel, u64, u64) -> bool = objc_open_file;
+                let f: extern "C" fn(&Object, Sel, *mut Object) = objc_occlusion_changed;
+                decl.add_method(sel!(applicationDidChangeOcclusionState:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object) = objc_screens_changed;
+                decl.add_method(sel!(applicationDidChangeScreenParameters:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object) -> BOOL = objc_open_file;
                 decl.add_method(sel!(application:openFile:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object) = objc_open_files;
+                decl.add_method(sel!(application:openFiles:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object) = objc_open_urls;
+                decl.add_method(sel!(application:openURLs:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object, *mut c_void) -> BOOL =
+                    objc_continue_activity;
+                decl.add_method(sel!(application:continueUserActivity:restorationHandler:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object) = objc_script_command;
+                decl.add_method(sel!(performScriptCommand:), f);
+                let f: extern "C" fn(&Object, Sel, Sel) -> BOOL = objc_responds_to_selector;
+                decl.add_method(sel!(respondsToSelector:), f);
+                let f: extern "C" fn(&Object, Sel, *mut Object) -> BOOL = objc_terminate_after_last_window;
+                decl.add_method(sel!(applicationShouldTerminateAfterLastWindowClosed:), f);
             }
 
             decl.register();
+            Some(name)
         });
 
-        Class::get("ObjcSubclass").unwrap()
+        match *name {
+            Some(ref name) => class(name),
+            None => Err(FruitError::ObjcError("failed to declare app delegate class".into())),
+        }
+    }
+}
+
+impl INSObject for ObjcSubclass {
+    fn class() -> &'static Class {
+        // Only reached through ObjcWrapper::new(), after a successful register()
+        ObjcSubclass::register(None).expect("ObjcSubclass class registered")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type of a top-level value in a plist dictionary, and its string value
+    unsafe fn plist_value(plist: *mut Object, key: &str) -> (&'static str, String) {
+        let value: *mut Object = msg_send![plist, objectForKey: str_to_nsstring(key)];
+        assert!(value != nil, "{} missing from Info.plist", key);
+        let number: BOOL = msg_send![value, isKindOfClass: class("NSNumber").unwrap()];
+        let string: BOOL = msg_send![value, isKindOfClass: class("NSString").unwrap()];
+        if number == YES {
+            let boolean: BOOL = msg_send![value, boolValue];
+            ("bool", if boolean == YES { "True" } else { "False" }.to_string())
+        } else if string == YES {
+            ("string", nsstring_to_string(value))
+        } else {
+            ("other", String::new())
+        }
+    }
+
+    #[test]
+    fn xml_plist_converts_only_unquoted_booleans() {
+        let dir = std::env::temp_dir()
+            .join(format!("fruitbasket-plist-{}", std::process::id()));
+        let bundle = Trampoline::new("True", "plisttest", "com.example.plisttest")
+            .plist_format(PlistFormat::Xml)
+            .plist_key("LSUIElement", "True")
+            .plist_key("NSQuotedFlag", "\"False\"")
+            .env("DEBUG", "False")
+            .create_bundle(InstallDir::Custom(dir.to_string_lossy().into_owned()))
+            .unwrap();
+        let info = bundle.join("Contents").join("Info.plist");
+        let xml = std::fs::read_to_string(&info).unwrap();
+        assert!(xml.starts_with("<?xml"));
+        unsafe {
+            let cls = class("NSDictionary").unwrap();
+            let path = str_to_nsstring(&info.to_string_lossy());
+            let plist: *mut Object = msg_send![cls, dictionaryWithContentsOfFile: path];
+            assert!(plist != nil);
+            assert_eq!(plist_value(plist, "NSHighResolutionCapable"), ("bool", "True".to_string()));
+            assert_eq!(plist_value(plist, "LSUIElement"), ("bool", "True".to_string()));
+            assert_eq!(plist_value(plist, "NSQuotedFlag"), ("string", "False".to_string()));
+            assert_eq!(plist_value(plist, "CFBundleName"), ("string", "True".to_string()));
+            let env: *mut Object = msg_send![plist, objectForKey: str_to_nsstring("LSEnvironment")];
+            assert_eq!(plist_value(env, "DEBUG"), ("string", "False".to_string()));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn expand_template_escapes_settings() {
+        let mut tramp = Trampoline::new("My \"App\"", "myapp", "com.example.myapp");
+        tramp.version("1.2.3");
+        let name = "\"My \\\"App\\\"\"";
+        assert_eq!(tramp.expand_template("\"${NAME}\""), name);
+        assert_eq!(tramp.expand_template("\"${EXECUTABLE}\""), "\"myapp\"");
+        assert_eq!(tramp.expand_template("(\"${IDENT}.view\")"), "(\"com.example.myapp.view\")");
+        assert_eq!(tramp.expand_template("\"v${VERSION}\""), "\"v1.2.3\"");
+        assert_eq!(tramp.expand_template("\"${YEAR}\""), format!("\"{}\"", current_year()));
+        // Outside of quotes, the setting becomes a quoted string
+        assert_eq!(tramp.expand_template("${NAME}"), name);
+        // An escaped quote doesn't end the string
+        assert_eq!(tramp.expand_template("\"a \\\" ${NAME}\""),
+                   "\"a \\\" My \\\"App\\\"\"");
+        assert_eq!(tramp.expand_template("\"${FOO} ${VERSION}\""), "\"${FOO} 1.2.3\"");
+        assert_eq!(tramp.expand_template("\"${VERSION\""), "\"${VERSION\"");
+        assert_eq!(tramp.expand_template("\"${VERSION} ${\""), "\"1.2.3 ${\"");
     }
 }
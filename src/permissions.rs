@@ -0,0 +1,52 @@
+//! Checks for privacy permissions granted by the user
+//!
+//! macOS asks the user before an app can use some sensitive capabilities,
+//! and remembers the answer per app in System Settings > Privacy & Security.
+//! Permissions are granted to the app's bundle identifier and code
+//! signature, so an app should run from a bundle (see
+//! [Trampoline](../struct.Trampoline.html)) for a grant to stick between
+//! launches.
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Class, Object, BOOL, NO, YES};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    static kAXTrustedCheckOptionPrompt: *mut Object;
+    fn AXIsProcessTrustedWithOptions(options: *mut Object) -> BOOL;
+}
+
+/// Check whether the app may control the computer through Accessibility
+///
+/// Accessibility access is required to monitor or post keyboard and mouse
+/// events in other apps, as global hotkeys and event taps do, and to control
+/// other apps' UI.  If `prompt` is true and the app isn't trusted yet, macOS
+/// shows a dialog directing the user to enable the app in System Settings.
+/// The prompt doesn't wait for an answer; check again later, or after the
+/// app is relaunched, to find out if access was granted.
+///
+/// # Arguments
+///
+/// `prompt` - Whether to ask the user to grant access if it isn't granted
+///
+/// # Returns
+///
+/// Whether the app is currently trusted for Accessibility
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn ensure_accessibility(prompt: bool) -> bool {
+    let (dict_cls, number_cls) = match (Class::get("NSDictionary"), Class::get("NSNumber")) {
+        (Some(dict_cls), Some(number_cls)) => (dict_cls, number_cls),
+        _ => return false,
+    };
+    unsafe {
+        let value: *mut Object = msg_send![number_cls, numberWithBool: if prompt { YES } else { NO }];
+        let options: *mut Object = msg_send![dict_cls, dictionaryWithObject: value
+                                             forKey: kAXTrustedCheckOptionPrompt];
+        AXIsProcessTrustedWithOptions(options) != NO
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn ensure_accessibility(_prompt: bool) -> bool { false }
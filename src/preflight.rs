@@ -0,0 +1,229 @@
+//! Checks of the environment the app is running in
+//!
+//! Whether the trampoline can bundle and relaunch an app depends on things
+//! outside of the app's control: where it was launched from, whether it is
+//! sandboxed, and which directories and tools are available.  Call
+//! [preflight](preflight) to inspect all of these up front, and pick an
+//! install directory or bundling strategy that will work, instead of finding
+//! out from an error halfway through.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use Trampoline;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use {Arch, FruitError};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::class;
+
+/// Report of the environment the app is running in
+///
+/// Returned by [preflight](preflight).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preflight {
+    /// macOS version, as (major, minor, patch).  All zeros on other
+    /// platforms.
+    pub os_version: (u32, u32, u32),
+    /// CPU architecture the executable was built for, such as `x86_64` or
+    /// `aarch64`
+    pub arch: &'static str,
+    /// Whether an Intel executable is running under Rosetta on Apple silicon
+    pub translated: bool,
+    /// Whether Rosetta is installed, so Intel executables can run on Apple
+    /// silicon.  Always false on Intel Macs.
+    pub rosetta: bool,
+    /// Whether Gatekeeper has translocated the app to a randomized read-only
+    /// location, as it does for quarantined apps run from where they were
+    /// downloaded
+    pub translocated: bool,
+    /// Whether the app is running in the App Sandbox
+    pub sandboxed: bool,
+    /// Whether the executable is already running from an app bundle
+    pub bundled: bool,
+    /// Whether bundles can be written to `InstallDir::Temp`
+    pub temp_writable: bool,
+    /// Whether bundles can be written to `InstallDir::SystemApplications`
+    pub system_applications_writable: bool,
+    /// Whether bundles can be written to `InstallDir::UserApplications`
+    pub user_applications_writable: bool,
+    /// Path to the `codesign` tool, if it is installed
+    pub codesign: Option<PathBuf>,
+    /// Path to the `hdiutil` tool, if it is installed
+    pub hdiutil: Option<PathBuf>,
+}
+
+/// Inspect the environment the app is running in
+///
+/// Nothing is modified: directories are checked for write permission without
+/// writing to them, and tools are found without running them.
+pub fn preflight() -> Preflight {
+    let exe = std::env::current_exe().unwrap_or_default();
+    let user_apps = dirs::home_dir().map(|home| home.join("Applications"));
+    Preflight {
+        os_version: os_version(),
+        arch: std::env::consts::ARCH,
+        translated: is_translated(),
+        rosetta: host_is_arm64() && Path::new(ROSETTA_PATH).exists(),
+        translocated: exe.to_string_lossy().contains("/AppTranslocation/"),
+        sandboxed: ::sandbox::is_sandboxed(),
+        bundled: Trampoline::is_bundled(),
+        temp_writable: is_writable(&std::env::temp_dir()),
+        system_applications_writable: is_writable(Path::new("/Applications")),
+        user_applications_writable: user_apps.is_some_and(|dir| is_writable(&dir)),
+        codesign: find_tool("codesign"),
+        hdiutil: find_tool("hdiutil"),
+    }
+}
+
+/// Whether a file could be created in `dir`
+///
+/// A directory that doesn't exist yet counts as writable if it could be
+/// created, which is what the trampoline does with missing install dirs.
+pub(crate) fn is_writable(dir: &Path) -> bool {
+    let existing = match dir.ancestors().find(|path| path.exists()) {
+        Some(path) => path,
+        None => return false,
+    };
+    let path = match std::ffi::CString::new(existing.to_string_lossy().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Find a command-line tool in `PATH`, falling back to `/usr/bin`
+fn find_tool(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(std::iter::once(PathBuf::from("/usr/bin")))
+        .map(|dir| dir.join(name))
+        .find(|tool| tool.is_file())
+}
+
+/// Rosetta runtime, which is only present once Rosetta is installed
+const ROSETTA_PATH: &str = "/Library/Apple/usr/share/rosetta/rosetta";
+
+/// Architectures a Mach-O executable contains code for
+///
+/// Empty if the file isn't a Mach-O executable, such as a script.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn executable_archs(path: &Path) -> Vec<Arch> {
+    const MH_MAGIC_64: u32 = 0xfeedfacf;
+    const FAT_MAGIC: u32 = 0xcafebabe;
+    const FAT_MAGIC_64: u32 = 0xcafebabf;
+    const CPU_TYPE_X86_64: u32 = 0x01000007;
+    const CPU_TYPE_ARM64: u32 = 0x0100000c;
+    let mut header = [0u8; 4096];
+    let len = match std::fs::File::open(path).and_then(|mut f| {
+        std::io::Read::read(&mut f, &mut header)
+    }) {
+        Ok(len) => len,
+        Err(_) => return Vec::new(),
+    };
+    let header = &header[..len];
+    let be = |off: usize| header.get(off..off + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let le = |off: usize| header.get(off..off + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let cputypes: Vec<u32> = match (be(0), le(0)) {
+        (Some(FAT_MAGIC), _) | (Some(FAT_MAGIC_64), _) => {
+            let entry_len = if be(0) == Some(FAT_MAGIC) { 20 } else { 32 };
+            let count = be(4).unwrap_or(0) as usize;
+            (0..count).filter_map(|i| be(8 + i * entry_len)).collect()
+        },
+        (_, Some(MH_MAGIC_64)) => le(4).into_iter().collect(),
+        _ => Vec::new(),
+    };
+    cputypes.iter().filter_map(|&cputype| match cputype {
+        CPU_TYPE_ARM64 => Some(Arch::Arm64),
+        CPU_TYPE_X86_64 => Some(Arch::X86_64),
+        _ => None,
+    }).collect()
+}
+
+/// Check that this Mac can run an executable
+///
+/// Catches Intel-only executables on Apple silicon Macs without Rosetta,
+/// which would otherwise fail to launch with an unhelpful LaunchServices
+/// error.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn check_runnable(exe: &Path) -> Result<(), FruitError> {
+    let archs = executable_archs(exe);
+    if host_is_arm64() && !archs.is_empty() && !archs.contains(&Arch::Arm64)
+        && !Path::new(ROSETTA_PATH).exists() {
+        return Err(FruitError::ArchitectureError(format!(
+            "{:?} is Intel-only and this Mac has no Rosetta; install it with \
+             `softwareupdate --install-rosetta`, or build for aarch64-apple-darwin",
+            exe)));
+    }
+    Ok(())
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" {
+    fn sysctlbyname(name: *const libc::c_char, oldp: *mut libc::c_void, oldlenp: *mut usize,
+                    newp: *mut libc::c_void, newlen: usize) -> libc::c_int;
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[repr(C)]
+struct NSOperatingSystemVersion {
+    major: isize,
+    minor: isize,
+    patch: isize,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn os_version() -> (u32, u32, u32) {
+    let cls = match class("NSProcessInfo") {
+        Ok(cls) => cls,
+        Err(e) => {
+            info!("Can't read OS version: {}", e);
+            return (0, 0, 0);
+        },
+    };
+    unsafe {
+        let info: *mut Object = msg_send![cls, processInfo];
+        let version: NSOperatingSystemVersion = msg_send![info, operatingSystemVersion];
+        (version.major as u32, version.minor as u32, version.patch as u32)
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn is_translated() -> bool {
+    let mut translated: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let status = unsafe {
+        sysctlbyname(b"sysctl.proc_translated\0".as_ptr() as *const libc::c_char,
+                     &mut translated as *mut libc::c_int as *mut libc::c_void,
+                     &mut size,
+                     std::ptr::null_mut(), 0)
+    };
+    status == 0 && translated == 1
+}
+
+/// Whether the Mac has Apple silicon, even if this process is translated
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn host_is_arm64() -> bool {
+    let mut arm64: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let status = unsafe {
+        sysctlbyname(b"hw.optional.arm64\0".as_ptr() as *const libc::c_char,
+                     &mut arm64 as *mut libc::c_int as *mut libc::c_void,
+                     &mut size,
+                     std::ptr::null_mut(), 0)
+    };
+    status == 0 && arm64 == 1
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+fn os_version() -> (u32, u32, u32) { (0, 0, 0) }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+fn is_translated() -> bool { false }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+fn host_is_arm64() -> bool { false }
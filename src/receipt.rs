@@ -0,0 +1,453 @@
+//! Mac App Store receipts
+//!
+//! Apps bought from the Mac App Store contain a receipt, a signed PKCS #7
+//! file at `Contents/_MASReceipt/receipt`, recording which app was bought
+//! and which version was first purchased.  An app checks its receipt on
+//! startup, and if it is missing or doesn't match the app, exits with status
+//! 173.  When launched from the App Store, that status asks the App Store to
+//! fetch a new receipt (prompting the user to sign in) and relaunch the app:
+//!
+//! ```no_run
+//! # use fruitbasket::receipt;
+//! let receipt = receipt::require_valid();
+//! println!("Originally bought version {}", receipt.original_app_version);
+//! ```
+//!
+//! Validation here checks that the receipt is well-formed and belongs to this
+//! app and version.  It does not verify the receipt's signature, or its hash
+//! against this Mac's identifier, so it only guards against casual copying.
+//! Apps needing stronger checks can verify [Receipt::hash](Receipt) with a
+//! crypto library.
+
+use std::path::PathBuf;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, nsstring_to_string, str_to_nsstring};
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// Exit status that asks the App Store to fetch a new receipt
+pub const EXIT_RECEIPT_INVALID: i32 = 173;
+
+/// Receipt attribute types
+const ATTR_BUNDLE_ID: u64 = 2;
+const ATTR_APP_VERSION: u64 = 3;
+const ATTR_OPAQUE: u64 = 4;
+const ATTR_HASH: u64 = 5;
+const ATTR_CREATION_DATE: u64 = 12;
+const ATTR_ORIGINAL_APP_VERSION: u64 = 19;
+const ATTR_EXPIRATION_DATE: u64 = 21;
+
+/// ASN.1 tags used by receipts
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTF8_STRING: u8 = 0x0c;
+const TAG_IA5_STRING: u8 = 0x16;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_EXPLICIT_0: u8 = 0xa0;
+/// Flag set in the tag of constructed (nested) elements
+const CONSTRUCTED: u8 = 0x20;
+/// How deeply indefinite-length and constructed elements may nest, which
+/// receipts never come close to
+const MAX_DEPTH: usize = 32;
+
+/// The contents of an App Store receipt
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Receipt {
+    /// Bundle identifier of the app the receipt was issued for
+    pub bundle_id: String,
+    /// Version of the app the receipt was issued for (`CFBundleShortVersionString`)
+    pub app_version: String,
+    /// Version of the app that was originally purchased
+    pub original_app_version: String,
+    /// When the receipt was created, in RFC 3339 format
+    pub creation_date: String,
+    /// When the receipt expires, in RFC 3339 format, if it does
+    pub expiration_date: Option<String>,
+    /// Opaque value used, with `bundle_id_data`, to compute `hash`
+    pub opaque: Vec<u8>,
+    /// SHA-1 hash of the Mac's GUID, `opaque`, and `bundle_id_data`
+    pub hash: Vec<u8>,
+    /// The encoded bundle identifier, as used to compute `hash`
+    pub bundle_id_data: Vec<u8>,
+}
+
+/// Parse the contents of a receipt file
+///
+/// Decodes the PKCS #7 container and the receipt attributes inside it,
+/// without verifying its signature.
+pub fn parse(data: &[u8]) -> Result<Receipt, FruitError> {
+    let payload = pkcs7_payload(data)
+        .ok_or_else(|| FruitError::ReceiptError("not a PKCS #7 signed data file".into()))?;
+    let malformed = || FruitError::ReceiptError("malformed receipt attributes".into());
+    let (attributes, _) = expect(&payload, TAG_SET).ok_or_else(malformed)?;
+    let mut receipt = Receipt::default();
+    let mut rest = attributes;
+    while !rest.is_empty() {
+        let (attribute, after) = expect(rest, TAG_SEQUENCE).ok_or_else(malformed)?;
+        rest = after;
+        let (kind, after) = expect(attribute, TAG_INTEGER).ok_or_else(malformed)?;
+        let (_version, after) = expect(after, TAG_INTEGER).ok_or_else(malformed)?;
+        let (tag, value, _) = next(after).ok_or_else(malformed)?;
+        let value = octet_string(tag, value).ok_or_else(malformed)?;
+        match integer(kind) {
+            ATTR_BUNDLE_ID => {
+                receipt.bundle_id = string(&value).unwrap_or_default();
+                receipt.bundle_id_data = value;
+            },
+            ATTR_APP_VERSION => receipt.app_version = string(&value).unwrap_or_default(),
+            ATTR_OPAQUE => receipt.opaque = value,
+            ATTR_HASH => receipt.hash = value,
+            ATTR_CREATION_DATE => receipt.creation_date = string(&value).unwrap_or_default(),
+            ATTR_ORIGINAL_APP_VERSION => {
+                receipt.original_app_version = string(&value).unwrap_or_default();
+            },
+            ATTR_EXPIRATION_DATE => receipt.expiration_date = string(&value),
+            _ => {},
+        }
+    }
+    if receipt.bundle_id.is_empty() {
+        return Err(FruitError::ReceiptError("receipt has no bundle identifier".into()));
+    }
+    Ok(receipt)
+}
+
+/// Path of the running app's receipt, whether or not it exists
+///
+/// None if not running from a bundle.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn receipt_path() -> Option<PathBuf> {
+    let cls = class("NSBundle").ok()?;
+    unsafe {
+        let bundle: *mut Object = msg_send![cls, mainBundle];
+        let url: *mut Object = msg_send![bundle, appStoreReceiptURL];
+        ::foundation::nsurl_to_path(url)
+    }
+}
+
+/// Read and parse the running app's receipt
+///
+/// Returns an I/O error if the app has no receipt.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn load() -> Result<Receipt, FruitError> {
+    let path = receipt_path().ok_or(FruitError::NotBundled)?;
+    parse(&std::fs::read(path)?)
+}
+
+/// Load the running app's receipt, and check that it was issued for it
+///
+/// The receipt must exist, and its bundle identifier and version must match
+/// the running app's `CFBundleIdentifier` and `CFBundleShortVersionString`.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn validate() -> Result<Receipt, FruitError> {
+    let receipt = load()?;
+    let ident = info_string("CFBundleIdentifier").ok_or(FruitError::NotBundled)?;
+    if receipt.bundle_id != ident {
+        return Err(FruitError::ReceiptError(
+            format!("receipt is for {}, not {}", receipt.bundle_id, ident)));
+    }
+    let version = info_string("CFBundleShortVersionString").unwrap_or_default();
+    if receipt.app_version != version {
+        return Err(FruitError::ReceiptError(
+            format!("receipt is for version {}, not {}", receipt.app_version, version)));
+    }
+    Ok(receipt)
+}
+
+/// Look up a string in the running app's Info.plist
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn info_string(key: &str) -> Option<String> {
+    let cls = class("NSBundle").ok()?;
+    unsafe {
+        let bundle: *mut Object = msg_send![cls, mainBundle];
+        let value: *mut Object = msg_send![bundle, objectForInfoDictionaryKey: str_to_nsstring(key)];
+        if value == nil {
+            return None;
+        }
+        Some(nsstring_to_string(value))
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn receipt_path() -> Option<PathBuf> { None }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn load() -> Result<Receipt, FruitError> { Err(FruitError::UnsupportedPlatform) }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn validate() -> Result<Receipt, FruitError> { Err(FruitError::UnsupportedPlatform) }
+
+/// Exit, asking the App Store to fetch a new receipt and relaunch the app
+///
+/// Exits with status 173.  This only has an effect for apps launched by the
+/// App Store or Finder; otherwise the app just exits.
+pub fn request_new_receipt() -> ! {
+    info!("App Store receipt missing or invalid.  Exiting to request a new one.");
+    std::process::exit(EXIT_RECEIPT_INVALID);
+}
+
+/// Validate the running app's receipt, or exit to request a new one
+///
+/// The conventional receipt check for App Store apps, to call early in
+/// `main()`.  Returns the receipt if [validate](validate) succeeds, and
+/// calls [request_new_receipt](request_new_receipt) otherwise.
+pub fn require_valid() -> Receipt {
+    match validate() {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            info!("Receipt validation failed: {}", e);
+            request_new_receipt()
+        },
+    }
+}
+
+/// The payload of PKCS #7 signed data
+///
+/// ContentInfo { contentType, [0] SignedData { version, digestAlgorithms,
+/// encapContentInfo { eContentType, [0] eContent }, ... } }
+fn pkcs7_payload(data: &[u8]) -> Option<Vec<u8>> {
+    let (content_info, _) = expect(data, TAG_SEQUENCE)?;
+    let (_, rest) = expect(content_info, TAG_OID)?;
+    let (explicit, _) = expect(rest, TAG_EXPLICIT_0)?;
+    let (signed_data, _) = expect(explicit, TAG_SEQUENCE)?;
+    let (_, rest) = expect(signed_data, TAG_INTEGER)?;
+    let (_, rest) = expect(rest, TAG_SET)?;
+    let (encap, _) = expect(rest, TAG_SEQUENCE)?;
+    let (_, rest) = expect(encap, TAG_OID)?;
+    let (explicit, _) = expect(rest, TAG_EXPLICIT_0)?;
+    let (tag, content, _) = next(explicit)?;
+    octet_string(tag, content)
+}
+
+/// Split the first element off `data`, returning its tag, contents, and the
+/// elements after it
+///
+/// Accepts the BER indefinite-length form that receipts may use, as well as
+/// DER.
+fn next(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    next_nested(data, 0)
+}
+
+/// [next](next) for an element `depth` levels inside an indefinite-length
+/// element, failing if nested more than `MAX_DEPTH` deep
+fn next_nested(data: &[u8], depth: usize) -> Option<(u8, &[u8], &[u8])> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    let tag = *data.first()?;
+    if tag & 0x1f == 0x1f {
+        // Multi-byte tags aren't used in receipts
+        return None;
+    }
+    let first = *data.get(1)? as usize;
+    let (header, len) = match first {
+        0..=0x7f => (2, Some(first)),
+        0x80 => (2, None),
+        _ => {
+            let count = first & 0x7f;
+            if count > std::mem::size_of::<usize>() {
+                return None;
+            }
+            let bytes = data.get(2..2 + count)?;
+            (2 + count, Some(bytes.iter().fold(0, |len, &b| len << 8 | b as usize)))
+        },
+    };
+    let body = &data[header..];
+    match len {
+        Some(len) => {
+            let content = body.get(..len)?;
+            Some((tag, content, &body[len..]))
+        },
+        None => {
+            // Indefinite length: nested elements until an end-of-contents marker
+            if tag & CONSTRUCTED == 0 {
+                return None;
+            }
+            let mut rest = body;
+            while !rest.starts_with(&[0, 0]) {
+                let (_, _, after) = next_nested(rest, depth + 1)?;
+                rest = after;
+            }
+            let len = body.len() - rest.len();
+            Some((tag, &body[..len], &rest[2..]))
+        },
+    }
+}
+
+/// Split off the first element, if it has the given tag
+fn expect(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match next(data)? {
+        (found, content, rest) if found == tag => Some((content, rest)),
+        _ => None,
+    }
+}
+
+/// The bytes of an OCTET STRING, joining the chunks of a constructed one
+fn octet_string(tag: u8, content: &[u8]) -> Option<Vec<u8>> {
+    octet_string_nested(tag, content, 0)
+}
+
+/// [octet_string](octet_string) for a chunk `depth` levels inside a
+/// constructed OCTET STRING, failing if nested more than `MAX_DEPTH` deep
+fn octet_string_nested(tag: u8, content: &[u8], depth: usize) -> Option<Vec<u8>> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    if tag == TAG_OCTET_STRING {
+        return Some(content.to_vec());
+    }
+    if tag != TAG_OCTET_STRING | CONSTRUCTED {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let (tag, chunk, after) = next(rest)?;
+        bytes.extend(octet_string_nested(tag, chunk, depth + 1)?);
+        rest = after;
+    }
+    Some(bytes)
+}
+
+/// The value of a non-negative INTEGER, saturating if it doesn't fit
+fn integer(content: &[u8]) -> u64 {
+    if content.len() > 8 {
+        return u64::MAX;
+    }
+    content.iter().fold(0, |value, &b| value << 8 | b as u64)
+}
+
+/// The text of an encoded UTF8String or IA5String
+fn string(data: &[u8]) -> Option<String> {
+    match next(data)? {
+        (TAG_UTF8_STRING, content, _) | (TAG_IA5_STRING, content, _) => {
+            Some(String::from_utf8_lossy(content).into_owned())
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// OID 1.2.840.113549.1.7.2, PKCS #7 signed data
+    const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+    /// OID 1.2.840.113549.1.7.1, PKCS #7 data
+    const OID_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+    /// OID 1.3.14.3.2.26, SHA-1
+    const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+
+    /// A DER element, with a long-form length when needed
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            let len = (content.len() as u32).to_be_bytes();
+            let len: Vec<u8> = len.iter().cloned().skip_while(|&b| b == 0).collect();
+            out.push(0x80 | len.len() as u8);
+            out.extend(len);
+        }
+        out.extend(content);
+        out
+    }
+
+    /// A BER indefinite-length element
+    fn indefinite(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag, 0x80];
+        out.extend(content);
+        out.extend(&[0, 0]);
+        out
+    }
+
+    fn attribute(kind: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut content = der(TAG_INTEGER, kind);
+        content.extend(der(TAG_INTEGER, &[1]));
+        content.extend(der(TAG_OCTET_STRING, value));
+        der(TAG_SEQUENCE, &content)
+    }
+
+    /// A receipt laid out like the App Store's: indefinite-length PKCS #7
+    /// containers around a DER attribute set, with the payload split into
+    /// constructed OCTET STRING chunks
+    fn receipt_fixture() -> Vec<u8> {
+        let mut attributes = Vec::new();
+        attributes.extend(attribute(&[ATTR_BUNDLE_ID as u8], &der(TAG_UTF8_STRING, b"com.example.app")));
+        attributes.extend(attribute(&[ATTR_APP_VERSION as u8], &der(TAG_UTF8_STRING, b"1.2")));
+        attributes.extend(attribute(&[ATTR_OPAQUE as u8], &[1, 2, 3, 4]));
+        attributes.extend(attribute(&[ATTR_HASH as u8], &[0xab; 20]));
+        attributes.extend(attribute(&[ATTR_CREATION_DATE as u8],
+                                    &der(TAG_IA5_STRING, b"2026-01-02T03:04:05Z")));
+        attributes.extend(attribute(&[ATTR_ORIGINAL_APP_VERSION as u8], &der(TAG_UTF8_STRING, b"1.0")));
+        attributes.extend(attribute(&[0x27, 0x0f], &der(TAG_UTF8_STRING, &[b'x'; 300])));
+        let payload = der(TAG_SET, &attributes);
+        let (first, second) = payload.split_at(payload.len() / 2);
+        let mut chunks = der(TAG_OCTET_STRING, first);
+        chunks.extend(der(TAG_OCTET_STRING, second));
+
+        let mut encap = der(TAG_OID, OID_DATA);
+        encap.extend(indefinite(TAG_EXPLICIT_0, &indefinite(TAG_OCTET_STRING | CONSTRUCTED, &chunks)));
+        let mut signed_data = der(TAG_INTEGER, &[1]);
+        signed_data.extend(der(TAG_SET, &der(TAG_SEQUENCE, &der(TAG_OID, OID_SHA1))));
+        signed_data.extend(indefinite(TAG_SEQUENCE, &encap));
+        let mut content_info = der(TAG_OID, OID_SIGNED_DATA);
+        content_info.extend(indefinite(TAG_EXPLICIT_0, &indefinite(TAG_SEQUENCE, &signed_data)));
+        indefinite(TAG_SEQUENCE, &content_info)
+    }
+
+    #[test]
+    fn parse_receipt() {
+        let receipt = parse(&receipt_fixture()).unwrap();
+        assert_eq!(receipt.bundle_id, "com.example.app");
+        assert_eq!(receipt.bundle_id_data, der(TAG_UTF8_STRING, b"com.example.app"));
+        assert_eq!(receipt.app_version, "1.2");
+        assert_eq!(receipt.original_app_version, "1.0");
+        assert_eq!(receipt.creation_date, "2026-01-02T03:04:05Z");
+        assert_eq!(receipt.expiration_date, None);
+        assert_eq!(receipt.opaque, vec![1, 2, 3, 4]);
+        assert_eq!(receipt.hash, vec![0xab; 20]);
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let data = receipt_fixture();
+        for len in 0..data.len() {
+            assert!(parse(&data[..len]).is_err(), "parsed {} of {} bytes", len, data.len());
+        }
+    }
+
+    #[test]
+    fn parse_deep_nesting() {
+        let nested = |depth: usize| {
+            let mut data = [TAG_SEQUENCE, 0x80].repeat(depth);
+            data.extend([0, 0].repeat(depth));
+            data
+        };
+        assert!(next(&nested(MAX_DEPTH + 1)).is_some());
+        assert!(next(&nested(MAX_DEPTH + 2)).is_none());
+        assert!(parse(&nested(100_000)).is_err());
+
+        let chunks = |depth: usize| {
+            (0..depth).fold(der(TAG_OCTET_STRING, b"x"),
+                            |chunk, _| der(TAG_OCTET_STRING | CONSTRUCTED, &chunk))
+        };
+        let data = chunks(MAX_DEPTH);
+        let (tag, content, _) = next(&data).unwrap();
+        assert_eq!(octet_string(tag, content), Some(b"x".to_vec()));
+        let data = chunks(MAX_DEPTH + 1);
+        let (tag, content, _) = next(&data).unwrap();
+        assert_eq!(octet_string(tag, content), None);
+    }
+}
@@ -0,0 +1,210 @@
+//! Offer to move the app into the Applications folder
+//!
+//! Users often run apps straight from their Downloads folder or from the
+//! disk image they were distributed on.  Apps run from there can't update
+//! themselves, may be translocated by Gatekeeper to a read-only location,
+//! and disappear when the disk image is ejected.
+//!
+//! [move_to_applications](move_to_applications) detects this and, with the
+//! user's permission, copies the app bundle to `/Applications` (or
+//! `~/Applications` if that isn't writable), removes the quarantine flag,
+//! moves the original to the Trash, and relaunches from the new location.
+//! Call it early in `applicationDidFinishLaunching:`, or right after the
+//! trampoline.
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::path::{Path, PathBuf};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, BOOL, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, is_main_thread, nsstring_to_string, str_to_nsstring};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// NSAlertFirstButtonReturn
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FIRST_BUTTON_RETURN: i64 = 1000;
+
+/// Whether an app bundle at `path` is somewhere it shouldn't be run from
+///
+/// True for the Downloads folder, mounted disk images, temporary
+/// directories, and Gatekeeper's translocation directory.  Never true
+/// for anything already in an Applications folder.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn is_temporary_location(path: &Path) -> bool {
+    let home = dirs::home_dir().unwrap_or_default();
+    if path.starts_with("/Applications") || path.starts_with(home.join("Applications")) {
+        return false;
+    }
+    let lossy = path.to_string_lossy();
+    path.starts_with(home.join("Downloads")) ||
+        path.starts_with("/Volumes") ||
+        path.starts_with(std::env::temp_dir()) ||
+        path.starts_with("/private/var/folders") ||
+        path.starts_with("/tmp") ||
+        path.starts_with("/private/tmp") ||
+        lossy.contains("/AppTranslocation/")
+}
+
+/// Full path to the running app bundle
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn bundle_path() -> Option<PathBuf> {
+    if !::Trampoline::is_bundled() {
+        return None;
+    }
+    let cls = class("NSBundle").ok()?;
+    unsafe {
+        let bundle: *mut Object = msg_send![cls, mainBundle];
+        let path: *mut Object = msg_send![bundle, bundlePath];
+        Some(PathBuf::from(nsstring_to_string(path)))
+    }
+}
+
+/// Returns whether the running app should offer to move itself
+///
+/// False if the app isn't running from a bundle, or if the bundle is already
+/// in an Applications folder or anywhere else not considered temporary.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn needs_move() -> bool {
+    bundle_path().is_some_and(|path| is_temporary_location(&path))
+}
+
+/// Ask to move the app to the Applications folder, and relaunch if moved
+///
+/// Does nothing if [needs_move](needs_move) is false.  Otherwise, shows an
+/// alert asking the user whether to move the app.  If they agree, the app is
+/// moved, relaunched from its new location, and the current process exits;
+/// this function only returns if the app was not moved.
+///
+/// An app with the same name already in the destination folder is moved to
+/// the Trash first.  The original bundle is left in place if it can't be
+/// removed, such as when it's on a read-only disk image or was translocated.
+///
+/// # Returns
+///
+/// `Ok(())` if no move was needed or the user declined.
+/// `FruitError::MainThreadViolation` if not called from the main thread.
+/// `FruitError::ClassNotFound` if the alert's AppKit classes are missing.
+/// `FruitError::IOError` if the app could not be copied.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn move_to_applications() -> Result<(), FruitError> {
+    let src = match bundle_path() {
+        Some(ref path) if is_temporary_location(path) => path.clone(),
+        _ => return Ok(()),
+    };
+    if !is_main_thread() {
+        return Err(FruitError::MainThreadViolation);
+    }
+    if !ask_to_move()? {
+        info!("User declined moving to Applications folder.");
+        return Ok(());
+    }
+    let dest_dir = if ::preflight::is_writable(Path::new("/Applications")) {
+        PathBuf::from("/Applications")
+    } else {
+        let dir = dirs::home_dir().unwrap_or_default().join("Applications");
+        std::fs::create_dir_all(&dir)?;
+        dir
+    };
+    let dest = dest_dir.join(src.file_name().unwrap_or_default());
+    info!("Moving {:?} to {:?}", src, dest);
+    if dest.exists() && !trash(&dest) {
+        std::fs::remove_dir_all(&dest)?;
+    }
+    run_tool("/usr/bin/ditto", &[src.as_os_str(), dest.as_os_str()])?;
+    // Copied apps keep the quarantine flag, which would get them translocated
+    // again.  Failing to remove it is not fatal.
+    let _ = run_tool("/usr/bin/xattr",
+                     &["-d".as_ref(), "-r".as_ref(), "com.apple.quarantine".as_ref(),
+                       dest.as_os_str()]);
+    let translocated = src.to_string_lossy().contains("/AppTranslocation/");
+    if !src.starts_with("/Volumes") && !translocated && !trash(&src) {
+        info!("Could not move original app to the Trash: {:?}", src);
+    }
+    relaunch(&dest)?;
+    info!("Relaunched from Applications folder.  Exiting.");
+    std::process::exit(0);
+}
+
+/// Show the alert asking whether to move the app
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn ask_to_move() -> Result<bool, FruitError> {
+    unsafe {
+        let cls = class("NSApplication")?;
+        let app: *mut Object = msg_send![cls, sharedApplication];
+        let _:() = msg_send![app, activateIgnoringOtherApps: YES];
+        let cls = class("NSAlert")?;
+        let alert: *mut Object = msg_send![cls, new];
+        let _:() = msg_send![alert, setMessageText: str_to_nsstring("Move to Applications folder?")];
+        let _:() = msg_send![alert, setInformativeText:
+                             str_to_nsstring("This app can move itself to your Applications \
+                                              folder, so it keeps working after this \
+                                              download or disk image is removed.")];
+        let _: *mut Object = msg_send![alert, addButtonWithTitle: str_to_nsstring("Move to Applications Folder")];
+        let _: *mut Object = msg_send![alert, addButtonWithTitle: str_to_nsstring("Do Not Move")];
+        let response: i64 = msg_send![alert, runModal];
+        let _:() = msg_send![alert, release];
+        Ok(response == FIRST_BUTTON_RETURN)
+    }
+}
+
+/// Move a file or directory to the Trash
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn trash(path: &Path) -> bool {
+    let (url_cls, manager_cls) = match (class("NSURL"), class("NSFileManager")) {
+        (Ok(url_cls), Ok(manager_cls)) => (url_cls, manager_cls),
+        _ => return false,
+    };
+    unsafe {
+        let url: *mut Object = msg_send![url_cls, fileURLWithPath: str_to_nsstring(&path.to_string_lossy())];
+        let manager: *mut Object = msg_send![manager_cls, defaultManager];
+        let trashed: BOOL = msg_send![manager, trashItemAtURL: url
+                                      resultingItemURL: nil
+                                      error: nil];
+        trashed == YES
+    }
+}
+
+/// Run a command-line tool, failing if it exits unsuccessfully
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn run_tool(tool: &str, args: &[&std::ffi::OsStr]) -> Result<(), FruitError> {
+    let status = std::process::Command::new(tool).args(args).status()?;
+    if !status.success() {
+        return Err(FruitError::IOError(std::io::Error::new(
+            std::io::ErrorKind::Other, format!("{} failed: {}", tool, status))));
+    }
+    Ok(())
+}
+
+/// Launch the moved app once this process has exited
+///
+/// Waiting avoids LaunchServices handing the launch to this, still running,
+/// copy of the app.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn relaunch(app: &Path) -> Result<(), FruitError> {
+    run_after_exit(&["/usr/bin/open".as_ref(), app.as_os_str()])
+}
+
+/// Run a command from a detached shell once this process has exited
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub(crate) fn run_after_exit(command: &[&std::ffi::OsStr]) -> Result<(), FruitError> {
+    let script = "pid=\"$1\"; shift; while kill -0 \"$pid\" 2>/dev/null; do sleep 0.1; done; exec \"$@\"";
+    std::process::Command::new("/bin/sh")
+        .arg("-c").arg(script).arg("sh")
+        .arg(std::process::id().to_string())
+        .args(command)
+        .spawn()?;
+    Ok(())
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn needs_move() -> bool { false }
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn move_to_applications() -> Result<(), FruitError> { Ok(()) }
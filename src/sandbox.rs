@@ -0,0 +1,67 @@
+//! App Sandbox detection and sandbox-aware paths
+//!
+//! Apps running in the App Sandbox can only write inside their own container
+//! (`~/Library/Containers/<ident>/Data`), which macOS presents to the app as
+//! its home directory.  Libraries that look up the home directory from the
+//! environment or the password database, such as `dirs::home_dir()`, find the
+//! user's real home directory instead, where a sandboxed app can't write.
+//!
+//! Use [home_dir](home_dir) wherever an app stores its own files, so it works
+//! the same inside and outside of the sandbox.
+
+use std::path::PathBuf;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::nsstring_to_string;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "Foundation", kind = "framework")]
+extern "C" {
+    fn NSHomeDirectory() -> *mut Object;
+}
+
+/// Returns whether the app is running in the App Sandbox
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn is_sandboxed() -> bool {
+    std::env::var_os("APP_SANDBOX_CONTAINER_ID").is_some()
+}
+
+/// Directory of the app's sandbox container, if it is sandboxed
+///
+/// This is the writable `Data` directory of the container, which is also the
+/// app's home directory while sandboxed.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn container_dir() -> Option<PathBuf> {
+    if is_sandboxed() {
+        home_dir()
+    } else {
+        None
+    }
+}
+
+/// Home directory the app should store its files under
+///
+/// This is the sandbox container when sandboxed, and the user's home
+/// directory otherwise.  A drop-in replacement for `dirs::home_dir()`.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn home_dir() -> Option<PathBuf> {
+    let home = unsafe { NSHomeDirectory() };
+    if home.is_null() {
+        return dirs::home_dir();
+    }
+    Some(PathBuf::from(nsstring_to_string(home)))
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn is_sandboxed() -> bool { false }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn container_dir() -> Option<PathBuf> { None }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn home_dir() -> Option<PathBuf> { dirs::home_dir() }
@@ -0,0 +1,251 @@
+//! Discovering code signing identities
+//!
+//! [identities](identities) lists the code signing identities in the user's
+//! keychains, so bundler tools can offer a choice of identity, and a
+//! [post_build](../struct.Trampoline.html#method.post_build) hook can check
+//! that the identity it is about to pass to `codesign` exists before
+//! signing:
+//!
+//! ```no_run
+//! # use fruitbasket::*;
+//! let identity = signing::find_identity("Developer ID Application").unwrap();
+//! let _ = Trampoline::new("myapp", "myapp", "com.example.myapp")
+//!     .post_build(move |bundle| {
+//!         let status = std::process::Command::new("/usr/bin/codesign")
+//!             .args(&["--force", "--options", "runtime", "--sign", &identity.hash])
+//!             .arg(bundle)
+//!             .status()?;
+//!         if !status.success() {
+//!             return Err(FruitError::SignError(format!("codesign exited with {}", status)));
+//!         }
+//!         Ok(())
+//!     })
+//!     .build(InstallDir::Temp);
+//! ```
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, BOOL, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, nsstring_to_string};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// errSecItemNotFound
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ERR_ITEM_NOT_FOUND: i32 = -25300;
+/// CC_SHA1_DIGEST_LENGTH
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const SHA1_LENGTH: usize = 20;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "Security", kind = "framework")]
+extern "C" {
+    static kSecClass: *mut Object;
+    static kSecClassIdentity: *mut Object;
+    static kSecMatchLimit: *mut Object;
+    static kSecMatchLimitAll: *mut Object;
+    static kSecMatchPolicy: *mut Object;
+    static kSecReturnRef: *mut Object;
+    static kSecPolicyAppleCodeSigning: *mut Object;
+    static kSecOIDX509V1SubjectName: *mut Object;
+    static kSecOIDX509V1ValidityNotAfter: *mut Object;
+    static kSecOIDOrganizationalUnitName: *mut Object;
+    static kSecPropertyKeyLabel: *mut Object;
+    static kSecPropertyKeyValue: *mut Object;
+    fn SecItemCopyMatching(query: *mut Object, result: *mut *mut Object) -> i32;
+    fn SecPolicyCreateWithProperties(policy: *mut Object, properties: *mut Object) -> *mut Object;
+    fn SecIdentityCopyCertificate(identity: *mut Object, certificate: *mut *mut Object) -> i32;
+    fn SecCertificateCopySubjectSummary(certificate: *mut Object) -> *mut Object;
+    fn SecCertificateCopyData(certificate: *mut Object) -> *mut Object;
+    fn SecCertificateCopyValues(certificate: *mut Object, keys: *mut Object,
+                                error: *mut *mut Object) -> *mut Object;
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" {
+    fn CC_SHA1(data: *const u8, len: u32, md: *mut u8) -> *mut u8;
+}
+
+/// A certificate and private key that can sign code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    /// Common name of the certificate (ex: `Developer ID Application: Jane
+    /// Appleseed (ABCDE12345)`), which `codesign --sign` accepts
+    pub name: String,
+    /// SHA-1 hash of the certificate in uppercase hex, which identifies it
+    /// to `codesign --sign` even when several have the same name
+    pub hash: String,
+    /// Team identifier of the developer account the certificate belongs to
+    pub team_identifier: Option<String>,
+}
+
+impl Identity {
+    /// Whether this is a Developer ID identity, for distributing apps
+    /// outside the Mac App Store
+    pub fn is_developer_id(&self) -> bool {
+        self.name.starts_with("Developer ID Application:")
+    }
+}
+
+/// List the code signing identities in the user's keychains
+///
+/// Returns the same identities as `security find-identity -p codesigning`,
+/// with expired certificates left out.  Ad-hoc signing (`codesign --sign
+/// -`) needs no identity, and isn't listed.
+///
+/// # Returns
+///
+/// The identities, sorted by name.  `FruitError::KeychainError` if the
+/// keychains can't be searched.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn identities() -> Result<Vec<Identity>, FruitError> {
+    let dict_cls = class("NSMutableDictionary")?;
+    let number_cls = class("NSNumber")?;
+    unsafe {
+        let policy = SecPolicyCreateWithProperties(kSecPolicyAppleCodeSigning, nil);
+        let query: *mut Object = msg_send![dict_cls, dictionary];
+        let _:() = msg_send![query, setObject: kSecClassIdentity forKey: kSecClass];
+        let _:() = msg_send![query, setObject: kSecMatchLimitAll forKey: kSecMatchLimit];
+        let yes: *mut Object = msg_send![number_cls, numberWithBool: YES];
+        let _:() = msg_send![query, setObject: yes forKey: kSecReturnRef];
+        if policy != nil {
+            let _:() = msg_send![query, setObject: policy forKey: kSecMatchPolicy];
+            let _:() = msg_send![policy, release];
+        }
+        let mut result: *mut Object = nil;
+        let status = SecItemCopyMatching(query, &mut result);
+        if status == ERR_ITEM_NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        if status != 0 {
+            return Err(FruitError::KeychainError { os_status: status });
+        }
+        let mut identities: Vec<Identity> = ::foundation::nsarray_to_vec(result).into_iter()
+            .filter_map(|identity| identity_info(identity))
+            .collect();
+        let _:() = msg_send![result, release];
+        // Sort by hash too, so copies of one certificate end up adjacent
+        identities.sort_by(|a, b| (&a.name, &a.hash).cmp(&(&b.name, &b.hash)));
+        identities.dedup_by(|a, b| a.hash == b.hash);
+        Ok(identities)
+    }
+}
+
+/// Name, hash, and team of an identity's certificate, or None if it has
+/// expired
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn identity_info(identity: *mut Object) -> Option<Identity> {
+    let array_cls = class("NSArray").ok()?;
+    let date_cls = class("NSDate").ok()?;
+    let mut cert: *mut Object = nil;
+    if SecIdentityCopyCertificate(identity, &mut cert) != 0 || cert == nil {
+        return None;
+    }
+    let summary = SecCertificateCopySubjectSummary(cert);
+    let name = if summary == nil {
+        String::new()
+    } else {
+        let name = nsstring_to_string(summary);
+        let _:() = msg_send![summary, release];
+        name
+    };
+    let hash = certificate_hash(cert);
+    let keys: *mut Object = msg_send![array_cls, arrayWithObjects: [kSecOIDX509V1SubjectName,
+                                                                    kSecOIDX509V1ValidityNotAfter].as_ptr()
+                                      count: 2usize];
+    let values = SecCertificateCopyValues(cert, keys, std::ptr::null_mut());
+    let _:() = msg_send![cert, release];
+    let mut team_identifier = None;
+    if values != nil {
+        let not_after = property_value(values, kSecOIDX509V1ValidityNotAfter);
+        let expired = not_after != nil && {
+            let expires: f64 = msg_send![not_after, doubleValue];
+            let now: f64 = msg_send![date_cls, timeIntervalSinceReferenceDate];
+            expires < now
+        };
+        let subject = property_value(values, kSecOIDX509V1SubjectName);
+        if subject != nil {
+            team_identifier = ::foundation::nsarray_to_vec(subject).into_iter()
+                .find(|&field| {
+                    let label: *mut Object = msg_send![field, objectForKey: kSecPropertyKeyLabel];
+                    if label == nil {
+                        return false;
+                    }
+                    let equal: BOOL = msg_send![label, isEqualToString: kSecOIDOrganizationalUnitName];
+                    equal == YES
+                })
+                .and_then(|field| {
+                    let value: *mut Object = msg_send![field, objectForKey: kSecPropertyKeyValue];
+                    if value == nil { None } else { Some(nsstring_to_string(value)) }
+                });
+        }
+        let _:() = msg_send![values, release];
+        if expired {
+            info!("Skipping expired signing identity: {}", name);
+            return None;
+        }
+    }
+    Some(Identity {
+        name: name,
+        hash: hash,
+        team_identifier: team_identifier,
+    })
+}
+
+/// Value of one property in the dictionary from SecCertificateCopyValues
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn property_value(values: *mut Object, oid: *mut Object) -> *mut Object {
+    let property: *mut Object = msg_send![values, objectForKey: oid];
+    if property == nil {
+        return nil;
+    }
+    msg_send![property, objectForKey: kSecPropertyKeyValue]
+}
+
+/// SHA-1 hash of a certificate's DER encoding, in uppercase hex
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn certificate_hash(cert: *mut Object) -> String {
+    let data = SecCertificateCopyData(cert);
+    if data == nil {
+        return String::new();
+    }
+    let len: usize = msg_send![data, length];
+    let bytes: *const u8 = msg_send![data, bytes];
+    let mut digest = [0u8; SHA1_LENGTH];
+    CC_SHA1(bytes, len as u32, digest.as_mut_ptr());
+    let _:() = msg_send![data, release];
+    digest.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Find the one signing identity matching a name or hash
+///
+/// Matches the way `codesign --sign` does: `query` is either the
+/// identity's full SHA-1 hash, or any part of its name (ex: `Developer ID
+/// Application` or `ABCDE12345`).
+///
+/// # Returns
+///
+/// The matching identity.  `FruitError::SignError` if no identity matches,
+/// or if more than one does.  `FruitError::KeychainError` if the keychains
+/// can't be searched.
+pub fn find_identity(query: &str) -> Result<Identity, FruitError> {
+    let mut matches: Vec<Identity> = identities()?.into_iter()
+        .filter(|id| id.hash.eq_ignore_ascii_case(query) || id.name.contains(query))
+        .collect();
+    match matches.len() {
+        0 => Err(FruitError::SignError(format!("no signing identity matches {:?}", query))),
+        1 => Ok(matches.remove(0)),
+        n => Err(FruitError::SignError(
+            format!("{} signing identities match {:?}; use the hash to pick one", n, query))),
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn identities() -> Result<Vec<Identity>, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
@@ -0,0 +1,88 @@
+//! os_signpost intervals for profiling with Instruments
+//!
+//! With the 'signpost' feature enabled, bundle creation and run loop
+//! iterations are marked as intervals in the Points of Interest track of
+//! Instruments, under the "fruitbasket" subsystem, to show where startup and
+//! event handling time goes.  Without it, intervals compile to nothing.
+
+#[cfg(feature = "signpost")]
+use std::os::raw::{c_char, c_void};
+#[cfg(feature = "signpost")]
+use std::sync::OnceLock;
+
+#[cfg(feature = "signpost")]
+#[allow(non_camel_case_types)]
+type os_log_t = *mut c_void;
+
+/// OS_SIGNPOST_INTERVAL_BEGIN
+#[cfg(feature = "signpost")]
+const INTERVAL_BEGIN: u8 = 1;
+/// OS_SIGNPOST_INTERVAL_END
+#[cfg(feature = "signpost")]
+const INTERVAL_END: u8 = 2;
+
+#[cfg(feature = "signpost")]
+extern "C" {
+    static __dso_handle: c_void;
+    fn os_log_create(subsystem: *const c_char, category: *const c_char) -> os_log_t;
+    fn os_signpost_enabled(log: os_log_t) -> bool;
+    fn os_signpost_id_generate(log: os_log_t) -> u64;
+    fn _os_signpost_emit_with_name_impl(dso: *const c_void, log: os_log_t, kind: u8, id: u64,
+                                        name: *const c_char, format: *const c_char,
+                                        buf: *mut u8, size: u32);
+}
+
+/// Log handle signposts are emitted to, stored as an address to share it
+#[cfg(feature = "signpost")]
+static LOG: OnceLock<usize> = OnceLock::new();
+
+/// A signpost interval, which ends when dropped
+pub(crate) struct Interval {
+    #[cfg(feature = "signpost")]
+    name: &'static [u8],
+    #[cfg(feature = "signpost")]
+    id: u64,
+}
+
+/// Begin an interval named `name`, which must be nul-terminated
+#[cfg(feature = "signpost")]
+pub(crate) fn begin(name: &'static [u8]) -> Interval {
+    let log = *LOG.get_or_init(|| unsafe {
+        os_log_create(b"fruitbasket\0".as_ptr() as *const c_char,
+                      b"PointsOfInterest\0".as_ptr() as *const c_char) as usize
+    }) as os_log_t;
+    let id = unsafe { os_signpost_id_generate(log) };
+    emit(log, INTERVAL_BEGIN, id, name);
+    Interval { name: name, id: id }
+}
+
+/// Begin an interval, which does nothing without the 'signpost' feature
+#[cfg(not(feature = "signpost"))]
+pub(crate) fn begin(_name: &'static [u8]) -> Interval {
+    Interval {}
+}
+
+#[cfg(feature = "signpost")]
+impl Drop for Interval {
+    fn drop(&mut self) {
+        if let Some(&log) = LOG.get() {
+            emit(log as os_log_t, INTERVAL_END, self.id, self.name);
+        }
+    }
+}
+
+/// Emit a signpost with no message arguments
+#[cfg(feature = "signpost")]
+fn emit(log: os_log_t, kind: u8, id: u64, name: &'static [u8]) {
+    unsafe {
+        if !os_signpost_enabled(log) {
+            return;
+        }
+        // os_log argument buffer header: no flags, no arguments
+        let mut buf = [0u8; 2];
+        _os_signpost_emit_with_name_impl(&__dso_handle as *const c_void, log, kind, id,
+                                         name.as_ptr() as *const c_char,
+                                         b"\0".as_ptr() as *const c_char,
+                                         buf.as_mut_ptr(), buf.len() as u32);
+    }
+}
@@ -0,0 +1,332 @@
+//! Make app content searchable in Spotlight
+//!
+//! Apps can add their own content, such as documents, notes, or contacts
+//! stored in an app-specific database, to the Spotlight index.  Results are
+//! shown under the app's name, and clicking one opens the app, which
+//! receives it as an `NSUserActivity` in the
+//! `application:continueUserActivity:restorationHandler:` callback:
+//!
+//! ```no_run
+//! # use fruitbasket::*;
+//! # use fruitbasket::spotlight::{self, SearchableItem};
+//! # let mut app = FruitApp::new().unwrap();
+//! let mut item = SearchableItem::new("note-42", "Shopping list");
+//! item.keywords = vec!["groceries".into(), "errands".into()];
+//! let _ = spotlight::index_items(&[item]);
+//!
+//! app.register_callback(
+//!     FruitCallbackKey::Method("application:continueUserActivity:restorationHandler:"),
+//!     Box::new(|activity| {
+//!         if let Some(id) = spotlight::parse_activity(activity) {
+//!             println!("Show note {}", id);
+//!         }
+//!     }));
+//! ```
+//!
+//! Items are indexed under the app's bundle identifier, so the app must be
+//! running from a bundle (see [Trampoline](../struct.Trampoline.html)).
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, nsstring_to_string, str_to_nsstring};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "CoreSpotlight", kind = "framework")]
+extern "C" {
+    static CSSearchableItemActionType: *mut Object;
+    static CSSearchableItemActivityIdentifier: *mut Object;
+}
+
+/// A piece of app content to show in Spotlight results
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchableItem {
+    /// Identifier the app uses to find the content again when the user
+    /// clicks the result.  Unique within the app.
+    pub identifier: String,
+    /// Title shown in Spotlight results
+    pub title: String,
+    /// Optional text shown below the title
+    pub description: Option<String>,
+    /// Extra words the item should be found by
+    pub keywords: Vec<String>,
+    /// Optional group the item belongs to, such as a folder or account, so
+    /// a whole group can be removed with
+    /// [delete_domains](delete_domains)
+    pub domain: Option<String>,
+}
+
+impl SearchableItem {
+    /// Create an item with a title and no other details
+    pub fn new(identifier: &str, title: &str) -> SearchableItem {
+        SearchableItem {
+            identifier: identifier.to_string(),
+            title: title.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Add items to the Spotlight index, or update items already in it
+///
+/// Indexing happens in the background, and the items show up in Spotlight a
+/// short time later.  Items stay in the index after the app quits.
+///
+/// # Returns
+///
+/// `FruitError::NotBundled` if the app is not running from a bundle.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn index_items(items: &[SearchableItem]) -> Result<(), FruitError> {
+    let index = default_index()?;
+    let item_cls = class("CSSearchableItem")?;
+    let array_cls = class("NSMutableArray")?;
+    unsafe {
+        let array: *mut Object = msg_send![array_cls, array];
+        for item in items {
+            let attributes = attribute_set(item)?;
+            let domain = item.domain.as_deref().map_or(nil, str_to_nsstring);
+            let searchable: *mut Object = msg_send![item_cls, alloc];
+            let searchable: *mut Object = msg_send![searchable,
+                                                    initWithUniqueIdentifier: str_to_nsstring(&item.identifier)
+                                                    domainIdentifier: domain
+                                                    attributeSet: attributes];
+            let _:() = msg_send![array, addObject: searchable];
+            let _:() = msg_send![searchable, release];
+        }
+        let _:() = msg_send![index, indexSearchableItems: array completionHandler: nil];
+    }
+    Ok(())
+}
+
+/// Remove items from the Spotlight index by identifier
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn delete_items(identifiers: &[&str]) -> Result<(), FruitError> {
+    let index = default_index()?;
+    let array = string_array(identifiers)?;
+    unsafe {
+        let _:() = msg_send![index, deleteSearchableItemsWithIdentifiers: array completionHandler: nil];
+    }
+    Ok(())
+}
+
+/// Remove every item in the given domains from the Spotlight index
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn delete_domains(domains: &[&str]) -> Result<(), FruitError> {
+    let index = default_index()?;
+    let array = string_array(domains)?;
+    unsafe {
+        let _:() = msg_send![index, deleteSearchableItemsWithDomainIdentifiers: array
+                             completionHandler: nil];
+    }
+    Ok(())
+}
+
+/// Remove all of the app's items from the Spotlight index
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn delete_all() -> Result<(), FruitError> {
+    let index = default_index()?;
+    unsafe {
+        let _:() = msg_send![index, deleteAllSearchableItemsWithCompletionHandler: nil];
+    }
+    Ok(())
+}
+
+/// Get the identifier of the item a Spotlight result was opened for
+///
+/// Takes the NSUserActivity passed to the
+/// `application:continueUserActivity:restorationHandler:` callback.  Returns
+/// the item's identifier when the user clicked an item added with
+/// [index_items](index_items), or the identifier of a
+/// [UserActivity](UserActivity).  Returns None for other activities, such as
+/// universal links.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn parse_activity(activity: *mut Object) -> Option<String> {
+    if activity == nil {
+        return None;
+    }
+    unsafe {
+        let kind: *mut Object = msg_send![activity, activityType];
+        let info: *mut Object = msg_send![activity, userInfo];
+        if info == nil {
+            return None;
+        }
+        let key = if nsstring_to_string(kind) == nsstring_to_string(CSSearchableItemActionType) {
+            CSSearchableItemActivityIdentifier
+        } else {
+            str_to_nsstring(ACTIVITY_IDENTIFIER_KEY)
+        };
+        let ident: *mut Object = msg_send![info, objectForKey: key];
+        if ident == nil {
+            return None;
+        }
+        Some(nsstring_to_string(ident))
+    }
+}
+
+/// Key of the item identifier in a `UserActivity`'s userInfo
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ACTIVITY_IDENTIFIER_KEY: &str = "fruitbasketItemIdentifier";
+
+/// The content the user is currently viewing, as a searchable activity
+///
+/// An alternative to [index_items](index_items) for content the user has
+/// looked at, rather than everything the app has: while this exists, it is
+/// the app's current activity, and macOS adds it to Spotlight and suggests
+/// it elsewhere in the system.  Create a new one each time the user views
+/// different content.  It stops being current when dropped, but stays in the
+/// index.
+///
+/// The activity's type is the app's bundle identifier followed by `.view`
+/// (ex: `com.example.myapp.view`).  List it in the bundle's
+/// `NSUserActivityTypes`, such as with
+/// `plist_key("NSUserActivityTypes", "(\"${IDENT}.view\")")`, for the app to
+/// receive the activity when the user opens it again.
+///
+/// Must be created and used on the main thread.
+pub struct UserActivity {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    activity: *mut Object,
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl UserActivity {
+    /// Make `item` the app's current activity
+    ///
+    /// # Returns
+    ///
+    /// `FruitError::NotBundled` if the app is not running from a bundle.
+    pub fn new(item: &SearchableItem) -> Result<UserActivity, FruitError> {
+        let ident = bundle_identifier()?;
+        let activity_cls = class("NSUserActivity")?;
+        let dict_cls = class("NSDictionary")?;
+        let set_cls = class("NSSet")?;
+        let attributes = attribute_set(item)?;
+        unsafe {
+            let activity: *mut Object = msg_send![activity_cls, alloc];
+            let activity: *mut Object = msg_send![activity,
+                                                  initWithActivityType: str_to_nsstring(&format!("{}.view", ident))];
+            let _:() = msg_send![activity, setTitle: str_to_nsstring(&item.title)];
+            let keywords = string_array(&item.keywords.iter().map(|k| k.as_str()).collect::<Vec<_>>())?;
+            let keywords: *mut Object = msg_send![set_cls, setWithArray: keywords];
+            let _:() = msg_send![activity, setKeywords: keywords];
+            let info: *mut Object = msg_send![dict_cls,
+                                              dictionaryWithObject: str_to_nsstring(&item.identifier)
+                                              forKey: str_to_nsstring(ACTIVITY_IDENTIFIER_KEY)];
+            let _:() = msg_send![activity, setUserInfo: info];
+            let _:() = msg_send![activity, setContentAttributeSet: attributes];
+            let _:() = msg_send![activity, setEligibleForSearch: YES];
+            let _:() = msg_send![activity, becomeCurrent];
+            Ok(UserActivity {
+                activity: activity,
+                _not_send: std::marker::PhantomData,
+            })
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for UserActivity {
+    fn drop(&mut self) {
+        unsafe {
+            let _:() = msg_send![self.activity, resignCurrent];
+            let _:() = msg_send![self.activity, release];
+        }
+    }
+}
+
+/// The app's Spotlight index
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn default_index() -> Result<*mut Object, FruitError> {
+    bundle_identifier()?;
+    let cls = class("CSSearchableIndex")?;
+    Ok(unsafe { msg_send![cls, defaultSearchableIndex] })
+}
+
+/// The running app's bundle identifier, which Spotlight files items under
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn bundle_identifier() -> Result<String, FruitError> {
+    let cls = class("NSBundle")?;
+    unsafe {
+        let bundle: *mut Object = msg_send![cls, mainBundle];
+        let ident: *mut Object = msg_send![bundle, bundleIdentifier];
+        if ident == nil {
+            return Err(FruitError::NotBundled);
+        }
+        Ok(nsstring_to_string(ident))
+    }
+}
+
+/// Autoreleased attribute set describing an item
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn attribute_set(item: &SearchableItem) -> Result<*mut Object, FruitError> {
+    let cls = class("CSSearchableItemAttributeSet")?;
+    let keywords = string_array(&item.keywords.iter().map(|k| k.as_str()).collect::<Vec<_>>())?;
+    unsafe {
+        let attributes: *mut Object = msg_send![cls, alloc];
+        let attributes: *mut Object = msg_send![attributes,
+                                                initWithItemContentType: str_to_nsstring("public.content")];
+        let _:() = msg_send![attributes, setTitle: str_to_nsstring(&item.title)];
+        if let Some(ref description) = item.description {
+            let _:() = msg_send![attributes, setContentDescription: str_to_nsstring(description)];
+        }
+        let _:() = msg_send![attributes, setKeywords: keywords];
+        let _: *mut Object = msg_send![attributes, autorelease];
+        Ok(attributes)
+    }
+}
+
+/// Autoreleased NSArray of strings
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn string_array(strings: &[&str]) -> Result<*mut Object, FruitError> {
+    let cls = class("NSMutableArray")?;
+    unsafe {
+        let array: *mut Object = msg_send![cls, array];
+        for s in strings {
+            let _:() = msg_send![array, addObject: str_to_nsstring(s)];
+        }
+        Ok(array)
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn index_items(_items: &[SearchableItem]) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn delete_items(_identifiers: &[&str]) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn delete_domains(_domains: &[&str]) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn delete_all() -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn parse_activity(_activity: *mut u64) -> Option<String> { None }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+impl UserActivity {
+    /// Docs in OS X build.
+    pub fn new(_item: &SearchableItem) -> Result<UserActivity, FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+}
@@ -0,0 +1,458 @@
+//! Menu bar status items
+//!
+//! A status item is the icon or short text an app places on the right side
+//! of the menu bar, and is the main interface of agent (menu bar) apps.  See
+//! [FruitApp::new_agent](../struct.FruitApp.html#method.new_agent) for the
+//! quickest way to set one up.
+//!
+//! Menu bar extras usually show a template image: a black-and-transparent
+//! icon that AppKit tints to match the menu bar, so it stays legible in
+//! light and dark mode and while highlighted.  Bundle the icon as a resource
+//! and show it with
+//! [set_template_image](StatusItem::set_template_image).
+//!
+//! Instead of a menu, a status item can show a popover: a panel with an
+//! arrow pointing at the item, holding a view created by the app, such as
+//! one from another GUI toolkit.  See
+//! [set_popover](StatusItem::set_popover).
+
+use std::os::raw::c_void;
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Class, Object, Sel, BOOL, NO, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, declare_class, str_to_nsstring, NSRect};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// NSVariableStatusItemLength
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const VARIABLE_LENGTH: f64 = -1.0;
+
+/// Height of status item icons, in points, per Apple's guidelines
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ICON_HEIGHT: f64 = 18.0;
+
+/// NSImageLeft: icon to the left of the title, or alone if there is none
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const IMAGE_LEFT: u64 = 2;
+
+/// NSRectEdgeMinY: below the status item
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const EDGE_MIN_Y: u64 = 1;
+
+/// NSSize
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[repr(C)]
+struct NSSize {
+    width: f64,
+    height: f64,
+}
+
+/// When a status item's popover closes by itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopoverBehavior {
+    /// Only closes when the app closes it, or the status item is clicked
+    ApplicationDefined,
+    /// Also closes when the user clicks anywhere outside of it.  The
+    /// standard behavior for menu bar extras.
+    Transient,
+    /// Also closes when the user clicks in another window of the app, but
+    /// not in other apps
+    Semitransient,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl PopoverBehavior {
+    /// Value of the NSPopoverBehavior enum
+    fn to_ns(self) -> i64 {
+        match self {
+            PopoverBehavior::ApplicationDefined => 0,
+            PopoverBehavior::Transient => 1,
+            PopoverBehavior::Semitransient => 2,
+        }
+    }
+}
+
+/// An item in the menu bar's status area
+///
+/// Must be created and used on the main thread, after the `FruitApp`.  The
+/// item is removed from the menu bar when this is dropped.
+pub struct StatusItem {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    item: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    popover: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    popover_target: *mut Object,
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl StatusItem {
+    /// Add an item showing `title` to the menu bar
+    ///
+    /// Returns `FruitError::ClassNotFound` if `NSStatusBar` is missing from
+    /// the ObjC runtime.
+    pub fn new(title: &str) -> Result<StatusItem, FruitError> {
+        let item = unsafe {
+            let cls = class("NSStatusBar")?;
+            let bar: *mut Object = msg_send![cls, systemStatusBar];
+            let item: *mut Object = msg_send![bar, statusItemWithLength: VARIABLE_LENGTH];
+            let _: *mut Object = msg_send![item, retain];
+            item
+        };
+        let status_item = StatusItem {
+            item: item,
+            popover: nil,
+            popover_target: nil,
+            _not_send: std::marker::PhantomData,
+        };
+        status_item.set_title(title);
+        Ok(status_item)
+    }
+
+    /// Change the text shown in the menu bar
+    pub fn set_title(&self, title: &str) {
+        unsafe {
+            let button: *mut Object = msg_send![self.item, button];
+            let _:() = msg_send![button, setTitle: str_to_nsstring(title)];
+        }
+    }
+
+    /// Show a template image from the app bundle's resources
+    ///
+    /// Looks up `name` (ex: `"StatusIcon"`) in the bundle's Resources
+    /// directory like `[NSBundle imageForResource:]`, which finds any image
+    /// format and combines `StatusIcon.png` and `StatusIcon@2x.png` into one
+    /// image for standard and Retina displays.  Only the image's alpha
+    /// channel is used: AppKit draws it in the menu bar's text color, so it
+    /// adapts to light and dark menu bars automatically.
+    ///
+    /// Can be called again at any time to swap the icon, such as to show the
+    /// app's state.  The icon is scaled to the menu bar's icon height and
+    /// shown to the left of the title; set an empty title for an icon-only
+    /// item.
+    ///
+    /// # Returns
+    ///
+    /// `FruitError::IOError` with kind `NotFound` if the bundle has no such
+    /// image.
+    pub fn set_template_image(&self, name: &str) -> Result<(), FruitError> {
+        let bundle_cls = class("NSBundle")?;
+        let image = unsafe {
+            let bundle: *mut Object = msg_send![bundle_cls, mainBundle];
+            let image: *mut Object = msg_send![bundle, imageForResource: str_to_nsstring(name)];
+            image
+        };
+        if image == nil {
+            return Err(not_found(name));
+        }
+        self.show_image(image, true);
+        Ok(())
+    }
+
+    /// Show an image loaded from a file
+    ///
+    /// Like [set_template_image](StatusItem::set_template_image), but for
+    /// images outside the app bundle, in any format supported by NSImage.
+    /// Set `template` for a monochrome icon that adapts to the menu bar's
+    /// appearance, or clear it to show a full-color image as-is.
+    ///
+    /// # Returns
+    ///
+    /// `FruitError::IOError` with kind `NotFound` if the file can't be loaded
+    /// as an image.
+    pub fn set_image_file(&self, path: &str, template: bool) -> Result<(), FruitError> {
+        let image_cls = class("NSImage")?;
+        let image = unsafe {
+            let image: *mut Object = msg_send![image_cls, alloc];
+            let image: *mut Object = msg_send![image, initWithContentsOfFile: str_to_nsstring(path)];
+            image
+        };
+        if image == nil {
+            return Err(not_found(path));
+        }
+        self.show_image(image, template);
+        unsafe {
+            let _:() = msg_send![image, release];
+        }
+        Ok(())
+    }
+
+    /// Remove the image, leaving only the title
+    pub fn clear_image(&self) {
+        unsafe {
+            let button: *mut Object = msg_send![self.item, button];
+            let _:() = msg_send![button, setImage: nil];
+        }
+    }
+
+    /// Scale `image` to the menu bar and show it in the button
+    fn show_image(&self, image: *mut Object, template: bool) {
+        unsafe {
+            let size: NSSize = msg_send![image, size];
+            if size.height > 0. {
+                let size = NSSize {
+                    width: size.width * ICON_HEIGHT / size.height,
+                    height: ICON_HEIGHT,
+                };
+                let _:() = msg_send![image, setSize: size];
+            }
+            let template: BOOL = if template { YES } else { NO };
+            let _:() = msg_send![image, setTemplate: template];
+            let button: *mut Object = msg_send![self.item, button];
+            let _:() = msg_send![button, setImage: image];
+            let _:() = msg_send![button, setImagePosition: IMAGE_LEFT];
+        }
+    }
+
+    /// Show a view in a popover when the status item is clicked
+    ///
+    /// The popover hangs below the status item, sized to `width` by `height`
+    /// points, and shows `view` as its content.  Clicking the status item
+    /// opens it, or closes it if already open; it can also be opened and
+    /// closed with [show_popover](StatusItem::show_popover) and
+    /// [hide_popover](StatusItem::hide_popover).  The popover is
+    /// [Transient](PopoverBehavior::Transient) by default.
+    ///
+    /// Replaces any earlier popover.  A status item with a menu shows the
+    /// menu when clicked instead, so don't add menu items as well.
+    ///
+    /// # Safety
+    ///
+    /// `view` must point to a valid `NSView` that isn't in another window
+    /// or view.  The popover retains it.
+    pub unsafe fn set_popover(&mut self, view: *mut c_void, width: f64, height: f64)
+                              -> Result<(), FruitError> {
+        let popover_cls = class("NSPopover")?;
+        let controller_cls = class("NSViewController")?;
+        let target_cls = register_popover_target_class()?;
+        self.remove_popover();
+
+        let controller: *mut Object = msg_send![controller_cls, new];
+        let _:() = msg_send![controller, setView: view as *mut Object];
+        let popover: *mut Object = msg_send![popover_cls, new];
+        let _:() = msg_send![popover, setContentViewController: controller];
+        let _:() = msg_send![controller, release];
+        let _:() = msg_send![popover, setContentSize: NSSize { width, height }];
+        let _:() = msg_send![popover, setBehavior: PopoverBehavior::Transient.to_ns()];
+
+        let target: *mut Object = msg_send![target_cls, new];
+        (*target).set_ivar("_popover", popover as *const c_void);
+        let button: *mut Object = msg_send![self.item, button];
+        let _:() = msg_send![button, setTarget: target];
+        let _:() = msg_send![button, setAction: sel!(togglePopover:)];
+
+        self.popover = popover;
+        self.popover_target = target;
+        Ok(())
+    }
+
+    /// Remove the popover, closing it if open
+    pub fn remove_popover(&mut self) {
+        if self.popover == nil {
+            return;
+        }
+        unsafe {
+            let button: *mut Object = msg_send![self.item, button];
+            let _:() = msg_send![button, setTarget: nil];
+            let _:() = msg_send![button, setAction: Sel::from_ptr(std::ptr::null())];
+            let _:() = msg_send![self.popover, close];
+            let _:() = msg_send![self.popover, release];
+            let _:() = msg_send![self.popover_target, release];
+        }
+        self.popover = nil;
+        self.popover_target = nil;
+    }
+
+    /// Open the popover below the status item
+    ///
+    /// Also activates the app, so the popover's view receives keyboard
+    /// input and a transient popover closes when the user clicks elsewhere.
+    /// Does nothing if there is no popover.
+    pub fn show_popover(&self) {
+        if self.popover == nil {
+            return;
+        }
+        unsafe {
+            let button: *mut Object = msg_send![self.item, button];
+            show_popover(self.popover, button);
+        }
+    }
+
+    /// Close the popover
+    pub fn hide_popover(&self) {
+        if self.popover == nil {
+            return;
+        }
+        unsafe {
+            let _:() = msg_send![self.popover, performClose: nil];
+        }
+    }
+
+    /// Returns whether the popover is open
+    pub fn is_popover_shown(&self) -> bool {
+        if self.popover == nil {
+            return false;
+        }
+        unsafe {
+            let shown: BOOL = msg_send![self.popover, isShown];
+            shown == YES
+        }
+    }
+
+    /// Set when the popover closes without being told to
+    ///
+    /// Does nothing if there is no popover.
+    pub fn set_popover_behavior(&self, behavior: PopoverBehavior) {
+        if self.popover == nil {
+            return;
+        }
+        unsafe {
+            let _:() = msg_send![self.popover, setBehavior: behavior.to_ns()];
+        }
+    }
+
+    /// Add an item to the status item's menu that quits the app
+    ///
+    /// Clicking the status item shows its menu, which is created when the
+    /// first item is added.  The quit item also responds to Cmd-Q while the
+    /// menu is open.
+    ///
+    /// Returns `FruitError::ClassNotFound` if `NSMenu` or `NSMenuItem` is
+    /// missing from the ObjC runtime.
+    pub fn add_quit_item(&self, title: &str) -> Result<(), FruitError> {
+        unsafe {
+            let mut menu: *mut Object = msg_send![self.item, menu];
+            if menu == nil {
+                let cls = class("NSMenu")?;
+                menu = msg_send![cls, new];
+                let _:() = msg_send![self.item, setMenu: menu];
+                let _:() = msg_send![menu, release];
+            }
+            let cls = class("NSMenuItem")?;
+            let entry: *mut Object = msg_send![cls, alloc];
+            let entry: *mut Object = msg_send![entry,
+                                               initWithTitle: str_to_nsstring(title)
+                                               action: sel!(terminate:)
+                                               keyEquivalent: str_to_nsstring("q")];
+            let _:() = msg_send![menu, addItem: entry];
+            let _:() = msg_send![entry, release];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for StatusItem {
+    fn drop(&mut self) {
+        self.remove_popover();
+        unsafe {
+            if let Ok(cls) = class("NSStatusBar") {
+                let bar: *mut Object = msg_send![cls, systemStatusBar];
+                let _:() = msg_send![bar, removeStatusItem: self.item];
+            }
+            let _:() = msg_send![self.item, release];
+        }
+    }
+}
+
+/// Open `popover` below `button`, activating the app
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn show_popover(popover: *mut Object, button: *mut Object) {
+    let app_cls = match class("NSApplication") {
+        Ok(cls) => cls,
+        Err(e) => {
+            info!("Can't show popover: {}", e);
+            return;
+        },
+    };
+    let app: *mut Object = msg_send![app_cls, sharedApplication];
+    let _:() = msg_send![app, activateIgnoringOtherApps: YES];
+    let bounds: NSRect = msg_send![button, bounds];
+    let _:() = msg_send![popover, showRelativeToRect: bounds ofView: button preferredEdge: EDGE_MIN_Y];
+}
+
+/// Register the class that toggles a popover when its status item is clicked
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn register_popover_target_class() -> Result<&'static Class, FruitError> {
+    declare_class("FruitPopoverTarget", |decl| {
+        decl.add_ivar::<*const c_void>("_popover");
+
+        /// Action of the status item's button
+        extern "C" fn objc_toggle_popover(this: &Object, _cmd: Sel, sender: *mut Object) {
+            unsafe {
+                let popover: *const c_void = *this.get_ivar("_popover");
+                let popover = popover as *mut Object;
+                if popover == nil {
+                    return;
+                }
+                let shown: BOOL = msg_send![popover, isShown];
+                if shown == YES {
+                    let _:() = msg_send![popover, performClose: sender];
+                } else {
+                    show_popover(popover, sender);
+                }
+            }
+        }
+        unsafe {
+            let f: extern "C" fn(&Object, Sel, *mut Object) = objc_toggle_popover;
+            decl.add_method(sel!(togglePopover:), f);
+        }
+    })
+}
+
+/// Error for an image that couldn't be found or loaded
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn not_found(name: &str) -> FruitError {
+    FruitError::IOError(std::io::Error::new(std::io::ErrorKind::NotFound,
+                                            format!("no image at {}", name)))
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+impl StatusItem {
+    /// Docs in OS X build.
+    pub fn new(_title: &str) -> Result<StatusItem, FruitError> {
+        Ok(StatusItem { _not_send: std::marker::PhantomData })
+    }
+    /// Docs in OS X build.
+    pub fn set_title(&self, _title: &str) {}
+    /// Docs in OS X build.
+    pub fn add_quit_item(&self, _title: &str) -> Result<(), FruitError> {
+        Ok(())
+    }
+    /// Docs in OS X build.
+    pub fn set_template_image(&self, _name: &str) -> Result<(), FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+    /// Docs in OS X build.
+    pub fn set_image_file(&self, _path: &str, _template: bool) -> Result<(), FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+    /// Docs in OS X build.
+    pub fn clear_image(&self) {}
+    /// Docs in OS X build.
+    ///
+    /// # Safety
+    ///
+    /// See the OS X build.
+    pub unsafe fn set_popover(&mut self, _view: *mut c_void, _width: f64, _height: f64)
+                              -> Result<(), FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+    /// Docs in OS X build.
+    pub fn remove_popover(&mut self) {}
+    /// Docs in OS X build.
+    pub fn show_popover(&self) {}
+    /// Docs in OS X build.
+    pub fn hide_popover(&self) {}
+    /// Docs in OS X build.
+    pub fn is_popover_shown(&self) -> bool { false }
+    /// Docs in OS X build.
+    pub fn set_popover_behavior(&self, _behavior: PopoverBehavior) {}
+}
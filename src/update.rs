@@ -0,0 +1,553 @@
+//! Replace the running app with a newer version
+//!
+//! A minimal alternative to embedding Sparkle: the app finds and downloads an
+//! update however it likes, unpacks it to a new `.app` bundle, and passes
+//! that bundle to [install_update](install_update).  The new bundle is
+//! checked against the running one, swapped into its place in a single
+//! atomic rename, and launched in place of the current process.
+//!
+//! Verification requires the new bundle to have the same bundle identifier
+//! as the running app.  If the running app is code signed, the new bundle
+//! must also be validly signed and satisfy the running app's designated
+//! requirement, which ties it to the same developer identity.
+//!
+//! Updates can be fetched with [download](download), which uses the system's
+//! URL loading instead of bundling a TLS stack, so it follows the app's App
+//! Transport Security settings and the user's proxy configuration:
+//!
+//! ```no_run
+//! # use fruitbasket::*;
+//! # use std::path::Path;
+//! let mut app = FruitApp::new().unwrap();
+//! let _download = update::download("https://example.com/MyApp.zip",
+//!                                  Path::new("/tmp/MyApp.zip"), false,
+//!                                  Box::new(|event| println!("{:?}", event))).unwrap();
+//! app.run(RunPeriod::Forever).unwrap();
+//! ```
+
+use std::path::{Path, PathBuf};
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::cell::Cell;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::c_void;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Class, Object, Sel, BOOL, NO};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, declare_class, is_main_thread, nsstring_to_string, str_to_nsstring};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use relocate::{bundle_path, relaunch, run_tool};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// Swap the two paths atomically, for renamex_np()
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const RENAME_SWAP: u32 = 0x2;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" {
+    fn renamex_np(from: *const libc::c_char, to: *const libc::c_char, flags: u32) -> libc::c_int;
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "Foundation", kind = "framework")]
+extern "C" {
+    static NSURLSessionDownloadTaskResumeData: *mut Object;
+}
+
+/// NSURLSessionTransferSizeUnknown
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const TRANSFER_SIZE_UNKNOWN: i64 = -1;
+
+/// Progress and result of a [download](download)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadEvent {
+    /// More of the file was received
+    Progress {
+        /// Bytes received so far
+        received: u64,
+        /// Size of the whole file, if the server reported it
+        total: Option<u64>,
+    },
+    /// The file was downloaded and moved to the destination path
+    Finished(PathBuf),
+    /// The download failed or was cancelled
+    Failed {
+        /// Description of the failure
+        error: String,
+        /// Data to continue the download from where it stopped with
+        /// [resume_download](resume_download), if the server supports it.
+        /// Can be saved to continue after the app relaunches.
+        resume_data: Option<Vec<u8>>,
+    },
+}
+
+/// A boxed Fn type for receiving [DownloadEvent](DownloadEvent)s
+pub type DownloadCallback = Box<dyn Fn(&DownloadEvent)>;
+
+/// Destination and callback of a download, shared with its session delegate
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+struct DownloadState {
+    dest: PathBuf,
+    callback: DownloadCallback,
+    finished: Cell<bool>,
+}
+
+/// A download in progress
+///
+/// Returned by [download](download).  The download is cancelled if this is
+/// dropped before it finishes.
+pub struct Download {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    session: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    task: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    delegate: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    _state: Box<DownloadState>,
+    #[cfg(any(not(target_os = "macos"), feature="dummy"))]
+    _not_send: std::marker::PhantomData<*mut u8>,
+}
+
+impl Download {
+    /// Pause the download until [resume](Download::resume) is called
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    pub fn suspend(&self) {
+        unsafe {
+            let _:() = msg_send![self.task, suspend];
+        }
+    }
+
+    /// Continue a download paused with [suspend](Download::suspend)
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    pub fn resume(&self) {
+        unsafe {
+            let _:() = msg_send![self.task, resume];
+        }
+    }
+
+    /// Stop the download
+    ///
+    /// The callback receives `DownloadEvent::Failed` once the download has
+    /// stopped.
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    pub fn cancel(&self) {
+        unsafe {
+            let _:() = msg_send![self.task, cancel];
+        }
+    }
+
+    /// Docs in OS X build.
+    #[cfg(any(not(target_os = "macos"), feature="dummy"))]
+    pub fn suspend(&self) {}
+
+    /// Docs in OS X build.
+    #[cfg(any(not(target_os = "macos"), feature="dummy"))]
+    pub fn resume(&self) {}
+
+    /// Docs in OS X build.
+    #[cfg(any(not(target_os = "macos"), feature="dummy"))]
+    pub fn cancel(&self) {}
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for Download {
+    fn drop(&mut self) {
+        unsafe {
+            // The session reports the cancellation after the state is gone
+            (*self.delegate).set_ivar("_state", std::ptr::null::<c_void>());
+            let _:() = msg_send![self.session, invalidateAndCancel];
+            let _:() = msg_send![self.task, release];
+            let _:() = msg_send![self.delegate, release];
+        }
+    }
+}
+
+/// Check that a bundle is a valid update for the running app
+///
+/// Performs the same checks as [install_update](install_update) without
+/// installing anything, so apps can verify a download before asking the user
+/// to restart.
+///
+/// # Returns
+///
+/// `FruitError::NotBundled` if the running app is not in a bundle.
+/// `FruitError::UpdateError` if `new_app` has a different bundle identifier,
+/// or fails code signature verification.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn verify_update(new_app: &Path) -> Result<(), FruitError> {
+    let current = bundle_path().ok_or(FruitError::NotBundled)?;
+    let ident = bundle_identifier(&current);
+    let new_ident = bundle_identifier(new_app);
+    if new_ident.is_none() || new_ident != ident {
+        return Err(FruitError::UpdateError(
+            format!("bundle identifier {:?} does not match {:?}", new_ident, ident)));
+    }
+    if let Some(requirement) = ::bundle::designated_requirement(&current) {
+        let sig = ::bundle::verify(new_app, Some(&requirement))
+            .map_err(|e| FruitError::UpdateError(format!("code signature rejected: {}", e)))?;
+        if !sig.valid {
+            return Err(FruitError::UpdateError("code signature rejected: invalid signature".into()));
+        }
+        if sig.satisfies_requirement != Some(true) {
+            return Err(FruitError::UpdateError(
+                format!("code signature rejected: does not satisfy {}", requirement)));
+        }
+    }
+    Ok(())
+}
+
+/// Install a new version of the app and relaunch into it
+///
+/// Verifies `new_app` with [verify_update](verify_update), copies it next to
+/// the running bundle, removes its quarantine flag, and atomically swaps it
+/// into place.  The old version is deleted, the new version is launched once
+/// this process exits, and the current process exits.  This function only
+/// returns on error, in which case the installed app is left untouched.
+///
+/// `new_app` itself is left in place; delete it afterwards if it was a
+/// temporary download.
+///
+/// # Arguments
+///
+/// `new_app` - Path to the `.app` bundle of the new version
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn install_update(new_app: &Path) -> Result<(), FruitError> {
+    verify_update(new_app)?;
+    let current = bundle_path().ok_or(FruitError::NotBundled)?;
+    let parent = current.parent().unwrap_or(Path::new("/"));
+    let name = current.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let staging = parent.join(format!(".{}.update", name));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    info!("Staging update from {:?} at {:?}", new_app, staging);
+    run_tool("/usr/bin/ditto", &[new_app.as_os_str(), staging.as_os_str()])?;
+    let _ = run_tool("/usr/bin/xattr",
+                     &["-d".as_ref(), "-r".as_ref(), "com.apple.quarantine".as_ref(),
+                       staging.as_os_str()]);
+    if let Err(e) = swap(&staging, &current) {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(FruitError::UpdateError(format!("failed to replace app: {}", e)));
+    }
+    // The staging path now holds the old version
+    let _ = std::fs::remove_dir_all(&staging);
+    info!("Update installed.  Relaunching.");
+    relaunch(&current)?;
+    std::process::exit(0);
+}
+
+/// Download a file, such as an update, in the background
+///
+/// Calls `cb` on the main thread as the download progresses, while the
+/// app's run loop is running, and finally with either
+/// `DownloadEvent::Finished` or `DownloadEvent::Failed`.  The finished file
+/// is moved to `dest`, replacing any file already there.  Downloads that
+/// fail with an HTTP error status are reported as failed, and nothing is
+/// written to `dest`.
+///
+/// Requests go through NSURLSession, so they use the system's certificate
+/// trust, proxy settings, and App Transport Security policy from the app's
+/// Info.plist.
+///
+/// # Arguments
+///
+/// `url` - Address of the file to download
+///
+/// `dest` - Path to move the file to once finished
+///
+/// `background` - Whether to use a background session, named after the
+/// app's bundle identifier, which lets the system schedule the transfer and
+/// keep it going while the app is busy.  Only one background download can
+/// run at a time.  Requires the app to be bundled.
+///
+/// `cb` - Receives progress and the result
+///
+/// # Returns
+///
+/// A [Download](Download) that must be kept alive until the download
+/// finishes.  `FruitError::MainThreadViolation` if not called from the main
+/// thread, `FruitError::NotBundled` for a background download from an
+/// unbundled app, or `FruitError::UpdateError` if `url` is invalid.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn download(url: &str, dest: &Path, background: bool,
+                cb: DownloadCallback) -> Result<Download, FruitError> {
+    let cls = class("NSURL")?;
+    let nsurl: *mut Object = unsafe { msg_send![cls, URLWithString: str_to_nsstring(url)] };
+    if nsurl == nil {
+        return Err(FruitError::UpdateError(format!("invalid URL: {}", url)));
+    }
+    start_download(dest, background, cb, |session| unsafe {
+        msg_send![session, downloadTaskWithURL: nsurl]
+    })
+}
+
+/// Continue a failed or cancelled download from where it stopped
+///
+/// Works the same as [download](download), starting from the
+/// `resume_data` of a `DownloadEvent::Failed`.
+///
+/// # Returns
+///
+/// `FruitError::UpdateError` if the resume data is invalid.  See
+/// [download](download) for other errors.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn resume_download(resume_data: &[u8], dest: &Path, background: bool,
+                       cb: DownloadCallback) -> Result<Download, FruitError> {
+    let cls = class("NSData")?;
+    let data: *mut Object = unsafe {
+        msg_send![cls, dataWithBytes: resume_data.as_ptr() length: resume_data.len()]
+    };
+    start_download(dest, background, cb, |session| unsafe {
+        msg_send![session, downloadTaskWithResumeData: data]
+    })
+}
+
+/// Create a session delivering to the main thread, and start the download
+/// task made by `make_task`
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn start_download<F>(dest: &Path, background: bool, cb: DownloadCallback,
+                     make_task: F) -> Result<Download, FruitError>
+    where F: FnOnce(*mut Object) -> *mut Object {
+    if !is_main_thread() {
+        return Err(FruitError::MainThreadViolation);
+    }
+    let config_cls = class("NSURLSessionConfiguration")?;
+    let session_cls = class("NSURLSession")?;
+    let queue_cls = class("NSOperationQueue")?;
+    let delegate_cls = register_download_delegate_class()?;
+    let config: *mut Object = if background {
+        let current = bundle_path().ok_or(FruitError::NotBundled)?;
+        let ident = bundle_identifier(&current).ok_or(FruitError::NotBundled)?;
+        let ident = str_to_nsstring(&format!("{}.download", ident));
+        unsafe { msg_send![config_cls, backgroundSessionConfigurationWithIdentifier: ident] }
+    } else {
+        unsafe { msg_send![config_cls, defaultSessionConfiguration] }
+    };
+    let state = Box::new(DownloadState {
+        dest: dest.to_path_buf(),
+        callback: cb,
+        finished: Cell::new(false),
+    });
+    unsafe {
+        let delegate: *mut Object = msg_send![delegate_cls, new];
+        (*delegate).set_ivar("_state", &*state as *const DownloadState as *const c_void);
+        let queue: *mut Object = msg_send![queue_cls, mainQueue];
+        let session: *mut Object = msg_send![session_cls,
+                                             sessionWithConfiguration: config
+                                             delegate: delegate
+                                             delegateQueue: queue];
+        let task = make_task(session);
+        if task == nil {
+            let _:() = msg_send![session, invalidateAndCancel];
+            let _:() = msg_send![delegate, release];
+            return Err(FruitError::UpdateError("failed to start download".into()));
+        }
+        let task: *mut Object = msg_send![task, retain];
+        let _:() = msg_send![task, resume];
+        Ok(Download {
+            session: session,
+            task: task,
+            delegate: delegate,
+            _state: state,
+        })
+    }
+}
+
+/// Register the NSURLSession delegate class that reports to a `Download`'s
+/// callback
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn register_download_delegate_class() -> Result<&'static Class, FruitError> {
+    declare_class("FruitDownloadDelegate", |decl| {
+        decl.add_ivar::<*const c_void>("_state");
+        unsafe {
+            let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object, i64, i64, i64) =
+                objc_did_write_data;
+            decl.add_method(sel!(URLSession:downloadTask:didWriteData:totalBytesWritten:totalBytesExpectedToWrite:), f);
+            let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object, *mut Object) =
+                objc_did_finish_downloading;
+            decl.add_method(sel!(URLSession:downloadTask:didFinishDownloadingToURL:), f);
+            let f: extern "C" fn(&Object, Sel, *mut Object, *mut Object, *mut Object) =
+                objc_did_complete;
+            decl.add_method(sel!(URLSession:task:didCompleteWithError:), f);
+        }
+    })
+}
+
+/// State of the `Download` a delegate reports to, or None once it is dropped
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn download_state(this: &Object) -> Option<&DownloadState> {
+    unsafe {
+        let raw: *const c_void = *this.get_ivar("_state");
+        (raw as *const DownloadState).as_ref()
+    }
+}
+
+/// Reports download progress
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn objc_did_write_data(this: &Object, _cmd: Sel, _session: *mut Object,
+                                  _task: *mut Object, _written: i64, total_written: i64,
+                                  expected: i64) {
+    if let Some(state) = download_state(this) {
+        (state.callback)(&DownloadEvent::Progress {
+            received: total_written.max(0) as u64,
+            total: if expected == TRANSFER_SIZE_UNKNOWN { None } else { Some(expected.max(0) as u64) },
+        });
+    }
+}
+
+/// Moves the finished file to its destination.  The system deletes it once
+/// this returns.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn objc_did_finish_downloading(this: &Object, _cmd: Sel, _session: *mut Object,
+                                          task: *mut Object, location: *mut Object) {
+    let state = match download_state(this) {
+        Some(state) => state,
+        None => return,
+    };
+    state.finished.set(true);
+    let status = http_status(task);
+    let result = if status >= 400 {
+        Err(format!("HTTP status {}", status))
+    } else {
+        ::foundation::nsurl_to_path(location)
+            .ok_or_else(|| "download location missing".to_string())
+            .and_then(|tmp| move_file(&tmp, &state.dest).map_err(|e| e.to_string()))
+    };
+    let event = match result {
+        Ok(()) => DownloadEvent::Finished(state.dest.clone()),
+        Err(error) => DownloadEvent::Failed { error: error, resume_data: None },
+    };
+    (state.callback)(&event);
+}
+
+/// Reports failures.  Successful downloads were already reported when the
+/// file was moved into place.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn objc_did_complete(this: &Object, _cmd: Sel, _session: *mut Object,
+                                _task: *mut Object, error: *mut Object) {
+    let state = match download_state(this) {
+        Some(state) => state,
+        None => return,
+    };
+    if error == nil || state.finished.get() {
+        return;
+    }
+    unsafe {
+        let desc: *mut Object = msg_send![error, localizedDescription];
+        let info: *mut Object = msg_send![error, userInfo];
+        let data: *mut Object = if info == nil {
+            nil
+        } else {
+            msg_send![info, objectForKey: NSURLSessionDownloadTaskResumeData]
+        };
+        let resume_data = if data == nil {
+            None
+        } else {
+            let len: usize = msg_send![data, length];
+            let bytes: *const u8 = msg_send![data, bytes];
+            Some(if len == 0 { Vec::new() } else { std::slice::from_raw_parts(bytes, len).to_vec() })
+        };
+        (state.callback)(&DownloadEvent::Failed {
+            error: nsstring_to_string(desc),
+            resume_data: resume_data,
+        });
+    }
+}
+
+/// HTTP status code of a task's response, or 0 if it wasn't HTTP
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn http_status(task: *mut Object) -> i64 {
+    unsafe {
+        let response: *mut Object = msg_send![task, response];
+        if response == nil {
+            return 0;
+        }
+        let is_http: BOOL = msg_send![response, respondsToSelector: sel!(statusCode)];
+        if is_http == NO {
+            return 0;
+        }
+        msg_send![response, statusCode]
+    }
+}
+
+/// Move a file, copying it if it's on another volume
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if to.exists() {
+        std::fs::remove_file(to)?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        let _ = std::fs::remove_file(from);
+    }
+    Ok(())
+}
+
+/// Exchange two paths, atomically if the filesystem supports it
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn swap(a: &Path, b: &Path) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    let to_c = |path: &Path| std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+    let (ca, cb) = (to_c(a)?, to_c(b)?);
+    if unsafe { renamex_np(ca.as_ptr(), cb.as_ptr(), RENAME_SWAP) } == 0 {
+        return Ok(());
+    }
+    info!("Atomic swap failed ({}).  Swapping with renames.",
+          std::io::Error::last_os_error());
+    let backup = PathBuf::from(format!("{}.old", b.display()));
+    std::fs::rename(b, &backup)?;
+    if let Err(e) = std::fs::rename(a, b) {
+        let _ = std::fs::rename(&backup, b);
+        return Err(e);
+    }
+    std::fs::rename(&backup, a)
+}
+
+/// Bundle identifier from a bundle's Info.plist
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn bundle_identifier(path: &Path) -> Option<String> {
+    let cls = class("NSBundle").ok()?;
+    unsafe {
+        let bundle: *mut Object = msg_send![cls, bundleWithPath: str_to_nsstring(&path.to_string_lossy())];
+        if bundle == nil {
+            return None;
+        }
+        let ident: *mut Object = msg_send![bundle, bundleIdentifier];
+        if ident == nil {
+            return None;
+        }
+        Some(nsstring_to_string(ident))
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn verify_update(_new_app: &Path) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn install_update(_new_app: &Path) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn download(_url: &str, _dest: &Path, _background: bool,
+                _cb: DownloadCallback) -> Result<Download, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn resume_download(_resume_data: &[u8], _dest: &Path, _background: bool,
+                       _cb: DownloadCallback) -> Result<Download, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
@@ -0,0 +1,85 @@
+//! Self-pipe that tells external event loops when the app has work to do
+//!
+//! Each `FruitApp` owns the read end of a socket pair, and the `FruitStopper`
+//! and `FruitHandle` objects it hands out write a byte to the other end with
+//! every request.  On macOS, an observer on the main run loop also writes
+//! one each time the run loop wakes from waiting.  An external reactor polls
+//! the read end, and pumps the app when it becomes readable.
+
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::sync::Arc;
+
+/// Write end of the pipe, shared by every stopper and handle
+#[derive(Clone)]
+pub(crate) struct Waker {
+    #[cfg(unix)]
+    stream: Option<Arc<UnixStream>>,
+}
+
+/// Read end of the pipe, owned by the `FruitApp`
+pub(crate) struct WakeReceiver {
+    #[cfg(unix)]
+    stream: Option<UnixStream>,
+}
+
+/// Create a connected waker and receiver
+///
+/// If the socket pair can't be created, waking does nothing and the
+/// receiver's descriptor is -1.
+#[cfg(unix)]
+pub(crate) fn pair() -> (Waker, WakeReceiver) {
+    let streams = UnixStream::pair().and_then(|(tx, rx)| {
+        tx.set_nonblocking(true)?;
+        rx.set_nonblocking(true)?;
+        Ok((tx, rx))
+    });
+    match streams {
+        Ok((tx, rx)) => (Waker { stream: Some(Arc::new(tx)) }, WakeReceiver { stream: Some(rx) }),
+        Err(_) => (Waker { stream: None }, WakeReceiver { stream: None }),
+    }
+}
+
+/// Create a waker and receiver, which do nothing on this platform
+#[cfg(not(unix))]
+pub(crate) fn pair() -> (Waker, WakeReceiver) {
+    (Waker {}, WakeReceiver {})
+}
+
+impl Waker {
+    /// Make the receiver readable
+    ///
+    /// A full pipe is already readable, so a failed write is ignored.
+    pub(crate) fn wake(&self) {
+        #[cfg(unix)]
+        if let Some(ref stream) = self.stream {
+            let _ = (&**stream).write(&[1]);
+        }
+    }
+}
+
+impl WakeReceiver {
+    /// Descriptor to poll for readability
+    #[cfg(unix)]
+    pub(crate) fn fd(&self) -> RawFd {
+        self.stream.as_ref().map_or(-1, |stream| stream.as_raw_fd())
+    }
+
+    /// Read every pending wake-up, so the descriptor is no longer readable
+    pub(crate) fn drain(&self) {
+        #[cfg(unix)]
+        if let Some(ref stream) = self.stream {
+            let mut buf = [0u8; 64];
+            while let Ok(n) = (&*stream).read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+    }
+}
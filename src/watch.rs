@@ -0,0 +1,249 @@
+//! Watching directories for changes
+//!
+//! [watch](watch) reports files created, removed, renamed, or modified
+//! anywhere under a directory, such as the app's Application Support
+//! directory or a folder of documents the user opened.  Changes are read
+//! from FSEvents on the main run loop, so callbacks run on the main thread
+//! while the `FruitApp` is running, the same as every other callback, with
+//! no extra thread or event loop.
+//!
+//! ```no_run
+//! # use fruitbasket::*;
+//! # use std::path::Path;
+//! let mut app = FruitApp::new().unwrap();
+//! let _watcher = watch::watch(Path::new("/tmp"), Box::new(|event| {
+//!     println!("{} changed", event.path.display());
+//! })).unwrap();
+//! app.run(RunPeriod::Forever).unwrap();
+//! ```
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, is_main_thread, str_to_nsstring};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::{c_char, c_void};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::rc::Rc;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+/// kFSEventStreamEventIdSinceNow
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const SINCE_NOW: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+/// kFSEventStreamCreateFlagNoDefer | kFSEventStreamCreateFlagFileEvents
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const CREATE_FLAGS: u32 = 0x02 | 0x10;
+/// Seconds FSEvents waits to batch up changes before reporting them
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const LATENCY: f64 = 0.1;
+
+/// kFSEventStreamEventFlagMustScanSubDirs
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FLAG_MUST_SCAN: u32 = 0x1;
+/// kFSEventStreamEventFlagItemCreated
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FLAG_CREATED: u32 = 0x100;
+/// kFSEventStreamEventFlagItemRemoved
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FLAG_REMOVED: u32 = 0x200;
+/// kFSEventStreamEventFlagItemRenamed
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FLAG_RENAMED: u32 = 0x800;
+/// kFSEventStreamEventFlagItemModified | kFSEventStreamEventFlagItemInodeMetaMod
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FLAG_MODIFIED: u32 = 0x1000 | 0x400;
+/// kFSEventStreamEventFlagItemIsDir
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const FLAG_IS_DIR: u32 = 0x20000;
+
+/// A change to a file or directory under a watched directory
+///
+/// FSEvents coalesces changes made in quick succession, so more than one
+/// of the flags can be set, and a file that was created and then removed
+/// has both `created` and `removed` set.  Check whether the path still
+/// exists to tell where it ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// Absolute path of the file or directory that changed
+    pub path: PathBuf,
+    /// Whether the path is a directory
+    pub is_dir: bool,
+    /// The file was created
+    pub created: bool,
+    /// The file was removed
+    pub removed: bool,
+    /// The file was renamed, or moved in or out of the directory.  Both the
+    /// old and new paths are reported.
+    pub renamed: bool,
+    /// The file's contents or metadata were modified
+    pub modified: bool,
+    /// Changes were lost, and everything under `path` must be rescanned
+    pub rescan: bool,
+}
+
+/// A boxed Fn type for receiving changes found by [watch](watch)
+pub type WatchCallback = Box<dyn Fn(&WatchEvent)>;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+type FSEventStreamCallback = extern "C" fn(*mut c_void, *mut c_void, usize, *mut c_void,
+                                           *const u32, *const u64);
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[repr(C)]
+struct FSEventStreamContext {
+    version: isize,
+    info: *mut c_void,
+    retain: Option<extern "C" fn(*const c_void) -> *const c_void>,
+    release: Option<extern "C" fn(*const c_void)>,
+    copy_description: *const c_void,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "CoreServices", kind = "framework")]
+extern "C" {
+    static kCFRunLoopCommonModes: *mut Object;
+    fn CFRunLoopGetMain() -> *mut Object;
+    fn FSEventStreamCreate(allocator: *mut Object, callback: FSEventStreamCallback,
+                           context: *mut FSEventStreamContext, paths: *mut Object,
+                           since_when: u64, latency: f64, flags: u32) -> *mut c_void;
+    fn FSEventStreamScheduleWithRunLoop(stream: *mut c_void, run_loop: *mut Object,
+                                        mode: *mut Object);
+    fn FSEventStreamStart(stream: *mut c_void) -> u8;
+    fn FSEventStreamStop(stream: *mut c_void);
+    fn FSEventStreamInvalidate(stream: *mut c_void);
+    fn FSEventStreamRelease(stream: *mut c_void);
+}
+
+/// Registration for changes under a directory
+///
+/// Returned by [watch](watch).  Changes are reported for as long as this is
+/// alive.  A callback may drop its own `Watcher`.
+pub struct Watcher {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    stream: *mut c_void,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            FSEventStreamStop(self.stream);
+            FSEventStreamInvalidate(self.stream);
+            FSEventStreamRelease(self.stream);
+        }
+    }
+}
+
+/// Watch a directory, and everything under it, for changes
+///
+/// Calls `cb` on the main thread for each file or directory that changes
+/// from now on, while the app's run loop is running.  Changes made while
+/// the run loop isn't running are reported the next time it runs.
+///
+/// Symbolic links in `path` are resolved, so reported paths start with the
+/// resolved directory (ex: `/private/tmp` for `/tmp`).
+///
+/// # Returns
+///
+/// A [Watcher](Watcher) that must be kept alive to keep receiving changes.
+/// `FruitError::MainThreadViolation` if not called from the main thread, or
+/// `FruitError::IOError` if `path` doesn't exist or can't be watched.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn watch(path: &Path, cb: WatchCallback) -> Result<Watcher, FruitError> {
+    if !is_main_thread() {
+        return Err(FruitError::MainThreadViolation);
+    }
+    let path = ::expand_path(&path.to_string_lossy()).canonicalize()?;
+    let cls = class("NSArray")?;
+    // The stream holds its own reference to the callback, through the
+    // context's retain and release, so it outlives a Watcher dropped
+    // from inside it.
+    let info = Rc::into_raw(Rc::new(cb)) as *mut c_void;
+    let mut context = FSEventStreamContext {
+        version: 0,
+        info: info,
+        retain: Some(retain_callback),
+        release: Some(release_callback),
+        copy_description: std::ptr::null(),
+    };
+    unsafe {
+        let paths: *mut Object = msg_send![cls,
+                                           arrayWithObject: str_to_nsstring(&path.to_string_lossy())];
+        let stream = FSEventStreamCreate(nil, stream_callback, &mut context, paths,
+                                         SINCE_NOW, LATENCY, CREATE_FLAGS);
+        release_callback(info);
+        if stream.is_null() {
+            return Err(watch_error(&path));
+        }
+        FSEventStreamScheduleWithRunLoop(stream, CFRunLoopGetMain(), kCFRunLoopCommonModes);
+        if FSEventStreamStart(stream) == 0 {
+            FSEventStreamInvalidate(stream);
+            FSEventStreamRelease(stream);
+            return Err(watch_error(&path));
+        }
+        Ok(Watcher {
+            stream: stream,
+        })
+    }
+}
+
+/// Take another reference to the callback in a stream's context
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn retain_callback(info: *const c_void) -> *const c_void {
+    unsafe { Rc::increment_strong_count(info as *const WatchCallback) };
+    info
+}
+
+/// Drop a reference to the callback in a stream's context
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn release_callback(info: *const c_void) {
+    unsafe { Rc::decrement_strong_count(info as *const WatchCallback) };
+}
+
+/// Error for a directory FSEvents refused to watch
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn watch_error(path: &Path) -> FruitError {
+    FruitError::IOError(std::io::Error::new(
+        std::io::ErrorKind::Other, format!("failed to watch {}", path.display())))
+}
+
+/// Receives batches of changes from FSEvents and passes them to the callback
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn stream_callback(_stream: *mut c_void, info: *mut c_void, count: usize,
+                              paths: *mut c_void, flags: *const u32, _ids: *const u64) {
+    // Hold a reference while calling, in case the callback drops the Watcher
+    let cb = unsafe {
+        retain_callback(info);
+        Rc::from_raw(info as *const WatchCallback)
+    };
+    let (paths, flags) = unsafe {
+        (std::slice::from_raw_parts(paths as *const *const c_char, count),
+         std::slice::from_raw_parts(flags, count))
+    };
+    for (&path, &flags) in paths.iter().zip(flags) {
+        let path = unsafe { std::ffi::CStr::from_ptr(path) };
+        cb(&WatchEvent {
+            path: PathBuf::from(path.to_string_lossy().into_owned()),
+            is_dir: flags & FLAG_IS_DIR != 0,
+            created: flags & FLAG_CREATED != 0,
+            removed: flags & FLAG_REMOVED != 0,
+            renamed: flags & FLAG_RENAMED != 0,
+            modified: flags & FLAG_MODIFIED != 0,
+            rescan: flags & FLAG_MUST_SCAN != 0,
+        });
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn watch(_path: &Path, _cb: WatchCallback) -> Result<Watcher, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
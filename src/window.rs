@@ -0,0 +1,191 @@
+//! A single window with a pixel buffer, for apps without a GUI toolkit
+//!
+//! Requires the 'window' feature to be specified at compile time.
+//!
+//! Creates a plain NSWindow whose content is a Core Animation layer showing
+//! an image drawn by the Rust side.  Draw each frame into a buffer of pixels,
+//! hand it to [Window::present](Window::present), and keep pumping the
+//! `FruitApp` run loop as usual.  Useful for emulators, visualizations, and
+//! software renderers that just need somewhere to put their pixels.
+//!
+//! Pixels are `u32` values in `0x00RRGGBB` format, stored in rows from the
+//! top-left corner.  The image is scaled to fill the window.
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Object, NO, YES};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, str_to_nsstring, NSRect};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::c_void;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "CoreGraphics", kind = "framework")]
+#[link(name = "QuartzCore", kind = "framework")]
+extern "C" {
+    fn CGColorSpaceCreateDeviceRGB() -> *mut c_void;
+    fn CGColorSpaceRelease(space: *mut c_void);
+    fn CGDataProviderCreateWithCFData(data: *mut Object) -> *mut c_void;
+    fn CGDataProviderRelease(provider: *mut c_void);
+    fn CGImageCreate(width: usize, height: usize,
+                     bits_per_component: usize, bits_per_pixel: usize, bytes_per_row: usize,
+                     space: *mut c_void, bitmap_info: u32, provider: *mut c_void,
+                     decode: *const f64, should_interpolate: bool, intent: i32) -> *mut Object;
+    fn CGImageRelease(image: *mut Object);
+}
+
+/// kCGImageAlphaNoneSkipFirst | kCGBitmapByteOrder32Little, which is
+/// `0x00RRGGBB` in a native little-endian `u32`
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const BITMAP_INFO: u32 = 6 | (2 << 12);
+
+/// A window showing an image drawn by the app
+///
+/// Must be created and used on the main thread, after the `FruitApp`.  The
+/// window is closed when this is dropped.
+pub struct Window {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    window: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    layer: *mut Object,
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Window {
+    /// Create and show a new window
+    ///
+    /// # Arguments
+    ///
+    /// `title` - Text shown in the window's title bar
+    ///
+    /// `width`, `height` - Initial size of the window's content, in points
+    ///
+    /// # Returns
+    ///
+    /// The new window, or `FruitError::ClassNotFound` if AppKit or Core
+    /// Animation classes are missing from the ObjC runtime.
+    pub fn new(title: &str, width: u32, height: u32) -> Result<Window, FruitError> {
+        unsafe {
+            let frame = NSRect { x: 0., y: 0., width: width as f64, height: height as f64 };
+            // Titled | Closable | Miniaturizable | Resizable
+            let style: u64 = 1 | 2 | 4 | 8;
+            let cls = class("NSWindow")?;
+            let window: *mut Object = msg_send![cls, alloc];
+            let window: *mut Object = msg_send![window,
+                                                initWithContentRect: frame
+                                                styleMask: style
+                                                backing: 2u64 // NSBackingStoreBuffered
+                                                defer: NO];
+            // Owned by this struct, not freed behind its back by the close box
+            let _:() = msg_send![window, setReleasedWhenClosed: NO];
+            let _:() = msg_send![window, setTitle: str_to_nsstring(title)];
+
+            let cls = class("CALayer")?;
+            let layer: *mut Object = msg_send![cls, layer];
+            let layer: *mut Object = msg_send![layer, retain];
+            let view: *mut Object = msg_send![window, contentView];
+            let _:() = msg_send![view, setLayer: layer];
+            let _:() = msg_send![view, setWantsLayer: YES];
+
+            let _:() = msg_send![window, center];
+            let _:() = msg_send![window, makeKeyAndOrderFront: nil];
+            Ok(Window {
+                window,
+                layer,
+                _not_send: std::marker::PhantomData,
+            })
+        }
+    }
+
+    /// Change the text shown in the window's title bar
+    pub fn set_title(&self, title: &str) {
+        unsafe {
+            let _:() = msg_send![self.window, setTitle: str_to_nsstring(title)];
+        }
+    }
+
+    /// Show a new frame in the window
+    ///
+    /// The pixels are copied, so the buffer can be reused for the next frame
+    /// right away.  The frame appears the next time the run loop is pumped.
+    ///
+    /// # Arguments
+    ///
+    /// `pixels` - Image to show, in `0x00RRGGBB` format, row by row
+    ///
+    /// `width`, `height` - Size of the image, in pixels
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pixels` holds fewer than `width * height` pixels.
+    pub fn present(&self, pixels: &[u32], width: usize, height: usize) {
+        assert!(pixels.len() >= width * height, "Window::present(): buffer too small");
+        if width == 0 || height == 0 {
+            return;
+        }
+        let cls = match class("NSData") {
+            Ok(cls) => cls,
+            Err(e) => {
+                info!("Can't present frame: {}", e);
+                return;
+            },
+        };
+        unsafe {
+            let data: *mut Object = msg_send![cls, dataWithBytes: pixels.as_ptr() as *const c_void
+                                              length: width * height * 4];
+            let provider = CGDataProviderCreateWithCFData(data);
+            let space = CGColorSpaceCreateDeviceRGB();
+            let image = CGImageCreate(width, height, 8, 32, width * 4, space, BITMAP_INFO,
+                                      provider, std::ptr::null(), false, 0);
+            CGColorSpaceRelease(space);
+            CGDataProviderRelease(provider);
+            if !image.is_null() {
+                let _:() = msg_send![self.layer, setContents: image];
+                CGImageRelease(image);
+            }
+        }
+    }
+
+    /// Returns whether the window is still open and on screen
+    ///
+    /// False once the user has closed the window.
+    pub fn is_visible(&self) -> bool {
+        unsafe {
+            let visible: objc::runtime::BOOL = msg_send![self.window, isVisible];
+            visible != NO
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for Window {
+    fn drop(&mut self) {
+        unsafe {
+            let _:() = msg_send![self.window, close];
+            let _:() = msg_send![self.window, release];
+            let _:() = msg_send![self.layer, release];
+        }
+    }
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+impl Window {
+    /// Docs in OS X build.
+    pub fn new(_title: &str, _width: u32, _height: u32) -> Result<Window, FruitError> {
+        Ok(Window { _not_send: std::marker::PhantomData })
+    }
+    /// Docs in OS X build.
+    pub fn set_title(&self, _title: &str) {}
+    /// Docs in OS X build.
+    pub fn present(&self, pixels: &[u32], width: usize, height: usize) {
+        assert!(pixels.len() >= width * height, "Window::present(): buffer too small");
+    }
+    /// Docs in OS X build.
+    pub fn is_visible(&self) -> bool { false }
+}
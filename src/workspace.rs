@@ -0,0 +1,522 @@
+//! Awareness and control of other running applications
+//!
+//! Thin wrappers around `NSWorkspace` and `NSRunningApplication` for utility
+//! apps that need to know what else is running on the system, and to bring
+//! those applications forward, hide them, or ask them to quit.
+//!
+//! Applications are addressed by their bundle identifier (ex:
+//! `com.apple.Safari`).  All matching instances are affected when more than
+//! one copy of an application is running.
+//!
+//! Files, text, and links can also be sent to the system's share targets,
+//! such as Mail or AirDrop, with [share](share), and files can be moved to
+//! the Trash or duplicated the way Finder does.
+//!
+//! Apps that work with external drives, like backup tools, can be told when
+//! volumes are mounted, unmounted, or renamed with
+//! [watch_volumes](watch_volumes).
+
+use std::path::{Path, PathBuf};
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::Object;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{Class, Sel};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::c_void;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use objc::runtime::{BOOL, NO};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::nsstring_to_string;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::str_to_nsstring;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use osx::{class, declare_class, is_main_thread};
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_upper_case_globals)]
+const nil: *mut Object = 0 as *mut Object;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[link(name = "AppKit", kind = "framework")]
+extern "C" {
+    static NSSharingServiceNameComposeEmail: *mut Object;
+    static NSSharingServiceNameComposeMessage: *mut Object;
+    static NSSharingServiceNameSendViaAirDrop: *mut Object;
+    static NSWorkspaceDidMountNotification: *mut Object;
+    static NSWorkspaceWillUnmountNotification: *mut Object;
+    static NSWorkspaceDidUnmountNotification: *mut Object;
+    static NSWorkspaceDidRenameVolumeNotification: *mut Object;
+    static NSWorkspaceVolumeURLKey: *mut Object;
+    static NSWorkspaceVolumeOldURLKey: *mut Object;
+}
+
+/// Information about an application running on the system
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningApp {
+    /// Bundle identifier of the application, if it has one
+    pub bundle_id: Option<String>,
+    /// Process ID of the application
+    pub pid: i32,
+    /// Localized, user-visible name of the application
+    pub name: String,
+}
+
+/// Something to share with [share](share)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareItem {
+    /// Plain text
+    Text(String),
+    /// A link (ex: `https://example.com`)
+    Url(String),
+    /// A file on disk
+    File(PathBuf),
+}
+
+/// System share target to send items to
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShareService {
+    /// New email in Mail, with the items as the body and attachments
+    Email,
+    /// New message in Messages
+    Messages,
+    /// AirDrop to a nearby device, chosen by the user
+    AirDrop,
+    /// Copy the items to the clipboard
+    CopyLink,
+    /// Any other NSSharingService, by name
+    Named(String),
+}
+
+/// A change to the volumes mounted on the system, reported by
+/// [watch_volumes](watch_volumes)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeEvent {
+    /// A volume was mounted at the given path (ex: `/Volumes/Backup`)
+    Mounted(PathBuf),
+    /// A volume is about to be unmounted.  Close any files open on it so
+    /// the unmount can succeed.
+    WillUnmount(PathBuf),
+    /// A volume was unmounted
+    Unmounted(PathBuf),
+    /// A volume was renamed, which also changes its mount path
+    Renamed {
+        /// Path of the volume before it was renamed
+        from: PathBuf,
+        /// Path of the volume now
+        to: PathBuf,
+    },
+}
+
+/// A boxed Fn type for receiving volume changes
+pub type VolumeCallback = Box<dyn Fn(&VolumeEvent)>;
+
+/// Registration for volume changes
+///
+/// Returned by [watch_volumes](watch_volumes).  Changes are reported for as
+/// long as this is alive.
+pub struct VolumeWatcher {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    center: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    observer: *mut Object,
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    _callback: Box<VolumeCallback>,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for VolumeWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            let _:() = msg_send![self.center, removeObserver: self.observer];
+            let _:() = msg_send![self.observer, release];
+        }
+    }
+}
+
+/// List the applications currently running for this user
+///
+/// Returns every application known to `NSWorkspace`, which includes
+/// background-only and agent apps in addition to the ones visible in the
+/// Dock.  Returns an empty list if `NSWorkspace` is missing from the ObjC
+/// runtime.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn running_applications() -> Vec<RunningApp> {
+    let cls = match class("NSWorkspace") {
+        Ok(cls) => cls,
+        Err(e) => {
+            info!("Can't list running applications: {}", e);
+            return vec![];
+        },
+    };
+    unsafe {
+        let wspace: *mut Object = msg_send![cls, sharedWorkspace];
+        let apps: *mut Object = msg_send![wspace, runningApplications];
+        let count: usize = msg_send![apps, count];
+        (0..count).map(|idx| {
+            let app: *mut Object = msg_send![apps, objectAtIndex: idx];
+            let ident: *mut Object = msg_send![app, bundleIdentifier];
+            let name: *mut Object = msg_send![app, localizedName];
+            let pid: i32 = msg_send![app, processIdentifier];
+            RunningApp {
+                bundle_id: if ident.is_null() { None } else { Some(nsstring_to_string(ident)) },
+                pid,
+                name: nsstring_to_string(name),
+            }
+        }).collect()
+    }
+}
+
+/// Bring all running instances of an application to the foreground
+///
+/// # Returns
+///
+/// true if at least one running instance of the application was found
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn activate_app(bundle_id: &str) -> bool {
+    // NSApplicationActivateIgnoringOtherApps
+    for_each_app(bundle_id, |app| unsafe {
+        let _: BOOL = msg_send![app, activateWithOptions: 1u64 << 1];
+    })
+}
+
+/// Hide all running instances of an application
+///
+/// # Returns
+///
+/// true if at least one running instance of the application was found
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn hide_app(bundle_id: &str) -> bool {
+    for_each_app(bundle_id, |app| unsafe {
+        let _: BOOL = msg_send![app, hide];
+    })
+}
+
+/// Ask all running instances of an application to quit
+///
+/// This is a polite request, equivalent to the user selecting Quit from the
+/// application's menu.  The application may prompt the user or refuse.
+///
+/// # Returns
+///
+/// true if at least one running instance of the application was found
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn terminate_app(bundle_id: &str) -> bool {
+    for_each_app(bundle_id, |app| unsafe {
+        let _: BOOL = msg_send![app, terminate];
+    })
+}
+
+/// Send items to a system share target
+///
+/// Opens the service's own interface, such as a Mail compose window or the
+/// AirDrop device picker, with the items filled in; the user completes or
+/// cancels the share from there.  `CopyLink` copies the items to the
+/// clipboard immediately instead.
+///
+/// Must be called on the main thread.
+///
+/// # Returns
+///
+/// `FruitError::ShareError` if an item is invalid, or if the service is
+/// unavailable or can't share these items.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn share(items: &[ShareItem], service: ShareService) -> Result<(), FruitError> {
+    if !is_main_thread() {
+        return Err(FruitError::MainThreadViolation);
+    }
+    let array_cls = class("NSMutableArray")?;
+    let url_cls = class("NSURL")?;
+    unsafe {
+        let array: *mut Object = msg_send![array_cls, array];
+        for item in items {
+            let obj: *mut Object = match *item {
+                ShareItem::Text(ref text) => str_to_nsstring(text),
+                ShareItem::Url(ref url) => msg_send![url_cls, URLWithString: str_to_nsstring(url)],
+                ShareItem::File(ref path) => {
+                    if !path.exists() {
+                        return Err(FruitError::ShareError(
+                            format!("{} does not exist", path.display())));
+                    }
+                    ::foundation::path_to_nsurl(path)
+                },
+            };
+            if obj.is_null() {
+                return Err(FruitError::ShareError(format!("invalid item: {:?}", item)));
+            }
+            let _:() = msg_send![array, addObject: obj];
+        }
+        let name = match service {
+            ShareService::Email => NSSharingServiceNameComposeEmail,
+            ShareService::Messages => NSSharingServiceNameComposeMessage,
+            ShareService::AirDrop => NSSharingServiceNameSendViaAirDrop,
+            ShareService::Named(ref name) => str_to_nsstring(name),
+            ShareService::CopyLink => {
+                let pasteboard: *mut Object = msg_send![class("NSPasteboard")?, generalPasteboard];
+                let _: i64 = msg_send![pasteboard, clearContents];
+                let ok: BOOL = msg_send![pasteboard, writeObjects: array];
+                if ok == NO {
+                    return Err(FruitError::ShareError("failed to copy to clipboard".to_string()));
+                }
+                return Ok(());
+            },
+        };
+        let sharer: *mut Object = msg_send![class("NSSharingService")?, sharingServiceNamed: name];
+        if sharer.is_null() {
+            return Err(FruitError::ShareError(
+                format!("sharing service {} is unavailable", nsstring_to_string(name))));
+        }
+        let ok: BOOL = msg_send![sharer, canPerformWithItems: array];
+        if ok == NO {
+            return Err(FruitError::ShareError(
+                format!("sharing service {} can't share these items", nsstring_to_string(name))));
+        }
+        let _:() = msg_send![sharer, performWithItems: array];
+    }
+    Ok(())
+}
+
+/// Move a file or directory to the Trash
+///
+/// The item can be put back from the Trash in Finder, unlike one removed
+/// with `std::fs::remove_file()`.  Items on volumes without a Trash, such as
+/// some network shares, can't be moved to the Trash.
+///
+/// # Returns
+///
+/// The item's new path in the Trash, which is renamed if the Trash already
+/// holds an item with the same name.  `FruitError::IOError` if the item
+/// doesn't exist or can't be moved.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn move_to_trash(path: &Path) -> Result<PathBuf, FruitError> {
+    std::fs::symlink_metadata(path)?;
+    let cls = class("NSFileManager")?;
+    unsafe {
+        let manager: *mut Object = msg_send![cls, defaultManager];
+        let mut result: *mut Object = nil;
+        let mut error: *mut Object = nil;
+        let ok: BOOL = msg_send![manager, trashItemAtURL: ::foundation::path_to_nsurl(path)
+                                 resultingItemURL: &mut result
+                                 error: &mut error];
+        if ok == NO {
+            return Err(file_error(error));
+        }
+        Ok(::foundation::nsurl_to_path(result).unwrap_or_default())
+    }
+}
+
+/// Copy a file or directory next to itself, as Finder's Duplicate does
+///
+/// The copy is named like Finder names duplicates: `report copy.txt`, then
+/// `report copy 2.txt` and so on if that name is taken.  Directories are
+/// copied with everything in them, and metadata such as extended
+/// attributes is preserved.
+///
+/// # Returns
+///
+/// Path of the new copy.  `FruitError::IOError` if the item doesn't exist or
+/// can't be copied.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn duplicate(path: &Path) -> Result<PathBuf, FruitError> {
+    std::fs::symlink_metadata(path)?;
+    let copy = duplicate_path(path);
+    let cls = class("NSFileManager")?;
+    unsafe {
+        let manager: *mut Object = msg_send![cls, defaultManager];
+        let mut error: *mut Object = nil;
+        let ok: BOOL = msg_send![manager, copyItemAtURL: ::foundation::path_to_nsurl(path)
+                                 toURL: ::foundation::path_to_nsurl(&copy)
+                                 error: &mut error];
+        if ok == NO {
+            return Err(file_error(error));
+        }
+    }
+    Ok(copy)
+}
+
+/// First unused name for a duplicate of `path`, as Finder would name it
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn duplicate_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..).map(|n| {
+        let suffix = if n == 1 { " copy".to_string() } else { format!(" copy {}", n) };
+        path.with_file_name(format!("{}{}{}", stem, suffix, ext))
+    }).find(|candidate| std::fs::symlink_metadata(candidate).is_err())
+        .unwrap_or_default()
+}
+
+/// Convert a failed file operation's NSError into a FruitError
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn file_error(error: *mut Object) -> FruitError {
+    let message = if error == nil {
+        "unknown error".to_string()
+    } else {
+        let description: *mut Object = unsafe { msg_send![error, localizedDescription] };
+        nsstring_to_string(description)
+    };
+    FruitError::IOError(std::io::Error::new(std::io::ErrorKind::Other, message))
+}
+
+/// Be told when volumes are mounted, unmounted, or renamed
+///
+/// Calls `cb` on the main thread for each change while the app's run loop
+/// is running.  Covers every kind of volume that appears in Finder, such as
+/// external drives, disk images, and network shares.  Volumes that were
+/// already mounted are not reported.
+///
+/// # Returns
+///
+/// A [VolumeWatcher](VolumeWatcher) that must be kept alive to keep
+/// receiving changes.  `FruitError::MainThreadViolation` if not called from
+/// the main thread.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn watch_volumes(cb: VolumeCallback) -> Result<VolumeWatcher, FruitError> {
+    if !is_main_thread() {
+        return Err(FruitError::MainThreadViolation);
+    }
+    let cls = register_volume_observer_class()?;
+    let center = workspace_notification_center()?;
+    let callback = Box::new(cb);
+    unsafe {
+        let observer: *mut Object = msg_send![cls, new];
+        (*observer).set_ivar("_callback", &*callback as *const VolumeCallback as *const c_void);
+        for &name in &[NSWorkspaceDidMountNotification, NSWorkspaceWillUnmountNotification,
+                       NSWorkspaceDidUnmountNotification, NSWorkspaceDidRenameVolumeNotification] {
+            let _:() = msg_send![center, addObserver: observer
+                                 selector: sel!(volumeChanged:)
+                                 name: name
+                                 object: nil];
+        }
+        Ok(VolumeWatcher {
+            center: center,
+            observer: observer,
+            _callback: callback,
+        })
+    }
+}
+
+/// NSWorkspace's own notification center, which posts volume changes
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn workspace_notification_center() -> Result<*mut Object, FruitError> {
+    let cls = class("NSWorkspace")?;
+    unsafe {
+        let wspace: *mut Object = msg_send![cls, sharedWorkspace];
+        Ok(msg_send![wspace, notificationCenter])
+    }
+}
+
+/// Register the class that receives volume notifications for a
+/// `VolumeWatcher`
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn register_volume_observer_class() -> Result<&'static Class, FruitError> {
+    declare_class("FruitVolumeObserver", |decl| {
+        decl.add_ivar::<*const c_void>("_callback");
+
+        /// Convert a volume notification to a VolumeEvent for the callback
+        extern "C" fn objc_volume_changed(this: &Object, _cmd: Sel, notification: *mut Object) {
+            unsafe {
+                let raw: *const c_void = *this.get_ivar("_callback");
+                if raw.is_null() {
+                    return;
+                }
+                let cb = &*(raw as *const VolumeCallback);
+                let name: *mut Object = msg_send![notification, name];
+                let info: *mut Object = msg_send![notification, userInfo];
+                let url_for = |key: *mut Object| -> PathBuf {
+                    if info == nil {
+                        return PathBuf::new();
+                    }
+                    let url: *mut Object = msg_send![info, objectForKey: key];
+                    ::foundation::nsurl_to_path(url).unwrap_or_default()
+                };
+                let is = |other: *mut Object| -> bool {
+                    let equal: BOOL = msg_send![name, isEqualToString: other];
+                    equal != NO
+                };
+                let volume = url_for(NSWorkspaceVolumeURLKey);
+                let event = if is(NSWorkspaceDidMountNotification) {
+                    VolumeEvent::Mounted(volume)
+                } else if is(NSWorkspaceWillUnmountNotification) {
+                    VolumeEvent::WillUnmount(volume)
+                } else if is(NSWorkspaceDidUnmountNotification) {
+                    VolumeEvent::Unmounted(volume)
+                } else if is(NSWorkspaceDidRenameVolumeNotification) {
+                    VolumeEvent::Renamed { from: url_for(NSWorkspaceVolumeOldURLKey), to: volume }
+                } else {
+                    return;
+                };
+                cb(&event);
+            }
+        }
+        unsafe {
+            let f: extern "C" fn(&Object, Sel, *mut Object) = objc_volume_changed;
+            decl.add_method(sel!(volumeChanged:), f);
+        }
+    })
+}
+
+/// Call `f` with each NSRunningApplication matching the bundle identifier
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn for_each_app<F: Fn(*mut Object)>(bundle_id: &str, f: F) -> bool {
+    let cls = match class("NSRunningApplication") {
+        Ok(cls) => cls,
+        Err(e) => {
+            info!("Can't look up {}: {}", bundle_id, e);
+            return false;
+        },
+    };
+    unsafe {
+        let apps: *mut Object = msg_send![cls,
+                                          runningApplicationsWithBundleIdentifier:
+                                          str_to_nsstring(bundle_id)];
+        let count: usize = msg_send![apps, count];
+        for idx in 0..count {
+            let app: *mut Object = msg_send![apps, objectAtIndex: idx];
+            f(app);
+        }
+        count > 0
+    }
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn running_applications() -> Vec<RunningApp> { vec![] }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn activate_app(_bundle_id: &str) -> bool { false }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn hide_app(_bundle_id: &str) -> bool { false }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn terminate_app(_bundle_id: &str) -> bool { false }
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn share(_items: &[ShareItem], _service: ShareService) -> Result<(), FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn move_to_trash(_path: &Path) -> Result<PathBuf, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn duplicate(_path: &Path) -> Result<PathBuf, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+/// Docs in OS X build.
+pub fn watch_volumes(_cb: VolumeCallback) -> Result<VolumeWatcher, FruitError> {
+    Err(FruitError::UnsupportedPlatform)
+}
@@ -0,0 +1,277 @@
+//! Talk between an app and its bundled XPC services
+//!
+//! Requires the 'xpc' feature to be specified at compile time.
+//!
+//! XPC services are small helper executables, in their own `.xpc` bundles
+//! inside the app's `Contents/XPCServices` directory, that macOS launches on
+//! demand and runs in their own process, often with fewer privileges than the
+//! app.  Add one to the app bundle with
+//! [Trampoline::xpc_service](../struct.Trampoline.html#method.xpc_service).
+//!
+//! The service calls [serve](serve) from its `main()` to handle messages.
+//! The app opens a [Connection](Connection) to the service by its bundle
+//! identifier and sends messages, optionally waiting for a reply.
+//!
+//! Messages are plain byte buffers; serialize them however you like.
+
+use FruitError;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::os::raw::{c_char, c_void};
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use std::sync::OnceLock;
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+use block::{Block, ConcreteBlock};
+
+/// A boxed Fn type for receiving messages on a [Connection](Connection)
+pub type XpcCallback = Box<dyn Fn(&[u8]) + Send + Sync>;
+
+/// A boxed Fn type for handling requests in an XPC service
+///
+/// Called with each message received by the service.  If the sender is
+/// waiting for a reply, the returned bytes are sent back to it.
+pub type XpcHandler = Box<dyn Fn(&[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+#[allow(non_camel_case_types)]
+type xpc_object_t = *mut c_void;
+
+/// Dictionary key holding the message bytes
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const DATA_KEY: &[u8] = b"data\0";
+/// XPC_ERROR_KEY_DESCRIPTION
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+const ERROR_DESCRIPTION_KEY: &[u8] = b"XPCErrorDescription\0";
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" {
+    static _xpc_type_dictionary: c_void;
+    static _xpc_type_error: c_void;
+    fn xpc_connection_create(name: *const c_char, queue: *mut c_void) -> xpc_object_t;
+    fn xpc_connection_set_event_handler(connection: xpc_object_t,
+                                        handler: *const c_void);
+    fn xpc_connection_resume(connection: xpc_object_t);
+    fn xpc_connection_cancel(connection: xpc_object_t);
+    fn xpc_connection_send_message(connection: xpc_object_t, message: xpc_object_t);
+    fn xpc_connection_send_message_with_reply_sync(connection: xpc_object_t,
+                                                   message: xpc_object_t) -> xpc_object_t;
+    fn xpc_dictionary_create(keys: *const *const c_char, values: *const xpc_object_t,
+                             count: usize) -> xpc_object_t;
+    fn xpc_dictionary_create_reply(original: xpc_object_t) -> xpc_object_t;
+    fn xpc_dictionary_set_data(dictionary: xpc_object_t, key: *const c_char,
+                               bytes: *const c_void, length: usize);
+    fn xpc_dictionary_get_data(dictionary: xpc_object_t, key: *const c_char,
+                               length: *mut usize) -> *const c_void;
+    fn xpc_dictionary_get_string(dictionary: xpc_object_t, key: *const c_char) -> *const c_char;
+    fn xpc_dictionary_get_remote_connection(dictionary: xpc_object_t) -> xpc_object_t;
+    fn xpc_get_type(object: xpc_object_t) -> *const c_void;
+    fn xpc_release(object: xpc_object_t);
+    fn xpc_main(handler: extern "C" fn(xpc_object_t)) -> !;
+}
+
+/// Handler given to [serve](serve), shared by all of the service's connections
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+static SERVICE_HANDLER: OnceLock<XpcHandler> = OnceLock::new();
+
+/// A connection from the app to one of its XPC services
+///
+/// Messages can be sent from any thread.  The connection is closed when this
+/// is dropped.
+pub struct Connection {
+    #[cfg(all(target_os = "macos", not(feature="dummy")))]
+    connection: xpc_object_t,
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe impl Send for Connection {}
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe impl Sync for Connection {}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Connection {
+    /// Connect to an XPC service bundled with the app
+    ///
+    /// The service is launched when the first message is sent.  Messages the
+    /// service sends without being asked are passed to `cb`, on a background
+    /// thread.
+    ///
+    /// # Arguments
+    ///
+    /// `service` - Bundle identifier of the XPC service
+    ///
+    /// `cb` - Called with messages sent by the service
+    pub fn new(service: &str, cb: XpcCallback) -> Result<Connection, FruitError> {
+        let name = std::ffi::CString::new(service)
+            .map_err(|e| FruitError::IOError(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput, e)))?;
+        unsafe {
+            let connection = xpc_connection_create(name.as_ptr(), std::ptr::null_mut());
+            if connection.is_null() {
+                return Err(FruitError::IOError(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("failed to connect to XPC service {}", service))));
+            }
+            let handler = ConcreteBlock::new(move |event: xpc_object_t| {
+                if let Some(data) = message_data(event) {
+                    cb(data);
+                } else if xpc_get_type(event) == &_xpc_type_error as *const c_void {
+                    info!("XPC connection error: {}", error_description(event));
+                }
+            }).copy();
+            xpc_connection_set_event_handler(connection, block_ptr(&handler));
+            xpc_connection_resume(connection);
+            Ok(Connection { connection: connection })
+        }
+    }
+
+    /// Send a message to the service without waiting for a reply
+    pub fn send(&self, msg: &[u8]) {
+        unsafe {
+            let message = new_message(msg);
+            xpc_connection_send_message(self.connection, message);
+            xpc_release(message);
+        }
+    }
+
+    /// Send a message to the service and wait for its reply
+    ///
+    /// Blocks the calling thread until the service replies.  Avoid calling
+    /// this from the main thread if the service may take a while.
+    ///
+    /// # Returns
+    ///
+    /// The reply, or `FruitError::IOError` if the service could not be
+    /// reached or returned no reply.
+    pub fn send_with_reply(&self, msg: &[u8]) -> Result<Vec<u8>, FruitError> {
+        unsafe {
+            let message = new_message(msg);
+            let reply = xpc_connection_send_message_with_reply_sync(self.connection, message);
+            xpc_release(message);
+            let result = match message_data(reply) {
+                Some(data) => Ok(data.to_vec()),
+                None => Err(FruitError::IOError(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("XPC request failed: {}", error_description(reply))))),
+            };
+            xpc_release(reply);
+            result
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe {
+            xpc_connection_cancel(self.connection);
+            xpc_release(self.connection);
+        }
+    }
+}
+
+/// Run an XPC service, handling messages with `handler`
+///
+/// Call from the `main()` of an XPC service executable.  Takes over the
+/// process and never returns; the OS terminates the service when it is no
+/// longer needed.  `handler` is called on a background thread with each
+/// message, and its result is sent back to senders waiting for a reply.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+pub fn serve(handler: XpcHandler) -> ! {
+    let _ = SERVICE_HANDLER.set(handler);
+    unsafe { xpc_main(new_service_connection) }
+}
+
+/// Called by xpc_main() when a client connects to the service
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+extern "C" fn new_service_connection(connection: xpc_object_t) {
+    let handler = match SERVICE_HANDLER.get() {
+        Some(handler) => handler,
+        None => return,
+    };
+    unsafe {
+        let block = ConcreteBlock::new(move |event: xpc_object_t| {
+            let data = match message_data(event) {
+                Some(data) => data,
+                None => return,
+            };
+            let response = handler(data);
+            let reply = xpc_dictionary_create_reply(event);
+            if reply.is_null() {
+                return;
+            }
+            if let Some(response) = response {
+                xpc_dictionary_set_data(reply, DATA_KEY.as_ptr() as *const c_char,
+                                        response.as_ptr() as *const c_void, response.len());
+            }
+            xpc_connection_send_message(xpc_dictionary_get_remote_connection(event), reply);
+            xpc_release(reply);
+        }).copy();
+        xpc_connection_set_event_handler(connection, block_ptr(&block));
+        xpc_connection_resume(connection);
+    }
+}
+
+/// Pointer to a block, for passing to XPC, which copies it
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+fn block_ptr(block: &Block<(xpc_object_t,), ()>) -> *const c_void {
+    block as *const Block<(xpc_object_t,), ()> as *const c_void
+}
+
+/// Wrap bytes in an XPC dictionary
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn new_message(msg: &[u8]) -> xpc_object_t {
+    let message = xpc_dictionary_create(std::ptr::null(), std::ptr::null(), 0);
+    xpc_dictionary_set_data(message, DATA_KEY.as_ptr() as *const c_char,
+                            msg.as_ptr() as *const c_void, msg.len());
+    message
+}
+
+/// Bytes of a message, or None if the object isn't a message
+///
+/// A reply with no data, because the service's handler returned None, is an
+/// empty message.
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn message_data<'a>(event: xpc_object_t) -> Option<&'a [u8]> {
+    if event.is_null() || xpc_get_type(event) != &_xpc_type_dictionary as *const c_void {
+        return None;
+    }
+    let mut length: usize = 0;
+    let bytes = xpc_dictionary_get_data(event, DATA_KEY.as_ptr() as *const c_char, &mut length);
+    if bytes.is_null() {
+        return Some(&[]);
+    }
+    Some(std::slice::from_raw_parts(bytes as *const u8, length))
+}
+
+/// Description of an XPC error object
+#[cfg(all(target_os = "macos", not(feature="dummy")))]
+unsafe fn error_description(error: xpc_object_t) -> String {
+    if error.is_null() || xpc_get_type(error) != &_xpc_type_error as *const c_void {
+        return "unknown error".to_string();
+    }
+    let description = xpc_dictionary_get_string(error, ERROR_DESCRIPTION_KEY.as_ptr() as *const c_char);
+    if description.is_null() {
+        return "unknown error".to_string();
+    }
+    std::ffi::CStr::from_ptr(description).to_string_lossy().into_owned()
+}
+
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+impl Connection {
+    /// Docs in OS X build.
+    pub fn new(_service: &str, _cb: XpcCallback) -> Result<Connection, FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+    /// Docs in OS X build.
+    pub fn send(&self, _msg: &[u8]) {}
+    /// Docs in OS X build.
+    pub fn send_with_reply(&self, _msg: &[u8]) -> Result<Vec<u8>, FruitError> {
+        Err(FruitError::UnsupportedPlatform)
+    }
+}
+
+/// Docs in OS X build.
+#[cfg(any(not(target_os = "macos"), feature="dummy"))]
+pub fn serve(_handler: XpcHandler) -> ! {
+    panic!("XPC services are only supported on macOS");
+}